@@ -0,0 +1,181 @@
+//! Lists the connections that are currently open, as JSON.
+//!
+//! Connections may be filtered by their direction or by their peer's
+//! identity, i.e. `/connections?direction=inbound&identity=web.ns.serviceaccount.identity.linkerd.cluster.local`.
+
+use super::rsp;
+use crate::transport::{labels::TlsStatus, metrics};
+use futures::future;
+use http::{header, Method, StatusCode};
+use hyper::{service::Service, Body, Request, Response};
+use serde::Serialize;
+use std::io;
+use std::net::SocketAddr;
+
+#[derive(Clone, Debug)]
+pub struct Connections(metrics::Connections);
+
+#[derive(Debug, Default, PartialEq)]
+struct Filter {
+    direction: Option<String>,
+    identity: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Connection {
+    direction: &'static str,
+    peer_addr: SocketAddr,
+    orig_dst_addr: Option<SocketAddr>,
+    tls: String,
+    peer_identity: Option<String>,
+    protocol: Option<&'static str>,
+    read_bytes: u64,
+    write_bytes: u64,
+    age_ms: u64,
+}
+
+// === impl Connections ===
+
+impl Connections {
+    pub fn new(connections: metrics::Connections) -> Self {
+        Connections(connections)
+    }
+
+    fn list(&self, filter: &Filter) -> Vec<Connection> {
+        self.0
+            .list()
+            .into_iter()
+            .filter(|c| filter.matches(&c.description))
+            .map(|c| {
+                let d = c.description;
+                Connection {
+                    direction: d.direction,
+                    peer_addr: d.peer_addr,
+                    orig_dst_addr: d.orig_dst_addr,
+                    tls: TlsStatus::from(d.peer_identity.as_ref()).to_string(),
+                    peer_identity: d.peer_identity.value().map(|id| id.as_ref().to_string()),
+                    protocol: d.protocol,
+                    read_bytes: c.read_bytes,
+                    write_bytes: c.write_bytes,
+                    age_ms: c.age.as_secs() * 1_000 + u64::from(c.age.subsec_millis()),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Service for Connections {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = io::Error;
+    type Future = future::FutureResult<Response<Body>, Self::Error>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.method() != Method::GET {
+            return future::ok(
+                Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(header::ALLOW, "GET")
+                    .body(Body::empty())
+                    .expect("builder with known status code must not fail"),
+            );
+        }
+
+        let filter = match Filter::from_query(req.uri().query()) {
+            Ok(filter) => filter,
+            Err(error) => return future::ok(rsp(StatusCode::BAD_REQUEST, error)),
+        };
+
+        match serde_json::to_vec_pretty(&self.list(&filter)) {
+            Ok(json) => future::ok(
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(json.into())
+                    .expect("builder with known status code must not fail"),
+            ),
+            Err(error) => future::ok(rsp(StatusCode::INTERNAL_SERVER_ERROR, format!("{}", error))),
+        }
+    }
+}
+
+// === impl Filter ===
+
+impl Filter {
+    fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let mut filter = Filter::default();
+        for param in query.into_iter().flat_map(|q| q.split('&')) {
+            if param.is_empty() {
+                continue;
+            }
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().unwrap_or_default();
+            let value = kv.next().unwrap_or_default().to_string();
+            match key {
+                "direction" => filter.direction = Some(value),
+                "identity" => filter.identity = Some(value),
+                _ => return Err(format!("unsupported filter: {}\n", key)),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, description: &metrics::Description) -> bool {
+        if let Some(ref direction) = self.direction {
+            if description.direction != direction.as_str() {
+                return false;
+            }
+        }
+
+        if let Some(ref identity) = self.identity {
+            let id = description.peer_identity.value().map(|id| id.as_ref());
+            if id != Some(identity.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{proxy::identity, transport::tls};
+
+    fn description(direction: &'static str, id: Option<&str>) -> metrics::Description {
+        let peer_identity = match id {
+            Some(id) => {
+                tls::Conditional::Some(identity::Name::from_hostname(id.as_bytes()).unwrap())
+            }
+            None => tls::Conditional::None(tls::ReasonForNoPeerName::NotHttp.into()),
+        };
+        metrics::Description {
+            direction,
+            peer_addr: ([10, 1, 1, 1], 40000).into(),
+            orig_dst_addr: None,
+            peer_identity,
+            protocol: None,
+        }
+    }
+
+    #[test]
+    fn filters_by_direction_and_identity() {
+        let id = "web.ns.serviceaccount.identity.linkerd.cluster.local";
+        let filter = Filter::from_query(Some(&format!("direction=inbound&identity={}", id)))
+            .expect("filter must parse");
+        assert_eq!(
+            filter.direction.as_ref().map(String::as_str),
+            Some("inbound")
+        );
+
+        assert!(filter.matches(&description("inbound", Some(id))));
+        assert!(!filter.matches(&description("outbound", Some(id))));
+        assert!(!filter.matches(&description("inbound", None)));
+
+        let all = Filter::from_query(None).unwrap();
+        assert!(all.matches(&description("outbound", None)));
+
+        assert!(Filter::from_query(Some("port=80")).is_err());
+    }
+}
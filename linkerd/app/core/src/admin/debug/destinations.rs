@@ -0,0 +1,473 @@
+//! Records the endpoints of each concrete destination and their loads.
+//!
+//! Loads are reported by each endpoint on every request, so each endpoint's
+//! load is stored in its own atomic that is only read when the destinations
+//! are described.
+
+use super::{Described, LastUsed, Registry};
+use crate::proxy::{
+    api_resolve::{Metadata, ProtocolHint},
+    core::resolve::{self, Update},
+    http::balance::{locality::HasLocality, Cost, HasWeight, ReportLoad, Weight},
+};
+use crate::svc::NewService;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tower::discover::{Change, Discover};
+
+#[derive(Debug, Default)]
+pub(super) struct Destination {
+    state: Mutex<State>,
+    last_used: LastUsed,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    endpoints: IndexMap<SocketAddr, Metadata>,
+    loads: IndexMap<SocketAddr, Weak<Load>>,
+}
+
+/// An endpoint's most recently reported load, as the bits of an `f64`, or
+/// `NaN` if no load has been reported.
+#[derive(Debug)]
+struct Load(AtomicU64);
+
+/// Records the endpoints resolved for each destination.
+#[derive(Clone, Debug)]
+pub struct RecordResolve<R> {
+    registry: Registry,
+    inner: R,
+}
+
+pub struct RecordResolveFuture<F> {
+    inner: F,
+    dst: Option<Arc<Destination>>,
+}
+
+pub struct RecordResolution<R> {
+    inner: R,
+    dst: Arc<Destination>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    registry: Registry,
+}
+
+/// Records the load of each endpoint in the balancer built for each
+/// destination.
+#[derive(Clone, Debug)]
+pub struct MakeDiscover<M> {
+    registry: Registry,
+    inner: M,
+}
+
+pub struct MakeDiscoverFuture<F> {
+    inner: F,
+    dst: Option<Arc<Destination>>,
+}
+
+pub struct RecordDiscover<D> {
+    inner: D,
+    dst: Arc<Destination>,
+}
+
+/// An endpoint service that records its load.
+pub struct RecordLoad<S> {
+    inner: S,
+    load: Arc<Load>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BalancerLayer {
+    registry: Registry,
+}
+
+/// Records each destination while its balancer is cached.
+#[derive(Clone, Debug)]
+pub struct NewRecordBalancer<N> {
+    registry: Registry,
+    inner: N,
+}
+
+/// A balancer that records when it was last used.
+#[derive(Clone, Debug)]
+pub struct RecordBalancer<S> {
+    inner: S,
+    dst: Arc<Destination>,
+}
+
+#[derive(Debug, Serialize)]
+struct Endpoints {
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Serialize)]
+struct Endpoint {
+    addr: SocketAddr,
+    weight: u32,
+    labels: BTreeMap<String, String>,
+    identity: Option<String>,
+    protocol_hint: &'static str,
+    authority_override: Option<String>,
+    /// The endpoint's most recent Peak-EWMA cost, if it has been balanced.
+    load: Option<f64>,
+}
+
+// === impl Destination ===
+
+impl Destination {
+    pub(super) fn describe(&self, addr: &str) -> impl Serialize {
+        let state = self.state.lock().expect("destination lock poisoned");
+        let endpoints = state
+            .endpoints
+            .iter()
+            .map(|(addr, meta)| Endpoint {
+                addr: *addr,
+                weight: meta.weight(),
+                labels: meta
+                    .labels()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                identity: meta.identity().map(|id| id.as_ref().to_string()),
+                protocol_hint: match meta.protocol_hint() {
+                    ProtocolHint::Unknown => "unknown",
+                    ProtocolHint::Http2 => "h2",
+                },
+                authority_override: meta.authority_override().map(|a| a.to_string()),
+                load: state
+                    .loads
+                    .get(addr)
+                    .and_then(Weak::upgrade)
+                    .and_then(|load| load.get()),
+            })
+            .collect();
+        Described {
+            addr: addr.to_string(),
+            idle_ms: self.last_used.idle_ms(),
+            state: Endpoints { endpoints },
+        }
+    }
+
+    fn update(&self, update: &Update<Metadata>) {
+        let mut state = self.state.lock().expect("destination lock poisoned");
+        match update {
+            Update::Add(ref eps) => state.endpoints.extend(eps.iter().cloned()),
+            Update::Remove(ref addrs) => {
+                for addr in addrs {
+                    state.endpoints.remove(addr);
+                }
+            }
+            Update::Empty | Update::DoesNotExist => state.endpoints.clear(),
+        }
+    }
+
+    /// Registers an endpoint's load, replacing any load previously registered
+    /// for its address.
+    fn register_load(&self, addr: SocketAddr) -> Arc<Load> {
+        let load = Arc::new(Load(AtomicU64::new(std::f64::NAN.to_bits())));
+        let mut state = self.state.lock().expect("destination lock poisoned");
+        state.loads.retain(|_, l| l.strong_count() > 0);
+        state.loads.insert(addr, Arc::downgrade(&load));
+        load
+    }
+}
+
+// === impl Load ===
+
+impl Load {
+    fn set(&self, load: f64) {
+        self.0.store(load.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<f64> {
+        let load = f64::from_bits(self.0.load(Ordering::Relaxed));
+        if load.is_nan() {
+            None
+        } else {
+            Some(load)
+        }
+    }
+}
+
+// === impl RecordResolve ===
+
+impl<R> RecordResolve<R> {
+    pub(super) fn new(registry: Registry, inner: R) -> Self {
+        Self { registry, inner }
+    }
+}
+
+impl<T, R> tower::Service<T> for RecordResolve<R>
+where
+    T: fmt::Display,
+    R: resolve::Resolve<T, Endpoint = Metadata>,
+{
+    type Response = RecordResolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = RecordResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let dst = self.registry.destination(target.to_string());
+        RecordResolveFuture {
+            inner: self.inner.resolve(target),
+            dst: Some(dst),
+        }
+    }
+}
+
+impl<F> Future for RecordResolveFuture<F>
+where
+    F: Future,
+    F::Item: resolve::Resolution<Endpoint = Metadata>,
+{
+    type Item = RecordResolution<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let dst = self.dst.take().expect("polled after ready");
+        Ok(Async::Ready(RecordResolution { inner, dst }))
+    }
+}
+
+impl<R> resolve::Resolution for RecordResolution<R>
+where
+    R: resolve::Resolution<Endpoint = Metadata>,
+{
+    type Endpoint = Metadata;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.inner.poll());
+        self.dst.update(&update);
+        Ok(Async::Ready(update))
+    }
+}
+
+// === impl Layer ===
+
+impl Layer {
+    pub(super) fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeDiscover<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeDiscover {
+            registry: self.registry.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl MakeDiscover ===
+
+impl<T, M> tower::Service<T> for MakeDiscover<M>
+where
+    T: fmt::Display,
+    M: tower::Service<T>,
+    M::Response: Discover<Key = SocketAddr>,
+{
+    type Response = RecordDiscover<M::Response>;
+    type Error = M::Error;
+    type Future = MakeDiscoverFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let dst = self.registry.destination(target.to_string());
+        MakeDiscoverFuture {
+            inner: self.inner.call(target),
+            dst: Some(dst),
+        }
+    }
+}
+
+impl<F> Future for MakeDiscoverFuture<F>
+where
+    F: Future,
+    F::Item: Discover<Key = SocketAddr>,
+{
+    type Item = RecordDiscover<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let dst = self.dst.take().expect("polled after ready");
+        Ok(Async::Ready(RecordDiscover { inner, dst }))
+    }
+}
+
+// === impl RecordDiscover ===
+
+impl<D> Discover for RecordDiscover<D>
+where
+    D: Discover<Key = SocketAddr>,
+{
+    type Key = SocketAddr;
+    type Service = RecordLoad<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<SocketAddr, Self::Service>, D::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(addr, inner) => {
+                let svc = RecordLoad {
+                    inner,
+                    load: self.dst.register_load(addr),
+                };
+                Change::Insert(addr, svc)
+            }
+            Change::Remove(addr) => Change::Remove(addr),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl RecordLoad ===
+
+impl<S, Req> tower::Service<Req> for RecordLoad<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+impl<S> ReportLoad for RecordLoad<S> {
+    fn report_load(&self, cost: Cost) {
+        self.load.set(cost.into());
+    }
+}
+
+impl<S: HasWeight> HasWeight for RecordLoad<S> {
+    fn weight(&self) -> Weight {
+        self.inner.weight()
+    }
+}
+
+impl<S: HasLocality> HasLocality for RecordLoad<S> {
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+}
+
+// === impl BalancerLayer ===
+
+impl BalancerLayer {
+    pub(super) fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+}
+
+impl<N> tower::layer::Layer<N> for BalancerLayer {
+    type Service = NewRecordBalancer<N>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        NewRecordBalancer {
+            registry: self.registry.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl NewRecordBalancer ===
+
+impl<T, N> NewService<T> for NewRecordBalancer<N>
+where
+    T: fmt::Display,
+    N: NewService<T>,
+{
+    type Service = RecordBalancer<N::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        let dst = self.registry.destination(target.to_string());
+        RecordBalancer {
+            inner: self.inner.new_service(target),
+            dst,
+        }
+    }
+}
+
+// === impl RecordBalancer ===
+
+impl<S, Req> tower::Service<Req> for RecordBalancer<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.dst.last_used.touch();
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn describes_endpoints_with_loads() {
+        let registry = Registry::default();
+        let dst = registry.destination("web.ns.svc.cluster.local:8080".into());
+        dst.update(&Update::Add(vec![
+            (addr("10.1.1.1:8080"), Metadata::empty()),
+            (addr("10.1.1.2:8080"), Metadata::empty()),
+        ]));
+        dst.update(&Update::Remove(vec![addr("10.1.1.2:8080")]));
+
+        let svc = RecordLoad {
+            inner: (),
+            load: dst.register_load(addr("10.1.1.1:8080")),
+        };
+        svc.report_load(Cost::from(2.5));
+
+        let json = String::from_utf8(registry.destinations_json().unwrap()).unwrap();
+        let dsts: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(dsts[0]["addr"], "web.ns.svc.cluster.local:8080");
+        let endpoints = dsts[0]["endpoints"].as_array().unwrap();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0]["addr"], "10.1.1.1:8080");
+        assert_eq!(endpoints[0]["load"], 2.5);
+
+        drop(svc);
+        drop(dst);
+        assert_eq!(registry.destinations_json().unwrap(), b"[]");
+    }
+}
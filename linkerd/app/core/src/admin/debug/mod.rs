@@ -0,0 +1,217 @@
+//! Records the state of discovery, balancers, and profiles so that it may be
+//! inspected via the admin server.
+//!
+//! * `/debug/destinations` -- lists each concrete destination's endpoints,
+//!   with their metadata and load.
+//! * `/debug/profiles` -- lists each logical destination's routes and
+//!   destination overrides.
+//!
+//! Each destination is listed for as long as it is cached by the proxy.
+
+use super::rsp;
+use crate::Addr;
+use futures::future;
+use http::{header, Method, StatusCode};
+use hyper::{service::Service, Body, Request, Response};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+mod destinations;
+mod profiles;
+
+pub use self::destinations::{
+    MakeDiscover, MakeDiscoverFuture, NewRecordBalancer, RecordBalancer, RecordDiscover,
+    RecordLoad, RecordResolution, RecordResolve, RecordResolveFuture,
+};
+pub use self::profiles::{NewRecordProfile, RecordProfile, RecordRoutes, RecordRoutesFuture};
+
+/// Records the destinations cached by the proxy.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    destinations: IndexMap<String, Weak<destinations::Destination>>,
+    profiles: IndexMap<Addr, Weak<profiles::Profile>>,
+}
+
+/// Tracks how long a destination's cached service has been idle, i.e. the
+/// age that the cache's idle timeout measures.
+#[derive(Debug)]
+struct LastUsed {
+    created: Instant,
+    /// Milliseconds after `created` when the service was last called.
+    last_ms: AtomicU64,
+}
+
+// === impl Registry ===
+
+impl Registry {
+    /// Records the endpoints resolved for each concrete destination.
+    pub fn record_resolve<R>(&self, inner: R) -> RecordResolve<R> {
+        RecordResolve::new(self.clone(), inner)
+    }
+
+    /// Records the load of each endpoint in the balancer built for each
+    /// concrete destination.
+    ///
+    /// This must be applied to a `MakeService` that builds `Discover`s.
+    pub fn record_balance(&self) -> destinations::Layer {
+        destinations::Layer::new(self.clone())
+    }
+
+    /// Records how long each concrete destination's balancer has been idle.
+    ///
+    /// This must be applied to the balancers held in the cache, alongside
+    /// the cache's idle timeout.
+    pub fn record_balancers(&self) -> destinations::BalancerLayer {
+        destinations::BalancerLayer::new(self.clone())
+    }
+
+    /// Records each logical destination while its profile stack is cached.
+    pub fn record_profiles(&self) -> profiles::Layer {
+        profiles::Layer::new(self.clone())
+    }
+
+    /// Records the routes obtained for each logical destination.
+    pub fn record_routes<P>(&self, inner: P) -> RecordRoutes<P> {
+        RecordRoutes::new(self.clone(), inner)
+    }
+
+    fn destination(&self, key: String) -> Arc<destinations::Destination> {
+        let mut inner = self.0.lock().expect("debug registry lock poisoned");
+        if let Some(dst) = inner.destinations.get(&key).and_then(Weak::upgrade) {
+            return dst;
+        }
+        inner.destinations.retain(|_, d| d.strong_count() > 0);
+        let dst = Arc::new(destinations::Destination::default());
+        inner.destinations.insert(key, Arc::downgrade(&dst));
+        dst
+    }
+
+    fn profile(&self, addr: &Addr) -> Option<Arc<profiles::Profile>> {
+        let inner = self.0.lock().expect("debug registry lock poisoned");
+        inner.profiles.get(addr).and_then(Weak::upgrade)
+    }
+
+    fn new_profile(&self, addr: Addr) -> Arc<profiles::Profile> {
+        let mut inner = self.0.lock().expect("debug registry lock poisoned");
+        inner.profiles.retain(|_, p| p.strong_count() > 0);
+        let profile = Arc::new(profiles::Profile::default());
+        inner.profiles.insert(addr, Arc::downgrade(&profile));
+        profile
+    }
+
+    fn destinations_json(&self) -> serde_json::Result<Vec<u8>> {
+        let inner = self.0.lock().expect("debug registry lock poisoned");
+        let dsts = inner
+            .destinations
+            .iter()
+            .filter_map(|(addr, dst)| Some(dst.upgrade()?.describe(addr)))
+            .collect::<Vec<_>>();
+        serde_json::to_vec_pretty(&dsts)
+    }
+
+    fn profiles_json(&self) -> serde_json::Result<Vec<u8>> {
+        let inner = self.0.lock().expect("debug registry lock poisoned");
+        let profiles = inner
+            .profiles
+            .iter()
+            .filter_map(|(addr, p)| Some(p.upgrade()?.describe(addr)))
+            .collect::<Vec<_>>();
+        serde_json::to_vec_pretty(&profiles)
+    }
+}
+
+impl Service for Registry {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = io::Error;
+    type Future = future::FutureResult<Response<Body>, Self::Error>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.method() != Method::GET {
+            return future::ok(
+                Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(header::ALLOW, "GET")
+                    .body(Body::empty())
+                    .expect("builder with known status code must not fail"),
+            );
+        }
+
+        let json = match req.uri().path() {
+            "/debug/destinations" => self.destinations_json(),
+            "/debug/profiles" => self.profiles_json(),
+            _ => return future::ok(rsp(StatusCode::NOT_FOUND, Body::empty())),
+        };
+        match json {
+            Ok(json) => future::ok(
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(json.into())
+                    .expect("builder with known status code must not fail"),
+            ),
+            Err(error) => future::ok(rsp(StatusCode::INTERNAL_SERVER_ERROR, format!("{}", error))),
+        }
+    }
+}
+
+// === impl LastUsed ===
+
+impl LastUsed {
+    fn touch(&self) {
+        self.last_ms
+            .store(millis(self.created.elapsed()), Ordering::Relaxed);
+    }
+
+    /// The number of milliseconds since the destination was last used.
+    fn idle_ms(&self) -> u64 {
+        let last = self.last_ms.load(Ordering::Relaxed);
+        millis(self.created.elapsed()).saturating_sub(last)
+    }
+}
+
+impl Default for LastUsed {
+    fn default() -> Self {
+        Self {
+            created: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1_000 + u64::from(d.subsec_millis())
+}
+
+/// Describes a destination's routes or endpoints.
+#[derive(Debug, Serialize)]
+struct Described<T> {
+    addr: String,
+    idle_ms: u64,
+    #[serde(flatten)]
+    state: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_since_last_used() {
+        let last_used = LastUsed {
+            created: Instant::now() - Duration::from_secs(10),
+            last_ms: AtomicU64::new(0),
+        };
+        assert!(last_used.idle_ms() >= 10_000);
+
+        last_used.touch();
+        assert!(last_used.idle_ms() < 10_000);
+    }
+}
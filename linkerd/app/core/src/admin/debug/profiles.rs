@@ -0,0 +1,228 @@
+//! Records the routes of each logical destination.
+
+use super::{millis, Described, LastUsed, Registry};
+use crate::{profiles, svc::NewService, Addr};
+use futures::{try_ready, Async, Future, Poll};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+pub(super) struct Profile {
+    routes: Mutex<Option<profiles::Receiver>>,
+    last_used: LastUsed,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    registry: Registry,
+}
+
+/// Records each logical destination while its service is held.
+#[derive(Clone, Debug)]
+pub struct NewRecordProfile<N> {
+    registry: Registry,
+    inner: N,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordProfile<S> {
+    inner: S,
+    profile: Arc<Profile>,
+}
+
+/// Records the routes obtained for each logical destination.
+#[derive(Clone, Debug)]
+pub struct RecordRoutes<P> {
+    registry: Registry,
+    inner: P,
+}
+
+pub struct RecordRoutesFuture<F> {
+    inner: F,
+    registry: Registry,
+    addr: Addr,
+}
+
+#[derive(Debug, Serialize)]
+struct Routes {
+    routes: Vec<Route>,
+    dst_overrides: Vec<DstOverride>,
+    mirror: Option<Mirror>,
+}
+
+#[derive(Debug, Serialize)]
+struct Route {
+    #[serde(rename = "match")]
+    request_match: String,
+    labels: BTreeMap<String, String>,
+    timeout_ms: Option<u64>,
+    retryable: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DstOverride {
+    addr: String,
+    weight: u32,
+    headers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Mirror {
+    addr: String,
+    ratio: f64,
+}
+
+// === impl Profile ===
+
+impl Profile {
+    pub(super) fn describe(&self, addr: &Addr) -> impl Serialize {
+        let routes = self
+            .routes
+            .lock()
+            .expect("profile lock poisoned")
+            .as_ref()
+            .map(|rx| describe_routes(&*rx.get_ref()));
+        Described {
+            addr: addr.to_string(),
+            idle_ms: self.last_used.idle_ms(),
+            state: routes,
+        }
+    }
+}
+
+fn describe_routes(routes: &profiles::Routes) -> Routes {
+    Routes {
+        routes: routes
+            .routes
+            .iter()
+            .map(|(request_match, route)| Route {
+                request_match: format!("{:?}", request_match),
+                labels: route
+                    .labels()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                timeout_ms: route.timeout().map(millis),
+                retryable: route.retries().is_some(),
+            })
+            .collect(),
+        dst_overrides: routes
+            .dst_overrides
+            .iter()
+            .map(|o| DstOverride {
+                addr: o.addr.to_string(),
+                weight: o.weight,
+                headers: o.headers.iter().map(|(name, _)| name.to_string()).collect(),
+            })
+            .collect(),
+        mirror: routes.mirror.as_ref().map(|m| Mirror {
+            addr: m.addr.to_string(),
+            ratio: m.ratio,
+        }),
+    }
+}
+
+// === impl Layer ===
+
+impl Layer {
+    pub(super) fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+}
+
+impl<N> tower::layer::Layer<N> for Layer {
+    type Service = NewRecordProfile<N>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        NewRecordProfile {
+            registry: self.registry.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl NewRecordProfile ===
+
+impl<T, N> NewService<T> for NewRecordProfile<N>
+where
+    T: profiles::HasDestination,
+    N: NewService<T>,
+{
+    type Service = RecordProfile<N::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        // The profile is registered before the inner service is built, so
+        // that its routes are recorded when they are obtained.
+        let profile = self.registry.new_profile(target.destination());
+        RecordProfile {
+            inner: self.inner.new_service(target),
+            profile,
+        }
+    }
+}
+
+// === impl RecordProfile ===
+
+impl<S, Req> tower::Service<Req> for RecordProfile<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.profile.last_used.touch();
+        self.inner.call(req)
+    }
+}
+
+// === impl RecordRoutes ===
+
+impl<P> RecordRoutes<P> {
+    pub(super) fn new(registry: Registry, inner: P) -> Self {
+        Self { registry, inner }
+    }
+}
+
+impl<P> tower::Service<Addr> for RecordRoutes<P>
+where
+    P: tower::Service<Addr, Response = profiles::Receiver>,
+{
+    type Response = profiles::Receiver;
+    type Error = P::Error;
+    type Future = RecordRoutesFuture<P::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, addr: Addr) -> Self::Future {
+        RecordRoutesFuture {
+            inner: self.inner.call(addr.clone()),
+            registry: self.registry.clone(),
+            addr,
+        }
+    }
+}
+
+impl<F> Future for RecordRoutesFuture<F>
+where
+    F: Future<Item = profiles::Receiver>,
+{
+    type Item = profiles::Receiver;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rx = try_ready!(self.inner.poll());
+        if let Some(profile) = self.registry.profile(&self.addr) {
+            *profile.routes.lock().expect("profile lock poisoned") = Some(rx.clone());
+        }
+        Ok(Async::Ready(rx))
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! * `/metrics` -- reports prometheus-formatted metrics.
 //! * `/ready` -- returns 200 when the proxy is ready to participate in meshed traffic.
+//! * `/debug/*` -- reports the state of discovery, balancers, and profiles as JSON.
+//! * `/connections` -- lists the connections that are currently open as JSON.
 
 use crate::{svc, transport::tls::accept::Connection};
 use futures::{future, Future, Poll};
@@ -11,9 +13,12 @@ use hyper::{Body, Request, Response};
 use linkerd2_metrics::{self as metrics, FmtMetrics};
 use std::io;
 
+mod connections;
+pub mod debug;
 mod readiness;
 mod trace_level;
 
+pub use self::connections::Connections;
 pub use self::readiness::{Latch, Readiness};
 use self::trace_level::TraceLevel;
 
@@ -22,6 +27,8 @@ pub struct Admin<M: FmtMetrics> {
     metrics: metrics::Serve<M>,
     trace_level: TraceLevel,
     ready: Readiness,
+    debug: debug::Registry,
+    connections: Connections,
 }
 
 #[derive(Debug, Clone)]
@@ -34,11 +41,19 @@ pub type ResponseFuture =
     Box<dyn Future<Item = Response<Body>, Error = io::Error> + Send + 'static>;
 
 impl<M: FmtMetrics> Admin<M> {
-    pub fn new(m: M, ready: Readiness, trace_level: TraceLevel) -> Self {
+    pub fn new(
+        m: M,
+        ready: Readiness,
+        trace_level: TraceLevel,
+        debug: debug::Registry,
+        connections: Connections,
+    ) -> Self {
         Self {
             metrics: metrics::Serve::new(m),
             trace_level,
             ready,
+            debug,
+            connections,
         }
     }
 
@@ -72,6 +87,8 @@ impl<M: FmtMetrics> Service for Admin<M> {
             "/metrics" => Box::new(self.metrics.call(req)),
             "/proxy-log-level" => self.trace_level.call(req),
             "/ready" => Box::new(future::ok(self.ready_rsp())),
+            "/connections" => Box::new(self.connections.call(req)),
+            path if path.starts_with("/debug/") => Box::new(self.debug.call(req)),
             _ => Box::new(future::ok(rsp(StatusCode::NOT_FOUND, Body::empty()))),
         }
     }
@@ -129,7 +146,13 @@ mod tests {
         let l1 = l0.clone();
 
         let mut rt = Runtime::new().unwrap();
-        let mut srv = Admin::new((), r, TraceLevel::dangling());
+        let mut srv = Admin::new(
+            (),
+            r,
+            TraceLevel::dangling(),
+            debug::Registry::default(),
+            Connections::new(Default::default()),
+        );
         macro_rules! call {
             () => {{
                 let r = Request::builder()
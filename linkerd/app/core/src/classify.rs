@@ -1,3 +1,4 @@
+use crate::fault;
 use crate::profiles;
 use http;
 use linkerd2_error::Error;
@@ -27,6 +28,13 @@ pub enum Eos {
     Default(http::StatusCode),
     Grpc(GrpcEos),
     Profile(Class),
+    /// Profile classes that may match on trailers are evaluated once the
+    /// stream ends.
+    ProfileTrailers {
+        classes: profiles::ResponseClasses,
+        status: http::StatusCode,
+        headers: http::HeaderMap,
+    },
     Error(&'static str),
 }
 
@@ -108,18 +116,17 @@ impl Response {
         rsp: &http::Response<B>,
         classes: &[profiles::ResponseClass],
     ) -> Option<Class> {
-        for class in classes {
-            if class.is_match(rsp) {
-                let result = if class.is_failure() {
-                    SuccessOrFailure::Failure
-                } else {
-                    SuccessOrFailure::Success
-                };
-                return Some(Class::Default(result));
-            }
-        }
+        classes
+            .iter()
+            .find(|class| class.is_match(rsp))
+            .map(profile_class)
+    }
 
-        None
+    /// Classifies a response that is not matched by any profile class.
+    fn fallback(status: http::StatusCode, headers: &http::HeaderMap) -> Eos {
+        grpc_class(headers)
+            .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
+            .unwrap_or_else(|| Eos::Default(status))
     }
 }
 
@@ -128,6 +135,11 @@ impl classify::ClassifyResponse for Response {
     type ClassifyEos = Eos;
 
     fn start<B>(self, rsp: &http::Response<B>) -> Eos {
+        // Injected faults are distinguished from the endpoint's failures.
+        if rsp.extensions().get::<fault::Aborted>().is_some() {
+            return Eos::Error("fault");
+        }
+
         match self {
             Response::Default => grpc_class(rsp.headers())
                 .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
@@ -135,13 +147,19 @@ impl classify::ClassifyResponse for Response {
             Response::Grpc => grpc_class(rsp.headers())
                 .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
                 .unwrap_or(Eos::Grpc(GrpcEos::Open)),
-            Response::Profile(ref classes) => Self::match_class(rsp, classes.as_ref())
-                .map(Eos::Profile)
-                .unwrap_or_else(|| {
-                    grpc_class(rsp.headers())
-                        .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
-                        .unwrap_or_else(|| Eos::Default(rsp.status()))
-                }),
+            Response::Profile(classes) => {
+                if classes.iter().any(|c| c.inspects_trailers()) {
+                    return Eos::ProfileTrailers {
+                        classes,
+                        status: rsp.status(),
+                        headers: rsp.headers().clone(),
+                    };
+                }
+
+                Self::match_class(rsp, classes.as_ref())
+                    .map(Eos::Profile)
+                    .unwrap_or_else(|| Self::fallback(rsp.status(), rsp.headers()))
+            }
         }
     }
 
@@ -174,6 +192,15 @@ impl classify::ClassifyEos for Eos {
                 .and_then(grpc_class)
                 .unwrap_or_else(|| Class::Grpc(SuccessOrFailure::Success, 0)),
             Eos::Profile(class) => class,
+            Eos::ProfileTrailers {
+                classes,
+                status,
+                headers,
+            } => classes
+                .iter()
+                .find(|class| class.is_match_eos(status, &headers, trailers))
+                .map(profile_class)
+                .unwrap_or_else(|| Response::fallback(status, &headers).eos(trailers)),
             Eos::Error(msg) => Class::Stream(SuccessOrFailure::Failure, msg.into()),
         }
     }
@@ -183,6 +210,14 @@ impl classify::ClassifyEos for Eos {
     }
 }
 
+fn profile_class(class: &profiles::ResponseClass) -> Class {
+    if class.is_failure() {
+        Class::Default(SuccessOrFailure::Failure)
+    } else {
+        Class::Default(SuccessOrFailure::Success)
+    }
+}
+
 fn grpc_class(headers: &http::HeaderMap) -> Option<Class> {
     headers
         .get("grpc-status")
@@ -225,6 +260,12 @@ impl Class {
     }
 }
 
+impl linkerd2_proxy_http::balance::outlier::IsFailure for Class {
+    fn is_failure(&self) -> bool {
+        Class::is_failure(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Class, SuccessOrFailure};
@@ -319,4 +360,33 @@ mod tests {
             .eos(Some(&trailers));
         assert_eq!(class, Class::Grpc(SuccessOrFailure::Failure, 4));
     }
+
+    #[test]
+    fn profile_grpc_status_in_trailers() {
+        use crate::profiles::{ResponseClass, ResponseMatch, Route};
+
+        let route = Route::new(
+            std::iter::empty(),
+            vec![ResponseClass::new(
+                true,
+                ResponseMatch::GrpcStatus { min: 14, max: 14 },
+            )],
+        );
+        let classes = route.response_classes().clone();
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 14.into());
+        let class = super::Response::Profile(classes.clone())
+            .start(&rsp)
+            .eos(Some(&trailers));
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+
+        // Responses that do not match fall back to the default classification.
+        trailers.insert("grpc-status", 4.into());
+        let class = super::Response::Profile(classes)
+            .start(&rsp)
+            .eos(Some(&trailers));
+        assert_eq!(class, Class::Grpc(SuccessOrFailure::Failure, 4));
+    }
 }
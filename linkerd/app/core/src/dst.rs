@@ -1,16 +1,23 @@
 use super::classify;
-use crate::profiles;
+use super::fault;
+use super::metric_labels::Direction;
+use crate::proxy::{identity, tap};
+use crate::transport::tls;
+use crate::{profiles, Conditional};
+use indexmap::IndexMap;
 use linkerd2_addr::Addr;
 use linkerd2_http_classify::CanClassify;
 use linkerd2_proxy_http::timeout;
 use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Route {
     pub target: Addr,
     pub route: profiles::Route,
-    pub direction: super::metric_labels::Direction,
+    pub direction: Direction,
 }
 
 // === impl Route ===
@@ -29,6 +36,54 @@ impl timeout::HasTimeout for Route {
     }
 }
 
+/// Describes requests that are answered by the route stack itself, i.e.
+/// because a fault aborted them, and are never dispatched to an endpoint.
+impl tap::Inspect for Route {
+    fn src_addr<B>(&self, req: &http::Request<B>) -> Option<SocketAddr> {
+        req.extensions()
+            .get::<tls::accept::Meta>()
+            .map(|s| s.addrs.peer())
+    }
+
+    fn src_tls<'a, B>(
+        &self,
+        req: &'a http::Request<B>,
+    ) -> Conditional<&'a identity::Name, tls::ReasonForNoIdentity> {
+        match self.direction {
+            Direction::Out => Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
+            Direction::In => req
+                .extensions()
+                .get::<tls::accept::Meta>()
+                .map(|s| s.peer_identity.as_ref())
+                .unwrap_or_else(|| Conditional::None(tls::ReasonForNoIdentity::Disabled)),
+        }
+    }
+
+    fn dst_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+        None
+    }
+
+    fn dst_labels<B>(&self, _: &http::Request<B>) -> Option<&IndexMap<String, String>> {
+        None
+    }
+
+    fn dst_tls<B>(
+        &self,
+        _: &http::Request<B>,
+    ) -> Conditional<&identity::Name, tls::ReasonForNoIdentity> {
+        // The response is produced by the proxy itself.
+        Conditional::None(tls::ReasonForNoPeerName::Loopback.into())
+    }
+
+    fn route_labels<B>(&self, req: &http::Request<B>) -> Option<Arc<IndexMap<String, String>>> {
+        Some(fault::route_labels(self.route.labels().clone(), req))
+    }
+
+    fn is_outbound<B>(&self, _: &http::Request<B>) -> bool {
+        self.direction == Direction::Out
+    }
+}
+
 impl fmt::Display for Route {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.target.fmt(f)
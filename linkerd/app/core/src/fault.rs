@@ -0,0 +1,485 @@
+//! Injects faults into requests, so that clients' tolerance of slow or failing
+//! dependencies may be tested without modifying applications.
+//!
+//! Faults are configured per-route. The route stack decides which faults are
+//! injected into each request and records them as an `Injected` request
+//! extension. Delays and aborts are both applied by the route stack, outside
+//! of retries and hedges, so that each request is subject to a single
+//! injection decision. Aborted requests are answered before they reach a
+//! balancer and are never dispatched to an endpoint: they are answered by a
+//! per-route service, so that they may be tapped, and are recorded by the
+//! route's metrics. Their responses are marked with an `Aborted` extension.
+
+use super::dst::Route;
+use super::http_metrics::faults::{Handle, Kind};
+use super::HttpRouteFault;
+use crate::profiles;
+use futures::{future, Async, Future, Poll};
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_stack::{NewService, Proxy, ProxyService};
+use rand::Rng;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tower::util::{Oneshot, ServiceExt};
+use tracing::debug;
+
+/// Builds a layer that injects each route's faults into its requests.
+pub fn layer(metrics: HttpRouteFault) -> Layer {
+    Layer { metrics }
+}
+
+/// Builds a layer that answers requests that are to be aborted with `respond`
+/// services, rather than dispatching them.
+///
+/// This must be applied within the route's fault `layer`, which marks the
+/// requests that are to be aborted. `respond` builds a service for each route,
+/// e.g. a `Respond` service that is instrumented with taps.
+pub fn abort_layer<R>(respond: R) -> AbortLayer<R> {
+    AbortLayer { respond }
+}
+
+/// Builds `Respond` services, which answer aborted requests.
+pub fn respond<B>() -> Respond<B> {
+    Respond(PhantomData)
+}
+
+/// Adds a `fault` label, naming the faults that were injected into a request,
+/// to a route's labels.
+pub fn route_labels<B>(
+    labels: Arc<IndexMap<String, String>>,
+    req: &http::Request<B>,
+) -> Arc<IndexMap<String, String>> {
+    match req.extensions().get::<Injected>() {
+        Some(injected) => {
+            let mut labels = (*labels).clone();
+            labels.insert("fault".to_owned(), injected.to_string());
+            Arc::new(labels)
+        }
+        None => labels,
+    }
+}
+
+/// Describes the faults that were injected into a request.
+///
+/// Set as a request extension.
+#[derive(Clone, Debug, Default)]
+pub struct Injected {
+    pub delay: Option<Duration>,
+    pub abort: Option<profiles::Abort>,
+}
+
+/// Marks the response to an aborted request.
+///
+/// Set as a response extension.
+#[derive(Clone, Debug)]
+pub struct Aborted(());
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    metrics: HttpRouteFault,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewFault<N> {
+    inner: N,
+    metrics: HttpRouteFault,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fault<P> {
+    inner: P,
+    fault: Option<(profiles::Fault, Handle)>,
+}
+
+pub enum ResponseFuture<P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+    S::Error: Into<Error>,
+{
+    Dispatch(P::Future),
+    Delay {
+        delay: Delay,
+        inner: Option<(ProxyService<P, S>, Req)>,
+    },
+    Delayed(Oneshot<ProxyService<P, S>, Req>),
+}
+
+#[derive(Clone, Debug)]
+pub struct AbortLayer<R> {
+    respond: R,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewAbort<N, R> {
+    inner: N,
+    respond: R,
+}
+
+#[derive(Clone, Debug)]
+pub struct Abort<P, R> {
+    inner: P,
+    respond: R,
+}
+
+/// Answers each aborted request with its injected abort.
+#[derive(Debug)]
+pub struct Respond<B>(PhantomData<fn() -> B>);
+
+// === impl Injected ===
+
+impl Injected {
+    fn is_empty(&self) -> bool {
+        self.delay.is_none() && self.abort.is_none()
+    }
+}
+
+impl std::fmt::Display for Injected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.delay.is_some(), self.abort.is_some()) {
+            (true, true) => write!(f, "delay,abort"),
+            (true, false) => write!(f, "delay"),
+            (false, true) => write!(f, "abort"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<N> tower::layer::Layer<N> for Layer {
+    type Service = NewFault<N>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        NewFault {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl NewFault ===
+
+impl<N> NewService<Route> for NewFault<N>
+where
+    N: NewService<Route>,
+{
+    type Service = Fault<N::Service>;
+
+    fn new_service(&self, route: Route) -> Self::Service {
+        let fault = route
+            .route
+            .fault()
+            .cloned()
+            .map(|fault| (fault, self.metrics.get_handle(route.clone())));
+
+        let inner = self.inner.new_service(route);
+        Fault { inner, fault }
+    }
+}
+
+// === impl Fault ===
+
+impl<P, S, B> Proxy<http::Request<B>, S> for Fault<P>
+where
+    P: Proxy<http::Request<B>, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = Error;
+    type Future = ResponseFuture<P, S, http::Request<B>>;
+
+    fn proxy(&self, svc: &mut S, mut req: http::Request<B>) -> Self::Future {
+        let (fault, metrics) = match self.fault.as_ref() {
+            Some(f) => f,
+            None => return ResponseFuture::Dispatch(self.inner.proxy(svc, req)),
+        };
+
+        let injected = inject(fault, &mut rand::thread_rng());
+        if injected.is_empty() {
+            return ResponseFuture::Dispatch(self.inner.proxy(svc, req));
+        }
+
+        if let Some(abort) = injected.abort {
+            debug!(?abort, "Injecting abort");
+            metrics.incr_injected(Kind::Abort);
+        }
+        let delay = injected.delay;
+        req.extensions_mut().insert(injected);
+
+        match delay {
+            None => ResponseFuture::Dispatch(self.inner.proxy(svc, req)),
+            Some(delay) => {
+                debug!(?delay, "Injecting delay");
+                metrics.incr_injected(Kind::Delay);
+                let svc = self.inner.clone().wrap_service(svc.clone());
+                ResponseFuture::Delay {
+                    delay: Delay::new(clock::now() + delay),
+                    inner: Some((svc, req)),
+                }
+            }
+        }
+    }
+}
+
+/// Determines which of a route's faults are injected into a request.
+fn inject<R: Rng>(fault: &profiles::Fault, rng: &mut R) -> Injected {
+    let delay = fault
+        .delay
+        .as_ref()
+        .filter(|d| rng.gen::<f64>() < d.ratio)
+        .map(|d| {
+            if d.max > d.min {
+                rng.gen_range(d.min, d.max)
+            } else {
+                d.min
+            }
+        });
+
+    let abort = fault
+        .abort
+        .as_ref()
+        .filter(|a| rng.gen::<f64>() < a.ratio)
+        .map(|a| a.abort);
+
+    Injected { delay, abort }
+}
+
+impl<P, S, Req> Future for ResponseFuture<P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+    S::Error: Into<Error>,
+{
+    type Item = P::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                ResponseFuture::Dispatch(ref mut f) => return f.poll().map_err(Into::into),
+                ResponseFuture::Delayed(ref mut f) => return f.poll(),
+                ResponseFuture::Delay {
+                    ref mut delay,
+                    ref mut inner,
+                } => {
+                    match delay.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(())) => {}
+                        // If the timer fails, dispatch the request without
+                        // waiting.
+                        Err(error) => debug!(%error, "Fault delay failed"),
+                    }
+                    let (svc, req) = inner.take().expect("polled after ready");
+                    ResponseFuture::Delayed(svc.oneshot(req))
+                }
+            };
+        }
+    }
+}
+
+// === impl AbortLayer ===
+
+impl<N, R: Clone> tower::layer::Layer<N> for AbortLayer<R> {
+    type Service = NewAbort<N, R>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        NewAbort {
+            inner,
+            respond: self.respond.clone(),
+        }
+    }
+}
+
+// === impl NewAbort ===
+
+impl<T, N, R> NewService<T> for NewAbort<N, R>
+where
+    T: Clone,
+    N: NewService<T>,
+    R: NewService<T>,
+{
+    type Service = Abort<N::Service, R::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        Abort {
+            respond: self.respond.new_service(target.clone()),
+            inner: self.inner.new_service(target),
+        }
+    }
+}
+
+// === impl Abort ===
+
+impl<P, R, S, A, B> Proxy<http::Request<A>, S> for Abort<P, R>
+where
+    P: Proxy<http::Request<A>, S, Response = http::Response<B>>,
+    P::Error: Into<Error>,
+    R: tower::Service<http::Request<A>, Response = http::Response<B>> + Clone,
+    R::Error: Into<Error>,
+    S: tower::Service<P::Request>,
+{
+    type Request = P::Request;
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = future::Either<
+        future::MapErr<Oneshot<R, http::Request<A>>, fn(R::Error) -> Error>,
+        future::MapErr<P::Future, fn(P::Error) -> Error>,
+    >;
+
+    fn proxy(&self, svc: &mut S, req: http::Request<A>) -> Self::Future {
+        let is_aborted = req
+            .extensions()
+            .get::<Injected>()
+            .map(|injected| injected.abort.is_some())
+            .unwrap_or(false);
+
+        if is_aborted {
+            let respond = self.respond.clone().oneshot(req);
+            return future::Either::A(respond.map_err(Into::into));
+        }
+
+        future::Either::B(self.inner.proxy(svc, req).map_err(Into::into))
+    }
+}
+
+// === impl Respond ===
+
+impl<B> Clone for Respond<B> {
+    fn clone(&self) -> Self {
+        Respond(PhantomData)
+    }
+}
+
+impl<T, B> NewService<T> for Respond<B> {
+    type Service = Self;
+
+    fn new_service(&self, _: T) -> Self::Service {
+        self.clone()
+    }
+}
+
+impl<A, B: Default> tower::Service<http::Request<A>> for Respond<B> {
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        match req.extensions().get::<Injected>().and_then(|i| i.abort) {
+            Some(abort) => future::ok(abort_response(abort, req.version())),
+            None => future::err("request was not aborted".into()),
+        }
+    }
+}
+
+fn abort_response<B: Default>(abort: profiles::Abort, version: http::Version) -> http::Response<B> {
+    let mut rsp = http::Response::builder();
+    rsp.version(version).header(CONTENT_LENGTH, "0");
+    match abort {
+        profiles::Abort::Http(status) => {
+            rsp.status(status);
+        }
+        profiles::Abort::Grpc(code) => {
+            rsp.header(CONTENT_TYPE, "application/grpc")
+                .header("grpc-status", HeaderValue::from(code));
+        }
+    }
+
+    let mut rsp = rsp
+        .body(B::default())
+        .expect("fault response must be valid");
+    rsp.extensions_mut().insert(Aborted(()));
+    rsp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn fault(delay_ratio: f64, abort_ratio: f64) -> profiles::Fault {
+        profiles::Fault {
+            delay: Some(profiles::FaultDelay {
+                ratio: delay_ratio,
+                min: Duration::from_millis(10),
+                max: Duration::from_millis(20),
+            }),
+            abort: Some(profiles::FaultAbort {
+                ratio: abort_ratio,
+                abort: profiles::Abort::Http(http::StatusCode::SERVICE_UNAVAILABLE),
+            }),
+        }
+    }
+
+    #[test]
+    fn injects_configured_ratios() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let never = fault(0.0, 0.0);
+        for _ in 0..100 {
+            assert!(inject(&never, &mut rng).is_empty());
+        }
+
+        let always = fault(1.0, 1.0);
+        for _ in 0..100 {
+            let injected = inject(&always, &mut rng);
+            let delay = injected.delay.expect("must delay");
+            assert!(delay >= Duration::from_millis(10) && delay < Duration::from_millis(20));
+            assert_eq!(
+                injected.abort,
+                Some(profiles::Abort::Http(http::StatusCode::SERVICE_UNAVAILABLE))
+            );
+        }
+    }
+
+    #[test]
+    fn aborts_marked_requests() {
+        let abort = Abort {
+            inner: (),
+            respond: respond::<()>(),
+        };
+        let mut svc = tower::service_fn(|_: http::Request<()>| {
+            future::ok::<_, Error>(http::Response::new(()))
+        });
+
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(Injected {
+            delay: None,
+            abort: Some(profiles::Abort::Grpc(14)),
+        });
+        let rsp = abort.proxy(&mut svc, req).wait().unwrap();
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(rsp.headers()["grpc-status"], "14");
+        assert!(rsp.extensions().get::<Aborted>().is_some());
+
+        let rsp = abort
+            .proxy(&mut svc, http::Request::new(()))
+            .wait()
+            .unwrap();
+        assert!(rsp.extensions().get::<Aborted>().is_none());
+    }
+
+    #[test]
+    fn labels_injected_faults() {
+        let labels = Arc::new(IndexMap::new());
+        let mut req = http::Request::new(());
+        assert!(route_labels(labels.clone(), &req).get("fault").is_none());
+
+        req.extensions_mut().insert(Injected {
+            delay: None,
+            abort: Some(profiles::Abort::Grpc(14)),
+        });
+        let labels = route_labels(labels, &req);
+        assert_eq!(labels.get("fault").map(String::as_str), Some("abort"));
+    }
+}
@@ -1,5 +1,5 @@
 use super::metric_labels::Direction;
-use linkerd2_metrics::{latency, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric};
+use linkerd2_metrics::{latency, Bounds, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric};
 use linkerd2_proxy_http::insert;
 use std::fmt;
 use std::sync::atomic::{self, AtomicUsize, Ordering};
@@ -19,10 +19,12 @@ impl Metrics {
         "A histogram of the time in microseconds between when a request is \
          received and when it is sent upstream.";
 
-    pub fn new() -> Self {
+    /// Records handle times into histograms with the given bounds, in
+    /// microseconds.
+    pub fn new(bounds: Bounds) -> Self {
         Self {
-            inbound: Scope::new(),
-            outbound: Scope::new(),
+            inbound: Scope::new(bounds.clone()),
+            outbound: Scope::new(bounds),
         }
     }
 
@@ -119,8 +121,8 @@ impl insert::Lazy<Tracker> for InsertTracker {
 // ===== impl Scope =====
 
 impl Scope {
-    pub fn new() -> Self {
-        Scope(Arc::new(Shared::new()))
+    pub fn new(bounds: Bounds) -> Self {
+        Scope(Arc::new(Shared::new(bounds)))
     }
 
     pub fn layer(&self) -> insert::Layer<InsertTracker, Tracker> {
@@ -177,11 +179,11 @@ impl Drop for Tracker {
 impl Shared {
     const INITIAL_RECORDERS: usize = 32;
 
-    fn new() -> Self {
+    fn new(bounds: Bounds) -> Self {
         let mut counts = Vec::with_capacity(Self::INITIAL_RECORDERS);
         Self::add_counts(&mut counts, Self::INITIAL_RECORDERS);
         Self {
-            histogram: Mutex::new(Histogram::default()), // TODO(eliza): should we change the bounds here?
+            histogram: Mutex::new(Histogram::new(bounds)),
             counts: RwLock::new(counts),
             idle_head: AtomicUsize::new(0),
         }
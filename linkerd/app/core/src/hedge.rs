@@ -0,0 +1,283 @@
+use super::classify;
+use super::dst::Route;
+use super::http_metrics::{hedges::Handle, requests::Latencies};
+use super::proxy::http::{balance::Declined, boxed};
+use super::retry::CloneRequest;
+use super::transport::connect::ConnectAddr;
+use super::{HttpRouteHedge, HttpRouteMetrics};
+use futures::{future, try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use linkerd2_buffer as buffer;
+use linkerd2_error::Error;
+use linkerd2_hedge::NewHedgeLayer;
+use linkerd2_lock as lock;
+use linkerd2_stack::NewService;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::retry::budget::Budget;
+use tracing::debug;
+
+/// The number of responses a route must have recorded before its latency
+/// distribution is used to hedge requests.
+const MIN_SAMPLES: u64 = 100;
+
+/// Builds a layer that hedges requests on hedgeable routes.
+///
+/// Requests are hedged once they have been outstanding for longer than the
+/// route's configured latency percentile, as recorded in `latencies`. Hedges
+/// are dispatched through the same balancer as the original request. A hedge
+/// that the balancer dispatches to the original request's endpoint is
+/// declined by the endpoint's `AvoidPrimary` layer and dispatched again.
+/// Declined hedges count against neither the endpoint's load nor its outlier
+/// detection.
+pub fn layer(latencies: HttpRouteMetrics, metrics: HttpRouteHedge) -> NewHedgeLayer<NewHedge> {
+    NewHedgeLayer::new(NewHedge { latencies, metrics })
+}
+
+#[derive(Clone, Debug)]
+pub struct NewHedge {
+    latencies: HttpRouteMetrics,
+    metrics: HttpRouteHedge,
+}
+
+#[derive(Clone)]
+pub struct Hedge {
+    latencies: Latencies<classify::Class>,
+    metrics: Handle,
+    budget: Arc<Budget>,
+    percentile: f64,
+}
+
+/// A request extension that records the endpoint to which a hedged request
+/// was dispatched.
+#[derive(Clone, Debug, Default)]
+pub struct Dispatched(Arc<Mutex<Option<SocketAddr>>>);
+
+/// A request extension that marks a request as a hedge of another request.
+#[derive(Clone, Debug)]
+pub struct HedgeOf(Dispatched);
+
+/// Indicates that a hedge was dispatched to the same endpoint as the request
+/// it hedges.
+#[derive(Clone, Debug)]
+pub struct SameEndpoint(SocketAddr);
+
+/// Builds a layer that prevents hedges from being sent to the endpoint that
+/// is processing the original request.
+///
+/// This must be applied to each endpoint, within the balancer.
+pub fn avoid_primary_layer() -> AvoidPrimaryLayer {
+    AvoidPrimaryLayer(())
+}
+
+#[derive(Clone, Debug)]
+pub struct AvoidPrimaryLayer(());
+
+#[derive(Clone, Debug)]
+pub struct MakeAvoidPrimary<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    addr: SocketAddr,
+    inner: F,
+}
+
+#[derive(Clone, Debug)]
+pub struct AvoidPrimary<S> {
+    addr: SocketAddr,
+    inner: S,
+}
+
+impl linkerd2_hedge::NewPolicy<Route> for NewHedge {
+    type Policy = Hedge;
+
+    fn new_policy(&self, route: &Route) -> Option<Self::Policy> {
+        let retries = route.route.retries()?;
+        let percentile = retries.hedge_percentile()?;
+
+        Some(Hedge {
+            latencies: self.latencies.latencies(route.clone()),
+            metrics: self.metrics.get_handle(route.clone()),
+            budget: retries.budget().clone(),
+            percentile,
+        })
+    }
+}
+
+impl linkerd2_hedge::Policy<http::Request<boxed::Payload>> for Hedge {
+    fn delay(&self, req: &http::Request<boxed::Payload>) -> Option<Duration> {
+        // Only requests that may safely be processed more than once are
+        // hedged. Requests with bodies are left to be retried, since the body
+        // may only be replayed by one request at a time.
+        let is_idempotent = match *req.method() {
+            http::Method::GET
+            | http::Method::HEAD
+            | http::Method::OPTIONS
+            | http::Method::TRACE => true,
+            _ => false,
+        };
+        if !is_idempotent || !req.body().is_end_stream() {
+            return None;
+        }
+
+        self.latencies.quantile(self.percentile, MIN_SAMPLES)
+    }
+
+    fn clone_request(
+        &self,
+        req: &mut http::Request<boxed::Payload>,
+    ) -> Option<http::Request<boxed::Payload>> {
+        let mut clone = <() as CloneRequest<_>>::clone_request(req)?;
+
+        // A clone of a hedge hedges the same original request.
+        let hedge_of = match req.extensions().get::<HedgeOf>() {
+            Some(hedge_of) => hedge_of.clone(),
+            None => {
+                let dispatched = Dispatched::default();
+                req.extensions_mut().insert(dispatched.clone());
+                HedgeOf(dispatched)
+            }
+        };
+        clone.extensions_mut().insert(hedge_of);
+
+        Some(clone)
+    }
+
+    fn should_redispatch(&self, error: &Error) -> bool {
+        is_same_endpoint(&**error)
+    }
+
+    fn can_hedge(&self) -> bool {
+        let withdrew = self.budget.withdraw().is_ok();
+        self.metrics.incr_hedgeable(withdrew);
+        withdrew
+    }
+
+    fn hedge_won(&self) {
+        self.metrics.incr_won();
+    }
+}
+
+fn is_same_endpoint(error: &(dyn std::error::Error + 'static)) -> bool {
+    if error.is::<SameEndpoint>() {
+        return true;
+    }
+
+    if let Some(e) = error.downcast_ref::<buffer::error::ServiceError>() {
+        return is_same_endpoint(&**e.inner());
+    }
+
+    if let Some(e) = error.downcast_ref::<lock::error::ServiceError>() {
+        return is_same_endpoint(&**e.inner());
+    }
+
+    error.source().map(is_same_endpoint).unwrap_or(false)
+}
+
+// === impl SameEndpoint ===
+
+impl std::fmt::Display for SameEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hedge dispatched to the original request's endpoint {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for SameEndpoint {}
+
+// === impl AvoidPrimaryLayer ===
+
+impl<M> tower::layer::Layer<M> for AvoidPrimaryLayer {
+    type Service = MakeAvoidPrimary<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeAvoidPrimary { inner }
+    }
+}
+
+// === impl MakeAvoidPrimary ===
+
+impl<T, M> NewService<T> for MakeAvoidPrimary<M>
+where
+    T: ConnectAddr,
+    M: NewService<T>,
+{
+    type Service = AvoidPrimary<M::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        let addr = target.connect_addr();
+        let inner = self.inner.new_service(target);
+        AvoidPrimary { addr, inner }
+    }
+}
+
+impl<T, M> tower::Service<T> for MakeAvoidPrimary<M>
+where
+    T: ConnectAddr,
+    M: tower::Service<T>,
+{
+    type Response = AvoidPrimary<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let addr = target.connect_addr();
+        let inner = self.inner.call(target);
+        MakeFuture { addr, inner }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = AvoidPrimary<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(AvoidPrimary {
+            addr: self.addr,
+            inner,
+        }))
+    }
+}
+
+// === impl AvoidPrimary ===
+
+impl<S, B> tower::Service<http::Request<B>> for AvoidPrimary<S>
+where
+    S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = future::Either<
+        future::FutureResult<Self::Response, Self::Error>,
+        future::MapErr<S::Future, fn(S::Error) -> Error>,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if let Some(HedgeOf(Dispatched(primary))) = req.extensions().get::<HedgeOf>() {
+            if *primary.lock().expect("hedge lock poisoned") == Some(self.addr) {
+                debug!(addr = %self.addr, "Rejecting hedge to the original endpoint");
+                let declined = Declined::new(SameEndpoint(self.addr));
+                return future::Either::A(future::err(declined.into()));
+            }
+        } else if let Some(Dispatched(addr)) = req.extensions().get::<Dispatched>() {
+            *addr.lock().expect("hedge lock poisoned") = Some(self.addr);
+        }
+
+        future::Either::B(self.inner.call(req).map_err(Into::into))
+    }
+}
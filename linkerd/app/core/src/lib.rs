@@ -34,8 +34,11 @@ pub mod control;
 pub mod dns;
 pub mod dst;
 pub mod errors;
+pub mod fault;
 pub mod handle_time;
+pub mod hedge;
 pub mod metric_labels;
+pub mod mirror;
 pub mod proxy;
 pub mod retry;
 pub mod serve;
@@ -87,6 +90,12 @@ pub type HttpRouteMetrics = http_metrics::Requests<metric_labels::RouteLabels, c
 
 pub type HttpRouteRetry = http_metrics::Retries<metric_labels::RouteLabels>;
 
+pub type HttpRouteHedge = http_metrics::Hedges<metric_labels::RouteLabels>;
+
+pub type HttpRouteFault = http_metrics::Faults<metric_labels::RouteLabels>;
+
+pub type HttpMirrorMetrics = http_metrics::Mirrors<metric_labels::MirrorLabels>;
+
 pub type StackMetrics = stack_metrics::Registry<metric_labels::StackLabels>;
 
 #[derive(Clone)]
@@ -95,6 +104,9 @@ pub struct ProxyMetrics {
     pub http_route: HttpRouteMetrics,
     pub http_route_actual: HttpRouteMetrics,
     pub http_route_retry: HttpRouteRetry,
+    pub http_route_hedge: HttpRouteHedge,
+    pub http_route_fault: HttpRouteFault,
+    pub http_mirror: HttpMirrorMetrics,
     pub http_endpoint: HttpEndpointMetrics,
     pub http_errors: errors::MetricsLayer,
     pub stack: StackMetrics,
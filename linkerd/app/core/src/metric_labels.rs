@@ -1,6 +1,7 @@
+use crate::profiles;
 use crate::proxy::identity;
 use crate::transport::{labels::TlsStatus, tls};
-use linkerd2_addr::Addr;
+use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_conditional::Conditional;
 use linkerd2_metrics::FmtLabels;
 use std::fmt::{self, Write};
@@ -25,6 +26,7 @@ pub struct EndpointLabels {
 pub struct StackLabels {
     pub direction: Direction,
     pub name: &'static str,
+    pub dst: Option<Addr>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -34,6 +36,11 @@ pub struct RouteLabels {
     labels: Option<String>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MirrorLabels {
+    target: NameAddr,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     In,
@@ -94,6 +101,20 @@ impl FmtLabels for RouteLabels {
     }
 }
 
+// === impl MirrorLabels ===
+
+impl From<profiles::Mirrored> for MirrorLabels {
+    fn from(m: profiles::Mirrored) -> Self {
+        Self { target: m.addr }
+    }
+}
+
+impl FmtLabels for MirrorLabels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dst=\"{}\"", self.target)
+    }
+}
+
 // === impl EndpointLabels ===
 
 impl FmtLabels for EndpointLabels {
@@ -187,6 +208,7 @@ impl StackLabels {
         Self {
             direction: Direction::In,
             name,
+            dst: None,
         }
     }
 
@@ -194,6 +216,15 @@ impl StackLabels {
         Self {
             direction: Direction::Out,
             name,
+            dst: None,
+        }
+    }
+
+    /// Scopes the stack's metrics to a single destination.
+    pub fn with_dst(self, dst: Addr) -> Self {
+        Self {
+            dst: Some(dst),
+            ..self
         }
     }
 }
@@ -201,6 +232,10 @@ impl StackLabels {
 impl FmtLabels for StackLabels {
     fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.direction.fmt_labels(f)?;
-        write!(f, ",name=\"{}\"", self.name)
+        write!(f, ",name=\"{}\"", self.name)?;
+        if let Some(dst) = self.dst.as_ref() {
+            write!(f, ",dst=\"{}\"", dst)?;
+        }
+        Ok(())
     }
 }
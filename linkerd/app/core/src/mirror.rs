@@ -0,0 +1,211 @@
+//! Limits and records metrics for requests that are mirrored to a shadow
+//! destination.
+//!
+//! Mirrored requests are dispatched through the same concrete stacks as other
+//! requests, so they are distinguished by their `profiles::Mirrored`
+//! extension.
+//!
+//! Mirrors are fire-and-forget, so a slow mirror cannot apply backpressure to
+//! the requests that are copied to it. Instead, the number of mirrored
+//! requests that may be in flight is limited; mirrored requests beyond the
+//! limit are dropped without being dispatched, and counted.
+
+use super::http_metrics::mirrors::{Handle, Outcome};
+use super::HttpMirrorMetrics;
+use crate::profiles;
+use futures::{future, Async, Future, Poll};
+use linkerd2_error::Error;
+use std::sync::Arc;
+use tokio_sync::semaphore::{Permit, Semaphore};
+use tracing::debug;
+
+/// Builds a layer that records the outcomes of mirrored requests, permitting
+/// at most `max_in_flight` mirrored requests at a time across all of the
+/// services it builds.
+pub fn layer(metrics: HttpMirrorMetrics, max_in_flight: usize) -> Layer {
+    Layer {
+        metrics,
+        in_flight: Arc::new(Semaphore::new(max_in_flight)),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    metrics: HttpMirrorMetrics,
+    in_flight: Arc<Semaphore>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordMirror<S> {
+    inner: S,
+    metrics: HttpMirrorMetrics,
+    in_flight: Arc<Semaphore>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    handle: Option<Handle>,
+    /// Holds one of the semaphore's permits while a mirrored request is in
+    /// flight.
+    in_flight: Option<Arc<Semaphore>>,
+}
+
+/// Indicates that a mirrored request was dropped because too many mirrored
+/// requests were in flight.
+#[derive(Clone, Debug)]
+pub struct Dropped(());
+
+// === impl Layer ===
+
+impl<S> tower::layer::Layer<S> for Layer {
+    type Service = RecordMirror<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordMirror {
+            inner,
+            metrics: self.metrics.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+// === impl RecordMirror ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for RecordMirror<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future =
+        future::Either<future::FutureResult<Self::Response, Error>, ResponseFuture<S::Future>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let handle = match req.extensions().get::<profiles::Mirrored>() {
+            None => {
+                return future::Either::B(ResponseFuture {
+                    inner: self.inner.call(req),
+                    handle: None,
+                    in_flight: None,
+                })
+            }
+            Some(mirrored) => self.metrics.get_handle(mirrored.clone()),
+        };
+
+        let mut permit = Permit::new();
+        if permit.try_acquire(&self.in_flight).is_err() {
+            debug!("Too many mirrored requests in flight; dropping");
+            handle.incr_dropped();
+            return future::Either::A(future::err(Dropped(()).into()));
+        }
+        // The permit is returned when the response future is dropped.
+        permit.forget();
+
+        handle.incr_request();
+        future::Either::B(ResponseFuture {
+            inner: self.inner.call(req),
+            handle: Some(handle),
+            in_flight: Some(self.in_flight.clone()),
+        })
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll().map_err(Into::into) {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => {
+                if let Some(handle) = self.handle.take() {
+                    let outcome = if rsp.status().is_server_error() {
+                        Outcome::Failure
+                    } else {
+                        Outcome::Success
+                    };
+                    handle.incr_response(outcome);
+                }
+                Ok(Async::Ready(rsp))
+            }
+            Err(e) => {
+                if let Some(handle) = self.handle.take() {
+                    handle.incr_response(Outcome::Failure);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<F> Drop for ResponseFuture<F> {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.in_flight.take() {
+            in_flight.add_permits(1);
+        }
+    }
+}
+
+// === impl Dropped ===
+
+impl std::fmt::Display for Dropped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "too many mirrored requests in flight")
+    }
+}
+
+impl std::error::Error for Dropped {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_addr::NameAddr;
+    use tower::layer::Layer as _;
+    use tower::Service;
+
+    fn mirrored() -> http::Request<()> {
+        let mut req = http::Request::new(());
+        req.extensions_mut().insert(profiles::Mirrored {
+            addr: NameAddr::from_str("mirror.ns.svc.cluster.local:80").unwrap(),
+        });
+        req
+    }
+
+    #[test]
+    fn drops_mirrored_requests_beyond_limit() {
+        let (tx, rx) = futures::sync::oneshot::channel::<()>();
+        let rx = rx.shared();
+        let mut svc = layer(HttpMirrorMetrics::default(), 1).layer(tower::service_fn(
+            move |_: http::Request<()>| {
+                rx.clone()
+                    .map(|_| http::Response::new(()))
+                    .map_err(|_| -> Error { "canceled".into() })
+            },
+        ));
+
+        let mut first = svc.call(mirrored());
+        assert!(first.poll().unwrap().is_not_ready());
+
+        // The limit only applies to mirrored requests.
+        let mut original = svc.call(http::Request::new(()));
+        assert!(original.poll().unwrap().is_not_ready());
+
+        let error = svc.call(mirrored()).wait().unwrap_err();
+        assert!(error.is::<Dropped>());
+
+        // Once the first mirrored request completes, another may be sent.
+        tx.send(()).unwrap();
+        first.wait().unwrap();
+        original.wait().unwrap();
+        svc.call(mirrored()).wait().unwrap();
+    }
+}
@@ -34,6 +34,20 @@ pub struct ProtocolDetect {
     skip_ports: Arc<IndexSet<u16>>,
 }
 
+impl Protocol {
+    /// Describes an accepted connection so that it may be listed while it is
+    /// open.
+    pub fn describe(&self, direction: &'static str) -> transport::metrics::Description {
+        transport::metrics::Description {
+            direction,
+            peer_addr: self.tls.addrs.peer(),
+            orig_dst_addr: self.tls.addrs.orig_dst(),
+            peer_identity: self.tls.peer_identity.clone(),
+            protocol: self.http.map(|v| v.as_str()),
+        }
+    }
+}
+
 impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
     type Target = Protocol;
 
@@ -151,7 +165,9 @@ where
         // TODO move this into a distinct Accept?
         let io = {
             let labels = self.transport_labels.transport_labels(&proto);
-            self.transport_metrics.wrap_server_transport(labels, io)
+            let description = self.transport_labels.describe(&proto);
+            self.transport_metrics
+                .wrap_server_transport(labels, description, io)
         };
 
         let drain = self.drain.clone();
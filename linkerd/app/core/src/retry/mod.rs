@@ -0,0 +1,387 @@
+use super::classify;
+use super::dst::Route;
+use super::handle_time;
+use super::http_metrics::retries::{Handle, Reason};
+use super::proxy::http::{boxed, HasH2Reason};
+use super::transport::tls;
+use super::HttpRouteRetry;
+use crate::profiles;
+use futures::{Async, Future, Poll};
+use hyper::body::Payload;
+use linkerd2_buffer as buffer;
+use linkerd2_error::Error;
+use linkerd2_exp_backoff::ExponentialBackoff;
+use linkerd2_http_classify::{Classify, ClassifyEos, ClassifyResponse};
+use linkerd2_lock as lock;
+use linkerd2_retry::{NewRetryLayer, PrepareRequest};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tokio_timer::{clock, Delay};
+use tower::retry::budget::Budget;
+use tracing::debug;
+
+mod replay;
+
+pub use self::replay::{Replay, ReplayBody};
+
+/// Builds a retry layer that buffers up to `max_buffer` bytes of each request
+/// body so that requests with bodies may be retried.
+pub fn layer(metrics: HttpRouteRetry, max_buffer: usize) -> NewRetryLayer<NewRetry> {
+    NewRetryLayer::new(NewRetry::new(metrics, max_buffer))
+}
+
+pub trait CloneRequest<Req> {
+    /// Prepares a request to be cloned, buffering at most `max_buffer` bytes
+    /// of its body.
+    fn prepare_request(req: Req, _max_buffer: usize) -> Req {
+        req
+    }
+
+    fn clone_request(req: &Req) -> Option<Req>;
+
+    /// Returns true if a previously cloned request may still be sent.
+    fn can_retry(_req: &Req) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NewRetry<C = ()> {
+    metrics: HttpRouteRetry,
+    max_buffer: usize,
+    _clone_request: PhantomData<C>,
+}
+
+pub struct Retry<C = ()> {
+    metrics: Handle,
+    budget: Arc<Budget>,
+    response_classes: profiles::ResponseClasses,
+    max_buffer: usize,
+    backoff: Option<ExponentialBackoff>,
+    /// The number of retries that have been attempted for this request.
+    attempts: u32,
+    _clone_request: PhantomData<C>,
+}
+
+/// Waits for the route's backoff before a request is retried.
+///
+/// The pending retry holds a clone of the request and, with it, the request's
+/// handle-time tracker, so time spent backing off is counted toward the
+/// request's handle time.
+pub struct Backoff<C> {
+    policy: Option<Retry<C>>,
+    delay: Option<Delay>,
+}
+
+impl NewRetry {
+    pub fn new(metrics: super::HttpRouteRetry, max_buffer: usize) -> Self {
+        Self {
+            metrics,
+            max_buffer,
+            _clone_request: PhantomData,
+        }
+    }
+
+    pub fn clone_requests_via<C>(self) -> NewRetry<C> {
+        NewRetry {
+            metrics: self.metrics,
+            max_buffer: self.max_buffer,
+            _clone_request: PhantomData,
+        }
+    }
+}
+
+impl<C> linkerd2_retry::NewPolicy<Route> for NewRetry<C> {
+    type Policy = Retry<C>;
+
+    fn new_policy(&self, route: &Route) -> Option<Self::Policy> {
+        let retries = route.route.retries().cloned()?;
+
+        let metrics = self.metrics.get_handle(route.clone());
+        Some(Retry {
+            metrics,
+            budget: retries.budget().clone(),
+            response_classes: route.route.response_classes().clone(),
+            max_buffer: self.max_buffer,
+            backoff: retries.backoff().cloned(),
+            attempts: 0,
+            _clone_request: self._clone_request,
+        })
+    }
+}
+
+impl<C, A, B> linkerd2_retry::Policy<http::Request<A>, http::Response<B>, Error> for Retry<C>
+where
+    C: CloneRequest<http::Request<A>>,
+{
+    type Future = Backoff<C>;
+
+    fn retry(
+        &self,
+        req: &http::Request<A>,
+        result: Result<&http::Response<B>, &Error>,
+    ) -> Option<Self::Future> {
+        let reason = match result {
+            Err(e) if is_retryable_error(&**e) => Some(Reason::Error),
+            Err(_) => None,
+            Ok(rsp) => {
+                let is_failure = classify::Request::from(self.response_classes.clone())
+                    .classify(req)
+                    .start(rsp)
+                    .eos(None)
+                    .is_failure();
+                if is_failure {
+                    Some(Reason::Response)
+                } else {
+                    None
+                }
+            }
+        };
+
+        let reason = match reason {
+            Some(reason) => reason,
+            None => {
+                self.budget.deposit();
+                return None;
+            }
+        };
+
+        // The request body may have exceeded the retry buffer while it was
+        // sent, in which case it cannot be replayed.
+        if !C::can_retry(req) {
+            return None;
+        }
+
+        let withdrew = self.budget.withdraw().is_ok();
+        self.metrics.incr_retryable(reason, withdrew);
+        if !withdrew {
+            return None;
+        }
+
+        let delay = self.backoff.as_ref().map(|backoff| {
+            let wait = backoff.backoff(self.attempts, &mut rand::thread_rng());
+            debug!(attempts = self.attempts, ?wait, "Backing off before retry");
+            Delay::new(clock::now() + wait)
+        });
+        let policy = Retry {
+            attempts: self.attempts.saturating_add(1),
+            ..self.clone()
+        };
+        Some(Backoff {
+            policy: Some(policy),
+            delay,
+        })
+    }
+
+    fn clone_request(&self, req: &http::Request<A>) -> Option<http::Request<A>> {
+        C::clone_request(req)
+    }
+}
+
+impl<C, A> PrepareRequest<http::Request<A>> for Retry<C>
+where
+    C: CloneRequest<http::Request<A>>,
+{
+    fn prepare_request(&self, req: http::Request<A>) -> http::Request<A> {
+        C::prepare_request(req, self.max_buffer)
+    }
+}
+
+impl<C> Clone for Retry<C> {
+    fn clone(&self) -> Self {
+        Self {
+            metrics: self.metrics.clone(),
+            budget: self.budget.clone(),
+            response_classes: self.response_classes.clone(),
+            max_buffer: self.max_buffer,
+            backoff: self.backoff,
+            attempts: self.attempts,
+            _clone_request: self._clone_request,
+        }
+    }
+}
+
+impl<C> Future for Backoff<C> {
+    type Item = Retry<C>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(delay) = self.delay.as_mut() {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                // If the timer fails, retry without waiting.
+                Err(error) => debug!(%error, "Retry backoff failed"),
+            }
+            self.delay = None;
+        }
+
+        Ok(Async::Ready(
+            self.policy.take().expect("polled after ready"),
+        ))
+    }
+}
+
+impl CloneRequest<http::Request<boxed::Payload>> for () {
+    fn prepare_request(
+        req: http::Request<boxed::Payload>,
+        max_buffer: usize,
+    ) -> http::Request<boxed::Payload> {
+        if req.body().is_end_stream() {
+            return req;
+        }
+
+        // Bodies that are known to exceed the buffer are never retried.
+        let too_large = req
+            .body()
+            .content_length()
+            .map(|len| len > max_buffer as u64)
+            .unwrap_or(false);
+        if too_large {
+            return req;
+        }
+
+        let (parts, body) = req.into_parts();
+        let (body, replay) = ReplayBody::new(body, max_buffer);
+        let mut req = http::Request::from_parts(parts, boxed::Payload::new(body));
+        req.extensions_mut().insert(replay);
+        req
+    }
+
+    fn clone_request(req: &http::Request<boxed::Payload>) -> Option<http::Request<boxed::Payload>> {
+        let body = if req.body().is_end_stream() {
+            boxed::Payload::default()
+        } else {
+            let replay = req.extensions().get::<Replay<boxed::Payload>>()?;
+            boxed::Payload::new(replay.replay()?)
+        };
+
+        let mut clone = http::Request::new(body);
+        *clone.method_mut() = req.method().clone();
+        *clone.uri_mut() = req.uri().clone();
+        *clone.headers_mut() = req.headers().clone();
+        *clone.version_mut() = req.version();
+
+        if let Some(ext) = req.extensions().get::<tls::accept::Meta>() {
+            clone.extensions_mut().insert(ext.clone());
+        }
+
+        // Count retries toward the request's total handle time.
+        if let Some(ext) = req.extensions().get::<handle_time::Tracker>() {
+            clone.extensions_mut().insert(ext.clone());
+        }
+
+        if let Some(ext) = req.extensions().get::<Replay<boxed::Payload>>() {
+            clone.extensions_mut().insert(ext.clone());
+        }
+
+        Some(clone)
+    }
+
+    fn can_retry(req: &http::Request<boxed::Payload>) -> bool {
+        req.extensions()
+            .get::<Replay<boxed::Payload>>()
+            .map(|replay| !replay.is_capped())
+            .unwrap_or(true)
+    }
+}
+
+/// Returns true if an error occurred before the request could have been
+/// processed by an endpoint, so that it is safe to retry the request.
+///
+/// This includes failures to connect to an endpoint, requests that were
+/// canceled before being written to a connection, and streams that were
+/// refused by an HTTP/2 server (e.g. because it is shutting down).
+fn is_retryable_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(e) = error.downcast_ref::<buffer::error::ServiceError>() {
+        return is_retryable_error(&**e.inner());
+    }
+
+    if let Some(e) = error.downcast_ref::<lock::error::ServiceError>() {
+        return is_retryable_error(&**e.inner());
+    }
+
+    if let Some(e) = error.downcast_ref::<hyper::Error>() {
+        // Streams refused by an HTTP/2 server are reported as the source of a
+        // hyper error.
+        return e.is_connect()
+            || e.is_canceled()
+            || error.h2_reason() == Some(h2::Reason::REFUSED_STREAM);
+    }
+
+    if let Some(e) = error.downcast_ref::<std::io::Error>() {
+        return e.kind() == std::io::ErrorKind::ConnectionRefused;
+    }
+
+    error.h2_reason() == Some(h2::Reason::REFUSED_STREAM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_retryable_error;
+    use linkerd2_error::Error;
+    use std::io;
+
+    fn retryable(error: impl Into<Error>) -> bool {
+        is_retryable_error(&*error.into())
+    }
+
+    #[test]
+    fn connection_refused_is_retryable() {
+        assert!(retryable(io::Error::from(io::ErrorKind::ConnectionRefused)));
+        assert!(!retryable(io::Error::from(io::ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn refused_stream_is_retryable() {
+        assert!(retryable(h2::Error::from(h2::Reason::REFUSED_STREAM)));
+        assert!(!retryable(h2::Error::from(h2::Reason::INTERNAL_ERROR)));
+    }
+
+    #[test]
+    fn refused_stream_from_hyper_is_retryable() {
+        use futures::{Future, Stream};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind(&([127, 0, 0, 1], 0).into()).expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        // Refuses every stream on the first connection.
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|_| ())
+            .and_then(|(io, _)| h2::server::handshake(io.expect("connection")).map_err(|_| ()))
+            .and_then(|conn| {
+                conn.for_each(|(_, mut rsp)| {
+                    rsp.send_reset(h2::Reason::REFUSED_STREAM);
+                    Ok(())
+                })
+                .map_err(|_| ())
+            });
+
+        let client = TcpStream::connect(&addr)
+            .map_err(Error::from)
+            .and_then(|io| {
+                hyper::client::conn::Builder::new()
+                    .http2_only(true)
+                    .handshake::<_, hyper::Body>(io)
+                    .map_err(Error::from)
+            })
+            .and_then(move |(mut client, conn)| {
+                tokio::spawn(conn.map_err(|_| ()));
+                let req = http::Request::get(format!("http://{}/", addr))
+                    .body(hyper::Body::empty())
+                    .unwrap();
+                client.send_request(req).then(Ok::<_, Error>)
+            });
+
+        let mut rt = tokio::runtime::Runtime::new().expect("runtime");
+        rt.spawn(server);
+        let error = rt
+            .block_on(client)
+            .expect("client must connect")
+            .expect_err("stream must be refused");
+        assert!(is_retryable_error(&error));
+    }
+}
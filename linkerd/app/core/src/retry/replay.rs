@@ -0,0 +1,310 @@
+//! Buffers request bodies so that they may be replayed when a request is
+//! retried.
+
+use bytes::{Buf, Bytes};
+use futures::{task::AtomicTask, try_ready, Async, Poll};
+use hyper::body::Payload;
+use linkerd2_error::Error;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A request body that records the data it streams so that it may be
+/// replayed by a retry.
+///
+/// Each replay first yields the data that has already been buffered and then
+/// continues to read from the original body. Only one body for a request may
+/// be read at a time: a replay waits until the body that is being read has
+/// been dropped.
+pub struct ReplayBody<B> {
+    shared: Arc<Shared<B>>,
+
+    /// The body state, held while this body is being read.
+    state: Option<State<B>>,
+
+    /// The number of buffered chunks that this body has yielded.
+    replayed: usize,
+
+    /// The number of bytes that this body has yielded.
+    position: usize,
+}
+
+/// Produces replays of a `ReplayBody`.
+pub struct Replay<B>(Arc<Shared<B>>);
+
+/// Indicates that a body was read after it exceeded the buffer limit, so the
+/// data that has already been sent cannot be replayed.
+#[derive(Debug)]
+pub struct Capped(());
+
+struct Shared<B> {
+    state: Mutex<Option<State<B>>>,
+    /// Notified when the body state is released, so that a replay that is
+    /// waiting to read may proceed.
+    released: AtomicTask,
+    is_capped: AtomicBool,
+    max_bytes: usize,
+    content_length: Option<u64>,
+}
+
+struct State<B> {
+    buf: Vec<Bytes>,
+
+    /// The number of bytes that have been read from the original body.
+    read: usize,
+
+    /// The original body, until its trailers have been read.
+    rest: Option<B>,
+    trailers: Option<http::HeaderMap>,
+}
+
+// === impl ReplayBody ===
+
+impl<B: Payload> ReplayBody<B> {
+    /// Wraps `body` so that up to `max_bytes` of its data are buffered.
+    pub fn new(body: B, max_bytes: usize) -> (Self, Replay<B>) {
+        let shared = Arc::new(Shared {
+            content_length: body.content_length(),
+            state: Mutex::new(Some(State {
+                buf: Vec::new(),
+                read: 0,
+                rest: Some(body),
+                trailers: None,
+            })),
+            released: AtomicTask::new(),
+            is_capped: AtomicBool::new(false),
+            max_bytes,
+        });
+        let body = Self::from_shared(shared.clone());
+        (body, Replay(shared))
+    }
+
+    fn from_shared(shared: Arc<Shared<B>>) -> Self {
+        Self {
+            shared,
+            state: None,
+            replayed: 0,
+            position: 0,
+        }
+    }
+
+    /// Takes the body state, or waits for it to be released if another body
+    /// is being read.
+    fn poll_acquire(&mut self) -> Async<()> {
+        if self.state.is_none() {
+            let mut shared = self.shared.state.lock().expect("replay lock");
+            match shared.take() {
+                Some(state) => self.state = Some(state),
+                None => {
+                    // The task is registered while the lock is held, so the
+                    // body that holds the state cannot release it unnoticed.
+                    self.shared.released.register();
+                    return Async::NotReady;
+                }
+            }
+        }
+        Async::Ready(())
+    }
+}
+
+impl<B> Payload for ReplayBody<B>
+where
+    B: Payload,
+    B::Error: Into<Error>,
+{
+    type Data = Cursor<Bytes>;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        match self.state.as_ref() {
+            Some(state) => {
+                self.replayed >= state.buf.len()
+                    && state.rest.as_ref().map(B::is_end_stream).unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        if let Async::NotReady = self.poll_acquire() {
+            return Ok(Async::NotReady);
+        }
+        let state = self.state.as_mut().expect("body state must be acquired");
+        let is_capped = self.shared.is_capped.load(Ordering::Acquire);
+
+        if !is_capped && self.replayed < state.buf.len() {
+            let chunk = state.buf[self.replayed].clone();
+            self.replayed += 1;
+            self.position += chunk.len();
+            return Ok(Async::Ready(Some(Cursor::new(chunk))));
+        }
+
+        if self.position != state.read {
+            // Data that was sent by a prior attempt was not buffered.
+            return Err(Capped(()).into());
+        }
+
+        let chunk = match state.rest.as_mut() {
+            Some(rest) => match try_ready!(rest.poll_data().map_err(Into::into)) {
+                Some(chunk) => chunk.collect::<Bytes>(),
+                None => return Ok(Async::Ready(None)),
+            },
+            None => return Ok(Async::Ready(None)),
+        };
+
+        state.read += chunk.len();
+        self.position += chunk.len();
+        if !is_capped {
+            if state.read > self.shared.max_bytes {
+                self.shared.is_capped.store(true, Ordering::Release);
+                state.buf.clear();
+            } else {
+                state.buf.push(chunk.clone());
+                self.replayed += 1;
+            }
+        }
+
+        Ok(Async::Ready(Some(Cursor::new(chunk))))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        if let Async::NotReady = self.poll_acquire() {
+            return Ok(Async::NotReady);
+        }
+        let state = self.state.as_mut().expect("body state must be acquired");
+
+        if let Some(rest) = state.rest.as_mut() {
+            let trailers = try_ready!(rest.poll_trailers().map_err(Into::into));
+            state.trailers = trailers.clone();
+            state.rest = None;
+            return Ok(Async::Ready(trailers));
+        }
+
+        Ok(Async::Ready(state.trailers.clone()))
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.shared.content_length
+    }
+}
+
+impl<B> Drop for ReplayBody<B> {
+    fn drop(&mut self) {
+        // Return the body state so that it may be read by a replay.
+        if let Some(state) = self.state.take() {
+            if let Ok(mut shared) = self.shared.state.lock() {
+                *shared = Some(state);
+            }
+            self.shared.released.notify();
+        }
+    }
+}
+
+// === impl Replay ===
+
+impl<B: Payload> Replay<B> {
+    /// Returns true if the body has exceeded the buffer limit, so that it
+    /// can no longer be replayed.
+    pub fn is_capped(&self) -> bool {
+        self.0.is_capped.load(Ordering::Acquire)
+    }
+
+    /// Returns a new replay of the body, unless it can no longer be replayed.
+    pub fn replay(&self) -> Option<ReplayBody<B>> {
+        if self.is_capped() {
+            return None;
+        }
+
+        Some(ReplayBody::from_shared(self.0.clone()))
+    }
+}
+
+impl<B> Clone for Replay<B> {
+    fn clone(&self) -> Self {
+        Replay(self.0.clone())
+    }
+}
+
+// === impl Capped ===
+
+impl std::fmt::Display for Capped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request body exceeded the retry buffer")
+    }
+}
+
+impl std::error::Error for Capped {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream, Future};
+
+    fn body(chunks: Vec<&'static str>) -> hyper::Body {
+        hyper::Body::wrap_stream(stream::iter_ok::<_, Error>(chunks))
+    }
+
+    fn read<B: Payload<Error = Error>>(body: &mut B) -> Result<String, Error> {
+        let mut data = Vec::new();
+        loop {
+            let chunk = future::poll_fn(|| body.poll_data()).wait()?;
+            match chunk {
+                Some(chunk) => data.extend_from_slice(chunk.bytes()),
+                None => return Ok(String::from_utf8(data).unwrap()),
+            }
+        }
+    }
+
+    #[test]
+    fn replays_buffered_body() {
+        let (mut initial, replay) = ReplayBody::new(body(vec!["hello ", "world"]), 64);
+        let mut retry = replay.replay().expect("must be replayable");
+        assert_eq!(read(&mut initial).unwrap(), "hello world");
+        drop(initial);
+
+        assert!(!replay.is_capped());
+        assert_eq!(read(&mut retry).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn replays_partially_read_body() {
+        let (mut initial, replay) = ReplayBody::new(body(vec!["hello ", "world"]), 64);
+        let mut retry = replay.replay().expect("must be replayable");
+        let chunk = future::poll_fn(|| initial.poll_data()).wait().unwrap();
+        assert_eq!(chunk.unwrap().bytes(), b"hello ");
+        drop(initial);
+
+        assert_eq!(read(&mut retry).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn caps_large_bodies() {
+        let (mut initial, replay) = ReplayBody::new(body(vec!["hello ", "world"]), 8);
+        let mut retry = replay.replay().expect("must be replayable");
+        assert_eq!(read(&mut initial).unwrap(), "hello world");
+        drop(initial);
+
+        assert!(replay.is_capped());
+        assert!(replay.replay().is_none());
+        assert!(read(&mut retry).is_err());
+    }
+
+    #[test]
+    fn retry_waits_for_the_initial_body_to_be_released() {
+        let (mut initial, replay) = ReplayBody::new(body(vec!["hello ", "world"]), 64);
+        let mut retry = replay.replay().expect("must be replayable");
+        let chunk = future::poll_fn(|| initial.poll_data()).wait().unwrap();
+        assert_eq!(chunk.unwrap().bytes(), b"hello ");
+
+        // The retry starts while the initial body is still held.
+        let mut retry_read = future::poll_fn(move || retry.poll_data());
+        let polled = future::lazy(|| Ok::<_, ()>(retry_read.poll().unwrap().is_ready()))
+            .wait()
+            .unwrap();
+        assert!(!polled, "retry must wait for the initial body");
+
+        drop(initial);
+        let chunk = retry_read.wait().unwrap();
+        assert_eq!(chunk.unwrap().bytes(), b"hello ");
+    }
+}
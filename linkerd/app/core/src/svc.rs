@@ -189,6 +189,12 @@ impl<S> Stack<S> {
         self.push(stack::MakeReadyLayer::new())
     }
 
+    /// Produces a `Service<()>` for each target that calls the inner service
+    /// with that target.
+    pub fn push_make_thunk(self) -> Stack<stack::MakeThunk<S>> {
+        self.push(stack::MakeThunkLayer::new())
+    }
+
     /// Buffer requests when when the next layer is out of capacity.
     pub fn spawn_buffer<Req>(self, capacity: usize) -> Stack<buffer::Buffer<Req, S::Future>>
     where
@@ -25,7 +25,13 @@ use linkerd2_app_core::{
     reconnect, router, serve,
     spans::SpanConverter,
     svc::{self, NewService},
-    transport::{self, io::BoxedIo, tls, OrigDstAddr, SysOrigDstAddr},
+    transport::{
+        self,
+        connect::ConnectAddr,
+        io::BoxedIo,
+        tls::{self, HasPeerIdentity},
+        OrigDstAddr, SysOrigDstAddr,
+    },
     Error, ProxyMetrics, TraceContextLayer, DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP,
     L5D_SERVER_ID,
 };
@@ -317,6 +323,16 @@ impl transport::metrics::TransportLabels<HttpEndpoint> for TransportLabels {
             tls::Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
         )
     }
+
+    fn describe(&self, endpoint: &HttpEndpoint) -> Option<transport::metrics::Description> {
+        Some(transport::metrics::Description {
+            direction: "inbound",
+            peer_addr: endpoint.connect_addr(),
+            orig_dst_addr: None,
+            peer_identity: endpoint.peer_identity(),
+            protocol: Some(endpoint.settings.version().as_str()),
+        })
+    }
 }
 
 impl transport::metrics::TransportLabels<TcpEndpoint> for TransportLabels {
@@ -328,6 +344,16 @@ impl transport::metrics::TransportLabels<TcpEndpoint> for TransportLabels {
             tls::Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
         )
     }
+
+    fn describe(&self, endpoint: &TcpEndpoint) -> Option<transport::metrics::Description> {
+        Some(transport::metrics::Description {
+            direction: "inbound",
+            peer_addr: endpoint.connect_addr(),
+            orig_dst_addr: None,
+            peer_identity: endpoint.peer_identity(),
+            protocol: None,
+        })
+    }
 }
 
 impl transport::metrics::TransportLabels<ServerProtocol> for TransportLabels {
@@ -336,6 +362,10 @@ impl transport::metrics::TransportLabels<ServerProtocol> for TransportLabels {
     fn transport_labels(&self, proto: &ServerProtocol) -> Self::Labels {
         transport::labels::Key::accept("inbound", proto.tls.peer_identity.as_ref())
     }
+
+    fn describe(&self, proto: &ServerProtocol) -> Option<transport::metrics::Description> {
+        Some(proto.describe("inbound"))
+    }
 }
 
 pub fn trace_labels() -> HashMap<String, String> {
@@ -642,6 +642,172 @@ mod http2 {
         let res = fut.wait().expect("/bye response");
         assert_eq!(res.status(), http::StatusCode::OK);
     }
+
+    #[test]
+    fn outbound_balancer_ejects_failing_endpoint() {
+        let _ = trace_init();
+
+        let good = server::http2().route("/", "hello").run();
+        let bad = server::http2()
+            .route_fn("/", |_| {
+                http::Response::builder()
+                    .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Default::default())
+                    .unwrap()
+            })
+            .run();
+
+        let host = "disco.test.svc.cluster.local";
+        let ctrl = controller::new();
+        ctrl.profile_tx_default(host);
+        let dst = ctrl.destination_tx(host);
+        dst.send_addr(good.addr);
+        dst.send_addr(bad.addr);
+
+        let mut env = TestEnv::new();
+        env.put(
+            app::env::ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES,
+            "1".into(),
+        );
+
+        let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+        let client = client::http2(proxy.outbound, host);
+        let metrics = client::http1(proxy.metrics, "localhost");
+
+        // Send requests until the failing endpoint has been selected.
+        let mut failed = false;
+        for _ in 0..100 {
+            let rsp = client.request(&mut client.request_builder("/"));
+            if rsp.status() == http::StatusCode::INTERNAL_SERVER_ERROR {
+                failed = true;
+                break;
+            }
+        }
+        assert!(failed, "the failing endpoint was never selected");
+
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            "stack_ejected{direction=\"outbound\",name=\"balance\",dst=\"disco.test.svc.cluster.local:80\"} 1"
+        );
+
+        // Once it has been ejected, the failing endpoint is no longer used.
+        for _ in 0..10 {
+            let rsp = client.request(&mut client.request_builder("/"));
+            assert_eq!(rsp.status(), StatusCode::OK);
+        }
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            "stack_ejection_total{direction=\"outbound\",name=\"balance\",dst=\"disco.test.svc.cluster.local:80\"} 1"
+        );
+    }
+
+    #[test]
+    fn outbound_balancer_prefers_local_endpoints() {
+        let _ = trace_init();
+
+        let local = server::http2().route("/", "local").run();
+        let remote = server::http2().route("/", "remote").run();
+
+        let host = "disco.test.svc.cluster.local";
+        let ctrl = controller::new();
+        ctrl.profile_tx_default(host);
+        let dst = ctrl.destination_tx(host);
+        let zone = |z: &str| {
+            let mut labels = HashMap::new();
+            labels.insert("zone".to_owned(), z.to_owned());
+            labels
+        };
+        dst.send(controller::destination_add_labeled(
+            remote.addr,
+            controller::Hint::Unknown,
+            HashMap::new(),
+            zone("b"),
+        ));
+        dst.send(controller::destination_add_labeled(
+            local.addr,
+            controller::Hint::Unknown,
+            HashMap::new(),
+            zone("a"),
+        ));
+
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_OUTBOUND_LOCALITY, "zone=a".into());
+
+        let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+        let client = client::http2(proxy.outbound, host);
+        let get = || {
+            let rsp = client.request(&mut client.request_builder("/"));
+            if !rsp.status().is_success() {
+                return None;
+            }
+            let body = rsp.into_body().concat2().wait().ok()?;
+            Some(String::from_utf8_lossy(&body).into_owned())
+        };
+
+        // Wait for the local endpoint to become available.
+        assert_eventually!(get() == Some("local".to_owned()));
+        for _ in 0..10 {
+            assert_eq!(get(), Some("local".to_owned()));
+        }
+
+        // When the local endpoint is lost, requests spill over to the remote
+        // endpoint.
+        drop(local);
+        assert_eventually!(get() == Some("remote".to_owned()), retries: 20);
+    }
+
+    #[test]
+    fn outbound_balancer_routes_by_affinity_header() {
+        let _ = trace_init();
+
+        let srv_a = server::http2().route("/", "a").run();
+        let srv_b = server::http2().route("/", "b").run();
+
+        let host = "disco.test.svc.cluster.local";
+        let ctrl = controller::new();
+        ctrl.profile_tx_default(host);
+        let dst = ctrl.destination_tx(host);
+        dst.send_addr(srv_a.addr);
+        dst.send_addr(srv_b.addr);
+
+        let mut env = TestEnv::new();
+        env.put(
+            app::env::ENV_OUTBOUND_AFFINITY,
+            format!("{}:80=header:x-user", host),
+        );
+
+        let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+        let client = client::http2(proxy.outbound, host);
+        let get = |user: usize| {
+            let rsp = client.request(
+                client
+                    .request_builder("/")
+                    .header("x-user", format!("user-{}", user)),
+            );
+            assert_eq!(rsp.status(), StatusCode::OK);
+            let body = rsp.into_body().concat2().wait().expect("body");
+            String::from_utf8_lossy(&body).into_owned()
+        };
+
+        // Wait for both endpoints to become available.
+        assert_eventually!(
+            {
+                let bodies = (0..20)
+                    .map(|user| get(user))
+                    .collect::<std::collections::HashSet<_>>();
+                bodies.len() == 2
+            },
+            retries: 20
+        );
+
+        // Each user is consistently served by the same endpoint.
+        for user in 0..20 {
+            let expected = get(user);
+            for _ in 0..5 {
+                assert_eq!(get(user), expected, "user-{}", user);
+            }
+        }
+    }
 }
 
 mod http1 {
@@ -885,4 +1051,53 @@ mod proxy_to_proxy {
             client: client::http2
         }
     }
+
+    #[test]
+    fn outbound_tcp_mtls_with_discovered_identity() {
+        let _ = trace_init();
+        let id = "foo.ns1.serviceaccount.identity.linkerd.cluster.local";
+        let id_env = identity::Identity::new("foo-ns1", id.to_string());
+
+        let msg1 = "custom tcp hello";
+        let msg2 = "custom tcp bye";
+
+        let srv = server::tcp()
+            .accept(move |read| {
+                assert_eq!(read, msg1.as_bytes());
+                msg2
+            })
+            .run();
+
+        let in_proxy = proxy::new()
+            .inbound(srv)
+            .identity(id_env.service().run())
+            .run_with_test_env(id_env.env.clone());
+
+        let orig_dst: SocketAddr = ([10, 1, 2, 3], in_proxy.inbound.port()).into();
+        let ctrl = controller::new();
+        let dst = ctrl.destination_tx(&orig_dst.to_string());
+        dst.send(controller::destination_add_tls(in_proxy.inbound, id));
+
+        let mut env = id_env.env.clone();
+        env.put(
+            app::env::ENV_DESTINATION_GET_NETWORKS,
+            "10.0.0.0/8".to_owned(),
+        );
+        let out_proxy = proxy::new()
+            .controller(ctrl.run())
+            .outbound_ip(orig_dst)
+            .identity(id_env.service().run())
+            .run_with_test_env(env);
+
+        let client = client::tcp(out_proxy.outbound);
+        let tcp_client = client.connect();
+        tcp_client.write(msg1);
+        assert_eq!(tcp_client.read(), msg2.as_bytes());
+
+        let metrics = client::http1(out_proxy.metrics, "localhost");
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            "tcp_open_total{direction=\"outbound\",peer=\"dst\",tls=\"true\"} 1"
+        );
+    }
 }
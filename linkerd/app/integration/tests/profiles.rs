@@ -1,7 +1,7 @@
 #![deny(warnings, rust_2018_idioms)]
 
 use linkerd2_app_integration::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 macro_rules! profile_test {
     (routes: [$($route:expr),+], budget: $budget:expr, with_client: $with_client:expr) => {
@@ -165,7 +165,7 @@ fn retry_uses_budget() {
         with_metrics: |metrics: client::Client| {
             assert_eventually_contains!(
                 metrics.get("/metrics"),
-                "route_retryable_total{direction=\"outbound\",dst=\"profiles.test.svc.cluster.local:80\",skipped=\"no_budget\"} 1"
+                "route_retryable_total{direction=\"outbound\",dst=\"profiles.test.svc.cluster.local:80\",reason=\"response\",skipped=\"no_budget\"} 1"
             );
         }
     }
@@ -208,7 +208,7 @@ fn does_not_retry_if_earlier_response_class_is_success() {
 }
 
 #[test]
-fn does_not_retry_if_request_has_body() {
+fn retry_if_request_has_body() {
     profile_test! {
         routes: [
             controller::route()
@@ -223,6 +223,27 @@ fn does_not_retry_if_request_has_body() {
                 .body("req has a body".into())
                 .unwrap();
             let res = client.request_body(req);
+            assert_eq!(res.status(), 200);
+        }
+    }
+}
+
+#[test]
+fn does_not_retry_if_request_body_exceeds_buffer() {
+    profile_test! {
+        routes: [
+            controller::route()
+                .request_any()
+                .response_failure(500..600)
+                .retryable(true)
+        ],
+        budget: Some(controller::retry_budget(Duration::from_secs(10), 0.1, 1)),
+        with_client: |client: client::Client| {
+            let req = client.request_builder("/0.5")
+                .method("POST")
+                .body(vec![b'x'; 1024 * 100].into())
+                .unwrap();
+            let res = client.request_body(req);
             assert_eq!(res.status(), 533);
         }
     }
@@ -338,3 +359,174 @@ fn timeout() {
         }
     }
 }
+
+#[test]
+fn hedge_to_another_endpoint_beats_slow_primary() {
+    let _ = trace_init();
+
+    let host = "hedge.test.svc.cluster.local";
+
+    // Hedging is configured per-route, from a profile file.
+    let dir = std::env::temp_dir().join(format!("linkerd2-hedge-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create profile dir");
+    std::fs::write(
+        dir.join(format!("{}.yaml", host)),
+        r#"
+routes:
+  - match:
+      path: /.*
+    route:
+      retries:
+        budget: { retry_ratio: 1.0, min_retries_per_second: 100, ttl: 10s }
+        hedge_percentile: 0.5
+"#,
+    )
+    .expect("write profile");
+
+    // Once `slow` is set, the first endpoint takes far longer to respond
+    // than the route's usual latency.
+    let slow = Arc::new(AtomicBool::new(false));
+    let delayed = Arc::new(AtomicUsize::new(0));
+    let srv_slow = {
+        let slow = slow.clone();
+        let delayed = delayed.clone();
+        server::http1()
+            .route_async("/", move |_req| {
+                let delay = if slow.load(Ordering::SeqCst) {
+                    delayed.fetch_add(1, Ordering::SeqCst);
+                    Duration::from_secs(10)
+                } else {
+                    Duration::from_secs(0)
+                };
+                tokio::timer::Delay::new(std::time::Instant::now() + delay)
+                    .map(|()| Response::builder().status(200).body("slow".into()).unwrap())
+            })
+            .run()
+    };
+    let srv_fast = server::http1().route("/", "fast").run();
+
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx(host);
+    dst.send_addr(srv_slow.addr);
+    dst.send_addr(srv_fast.addr);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_DIR,
+        dir.to_string_lossy().into_owned(),
+    );
+    // Any failure ejects an endpoint, so a hedge that counted against the
+    // slow endpoint would eject it.
+    env.put(
+        app::env::ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES,
+        "1".into(),
+    );
+    let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+    let client = client::http1(proxy.outbound, host);
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    // Record enough of the route's latency distribution for requests to be
+    // hedged.
+    for _ in 0..150 {
+        let body = client.get("/");
+        assert!(
+            body == "slow" || body == "fast",
+            "unexpected body {:?}",
+            body
+        );
+    }
+
+    slow.store(true, Ordering::SeqCst);
+    for _ in 0..20 {
+        let start = std::time::Instant::now();
+        assert_eq!(client.get("/"), "fast");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    assert!(
+        delayed.load(Ordering::SeqCst) > 0,
+        "no request was sent to the slow endpoint"
+    );
+    assert_contains!(
+        metrics.get("/metrics"),
+        "stack_ejection_total{direction=\"outbound\",name=\"balance\",dst=\"hedge.test.svc.cluster.local:80\"} 0"
+    );
+
+    std::fs::remove_dir_all(&dir).expect("remove profile dir");
+}
+
+#[test]
+fn mirrors_requests_to_shadow_destination() {
+    let _ = trace_init();
+
+    let host = "mirror.test.svc.cluster.local";
+    let shadow_host = "mirror-shadow.test.svc.cluster.local";
+
+    // Mirrors are configured from a profile file.
+    let dir = std::env::temp_dir().join(format!("linkerd2-mirror-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create profile dir");
+    std::fs::write(
+        dir.join(format!("{}.yaml", host)),
+        format!("mirror: {{ addr: '{}:80', ratio: 1.0 }}\n", shadow_host),
+    )
+    .expect("write profile");
+
+    let srv = server::http1().route("/", "primary").run();
+
+    let mirrored = Arc::new(AtomicUsize::new(0));
+    let shadow = {
+        let mirrored = mirrored.clone();
+        server::http1()
+            .route_async("/", move |req| {
+                let mirrored = mirrored.clone();
+                req.into_body()
+                    .concat2()
+                    .map(move |body| {
+                        assert_eq!(&body[..], b"hello");
+                        mirrored.fetch_add(1, Ordering::SeqCst);
+                        Response::builder()
+                            .status(200)
+                            .body("shadow".into())
+                            .unwrap()
+                    })
+                    .map_err(|()| "failed to read mirrored body")
+            })
+            .run()
+    };
+
+    let ctrl = controller::new_unordered();
+    ctrl.destination_tx(host).send_addr(srv.addr);
+    ctrl.destination_tx(shadow_host).send_addr(shadow.addr);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_DIR,
+        dir.to_string_lossy().into_owned(),
+    );
+    let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+    let client = client::http1(proxy.outbound, host);
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    let n = 10;
+    for _ in 0..n {
+        let req = client
+            .request_builder("/")
+            .method("POST")
+            .body("hello".into())
+            .unwrap();
+        let rsp = client.request_body(req);
+        assert_eq!(rsp.status(), 200);
+        let body = rsp.into_body().concat2().wait().unwrap();
+        assert_eq!(&body[..], b"primary");
+    }
+
+    // Mirrored requests are sent in the background, after the original
+    // request's body has been read.
+    assert_eventually!(mirrored.load(Ordering::SeqCst) == n, retries: 50);
+    let expected = format!(
+        "mirror_response_total{{dst=\"{}:80\",classification=\"success\"}} {}",
+        shadow_host, n
+    );
+    assert_eventually_contains!(metrics.get("/metrics"), &expected);
+
+    std::fs::remove_dir_all(&dir).expect("remove profile dir");
+}
@@ -213,3 +213,89 @@ fn grpc_headers_end() {
 
     assert_eq!(event.response_end_eos_grpc(), 1);
 }
+
+#[test]
+fn outbound_aborts_are_tapped() {
+    let _ = trace_init();
+
+    // Setup the tapped proxy's authority and identity
+    let proxy_authority = "foo.ns1.serviceaccount.identity.linkerd.cluster.local";
+    let identity::Identity {
+        mut env,
+        mut certify_rsp,
+        ..
+    } = identity::Identity::new("foo-ns1", proxy_authority.to_string());
+
+    // Setup tap client proxy authority and identity
+    let client_proxy_authority = "bar.ns1.serviceaccount.identity.linkerd.cluster.local";
+    let client_proxy_identity =
+        identity::Identity::new("bar-ns1", client_proxy_authority.to_string());
+
+    certify_rsp.valid_until = Some((SystemTime::now() + Duration::from_secs(666)).into());
+    let proxy_identity_svc = controller::identity().certify(move |_| certify_rsp).run();
+
+    env.put(
+        app::env::ENV_TAP_SVC_NAME,
+        client_proxy_authority.to_owned(),
+    );
+
+    // Every request to the destination is aborted, from a profile file.
+    let host = "abort.test.svc.cluster.local";
+    let dir = std::env::temp_dir().join(format!("linkerd2-tap-abort-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create profile dir");
+    std::fs::write(
+        dir.join(format!("{}.yaml", host)),
+        r#"
+routes:
+  - match:
+      path: /.*
+    route:
+      fault:
+        abort: { ratio: 1.0, http_status: 503 }
+"#,
+    )
+    .expect("write profile");
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_DIR,
+        dir.to_string_lossy().into_owned(),
+    );
+
+    let srv = server::http1().route("/", "hello").run();
+    let ctrl = controller::new();
+    ctrl.destination_tx(host).send_addr(srv.addr);
+
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .identity(proxy_identity_svc)
+        .run_with_test_env(env);
+
+    let client_proxy = proxy::new()
+        .outbound_ip(proxy.tap.unwrap())
+        .identity(client_proxy_identity.service().run())
+        .run_with_test_env(client_proxy_identity.env);
+
+    // Wait for the tapped proxy to become ready
+    let client = client::http1(proxy.metrics, "localhost");
+    let ready = || client.request(client.request_builder("/ready").method("GET"));
+    assert_eventually!(ready().status() == http::StatusCode::OK);
+
+    let mut tap = tap::client_with_auth(client_proxy.outbound, proxy_authority);
+    let events = tap.observe_with_require_id(tap::observe_request(), proxy_authority);
+
+    // The aborted request never reaches an endpoint, but is still tapped.
+    let client = client::http1(proxy.outbound, host);
+    let res = client.request(&mut client.request_builder("/"));
+    assert_eq!(res.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+    let mut events = events.wait().take(2);
+
+    let ev1 = events.next().expect("next1").expect("stream1");
+    assert!(ev1.is_outbound());
+    assert_eq!(ev1.request_init_path(), "/");
+
+    let ev2 = events.next().expect("next2").expect("stream2");
+    assert!(ev2.is_outbound());
+    assert_eq!(ev2.response_init_status(), 503);
+
+    std::fs::remove_dir_all(&dir).expect("remove profile dir");
+}
@@ -152,6 +152,50 @@ fn metrics_endpoint_outbound_request_count() {
     assert_eventually_contains!(metrics.get("/metrics"), "request_total{authority=\"tele.test.svc.cluster.local\",direction=\"outbound\",tls=\"no_identity\",no_tls_reason=\"not_provided_by_service_discovery\"} 1");
 }
 
+#[test]
+fn metrics_endpoint_inbound_body_bytes() {
+    let _ = trace_init();
+    let Fixture {
+        client,
+        metrics,
+        proxy: _proxy,
+    } = Fixture::inbound();
+    let labels =
+        "{authority=\"tele.test.svc.cluster.local\",direction=\"inbound\",tls=\"disabled\"}";
+
+    info!("client.request_body(/)");
+    let req = client
+        .request_builder("/")
+        .method("POST")
+        .body("req has a body".into())
+        .unwrap();
+    let rsp = client.request_body(req);
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+    let body = rsp.into_body().concat2().wait().expect("response body");
+    assert_eq!(&body[..], b"hello");
+
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("request_bytes_total{} 14", labels)
+    );
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("request_body_bytes_sum{} 14", labels)
+    );
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("request_body_bytes_count{} 1", labels)
+    );
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("response_bytes_total{} 5", labels)
+    );
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("response_body_bytes_count{} 1", labels)
+    );
+}
+
 mod response_classification {
     use super::Fixture;
     use linkerd2_app_integration::*;
@@ -1383,3 +1427,100 @@ fn metrics_compression() {
             "response_latency_ms_count{authority=\"tele.test.svc.cluster.local\",direction=\"inbound\",tls=\"disabled\",status_code=\"200\"} 2");
     }
 }
+
+#[test]
+fn metrics_openmetrics_exemplars() {
+    let _ = trace_init();
+
+    let Fixture {
+        client,
+        metrics,
+        proxy: _proxy,
+    } = Fixture::inbound();
+
+    let do_scrape = || {
+        let resp = metrics.request(
+            metrics
+                .request_builder("/metrics")
+                .method("GET")
+                .header("Accept", "application/openmetrics-text; version=1.0.0"),
+        );
+        let content_type = resp
+            .headers()
+            .get("content-type")
+            .and_then(|val| val.to_str().ok())
+            .map(String::from);
+        assert_eq!(
+            content_type.as_ref().map(String::as_str),
+            Some("application/openmetrics-text; version=1.0.0; charset=utf-8")
+        );
+        let body = resp
+            .into_body()
+            .concat2()
+            .wait()
+            .expect("response body concat");
+        String::from_utf8(body.to_vec()).expect("scrape must be utf-8")
+    };
+
+    info!("client.request(/) with a sampled trace");
+    let rsp = client.request(
+        client
+            .request_builder("/")
+            .method("GET")
+            .header("x-b3-traceid", "0123456789abcdef0123456789abcdef")
+            .header("x-b3-spanid", "0123456789abcdef")
+            .header("x-b3-sampled", "1"),
+    );
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+
+    assert_eventually_contains!(
+        do_scrape(),
+        "# {trace_id=\"0123456789abcdef0123456789abcdef\"}"
+    );
+
+    let scrape = do_scrape();
+    assert!(scrape.ends_with("# EOF\n"), "{}", scrape);
+    assert!(scrape.contains("# TYPE request counter\n"), "{}", scrape);
+    assert!(!metrics.get("/metrics").contains("trace_id"));
+}
+
+#[test]
+fn metrics_export_statsd() {
+    let _ = trace_init();
+
+    let statsd = std::net::UdpSocket::bind("127.0.0.1:0").expect("bind statsd");
+    statsd
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .expect("set read timeout");
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_METRICS_EXPORT_STATSD_ADDR,
+        statsd.local_addr().unwrap().to_string(),
+    );
+    env.put(app::env::ENV_METRICS_EXPORT_INTERVAL, "100ms".into());
+    env.put(app::env::ENV_METRICS_EXPORT_PREFIX, "linkerd.".into());
+    env.put(app::env::ENV_METRICS_EXPORT_LABELS, "pod=tele-0".into());
+
+    let srv = server::new().route("/", "hello").run();
+    let ctrl = controller::new();
+    ctrl.profile_tx_default("tele.test.svc.cluster.local");
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::new(proxy.inbound, "tele.test.svc.cluster.local");
+
+    info!("client.get(/)");
+    assert_eq!(client.get("/"), "hello");
+
+    let expected = "linkerd.request_total:1|c|#authority:tele.test.svc.cluster.local,direction:inbound,tls:disabled,pod:tele-0";
+    let mut buf = [0u8; 65_536];
+    loop {
+        let n = statsd.recv(&mut buf).expect("statsd datagram");
+        let datagram = std::str::from_utf8(&buf[..n]).expect("datagram must be utf-8");
+        if datagram.lines().any(|l| l == expected) {
+            break;
+        }
+    }
+}
@@ -58,6 +58,45 @@ fn outbound_tcp() {
     assert_eq!(tcp_client.read(), msg2.as_bytes());
 }
 
+#[test]
+fn outbound_tcp_uses_discovered_endpoint() {
+    let _ = trace_init();
+
+    let msg1 = "custom tcp hello";
+    let msg2 = "custom tcp bye";
+
+    let srv = server::tcp()
+        .accept(move |read| {
+            assert_eq!(read, msg1.as_bytes());
+            msg2
+        })
+        .run();
+
+    // The original destination is not routable, so the connection can only
+    // succeed if it is established to the discovered endpoint.
+    let orig_dst: SocketAddr = ([10, 1, 2, 3], srv.addr.port()).into();
+    let ctrl = controller::new();
+    ctrl.destination_tx(&orig_dst.to_string())
+        .send_addr(srv.addr);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_GET_NETWORKS,
+        "10.0.0.0/8".to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound_ip(orig_dst)
+        .run_with_test_env(env);
+
+    let client = client::tcp(proxy.outbound);
+
+    let tcp_client = client.connect();
+
+    tcp_client.write(msg1);
+    assert_eq!(tcp_client.read(), msg2.as_bytes());
+}
+
 #[test]
 fn inbound_tcp() {
     let _ = trace_init();
@@ -0,0 +1,130 @@
+use http::header::{HeaderName, COOKIE};
+use linkerd2_app_core::{proxy::http::balance::hash::HashRequest, transport::tls, Conditional};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Determines how requests to a destination are bound to endpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Affinity {
+    /// Requests with the same value for the given header are sent to the same
+    /// endpoint.
+    Header(HeaderName),
+
+    /// Requests with the same value for the given cookie are sent to the same
+    /// endpoint.
+    Cookie(String),
+
+    /// Requests from the same client are sent to the same endpoint.
+    ///
+    /// Clients are identified by the TLS identity of the connection on which
+    /// the request was accepted. Requests accepted without a peer identity,
+    /// e.g. from the local application, are balanced by load.
+    SourceIdentity,
+}
+
+// === impl Affinity ===
+
+impl<B> HashRequest<B> for Affinity {
+    fn hash_request(&self, req: &http::Request<B>) -> Option<u64> {
+        match self {
+            Affinity::Header(name) => {
+                let value = req.headers().get(name)?;
+                Some(hash(value.as_bytes()))
+            }
+            Affinity::Cookie(name) => req
+                .headers()
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(';'))
+                .filter_map(|cookie| {
+                    let mut parts = cookie.trim().splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(n), Some(value)) if n == name => Some(hash(value.as_bytes())),
+                        _ => None,
+                    }
+                })
+                .next(),
+            Affinity::SourceIdentity => {
+                let source = req.extensions().get::<tls::accept::Meta>()?;
+                match source.peer_identity {
+                    Conditional::Some(ref id) => Some(hash(id.as_ref())),
+                    Conditional::None(_) => None,
+                }
+            }
+        }
+    }
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_app_core::{proxy::identity, transport::listen::Addrs};
+
+    fn req(header: &str, value: &str) -> http::Request<()> {
+        http::Request::builder()
+            .header(header, value)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn hashes_header() {
+        let affinity = Affinity::Header(HeaderName::from_static("x-user"));
+        let a = affinity.hash_request(&req("x-user", "a"));
+        assert!(a.is_some());
+        assert_eq!(a, affinity.hash_request(&req("x-user", "a")));
+        assert_ne!(a, affinity.hash_request(&req("x-user", "b")));
+        assert_eq!(None, affinity.hash_request(&req("x-other", "a")));
+    }
+
+    #[test]
+    fn hashes_cookie() {
+        let affinity = Affinity::Cookie("session".to_owned());
+        let a = affinity.hash_request(&req("cookie", "theme=dark; session=a"));
+        assert!(a.is_some());
+        assert_eq!(a, affinity.hash_request(&req("cookie", "session=a")));
+        assert_ne!(a, affinity.hash_request(&req("cookie", "session=b")));
+        assert_eq!(None, affinity.hash_request(&req("cookie", "sessions=a")));
+    }
+
+    #[test]
+    fn hashes_source_identity() {
+        let req = |peer_identity: tls::PeerIdentity| {
+            let addr = ([10, 0, 0, 1], 4143).into();
+            let mut req = http::Request::new(());
+            req.extensions_mut().insert(tls::accept::Meta {
+                peer_identity,
+                addrs: Addrs::new(addr, addr, None),
+            });
+            req
+        };
+        let id =
+            |name: &str| Conditional::Some(identity::Name::from_hostname(name.as_bytes()).unwrap());
+
+        let affinity = Affinity::SourceIdentity;
+        let a = affinity.hash_request(&req(id("a.ns.serviceaccount.identity.linkerd")));
+        assert!(a.is_some());
+        assert_eq!(
+            a,
+            affinity.hash_request(&req(id("a.ns.serviceaccount.identity.linkerd")))
+        );
+        assert_ne!(
+            a,
+            affinity.hash_request(&req(id("b.ns.serviceaccount.identity.linkerd")))
+        );
+        assert_eq!(
+            None,
+            affinity.hash_request(&req(Conditional::None(
+                tls::ReasonForNoPeerName::Loopback.into()
+            )))
+        );
+        assert_eq!(None, affinity.hash_request(&http::Request::new(())));
+    }
+}
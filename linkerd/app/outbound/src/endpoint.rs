@@ -1,6 +1,6 @@
 use indexmap::IndexMap;
 use linkerd2_app_core::{
-    dst, metric_labels,
+    dst, fault, metric_labels,
     metric_labels::{prefix_labels, EndpointLabels},
     profiles,
     proxy::{
@@ -24,6 +24,10 @@ pub type Logical<T> = Target<T>;
 
 pub type Concrete<T> = Target<Logical<T>>;
 
+/// The original destination of a TCP connection that could not be decoded as
+/// HTTP, used as the target for discovery.
+pub type TcpLogical = Target<TcpOrigDst>;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Profile(Addr);
 
@@ -47,6 +51,15 @@ pub struct HttpEndpoint {
     pub metadata: Metadata,
 }
 
+/// The original destination address of a forwarded TCP connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TcpOrigDst {
+    pub addr: SocketAddr,
+    /// Set when protocol detection is disabled for the destination port, in
+    /// which case the peer's proxy does not terminate mTLS either.
+    pub skip_identity: bool,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TcpEndpoint {
     pub addr: SocketAddr,
@@ -112,6 +125,18 @@ impl<T: http::settings::HasSettings> http::settings::HasSettings for Target<T> {
     }
 }
 
+impl<T: http::balance::HasWeight> http::balance::HasWeight for Target<T> {
+    fn weight(&self) -> http::balance::Weight {
+        self.inner.weight()
+    }
+}
+
+impl<T: http::balance::locality::HasLabels> http::balance::locality::HasLabels for Target<T> {
+    fn labels(&self) -> &IndexMap<String, String> {
+        self.inner.labels()
+    }
+}
+
 impl<T: tls::HasPeerIdentity> tls::HasPeerIdentity for Target<T> {
     fn peer_identity(&self) -> tls::PeerIdentity {
         self.inner.peer_identity()
@@ -194,6 +219,19 @@ impl std::hash::Hash for HttpEndpoint {
     }
 }
 
+impl http::balance::HasWeight for HttpEndpoint {
+    fn weight(&self) -> http::balance::Weight {
+        // The destination service encodes weights such that 10,000 is 1.0.
+        http::balance::Weight::from(f64::from(self.metadata.weight()) / 10_000.0)
+    }
+}
+
+impl http::balance::locality::HasLabels for HttpEndpoint {
+    fn labels(&self) -> &IndexMap<String, String> {
+        self.metadata.labels()
+    }
+}
+
 impl tls::HasPeerIdentity for HttpEndpoint {
     fn peer_identity(&self) -> tls::PeerIdentity {
         self.identity.clone()
@@ -242,9 +280,10 @@ impl tap::Inspect for HttpEndpoint {
     }
 
     fn route_labels<B>(&self, req: &http::Request<B>) -> Option<Arc<IndexMap<String, String>>> {
+        // Marks requests into which faults were injected.
         req.extensions()
             .get::<dst::Route>()
-            .map(|r| r.route.labels().clone())
+            .map(|r| fault::route_labels(r.route.labels().clone(), req))
     }
 
     fn is_outbound<B>(&self, _: &http::Request<B>) -> bool {
@@ -262,13 +301,7 @@ impl MapEndpoint<Concrete<http::Settings>, Metadata> for FromMetadata {
         metadata: Metadata,
     ) -> Self::Out {
         tracing::trace!(%addr, ?metadata, "Resolved endpoint");
-        let identity = metadata
-            .identity()
-            .cloned()
-            .map(Conditional::Some)
-            .unwrap_or_else(|| {
-                Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into())
-            });
+        let identity = identity_from_metadata(&metadata);
 
         Target {
             // Use the logical addr for the target.
@@ -301,6 +334,39 @@ impl Into<EndpointLabels> for Target<HttpEndpoint> {
 
 // === impl TcpEndpoint ===
 
+impl TcpLogical {
+    pub fn new(addr: SocketAddr, skip_identity: bool) -> Self {
+        Target {
+            addr: addr.into(),
+            inner: TcpOrigDst {
+                addr,
+                skip_identity,
+            },
+        }
+    }
+}
+
+impl MapEndpoint<TcpLogical, Metadata> for FromMetadata {
+    type Out = TcpEndpoint;
+
+    fn map_endpoint(
+        &self,
+        logical: &TcpLogical,
+        addr: SocketAddr,
+        metadata: Metadata,
+    ) -> Self::Out {
+        tracing::trace!(%addr, ?metadata, "Resolved endpoint");
+        // Discovered endpoints may be meshed, in which case the connection is
+        // established with mTLS, unless the port skips the peer's proxy.
+        let identity = if logical.inner.skip_identity {
+            Conditional::None(tls::ReasonForNoPeerName::NotHttp.into())
+        } else {
+            identity_from_metadata(&metadata)
+        };
+        TcpEndpoint { addr, identity }
+    }
+}
+
 impl From<SocketAddr> for TcpEndpoint {
     fn from(addr: SocketAddr) -> Self {
         Self {
@@ -334,6 +400,17 @@ impl Into<EndpointLabels> for TcpEndpoint {
     }
 }
 
+/// Uses the identity provided by service discovery, if any, to establish mTLS.
+fn identity_from_metadata(metadata: &Metadata) -> tls::PeerIdentity {
+    metadata
+        .identity()
+        .cloned()
+        .map(Conditional::Some)
+        .unwrap_or_else(|| {
+            Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into())
+        })
+}
+
 // === impl LogicalPerRequest ===
 
 impl From<tls::accept::Meta> for LogicalPerRequest {
@@ -423,3 +500,40 @@ impl profiles::WithRoute for Profile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_id() -> identity::Name {
+        identity::Name::from_hostname(b"foo.ns.serviceaccount.identity.linkerd.cluster.local")
+            .unwrap()
+    }
+
+    fn meshed() -> Metadata {
+        Metadata::new(
+            IndexMap::default(),
+            ProtocolHint::Unknown,
+            Some(server_id()),
+            10_000,
+            None,
+        )
+    }
+
+    #[test]
+    fn tcp_endpoint_uses_discovered_identity() {
+        let addr = SocketAddr::from(([10, 1, 1, 1], 8080));
+        let ep = FromMetadata.map_endpoint(&TcpLogical::new(addr, false), addr, meshed());
+        assert_eq!(ep.identity, Conditional::Some(server_id()));
+    }
+
+    #[test]
+    fn tcp_endpoint_on_skip_port_has_no_identity() {
+        let addr = SocketAddr::from(([10, 1, 1, 1], 3306));
+        let ep = FromMetadata.map_endpoint(&TcpLogical::new(addr, true), addr, meshed());
+        assert_eq!(
+            ep.identity,
+            Conditional::None(tls::ReasonForNoPeerName::NotHttp.into())
+        );
+    }
+}
@@ -5,15 +5,17 @@
 
 #![deny(warnings, rust_2018_idioms)]
 
+pub use self::affinity::Affinity;
 pub use self::endpoint::{
     Concrete, HttpEndpoint, Logical, LogicalPerRequest, Profile, ProfilePerTarget, Target,
-    TcpEndpoint,
+    TcpEndpoint, TcpLogical,
 };
 use futures::future;
+use indexmap::IndexMap;
 use linkerd2_app_core::{
-    classify,
+    admin, classify,
     config::{ProxyConfig, ServerConfig},
-    dns, drain, dst, errors, metric_labels,
+    dns, drain, dst, errors, fault, hedge, metric_labels, mirror,
     opencensus::proto::trace::v1 as oc,
     profiles,
     proxy::{
@@ -24,11 +26,13 @@ use linkerd2_app_core::{
     spans::SpanConverter,
     svc::{self, NewService},
     transport::{self, tls, OrigDstAddr, SysOrigDstAddr},
-    Conditional, DiscoveryRejected, Error, ProxyMetrics, TraceContextLayer, CANONICAL_DST_HEADER,
-    DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP, L5D_REQUIRE_ID, L5D_SERVER_ID,
+    Addr, Conditional, DiscoveryRejected, Error, ProxyMetrics, TraceContextLayer,
+    CANONICAL_DST_HEADER, DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP, L5D_REQUIRE_ID,
+    L5D_SERVER_ID,
 };
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::info_span;
@@ -37,6 +41,7 @@ use tracing::info_span;
 mod add_remote_ip_on_rsp;
 #[allow(dead_code)] // TODO #2597
 mod add_server_id_on_rsp;
+mod affinity;
 mod endpoint;
 mod orig_proto_upgrade;
 mod require_identity_on_endpoint;
@@ -51,6 +56,12 @@ const EWMA_DECAY: Duration = Duration::from_secs(10);
 pub struct Config<A: OrigDstAddr = SysOrigDstAddr> {
     pub proxy: ProxyConfig<A>,
     pub canonicalize_timeout: Duration,
+    pub outlier_detection: http::balance::outlier::Config,
+    pub locality: http::balance::locality::Config,
+    pub affinity: IndexMap<Addr, Affinity>,
+    pub retry_buffer_limit: usize,
+    pub mirror_buffer_limit: usize,
+    pub mirror_max_in_flight: usize,
 }
 
 pub struct Outbound {
@@ -63,6 +74,12 @@ impl<A: OrigDstAddr> Config<A> {
         Config {
             proxy: self.proxy.with_orig_dst_addr(orig_dst_addr),
             canonicalize_timeout: self.canonicalize_timeout,
+            outlier_detection: self.outlier_detection,
+            locality: self.locality,
+            affinity: self.affinity,
+            retry_buffer_limit: self.retry_buffer_limit,
+            mirror_buffer_limit: self.mirror_buffer_limit,
+            mirror_max_in_flight: self.mirror_max_in_flight,
         }
     }
 
@@ -75,23 +92,33 @@ impl<A: OrigDstAddr> Config<A> {
         tap_layer: tap::Layer,
         metrics: ProxyMetrics,
         span_sink: Option<mpsc::Sender<oc::Span>>,
+        debug: admin::debug::Registry,
         drain: drain::Watch,
     ) -> Result<Outbound, Error>
     where
         A: Send + 'static,
         R: Resolve<Concrete<http::Settings>, Endpoint = proxy::api_resolve::Metadata>
+            + Resolve<TcpLogical, Endpoint = proxy::api_resolve::Metadata>
             + Clone
             + Send
             + Sync
             + 'static,
-        R::Future: Send,
-        R::Resolution: Send,
+        <R as Resolve<Concrete<http::Settings>>>::Future: Send,
+        <R as Resolve<Concrete<http::Settings>>>::Resolution: Send,
+        <R as Resolve<TcpLogical>>::Future: Send,
+        <R as Resolve<TcpLogical>>::Resolution: Send,
         P: profiles::GetRoutes<Profile> + Clone + Send + 'static,
         P::Future: Send,
     {
         use proxy::core::listen::{Bind, Listen};
         let Config {
             canonicalize_timeout,
+            outlier_detection,
+            locality,
+            affinity,
+            retry_buffer_limit,
+            mirror_buffer_limit,
+            mirror_max_in_flight,
             proxy:
                 ProxyConfig {
                     server: ServerConfig { bind, h2_settings },
@@ -120,11 +147,69 @@ impl<A: OrigDstAddr> Config<A> {
                 .push_timeout(connect.timeout)
                 .push(metrics.transport.layer_connect(TransportLabels));
 
+            // Resolves the original destination of each TCP connection via the control plane,
+            // building a balancer that establishes each connection to a discovered endpoint.
+            let tcp_balance = {
+                const BUFFER_CAPACITY: usize = 1_000;
+                let resolve = map_endpoint::Resolve::new::<TcpLogical>(
+                    endpoint::FromMetadata,
+                    resolve.clone(),
+                );
+                let discover = discover::Layer::<TcpLogical, _>::new(
+                    BUFFER_CAPACITY,
+                    cache_max_idle_age,
+                    resolve,
+                );
+
+                tcp_connect
+                    .clone()
+                    .push_make_thunk()
+                    .check_make_service::<TcpEndpoint, ()>()
+                    .push(discover)
+                    .push_on_response(tcp::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY))
+                    .into_new_service()
+                    .cache(
+                        svc::layers().push_on_response(
+                            svc::layers()
+                                // If the balancer has been ready & unused for `cache_max_idle_age`,
+                                // fail the balancer.
+                                .push_idle_timeout(cache_max_idle_age)
+                                // If the balancer has been empty/unavailable for 10s, eagerly fail
+                                // connections.
+                                .push_failfast(dispatch_timeout)
+                                // Shares the balancer, ensuring discovery errors are propagated.
+                                .push_spawn_buffer(buffer_capacity)
+                                .push(metrics.stack.layer(stack_labels("tcp.balance"))),
+                        ),
+                    )
+                    .instrument(|t: &TcpLogical| info_span!("tcp.balance", addr = %t.addr))
+                    // Ensure that buffers don't hold the cache's lock in poll_ready.
+                    .push_oneshot()
+            };
+
             // Forwards TCP streams that cannot be decoded as HTTP.
-            let tcp_forward = tcp_connect
-                .clone()
-                .push_map_target(|meta: tls::accept::Meta| {
-                    TcpEndpoint::from(meta.addrs.target_addr())
+            //
+            // If the original destination cannot be resolved, i.e., because discovery is rejected,
+            // the connection is established directly to the original destination.
+            let tcp_forward = tcp_balance
+                .check_make_service::<TcpLogical, ()>()
+                .push_make_ready()
+                .push_fallback_with_predicate(
+                    tcp_connect
+                        .clone()
+                        .push_map_target(|t: TcpLogical| TcpEndpoint::from(t.inner.addr))
+                        .push_make_thunk()
+                        .into_inner(),
+                    is_discovery_rejected,
+                )
+                // Obtains the balancer and establishes a connection through it.
+                .push(svc::make_response::Layer)
+                .push_map_target({
+                    let skip_ports = disable_protocol_detection_for_ports.clone();
+                    move |meta: tls::accept::Meta| {
+                        let addr = meta.addrs.target_addr();
+                        TcpLogical::new(addr, skip_ports.contains(&addr.port()))
+                    }
                 })
                 .push(svc::layer::mk(tcp::Forward::new));
 
@@ -171,6 +256,9 @@ impl<A: OrigDstAddr> Config<A> {
                     // This sets headers so that the inbound proxy can downgrade the request
                     // properly.
                     .push(OrigProtoUpgradeLayer::new())
+                    // Rejects hedges that were dispatched to the endpoint that
+                    // is processing the original request.
+                    .push(hedge::avoid_primary_layer())
                     .check_service::<Target<HttpEndpoint>>()
                     .instrument(|endpoint: &Target<HttpEndpoint>| {
                         info_span!("endpoint", peer.addr = %endpoint.inner.addr)
@@ -184,8 +272,16 @@ impl<A: OrigDstAddr> Config<A> {
             // full for `cache_max_idle_age`, then the resolution task fails.
             let discover = {
                 const BUFFER_CAPACITY: usize = 1_000;
-                let resolve = map_endpoint::Resolve::new(endpoint::FromMetadata, resolve.clone());
-                discover::Layer::new(BUFFER_CAPACITY, cache_max_idle_age, resolve)
+                let resolve = map_endpoint::Resolve::new::<Concrete<http::Settings>>(
+                    endpoint::FromMetadata,
+                    // Records each destination's endpoints for the admin server.
+                    debug.record_resolve(resolve.clone()),
+                );
+                discover::Layer::<Concrete<http::Settings>, _>::new(
+                    BUFFER_CAPACITY,
+                    cache_max_idle_age,
+                    resolve,
+                )
             };
 
             // Builds a balancer for each concrete destination.
@@ -198,23 +294,59 @@ impl<A: OrigDstAddr> Config<A> {
                         .box_http_request(),
                 )
                 .push_spawn_ready()
+                // Annotates each endpoint with its weight from service discovery.
+                .push(http::balance::weight::layer())
+                // Annotates each endpoint with whether it shares the proxy's locality.
+                .push(http::balance::locality::layer(&locality))
                 .check_service::<Target<HttpEndpoint>>()
                 .push(discover)
-                .push_on_response(http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY))
+                // Records each endpoint's load for the admin server.
+                .push(debug.record_balance())
+                // Ejects endpoints that fail too many requests.
+                .push(http::balance::outlier::layer::<classify::Response, _>(
+                    outlier_detection,
+                    {
+                        let registry = metrics.stack.clone();
+                        move |c: &Concrete<http::Settings>| {
+                            registry.ejections(stack_labels("balance").with_dst(c.addr.clone()))
+                        }
+                    },
+                ))
+                // Destinations configured with an affinity are balanced over a hash ring so that
+                // related requests are served by the same endpoint. All other destinations are
+                // balanced by load.
+                .push(http::balance::hash::layer(
+                    {
+                        let affinity = Arc::new(affinity);
+                        move |c: &Concrete<http::Settings>| affinity.get(&c.addr).cloned()
+                    },
+                    svc::layers()
+                        // Prefers endpoints in the proxy's locality, spilling over to other
+                        // endpoints when too few local endpoints are available.
+                        .push(http::balance::locality::PreferLocalLayer::new(
+                            locality.min_endpoints,
+                        ))
+                        .push(http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY))
+                        .box_http_response(),
+                ))
                 .into_new_service()
                 .cache(
-                    svc::layers().push_on_response(
-                        svc::layers()
-                            // If the balancer has been ready & unused for `cache_max_idle_age`,
-                            // fail the balancer.
-                            .push_idle_timeout(cache_max_idle_age)
-                            // If the balancer has been empty/unavailable for 10s, eagerly fail
-                            // requests.
-                            .push_failfast(dispatch_timeout)
-                            // Shares the balancer, ensuring discovery errors are propagated.
-                            .push_spawn_buffer(buffer_capacity)
-                            .push(metrics.stack.layer(stack_labels("balance"))),
-                    ),
+                    svc::layers()
+                        // Records how long each cached balancer has been idle for the admin
+                        // server.
+                        .push(debug.record_balancers())
+                        .push_on_response(
+                            svc::layers()
+                                // If the balancer has been ready & unused for `cache_max_idle_age`,
+                                // fail the balancer.
+                                .push_idle_timeout(cache_max_idle_age)
+                                // If the balancer has been empty/unavailable for 10s, eagerly fail
+                                // requests.
+                                .push_failfast(dispatch_timeout)
+                                // Shares the balancer, ensuring discovery errors are propagated.
+                                .push_spawn_buffer(buffer_capacity)
+                                .push(metrics.stack.layer(stack_labels("balance"))),
+                        ),
                 )
                 .instrument(|c: &Concrete<http::Settings>| info_span!("balance", addr = %c.addr))
                 // Ensure that buffers don't hold the cache's lock in poll_ready.
@@ -269,10 +401,34 @@ impl<A: OrigDstAddr> Config<A> {
                 )
                 .check_service::<Concrete<HttpEndpoint>>();
 
+            // Answers requests that are aborted by fault injection. These
+            // requests never reach an endpoint, so they are tapped here.
+            let fault_respond = svc::stack(fault::respond::<http::boxed::Payload>())
+                .push(tap_layer.clone())
+                .push_on_response(svc::layers().box_http_response().push_lock())
+                .into_inner();
+
             let http_profile_route_proxy = svc::proxies()
                 .check_new_clone_service::<dst::Route>()
-                // Sets an optional retry policy.
-                .push(retry::layer(metrics.http_route_retry))
+                // Hedges slow requests on hedgeable routes, based on the
+                // route's latency distribution.
+                .push(hedge::layer(
+                    metrics.http_route.clone(),
+                    metrics.http_route_hedge,
+                ))
+                .check_new_clone_service::<dst::Route>()
+                // Sets an optional retry policy, buffering request bodies so
+                // that they may be replayed.
+                .push(retry::layer(metrics.http_route_retry, retry_buffer_limit))
+                .check_new_clone_service::<dst::Route>()
+                // Responds to requests that are to be aborted by fault
+                // injection, before they are dispatched to a balancer.
+                .push(fault::abort_layer(fault_respond))
+                .check_new_clone_service::<dst::Route>()
+                // Injects the route's configured faults, if any. Faults are
+                // injected outside of retries and hedges, so that each request
+                // is only subject to a single injection decision.
+                .push(fault::layer(metrics.http_route_fault))
                 .check_new_clone_service::<dst::Route>()
                 // Sets an optional request timeout.
                 .push(http::MakeTimeoutLayer::default())
@@ -289,15 +445,26 @@ impl<A: OrigDstAddr> Config<A> {
             // resolutions are shared even as the type of request may vary.
             let http_logical_profile_cache = http_concrete
                 .clone()
-                .push_on_response(svc::layers().box_http_request())
+                .push_on_response(
+                    svc::layers()
+                        // Limits the number of mirrored requests in flight and
+                        // records metrics for requests that are mirrored to
+                        // this concrete destination.
+                        .push(mirror::layer(metrics.http_mirror, mirror_max_in_flight))
+                        .box_http_request(),
+                )
                 .check_service::<Concrete<HttpEndpoint>>()
                 // Provides route configuration. The profile service operates
                 // over `Concret` services. When overrides are in play, the
-                // Concrete destination may be overridden.
-                .push(profiles::Layer::with_overrides(
-                    profiles_client,
-                    http_profile_route_proxy.into_inner(),
-                ))
+                // Concrete destination may be overridden, and requests may be
+                // mirrored to another Concrete destination.
+                .push(
+                    profiles::Layer::with_overrides(
+                        profiles_client,
+                        http_profile_route_proxy.into_inner(),
+                    )
+                    .with_mirror_buffer_limit(mirror_buffer_limit),
+                )
                 .check_make_service::<Profile, Concrete<HttpEndpoint>>()
                 // Use the `Logical` target as a `Concrete` target. It may be
                 // overridden by the profile layer.
@@ -309,18 +476,21 @@ impl<A: OrigDstAddr> Config<A> {
                 )
                 .into_new_service()
                 .cache(
-                    svc::layers().push_on_response(
-                        svc::layers()
-                            // If the service has been ready & unused for `cache_max_idle_age`,
-                            // fail it.
-                            .push_idle_timeout(cache_max_idle_age)
-                            // If the service has been unavailable for an extend time, eagerly
-                            // fail requests.
-                            .push_failfast(dispatch_timeout)
-                            // Shares the service, ensuring discovery errors are propagated.
-                            .push_spawn_buffer(buffer_capacity)
-                            .push(metrics.stack.layer(stack_labels("profile"))),
-                    ),
+                    svc::layers()
+                        // Records each cached profile for the admin server.
+                        .push(debug.record_profiles())
+                        .push_on_response(
+                            svc::layers()
+                                // If the service has been ready & unused for `cache_max_idle_age`,
+                                // fail it.
+                                .push_idle_timeout(cache_max_idle_age)
+                                // If the service has been unavailable for an extend time, eagerly
+                                // fail requests.
+                                .push_failfast(dispatch_timeout)
+                                // Shares the service, ensuring discovery errors are propagated.
+                                .push_spawn_buffer(buffer_capacity)
+                                .push(metrics.stack.layer(stack_labels("profile"))),
+                        ),
                 )
                 .instrument(|_: &Profile| info_span!("profile"))
                 // Ensures that the cache isn't locked when polling readiness.
@@ -456,6 +626,16 @@ impl transport::metrics::TransportLabels<Target<HttpEndpoint>> for TransportLabe
     fn transport_labels(&self, endpoint: &Target<HttpEndpoint>) -> Self::Labels {
         transport::labels::Key::connect("outbound", endpoint.inner.identity.as_ref())
     }
+
+    fn describe(&self, endpoint: &Target<HttpEndpoint>) -> Option<transport::metrics::Description> {
+        Some(transport::metrics::Description {
+            direction: "outbound",
+            peer_addr: endpoint.inner.addr,
+            orig_dst_addr: None,
+            peer_identity: endpoint.inner.identity.clone(),
+            protocol: Some(endpoint.inner.settings.version().as_str()),
+        })
+    }
 }
 
 impl transport::metrics::TransportLabels<TcpEndpoint> for TransportLabels {
@@ -464,6 +644,16 @@ impl transport::metrics::TransportLabels<TcpEndpoint> for TransportLabels {
     fn transport_labels(&self, endpoint: &TcpEndpoint) -> Self::Labels {
         transport::labels::Key::connect("outbound", endpoint.identity.as_ref())
     }
+
+    fn describe(&self, endpoint: &TcpEndpoint) -> Option<transport::metrics::Description> {
+        Some(transport::metrics::Description {
+            direction: "outbound",
+            peer_addr: endpoint.addr,
+            orig_dst_addr: None,
+            peer_identity: endpoint.identity.clone(),
+            protocol: None,
+        })
+    }
 }
 
 impl transport::metrics::TransportLabels<proxy::server::Protocol> for TransportLabels {
@@ -472,6 +662,10 @@ impl transport::metrics::TransportLabels<proxy::server::Protocol> for TransportL
     fn transport_labels(&self, proto: &proxy::server::Protocol) -> Self::Labels {
         transport::labels::Key::accept("outbound", proto.tls.peer_identity.as_ref())
     }
+
+    fn describe(&self, proto: &proxy::server::Protocol) -> Option<transport::metrics::Description> {
+        Some(proto.describe("outbound"))
+    }
 }
 
 pub fn trace_labels() -> HashMap<String, String> {
@@ -1,7 +1,13 @@
 use crate::identity::LocalIdentity;
 use linkerd2_app_core::{
-    admin, config::ServerConfig, drain, metrics::FmtMetrics, serve, trace::LevelHandle,
-    transport::tls, Error,
+    admin,
+    config::ServerConfig,
+    drain,
+    metrics::FmtMetrics,
+    serve,
+    trace::LevelHandle,
+    transport::{self, tls},
+    Error,
 };
 use std::net::SocketAddr;
 use std::time::Duration;
@@ -10,6 +16,7 @@ use std::time::Duration;
 pub struct Config {
     pub server: ServerConfig,
     pub metrics_retain_idle: Duration,
+    pub metrics_histogram_bounds: crate::metrics::HistogramBounds,
 }
 
 pub struct Admin {
@@ -24,6 +31,8 @@ impl Config {
         identity: LocalIdentity,
         report: R,
         log_level: LevelHandle,
+        debug: admin::debug::Registry,
+        connections: transport::metrics::Connections,
         drain: drain::Watch,
     ) -> Result<Admin, Error>
     where
@@ -35,7 +44,8 @@ impl Config {
         let listen_addr = listen.listen_addr();
 
         let (ready, latch) = admin::Readiness::new();
-        let admin = admin::Admin::new(report, ready, log_level);
+        let connections = admin::Connections::new(connections);
+        let admin = admin::Admin::new(report, ready, log_level, debug, connections);
         let accept = tls::AcceptTls::new(identity, admin.into_accept());
         let serve = serve::serve(listen, accept, drain);
         Ok(Admin {
@@ -1,61 +1,148 @@
 mod resolve;
+pub mod snapshot;
 
 use indexmap::IndexSet;
 use linkerd2_app_core::{
     config::{ControlAddr, ControlConfig},
     dns, profiles, Error,
 };
+use linkerd2_snapshot::Writer;
+use std::path::PathBuf;
 use std::time::Duration;
+use tower::util::Either;
 use tower_grpc::{generic::client::GrpcService, Body, BoxBody};
 
 #[derive(Clone, Debug)]
 pub struct Config {
-    pub control: ControlConfig,
+    /// The destination service, if endpoints or profiles are discovered via
+    /// the control plane.
+    pub control: Option<ControlConfig>,
     pub context: String,
     pub get_suffixes: IndexSet<dns::Suffix>,
     pub get_networks: IndexSet<ipnet::IpNet>,
     pub profile_suffixes: IndexSet<dns::Suffix>,
     pub initial_profile_timeout: Duration,
+    /// If set, profiles are read from files in this directory instead of
+    /// being discovered via the destination service.
+    pub profile_dir: Option<PathBuf>,
+    pub profile_dir_interval: Duration,
+    /// If set, endpoints are resolved from this file instead of via the
+    /// destination service.
+    pub endpoints_file: Option<PathBuf>,
+    pub endpoints_file_interval: Duration,
+    /// If set, the last known endpoints and profiles discovered via the
+    /// destination service are persisted in this directory.
+    pub snapshot_dir: Option<PathBuf>,
+    /// How long endpoints are resolved before they are served from a
+    /// snapshot.
+    pub endpoints_snapshot_timeout: Duration,
 }
 
 /// Handles to destination service clients.
 ///
 /// The addr is preserved for logging.
 pub struct Dst<S> {
-    pub addr: ControlAddr,
-    pub profiles: profiles::Client<S, resolve::BackoffUnlessInvalidArgument>,
+    pub addr: Option<ControlAddr>,
+    pub profiles:
+        Either<profiles::Client<S, resolve::BackoffUnlessInvalidArgument>, profiles::file::Client>,
     pub resolve: resolve::Resolve<S>,
 }
 
+/// Indicates that the destination service is required but not configured.
+#[derive(Debug)]
+pub struct NoDestinationService(());
+
+/// Snapshots are written at most once per interval, however often a
+/// destination changes.
+const SNAPSHOT_DEBOUNCE: Duration = Duration::from_secs(1);
+
 impl Config {
     // XXX This is unfortunate -- the service should be built here, but it's annoying to name.
-    pub fn build<S>(self, svc: S) -> Result<Dst<S>, Error>
+    // It is only set when the destination service is configured.
+    pub fn build<S>(self, svc: Option<S>, snapshot: snapshot::Metrics) -> Result<Dst<S>, Error>
     where
         S: GrpcService<BoxBody> + Clone + Send + 'static,
         S::ResponseBody: Send,
         <S::ResponseBody as Body>::Data: Send,
         S::Future: Send,
     {
-        let resolve = resolve::new(
-            svc.clone(),
-            self.get_suffixes,
-            self.get_networks,
-            &self.context,
-            self.control.connect.backoff,
-        );
-
-        let profiles = profiles::Client::new(
-            svc,
-            resolve::BackoffUnlessInvalidArgument::from(self.control.connect.backoff),
-            self.initial_profile_timeout,
-            self.context,
-            self.profile_suffixes,
-        );
+        let addr = self.control.as_ref().map(|c| c.addr.clone());
+        let control = match (svc, self.control) {
+            (Some(svc), Some(control)) => Some((svc, control.connect.backoff)),
+            _ => None,
+        };
+
+        let writer = match self.snapshot_dir {
+            Some(_) => Some(Writer::spawn(SNAPSHOT_DEBOUNCE)?),
+            None => None,
+        };
+
+        let resolve = match self.endpoints_file {
+            Some(path) => resolve::from_file(
+                path,
+                self.endpoints_file_interval,
+                self.get_suffixes,
+                self.get_networks,
+            )?,
+            None => {
+                let (svc, backoff) = control.clone().ok_or(NoDestinationService(()))?;
+                let timeout = self.endpoints_snapshot_timeout;
+                let snapshot = self.snapshot_dir.as_ref().and_then(|dir| {
+                    let writer = writer.clone()?;
+                    Some((
+                        dir.join("endpoints"),
+                        timeout,
+                        snapshot.endpoints.clone(),
+                        writer,
+                    ))
+                });
+                resolve::new(
+                    svc,
+                    self.get_suffixes,
+                    self.get_networks,
+                    &self.context,
+                    backoff,
+                    snapshot,
+                )
+            }
+        };
+
+        let profiles = match self.profile_dir {
+            Some(dir) => Either::B(profiles::file::Client::spawn(
+                dir,
+                self.profile_dir_interval,
+            )?),
+            None => {
+                let (svc, backoff) = control.ok_or(NoDestinationService(()))?;
+                let mut profiles = profiles::Client::new(
+                    svc,
+                    resolve::BackoffUnlessInvalidArgument::from(backoff),
+                    self.initial_profile_timeout,
+                    self.context,
+                    self.profile_suffixes,
+                );
+                if let (Some(dir), Some(writer)) = (self.snapshot_dir, writer) {
+                    profiles =
+                        profiles.with_snapshot(dir.join("profiles"), snapshot.profiles, writer);
+                }
+                Either::A(profiles)
+            }
+        };
 
         Ok(Dst {
-            addr: self.control.addr,
+            addr,
             resolve,
             profiles,
         })
     }
 }
+
+// === impl NoDestinationService ===
+
+impl std::fmt::Display for NoDestinationService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no destination service configured")
+    }
+}
+
+impl std::error::Error for NoDestinationService {}
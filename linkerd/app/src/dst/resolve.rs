@@ -1,19 +1,25 @@
+use futures::{future, Future, Poll};
 use ipnet::{Contains, IpNet};
 use linkerd2_app_core::{
     dns::Suffix,
     exp_backoff::{ExponentialBackoff, ExponentialBackoffStream},
-    proxy::{api_resolve as api, resolve::recover},
+    metrics::Gauge,
+    proxy::{
+        api_resolve as api,
+        core::resolve::{self, Update},
+        resolve::recover,
+    },
     request_filter, Addr, DiscoveryRejected, Error, Recover,
 };
 use linkerd2_app_outbound::Target;
+use linkerd2_snapshot::Writer;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tower_grpc::{generic::client::GrpcService, Body, BoxBody, Code, Status};
 
-pub type Resolve<S> = request_filter::Service<
-    PermitConfiguredDsts,
-    recover::Resolve<BackoffUnlessInvalidArgument, api::Resolve<S>>,
->;
+pub type Resolve<S> = request_filter::Service<PermitConfiguredDsts, Source<S>>;
 
 pub fn new<S>(
     service: S,
@@ -21,6 +27,7 @@ pub fn new<S>(
     nets: impl IntoIterator<Item = IpNet>,
     token: &str,
     backoff: ExponentialBackoff,
+    snapshot: Option<(PathBuf, Duration, Arc<Gauge>, Writer)>,
 ) -> Resolve<S>
 where
     S: GrpcService<BoxBody> + Clone + Send + 'static,
@@ -28,15 +35,67 @@ where
     <S::ResponseBody as Body>::Data: Send,
     S::Future: Send,
 {
+    let mut resolve = api::snapshot::Resolve::new(recover::Resolve::new(
+        backoff.into(),
+        api::Resolve::new(service).with_context_token(token),
+    ));
+    if let Some((dir, timeout, stale, writer)) = snapshot {
+        resolve = resolve.with_snapshot(dir, timeout, stale, writer);
+    }
     request_filter::Service::new(
         PermitConfiguredDsts::new(suffixes, nets),
-        recover::Resolve::new(
-            backoff.into(),
-            api::Resolve::new(service).with_context_token(token),
-        ),
+        Source::Control(resolve),
     )
 }
 
+/// Resolves endpoints from a local file instead of the destination service.
+pub fn from_file<S>(
+    path: PathBuf,
+    interval: Duration,
+    suffixes: impl IntoIterator<Item = Suffix>,
+    nets: impl IntoIterator<Item = IpNet>,
+) -> std::io::Result<Resolve<S>> {
+    let resolve = api::file::Resolve::spawn(path, interval)?;
+    Ok(request_filter::Service::new(
+        PermitConfiguredDsts::new(suffixes, nets),
+        Source::File(resolve),
+    ))
+}
+
+/// Resolves endpoints via the destination service or from a local file.
+#[derive(Clone)]
+pub enum Source<S> {
+    Control(
+        api::snapshot::Resolve<recover::Resolve<BackoffUnlessInvalidArgument, api::Resolve<S>>>,
+    ),
+    File(api::file::Resolve),
+}
+
+pub enum SourceFuture<T, S>
+where
+    api::Resolve<S>: resolve::Resolve<T>,
+{
+    Control(
+        api::snapshot::ResolveFuture<
+            recover::ResolveFuture<T, BackoffUnlessInvalidArgument, api::Resolve<S>>,
+        >,
+    ),
+    File(future::FutureResult<api::file::Resolution, Error>),
+}
+
+pub enum Resolution<T, S>
+where
+    api::Resolve<S>: resolve::Resolve<T>,
+{
+    Control(
+        api::snapshot::Resolution<
+            recover::ResolveFuture<T, BackoffUnlessInvalidArgument, api::Resolve<S>>,
+            recover::Resolution<T, BackoffUnlessInvalidArgument, api::Resolve<S>>,
+        >,
+    ),
+    File(api::file::Resolution),
+}
+
 #[derive(Clone, Debug)]
 pub struct PermitConfiguredDsts {
     name_suffixes: Arc<Vec<Suffix>>,
@@ -46,6 +105,67 @@ pub struct PermitConfiguredDsts {
 #[derive(Clone, Debug, Default)]
 pub struct BackoffUnlessInvalidArgument(ExponentialBackoff);
 
+// === impl Source ===
+
+impl<T, S> tower::Service<T> for Source<S>
+where
+    T: ToString + Clone,
+    S: GrpcService<BoxBody> + Clone + Send + 'static,
+    S::ResponseBody: Send,
+    <S::ResponseBody as Body>::Data: Send,
+    S::Future: Send,
+{
+    type Response = Resolution<T, S>;
+    type Error = Error;
+    type Future = SourceFuture<T, S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self {
+            Source::Control(ref mut r) => tower::Service::<T>::poll_ready(r),
+            Source::File(ref mut r) => tower::Service::<T>::poll_ready(r),
+        }
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        match self {
+            Source::Control(ref mut r) => SourceFuture::Control(r.call(target)),
+            Source::File(ref mut r) => SourceFuture::File(r.call(target)),
+        }
+    }
+}
+
+impl<T, S> Future for SourceFuture<T, S>
+where
+    T: Clone,
+    api::Resolve<S>: resolve::Resolve<T, Endpoint = api::Metadata>,
+{
+    type Item = Resolution<T, S>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            SourceFuture::Control(ref mut f) => f.poll().map(|a| a.map(Resolution::Control)),
+            SourceFuture::File(ref mut f) => f.poll().map(|a| a.map(Resolution::File)),
+        }
+    }
+}
+
+impl<T, S> resolve::Resolution for Resolution<T, S>
+where
+    T: Clone,
+    api::Resolve<S>: resolve::Resolve<T, Endpoint = api::Metadata>,
+{
+    type Endpoint = api::Metadata;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        match self {
+            Resolution::Control(ref mut r) => r.poll(),
+            Resolution::File(ref mut r) => r.poll(),
+        }
+    }
+}
+
 // === impl PermitConfiguredDsts ===
 
 impl PermitConfiguredDsts {
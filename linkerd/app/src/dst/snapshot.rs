@@ -0,0 +1,39 @@
+use linkerd2_metrics::{metrics, FmtMetrics, Gauge};
+use std::fmt;
+use std::sync::Arc;
+
+metrics! {
+    destination_endpoints_stale: Gauge {
+        "Number of destinations whose endpoints are served from a stale snapshot"
+    },
+    destination_profiles_stale: Gauge {
+        "Number of destinations whose profiles are served from a stale snapshot"
+    }
+}
+
+/// Counts the destinations that are served from snapshots.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub endpoints: Arc<Gauge>,
+    pub profiles: Arc<Gauge>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Report(Metrics);
+
+pub fn new() -> (Metrics, Report) {
+    let metrics = Metrics::default();
+    (metrics.clone(), Report(metrics))
+}
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        destination_endpoints_stale.fmt_help(f)?;
+        destination_endpoints_stale.fmt_metric(f, &self.0.endpoints)?;
+
+        destination_profiles_stale.fmt_help(f)?;
+        destination_profiles_stale.fmt_metric(f, &self.0.profiles)?;
+
+        Ok(())
+    }
+}
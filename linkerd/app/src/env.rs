@@ -1,12 +1,16 @@
 use crate::core::{
     addr,
     config::*,
-    proxy::http::h2,
+    metrics,
+    proxy::http::{
+        balance::{locality, outlier},
+        h2, header,
+    },
     transport::{listen, tls},
     Addr,
 };
 use crate::{dns, identity, inbound, oc_collector, outbound};
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::net::SocketAddr;
@@ -37,14 +41,19 @@ pub enum EnvError {
 #[derive(Debug, Eq, PartialEq)]
 pub enum ParseError {
     NotADuration,
+    ZeroDuration,
     NotADomainSuffix,
     NotANumber,
     NotANetwork,
+    NotALabel,
+    NotAnAffinity,
+    NotARatio,
     HostIsNotAnIpAddress,
     AddrError(addr::Error),
     NameError,
     InvalidTokenSource,
     InvalidTrustAnchors,
+    NotHistogramBounds(metrics::InvalidBounds),
 }
 
 // Environment variables to look at when loading the configuration
@@ -53,6 +62,38 @@ pub const ENV_INBOUND_LISTEN_ADDR: &str = "LINKERD2_PROXY_INBOUND_LISTEN_ADDR";
 pub const ENV_CONTROL_LISTEN_ADDR: &str = "LINKERD2_PROXY_CONTROL_LISTEN_ADDR";
 pub const ENV_ADMIN_LISTEN_ADDR: &str = "LINKERD2_PROXY_ADMIN_LISTEN_ADDR";
 pub const ENV_METRICS_RETAIN_IDLE: &str = "LINKERD2_PROXY_METRICS_RETAIN_IDLE";
+
+/// Comma-separated, increasing upper bounds (in milliseconds) for the
+/// `response_latency_ms` histogram buckets. An `+Inf` bucket is always added.
+pub const ENV_METRICS_RESPONSE_LATENCY_BUCKETS: &str =
+    "LINKERD2_PROXY_METRICS_RESPONSE_LATENCY_BUCKETS";
+
+/// Comma-separated, increasing upper bounds (in microseconds) for the
+/// `request_handle_us` histogram buckets.
+pub const ENV_METRICS_REQUEST_HANDLE_BUCKETS: &str =
+    "LINKERD2_PROXY_METRICS_REQUEST_HANDLE_BUCKETS";
+
+/// Comma-separated, increasing upper bounds (in milliseconds) for the
+/// `tcp_connection_duration_ms` histogram buckets.
+pub const ENV_METRICS_TCP_CONNECTION_DURATION_BUCKETS: &str =
+    "LINKERD2_PROXY_METRICS_TCP_CONNECTION_DURATION_BUCKETS";
+
+/// Pushes metrics to a StatsD server at the given `ip:port`.
+pub const ENV_METRICS_EXPORT_STATSD_ADDR: &str = "LINKERD2_PROXY_METRICS_EXPORT_STATSD_ADDR";
+
+/// Pushes metrics to an OpenTelemetry (OTLP) metrics collector, configured
+/// by the `_ADDR` and `_NAME` variables with this prefix.
+pub const ENV_METRICS_EXPORT_OTLP_SVC_BASE: &str = "LINKERD2_PROXY_METRICS_EXPORT_OTLP_SVC";
+
+/// How often metrics are pushed, when an exporter is configured.
+pub const ENV_METRICS_EXPORT_INTERVAL: &str = "LINKERD2_PROXY_METRICS_EXPORT_INTERVAL";
+
+/// A prefix for the names of pushed metrics, e.g. `linkerd.`.
+pub const ENV_METRICS_EXPORT_PREFIX: &str = "LINKERD2_PROXY_METRICS_EXPORT_PREFIX";
+
+/// Comma-separated `key=value` labels that are added to every pushed series.
+pub const ENV_METRICS_EXPORT_LABELS: &str = "LINKERD2_PROXY_METRICS_EXPORT_LABELS";
+
 const ENV_INBOUND_DISPATCH_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_DISPATCH_TIMEOUT";
 const ENV_OUTBOUND_DISPATCH_TIMEOUT: &str = "LINKERD2_PROXY_OUTBOUND_DISPATCH_TIMEOUT";
 const ENV_INBOUND_CONNECT_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_CONNECT_TIMEOUT";
@@ -71,6 +112,52 @@ pub const ENV_OUTBOUND_ROUTER_MAX_IDLE_AGE: &str = "LINKERD2_PROXY_OUTBOUND_ROUT
 pub const ENV_INBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT";
 pub const ENV_OUTBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MAX_IN_FLIGHT";
 
+/// The number of consecutive failed responses after which an endpoint is
+/// ejected from its balancer. A value of 0, the default, disables this check.
+pub const ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES";
+
+/// The ratio of failed responses above which an endpoint is ejected from its
+/// balancer. A value of 1.0 or greater disables this check; this is the default.
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE: &str = "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE";
+
+/// The number of responses over which an endpoint's failure rate is computed.
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW";
+
+/// Describes the proxy's locality so that endpoints in the same locality are
+/// preferred by outbound balancers.
+///
+/// The value is a comma-separated list of `key=value` labels, e.g.
+/// `zone=us-east-1a`. Endpoints are local if their destination labels include
+/// all of these labels. If unset, all endpoints are treated equally.
+pub const ENV_OUTBOUND_LOCALITY: &str = "LINKERD2_PROXY_OUTBOUND_LOCALITY";
+
+/// The number of available local endpoints below which outbound balancers
+/// spill over to endpoints in other localities.
+pub const ENV_OUTBOUND_LOCALITY_MIN_ENDPOINTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_LOCALITY_MIN_ENDPOINTS";
+
+/// Configures destinations whose requests are balanced by an affinity key
+/// rather than by load, so that related requests reach the same endpoint.
+///
+/// The value is a comma-separated list of `addr=affinity` entries, where
+/// `affinity` is one of `header:<name>`, `cookie:<name>`, or
+/// `source-identity`, e.g. `web.ns.svc.cluster.local:80=cookie:session`.
+pub const ENV_OUTBOUND_AFFINITY: &str = "LINKERD2_PROXY_OUTBOUND_AFFINITY";
+
+/// The maximum number of bytes of each request body that are buffered so that
+/// the request may be retried. Requests with larger bodies are not retried.
+pub const ENV_OUTBOUND_RETRY_BUFFER_LIMIT: &str = "LINKERD2_PROXY_OUTBOUND_RETRY_BUFFER_LIMIT";
+
+/// The maximum number of bytes of each request body that are buffered so that
+/// the request may be mirrored. Requests with larger bodies are not mirrored.
+pub const ENV_OUTBOUND_MIRROR_BUFFER_LIMIT: &str = "LINKERD2_PROXY_OUTBOUND_MIRROR_BUFFER_LIMIT";
+
+/// The maximum number of mirrored requests that may be in flight at once.
+/// Requests that would exceed this limit are not mirrored.
+pub const ENV_OUTBOUND_MIRROR_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MIRROR_MAX_IN_FLIGHT";
+
 /// Constrains which destination names are resolved through the destination
 /// service.
 ///
@@ -132,6 +219,45 @@ pub const ENV_DESTINATION_CONTEXT: &str = "LINKERD2_PROXY_DESTINATION_CONTEXT";
 pub const ENV_DESTINATION_PROFILE_INITIAL_TIMEOUT: &str =
     "LINKERD2_PROXY_DESTINATION_PROFILE_INITIAL_TIMEOUT";
 
+/// Configures a directory from which service profiles are read, instead of
+/// being discovered via the destination service.
+///
+/// Each destination's profile is read from a YAML or JSON file named for the
+/// destination, e.g. `web.default.svc.cluster.local.yaml`.
+pub const ENV_DESTINATION_PROFILE_DIR: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_DIR";
+
+/// Configures how often profile files are checked for changes.
+pub const ENV_DESTINATION_PROFILE_DIR_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_DIR_INTERVAL";
+
+/// Configures a file from which endpoints are resolved, instead of via the
+/// destination service.
+///
+/// The file is a YAML or JSON map of destination addresses, e.g.
+/// `web.default.svc.cluster.local:8080`, to lists of endpoints. If
+/// `LINKERD2_PROXY_DESTINATION_PROFILE_DIR` is also set,
+/// `LINKERD2_PROXY_DESTINATION_SVC_ADDR` need not be.
+pub const ENV_DESTINATION_ENDPOINTS_FILE: &str = "LINKERD2_PROXY_DESTINATION_ENDPOINTS_FILE";
+
+/// Configures how often the endpoints file is checked for changes.
+pub const ENV_DESTINATION_ENDPOINTS_FILE_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_ENDPOINTS_FILE_INTERVAL";
+
+/// Configures a directory in which the last known endpoints and profile of
+/// each destination are persisted.
+///
+/// If a destination's profile is not discovered within
+/// `LINKERD2_PROXY_DESTINATION_PROFILE_INITIAL_TIMEOUT`, or its endpoints
+/// within `LINKERD2_PROXY_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT` (e.g.
+/// because the destination service cannot be reached at startup), its last
+/// known profile or endpoints are used until they are discovered.
+pub const ENV_DESTINATION_SNAPSHOT_DIR: &str = "LINKERD2_PROXY_DESTINATION_SNAPSHOT_DIR";
+
+/// Configures how long a destination's endpoints are resolved via the
+/// destination service before its last known endpoints are used.
+pub const ENV_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT: &str =
+    "LINKERD2_PROXY_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT";
+
 pub const ENV_TAP_DISABLED: &str = "LINKERD2_PROXY_TAP_DISABLED";
 pub const ENV_TAP_SVC_NAME: &str = "LINKERD2_PROXY_TAP_SVC_NAME";
 const ENV_RESOLV_CONF: &str = "LINKERD2_PROXY_RESOLV_CONF";
@@ -162,6 +288,7 @@ pub const DEFAULT_INBOUND_LISTEN_ADDR: &str = "0.0.0.0:4143";
 pub const DEFAULT_CONTROL_LISTEN_ADDR: &str = "0.0.0.0:4190";
 const DEFAULT_ADMIN_LISTEN_ADDR: &str = "127.0.0.1:4191";
 const DEFAULT_METRICS_RETAIN_IDLE: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_METRICS_EXPORT_INTERVAL: Duration = Duration::from_secs(10);
 const DEFAULT_INBOUND_DISPATCH_TIMEOUT: Duration = Duration::from_secs(1);
 const DEFAULT_INBOUND_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
 const DEFAULT_INBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
@@ -176,6 +303,18 @@ const DEFAULT_OUTBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff
     max: Duration::from_millis(500),
     jitter: 0.1,
 };
+const DEFAULT_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES: u32 = 0;
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE: f64 = 1.0;
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW: u32 = 100;
+const DEFAULT_OUTBOUND_OUTLIER_EJECTION_BACKOFF: ExponentialBackoff = ExponentialBackoff {
+    min: Duration::from_secs(10),
+    max: Duration::from_secs(5 * 60),
+    jitter: 0.1,
+};
+const DEFAULT_OUTBOUND_LOCALITY_MIN_ENDPOINTS: usize = 1;
+const DEFAULT_OUTBOUND_RETRY_BUFFER_LIMIT: usize = 64 * 1024;
+const DEFAULT_OUTBOUND_MIRROR_BUFFER_LIMIT: usize = 64 * 1024;
+const DEFAULT_OUTBOUND_MIRROR_MAX_IN_FLIGHT: usize = 100;
 const DEFAULT_DNS_CANONICALIZE_TIMEOUT: Duration = Duration::from_millis(100);
 const DEFAULT_RESOLV_CONF: &str = "/etc/resolv.conf";
 
@@ -197,6 +336,9 @@ const DEFAULT_OUTBOUND_MAX_IN_FLIGHT: usize = 10_000;
 const DEFAULT_DESTINATION_GET_SUFFIXES: &str = "svc.cluster.local.";
 const DEFAULT_DESTINATION_PROFILE_SUFFIXES: &str = "svc.cluster.local.";
 const DEFAULT_DESTINATION_PROFILE_INITIAL_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_DESTINATION_PROFILE_DIR_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_DESTINATION_ENDPOINTS_FILE_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(1);
 
 const DEFAULT_IDENTITY_MIN_REFRESH: Duration = Duration::from_secs(10);
 const DEFAULT_IDENTITY_MAX_REFRESH: Duration = Duration::from_secs(60 * 60 * 24);
@@ -212,6 +354,7 @@ const DEFAULT_PORTS_DISABLE_PROTOCOL_DETECTION: &[u16] = &[
 
 const INBOUND_CONNECT_BASE: &str = "INBOUND_CONNECT";
 const OUTBOUND_CONNECT_BASE: &str = "OUTBOUND_CONNECT";
+const OUTBOUND_OUTLIER_EJECTION_BASE: &str = "OUTBOUND_OUTLIER_EJECTION";
 
 /// Load a `App` by reading ENV variables.
 pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError> {
@@ -254,7 +397,45 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let inbound_max_in_flight = parse(strings, ENV_INBOUND_MAX_IN_FLIGHT, parse_number);
     let outbound_max_in_flight = parse(strings, ENV_OUTBOUND_MAX_IN_FLIGHT, parse_number);
 
+    let outbound_outlier_consecutive_failures = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES,
+        parse_number,
+    );
+    let outbound_outlier_failure_rate =
+        parse(strings, ENV_OUTBOUND_OUTLIER_FAILURE_RATE, parse_ratio);
+    let outbound_outlier_failure_rate_window = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW,
+        parse_number,
+    );
+
+    let outbound_locality = parse(strings, ENV_OUTBOUND_LOCALITY, parse_labels);
+    let outbound_locality_min_endpoints =
+        parse(strings, ENV_OUTBOUND_LOCALITY_MIN_ENDPOINTS, parse_number);
+    let outbound_affinity = parse(strings, ENV_OUTBOUND_AFFINITY, parse_affinities);
+    let outbound_retry_buffer_limit = parse(strings, ENV_OUTBOUND_RETRY_BUFFER_LIMIT, parse_number);
+    let outbound_mirror_buffer_limit =
+        parse(strings, ENV_OUTBOUND_MIRROR_BUFFER_LIMIT, parse_number);
+    let outbound_mirror_max_in_flight =
+        parse(strings, ENV_OUTBOUND_MIRROR_MAX_IN_FLIGHT, parse_number);
+
     let metrics_retain_idle = parse(strings, ENV_METRICS_RETAIN_IDLE, parse_duration);
+    let metrics_response_latency_buckets = parse(
+        strings,
+        ENV_METRICS_RESPONSE_LATENCY_BUCKETS,
+        parse_histogram_bounds,
+    );
+    let metrics_request_handle_buckets = parse(
+        strings,
+        ENV_METRICS_REQUEST_HANDLE_BUCKETS,
+        parse_histogram_bounds,
+    );
+    let metrics_tcp_connection_duration_buckets = parse(
+        strings,
+        ENV_METRICS_TCP_CONNECTION_DURATION_BUCKETS,
+        parse_histogram_bounds,
+    );
 
     // DNS
 
@@ -286,6 +467,18 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         parse_control_addr(strings, ENV_TRACE_COLLECTOR_SVC_BASE)
     };
 
+    let metrics_export_otlp_addr = if id_disabled {
+        parse_control_addr_disable_identity(strings, ENV_METRICS_EXPORT_OTLP_SVC_BASE)
+    } else {
+        parse_control_addr(strings, ENV_METRICS_EXPORT_OTLP_SVC_BASE)
+    };
+    let metrics_export_statsd_addr =
+        parse(strings, ENV_METRICS_EXPORT_STATSD_ADDR, parse_socket_addr);
+    let metrics_export_interval =
+        parse(strings, ENV_METRICS_EXPORT_INTERVAL, parse_nonzero_duration);
+    let metrics_export_prefix = strings.get(ENV_METRICS_EXPORT_PREFIX);
+    let metrics_export_labels = parse(strings, ENV_METRICS_EXPORT_LABELS, parse_labels);
+
     let dst_token = strings.get(ENV_DESTINATION_CONTEXT);
 
     let dst_get_suffixes = parse(strings, ENV_DESTINATION_GET_SUFFIXES, parse_dns_suffixes);
@@ -295,6 +488,30 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         ENV_DESTINATION_PROFILE_INITIAL_TIMEOUT,
         parse_duration,
     );
+    let dst_endpoints_file = parse(strings, ENV_DESTINATION_ENDPOINTS_FILE, |s| {
+        Ok(PathBuf::from(s))
+    });
+    let dst_endpoints_file_interval = parse(
+        strings,
+        ENV_DESTINATION_ENDPOINTS_FILE_INTERVAL,
+        parse_duration,
+    );
+    let dst_profile_dir = parse(strings, ENV_DESTINATION_PROFILE_DIR, |s| {
+        Ok(PathBuf::from(s))
+    });
+    let dst_profile_dir_interval = parse(
+        strings,
+        ENV_DESTINATION_PROFILE_DIR_INTERVAL,
+        parse_duration,
+    );
+    let dst_snapshot_dir = parse(strings, ENV_DESTINATION_SNAPSHOT_DIR, |s| {
+        Ok(PathBuf::from(s))
+    });
+    let dst_endpoints_snapshot_timeout = parse(
+        strings,
+        ENV_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT,
+        parse_duration,
+    );
     let dst_profile_suffixes = parse(
         strings,
         ENV_DESTINATION_PROFILE_SUFFIXES,
@@ -338,9 +555,36 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             )?,
             h2_settings,
         };
+        let outlier_detection = outlier::Config {
+            consecutive_failures: outbound_outlier_consecutive_failures?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES),
+            failure_rate: outbound_outlier_failure_rate?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE),
+            failure_rate_window: outbound_outlier_failure_rate_window?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW),
+            backoff: parse_backoff(
+                strings,
+                OUTBOUND_OUTLIER_EJECTION_BASE,
+                DEFAULT_OUTBOUND_OUTLIER_EJECTION_BACKOFF,
+            )?,
+        };
+        let locality = locality::Config {
+            labels: outbound_locality?.unwrap_or_default(),
+            min_endpoints: outbound_locality_min_endpoints?
+                .unwrap_or(DEFAULT_OUTBOUND_LOCALITY_MIN_ENDPOINTS),
+        };
         outbound::Config {
             canonicalize_timeout: dns_canonicalize_timeout?
                 .unwrap_or(DEFAULT_DNS_CANONICALIZE_TIMEOUT),
+            outlier_detection,
+            locality,
+            affinity: outbound_affinity?.unwrap_or_default(),
+            retry_buffer_limit: outbound_retry_buffer_limit?
+                .unwrap_or(DEFAULT_OUTBOUND_RETRY_BUFFER_LIMIT),
+            mirror_buffer_limit: outbound_mirror_buffer_limit?
+                .unwrap_or(DEFAULT_OUTBOUND_MIRROR_BUFFER_LIMIT),
+            mirror_max_in_flight: outbound_mirror_max_in_flight?
+                .unwrap_or(DEFAULT_OUTBOUND_MIRROR_MAX_IN_FLIGHT),
             proxy: ProxyConfig {
                 server,
                 connect,
@@ -397,11 +641,25 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     };
 
     let dst = {
-        let addr = dst_addr?.ok_or(EnvError::NoDestinationAddress)?;
-        let connect = if addr.addr.is_loopback() {
-            inbound.proxy.connect.clone()
-        } else {
-            outbound.proxy.connect.clone()
+        let endpoints_file = dst_endpoints_file?;
+        let profile_dir = dst_profile_dir?;
+        // The destination service is only optional if both endpoints and
+        // profiles are read from local files.
+        let control = match dst_addr? {
+            Some(addr) => {
+                let connect = if addr.addr.is_loopback() {
+                    inbound.proxy.connect.clone()
+                } else {
+                    outbound.proxy.connect.clone()
+                };
+                Some(ControlConfig {
+                    addr,
+                    connect,
+                    buffer_capacity,
+                })
+            }
+            None if endpoints_file.is_some() && profile_dir.is_some() => None,
+            None => return Err(EnvError::NoDestinationAddress),
         };
         super::dst::Config {
             context: dst_token?.unwrap_or_default(),
@@ -412,16 +670,31 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 .unwrap_or(parse_dns_suffixes(DEFAULT_DESTINATION_PROFILE_SUFFIXES).unwrap()),
             initial_profile_timeout: dst_profile_initial_timeout?
                 .unwrap_or(DEFAULT_DESTINATION_PROFILE_INITIAL_TIMEOUT),
-            control: ControlConfig {
-                addr,
-                connect,
-                buffer_capacity,
-            },
+            profile_dir,
+            profile_dir_interval: dst_profile_dir_interval?
+                .unwrap_or(DEFAULT_DESTINATION_PROFILE_DIR_INTERVAL),
+            endpoints_file,
+            endpoints_file_interval: dst_endpoints_file_interval?
+                .unwrap_or(DEFAULT_DESTINATION_ENDPOINTS_FILE_INTERVAL),
+            snapshot_dir: dst_snapshot_dir?,
+            endpoints_snapshot_timeout: dst_endpoints_snapshot_timeout?
+                .unwrap_or(DEFAULT_DESTINATION_ENDPOINTS_SNAPSHOT_TIMEOUT),
+            control,
         }
     };
 
     let admin = super::admin::Config {
         metrics_retain_idle: metrics_retain_idle?.unwrap_or(DEFAULT_METRICS_RETAIN_IDLE),
+        metrics_histogram_bounds: {
+            let default = crate::metrics::HistogramBounds::default();
+            crate::metrics::HistogramBounds {
+                response_latency: metrics_response_latency_buckets?
+                    .unwrap_or(default.response_latency),
+                request_handle: metrics_request_handle_buckets?.unwrap_or(default.request_handle),
+                tcp_connection_duration: metrics_tcp_connection_duration_buckets?
+                    .unwrap_or(default.tcp_connection_duration),
+            }
+        },
         server: ServerConfig {
             bind: listen::Bind::new(
                 admin_listener_addr?
@@ -459,6 +732,43 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         }
     };
 
+    let metrics_export = {
+        let export = super::metrics_export::ExportConfig {
+            interval: metrics_export_interval?.unwrap_or(DEFAULT_METRICS_EXPORT_INTERVAL),
+            prefix: metrics_export_prefix?.unwrap_or_default(),
+            labels: metrics_export_labels?
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        };
+        match (metrics_export_statsd_addr?, metrics_export_otlp_addr?) {
+            (None, None) => super::metrics_export::Config::Disabled,
+            (Some(addr), None) => super::metrics_export::Config::StatsD { addr, export },
+            (None, Some(addr)) => {
+                let connect = if addr.addr.is_loopback() {
+                    inbound.proxy.connect.clone()
+                } else {
+                    outbound.proxy.connect.clone()
+                };
+                super::metrics_export::Config::Otlp {
+                    control: ControlConfig {
+                        addr,
+                        connect,
+                        buffer_capacity: 10,
+                    },
+                    export,
+                }
+            }
+            (Some(_), Some(_)) => {
+                error!(
+                    "{} and {}_ADDR may not both be set",
+                    ENV_METRICS_EXPORT_STATSD_ADDR, ENV_METRICS_EXPORT_OTLP_SVC_BASE
+                );
+                return Err(EnvError::InvalidEnvVar);
+            }
+        }
+    };
+
     let tap = tap?
         .map(|(addr, ids)| super::tap::Config::Enabled {
             permitted_peer_identities: ids,
@@ -494,6 +804,7 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         dst,
         tap,
         oc_collector,
+        metrics_export,
         identity,
         outbound,
         inbound,
@@ -584,23 +895,34 @@ where
     s.parse().map_err(|_| ParseError::NotANumber)
 }
 
-fn parse_duration(s: &str) -> Result<Duration, ParseError> {
-    use regex::Regex;
-
-    let re = Regex::new(r"^\s*(\d+)(ms|s|m|h|d)?\s*$").expect("duration regex");
+fn parse_ratio(s: &str) -> Result<f64, ParseError> {
+    let r = parse_number::<f64>(s)?;
+    if r >= 0.0 && r <= 1.0 {
+        Ok(r)
+    } else {
+        Err(ParseError::NotARatio)
+    }
+}
 
-    let cap = re.captures(s).ok_or(ParseError::NotADuration)?;
+fn parse_duration(s: &str) -> Result<Duration, ParseError> {
+    linkerd2_duration::parse(s).map_err(|e| match e {
+        linkerd2_duration::ParseError::NotANumber => ParseError::NotANumber,
+        linkerd2_duration::ParseError::NotADuration => ParseError::NotADuration,
+    })
+}
 
-    let magnitude = parse_number(&cap[1])?;
-    match cap.get(2).map(|m| m.as_str()) {
-        None if magnitude == 0 => Ok(Duration::from_secs(0)),
-        Some("ms") => Ok(Duration::from_millis(magnitude)),
-        Some("s") => Ok(Duration::from_secs(magnitude)),
-        Some("m") => Ok(Duration::from_secs(magnitude * 60)),
-        Some("h") => Ok(Duration::from_secs(magnitude * 60 * 60)),
-        Some("d") => Ok(Duration::from_secs(magnitude * 60 * 60 * 24)),
-        _ => Err(ParseError::NotADuration),
+/// Parses a duration that must not be zero, e.g. because it is used as the
+/// period of an `Interval`.
+fn parse_nonzero_duration(s: &str) -> Result<Duration, ParseError> {
+    let d = parse_duration(s)?;
+    if d == Duration::from_secs(0) {
+        return Err(ParseError::ZeroDuration);
     }
+    Ok(d)
+}
+
+fn parse_histogram_bounds(s: &str) -> Result<metrics::Bounds, ParseError> {
+    s.parse().map_err(ParseError::NotHistogramBounds)
 }
 
 fn parse_socket_addr(s: &str) -> Result<SocketAddr, ParseError> {
@@ -701,6 +1023,73 @@ fn parse_dns_suffix(s: &str) -> Result<dns::Suffix, ParseError> {
         .map_err(|_| ParseError::NotADomainSuffix)
 }
 
+fn parse_labels(list: &str) -> Result<IndexMap<String, String>, ParseError> {
+    let mut labels = IndexMap::new();
+    for item in list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let mut parts = item.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) if !k.trim().is_empty() => {
+                labels.insert(k.trim().to_string(), v.trim().to_string());
+            }
+            _ => {
+                error!("Invalid label: {}", item);
+                return Err(ParseError::NotALabel);
+            }
+        }
+    }
+    Ok(labels)
+}
+
+fn parse_affinities(list: &str) -> Result<IndexMap<Addr, outbound::Affinity>, ParseError> {
+    let mut affinities = IndexMap::new();
+    for item in list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let mut parts = item.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(addr), Some(affinity)) => {
+                let addr = parse_addr(addr.trim())?;
+                let affinity = parse_affinity(affinity.trim())?;
+                affinities.insert(addr, affinity);
+            }
+            _ => {
+                error!("Invalid affinity: {}", item);
+                return Err(ParseError::NotAnAffinity);
+            }
+        }
+    }
+    Ok(affinities)
+}
+
+fn parse_affinity(s: &str) -> Result<outbound::Affinity, ParseError> {
+    if s == "source-identity" {
+        return Ok(outbound::Affinity::SourceIdentity);
+    }
+
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("header"), Some(name)) => header::HeaderName::from_bytes(name.as_bytes())
+            .map(outbound::Affinity::Header)
+            .map_err(|_| {
+                error!("Not a valid header name: {}", name);
+                ParseError::NotAnAffinity
+            }),
+        (Some("cookie"), Some(name)) if !name.is_empty() => {
+            Ok(outbound::Affinity::Cookie(name.to_owned()))
+        }
+        _ => {
+            error!("Invalid affinity: {}", s);
+            Err(ParseError::NotAnAffinity)
+        }
+    }
+}
+
 fn parse_networks(list: &str) -> Result<IndexSet<ipnet::IpNet>, ParseError> {
     let mut nets = IndexSet::new();
     for input in list.split(',') {
@@ -1038,4 +1427,115 @@ mod tests {
             "names are coerced to lowercase"
         );
     }
+
+    #[test]
+    fn labels() {
+        fn p(s: &str) -> Result<Vec<(String, String)>, ParseError> {
+            Ok(parse_labels(s)?.into_iter().collect())
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(p(",,"), Ok(vec![]), "empty list components are ignored");
+        assert_eq!(
+            p(" zone = a , region=r"),
+            Ok(vec![
+                ("zone".to_owned(), "a".to_owned()),
+                ("region".to_owned(), "r".to_owned())
+            ]),
+            "whitespace is ignored"
+        );
+        assert_eq!(p("zone"), Err(ParseError::NotALabel), "missing value");
+        assert_eq!(p("=a"), Err(ParseError::NotALabel), "missing key");
+    }
+
+    #[test]
+    fn affinities() {
+        fn p(s: &str) -> Result<Vec<(String, outbound::Affinity)>, ParseError> {
+            Ok(parse_affinities(s)?
+                .into_iter()
+                .map(|(a, aff)| (a.to_string(), aff))
+                .collect())
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p("web.ns:80=header:x-user, 10.1.1.1:8080=cookie:session,api.ns:80=source-identity"),
+            Ok(vec![
+                (
+                    "web.ns:80".to_owned(),
+                    outbound::Affinity::Header(header::HeaderName::from_static("x-user"))
+                ),
+                (
+                    "10.1.1.1:8080".to_owned(),
+                    outbound::Affinity::Cookie("session".to_owned())
+                ),
+                ("api.ns:80".to_owned(), outbound::Affinity::SourceIdentity),
+            ])
+        );
+        assert_eq!(
+            p("web.ns:80"),
+            Err(ParseError::NotAnAffinity),
+            "missing affinity"
+        );
+        assert_eq!(
+            p("web.ns:80=header:"),
+            Err(ParseError::NotAnAffinity),
+            "missing header name"
+        );
+        assert_eq!(
+            p("web.ns:80=query:user"),
+            Err(ParseError::NotAnAffinity),
+            "unknown affinity"
+        );
+    }
+
+    #[test]
+    fn nonzero_durations() {
+        assert_eq!(parse_nonzero_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_nonzero_duration("0"), Err(ParseError::ZeroDuration));
+        assert_eq!(parse_nonzero_duration("0ms"), Err(ParseError::ZeroDuration));
+        assert_eq!(parse_nonzero_duration("1"), Err(ParseError::NotADuration));
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(parse_ratio("0"), Ok(0.0));
+        assert_eq!(parse_ratio("0.5"), Ok(0.5));
+        assert_eq!(parse_ratio("1"), Ok(1.0));
+        assert_eq!(parse_ratio("-0.1"), Err(ParseError::NotARatio));
+        assert_eq!(parse_ratio("1.5"), Err(ParseError::NotARatio));
+        assert_eq!(parse_ratio("NaN"), Err(ParseError::NotARatio));
+        assert_eq!(parse_ratio("half"), Err(ParseError::NotANumber));
+    }
+
+    #[test]
+    fn parse_histogram_bounds_valid() {
+        let bounds = parse_histogram_bounds("5, 10,25,50").unwrap();
+        assert_eq!(
+            bounds.buckets(),
+            &[
+                metrics::Bucket::Le(5),
+                metrics::Bucket::Le(10),
+                metrics::Bucket::Le(25),
+                metrics::Bucket::Le(50),
+                metrics::Bucket::Inf,
+            ][..]
+        );
+    }
+
+    #[test]
+    fn parse_histogram_bounds_invalid() {
+        assert_eq!(
+            parse_histogram_bounds("10,5"),
+            Err(ParseError::NotHistogramBounds(
+                metrics::InvalidBounds::NotIncreasing(5)
+            ))
+        );
+        assert_eq!(
+            parse_histogram_bounds("10ms"),
+            Err(ParseError::NotHistogramBounds(
+                metrics::InvalidBounds::NotANumber("10ms".into())
+            ))
+        );
+    }
 }
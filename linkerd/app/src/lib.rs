@@ -7,6 +7,7 @@ pub mod dst;
 pub mod env;
 pub mod identity;
 pub mod metrics;
+pub mod metrics_export;
 pub mod oc_collector;
 pub mod tap;
 
@@ -14,6 +15,7 @@ use self::metrics::Metrics;
 use futures::{future, Async, Future};
 pub use linkerd2_app_core::{self as core, trace};
 use linkerd2_app_core::{
+    admin::debug,
     config::ControlAddr,
     dns, drain,
     svc::{self, NewService},
@@ -49,15 +51,17 @@ pub struct Config<A: OrigDstAddr = SysOrigDstAddr> {
     pub admin: admin::Config,
     pub tap: tap::Config,
     pub oc_collector: oc_collector::Config,
+    pub metrics_export: metrics_export::Config,
 }
 
 pub struct App {
     admin: admin::Admin,
     dns: dns::Task,
     drain: drain::Signal,
-    dst: ControlAddr,
+    dst: Option<ControlAddr>,
     identity: identity::Identity,
     inbound: inbound::Inbound,
+    metrics_export: metrics_export::MetricsExport,
     oc_collector: oc_collector::OcCollector,
     outbound: outbound::Outbound,
     tap: tap::Tap,
@@ -83,6 +87,7 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             admin: self.admin,
             tap: self.tap,
             oc_collector: self.oc_collector,
+            metrics_export: self.metrics_export,
         }
     }
 
@@ -97,12 +102,16 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             dst,
             identity,
             inbound,
+            metrics_export,
             oc_collector,
             outbound,
             tap,
         } = self;
         debug!("building app");
-        let (metrics, report) = Metrics::new(admin.metrics_retain_idle);
+        let (metrics, report) = Metrics::new(
+            admin.metrics_retain_idle,
+            admin.metrics_histogram_bounds.clone(),
+        );
 
         let dns = info_span!("dns").in_scope(|| dns.build())?;
 
@@ -116,6 +125,7 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
         let dst = {
             use linkerd2_app_core::{classify, control, proxy::grpc, reconnect, transport::tls};
 
+            let snapshot = metrics.dst_snapshot.clone();
             let metrics = metrics.control.clone();
             let dns = dns.resolver.clone();
             info_span!("dst").in_scope(|| {
@@ -124,25 +134,27 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
                 // happening today. Really, we should daemonize the whole client
                 // into a task so consumers can be ignorant. This would also
                 // probably enable the use of a lock.
-                let svc = svc::connect(dst.control.connect.keepalive)
-                    .push(tls::ConnectLayer::new(identity.local()))
-                    .push_timeout(dst.control.connect.timeout)
-                    .push(control::client::layer())
-                    .push(control::resolve::layer(dns))
-                    .push(reconnect::layer({
-                        let backoff = dst.control.connect.backoff;
-                        move |_| Ok(backoff.stream())
-                    }))
-                    .push(metrics.into_layer::<classify::Response>())
-                    .push(control::add_origin::Layer::new())
-                    .into_new_service()
-                    .push_on_response(
-                        svc::layers()
-                            .push(grpc::req_body_as_payload::layer())
-                            .push_spawn_buffer(dst.control.buffer_capacity),
-                    )
-                    .new_service(dst.control.addr.clone());
-                dst.build(svc)
+                let svc = dst.control.as_ref().map(|config| {
+                    svc::connect(config.connect.keepalive)
+                        .push(tls::ConnectLayer::new(identity.local()))
+                        .push_timeout(config.connect.timeout)
+                        .push(control::client::layer())
+                        .push(control::resolve::layer(dns))
+                        .push(reconnect::layer({
+                            let backoff = config.connect.backoff;
+                            move |_| Ok(backoff.stream())
+                        }))
+                        .push(metrics.into_layer::<classify::Response>())
+                        .push(control::add_origin::Layer::new())
+                        .into_new_service()
+                        .push_on_response(
+                            svc::layers()
+                                .push(grpc::req_body_as_payload::layer())
+                                .push_spawn_buffer(config.buffer_capacity),
+                        )
+                        .new_service(config.addr.clone())
+                });
+                dst.build(svc, snapshot)
             })
         }?;
 
@@ -153,10 +165,25 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             info_span!("opencensus").in_scope(|| oc_collector.build(identity, dns, metrics))
         }?;
 
+        let metrics_export = {
+            let identity = identity.local();
+            let dns = dns.resolver.clone();
+            let report = report.clone();
+            info_span!("metrics_export").in_scope(|| metrics_export.build(report, identity, dns))
+        }?;
+
+        // Records the state of discovery for the admin server.
+        let debug = debug::Registry::default();
+
         let admin = {
             let identity = identity.local();
+            let debug = debug.clone();
+            // Inbound and outbound transports share a registry.
+            let connections = metrics.inbound.transport.connections();
             let drain = drain_rx.clone();
-            info_span!("admin").in_scope(move || admin.build(identity, report, log_level, drain))?
+            info_span!("admin").in_scope(move || {
+                admin.build(identity, report, log_level, debug, connections, drain)
+            })?
         };
 
         let dst_addr = dst.addr.clone();
@@ -177,15 +204,17 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             let tap = tap.layer();
             let metrics = metrics.outbound;
             let oc = oc_collector.span_sink();
+            let profiles = debug.record_routes(dst.profiles);
             info_span!("outbound").in_scope(move || {
                 outbound.build(
                     identity,
                     dst.resolve,
                     dns,
-                    dst.profiles,
+                    profiles,
                     tap,
                     metrics,
                     oc,
+                    debug,
                     drain_rx,
                 )
             })?
@@ -198,6 +227,7 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             drain: drain_tx,
             identity,
             inbound,
+            metrics_export,
             oc_collector,
             outbound,
             tap,
@@ -225,8 +255,10 @@ impl App {
         }
     }
 
-    pub fn dst_addr(&self) -> &ControlAddr {
-        &self.dst
+    /// Returns the address of the destination service, unless endpoints and
+    /// profiles are read from local files.
+    pub fn dst_addr(&self) -> Option<&ControlAddr> {
+        self.dst.as_ref()
     }
 
     pub fn local_identity(&self) -> Option<&identity::Local> {
@@ -257,6 +289,7 @@ impl App {
             drain,
             identity,
             inbound,
+            metrics_export,
             oc_collector,
             outbound,
             tap,
@@ -330,6 +363,14 @@ impl App {
                                 );
                             }
 
+                            if let metrics_export::MetricsExport::Enabled { task } = metrics_export
+                            {
+                                tokio::spawn(
+                                    task.map_err(|error| error!(%error, "exporter died"))
+                                        .instrument(info_span!("metrics_export")),
+                                );
+                            }
+
                             if let oc_collector::OcCollector::Enabled { task, .. } = oc_collector {
                                 tokio::spawn(
                                     task.map_err(|error| error!(%error, "client died"))
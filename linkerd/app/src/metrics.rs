@@ -1,37 +1,64 @@
+use crate::dst;
 pub use linkerd2_app_core::{
     classify::Class,
     errors, handle_time, http_metrics as metrics,
-    metric_labels::{ControlLabels, EndpointLabels, RouteLabels},
-    metrics::FmtMetrics,
+    metric_labels::{ControlLabels, EndpointLabels, MirrorLabels, RouteLabels},
+    metrics::{latency, Bounds, FmtMetrics},
     opencensus, proxy, stack_metrics, telemetry, transport, ControlHttpMetrics, ProxyMetrics,
 };
 use std::time::{Duration, SystemTime};
 
+/// Configures the bucket bounds of each latency histogram family.
+#[derive(Clone, Debug)]
+pub struct HistogramBounds {
+    /// Bounds for `response_latency_ms`, in milliseconds.
+    pub response_latency: Bounds,
+    /// Bounds for `request_handle_us`, in microseconds.
+    pub request_handle: Bounds,
+    /// Bounds for `tcp_connection_duration_ms`, in milliseconds.
+    pub tcp_connection_duration: Bounds,
+}
+
 pub struct Metrics {
     pub inbound: ProxyMetrics,
     pub outbound: ProxyMetrics,
     pub control: ControlHttpMetrics,
     pub opencensus: opencensus::metrics::Registry,
+    pub dst_snapshot: dst::snapshot::Metrics,
+}
+
+impl Default for HistogramBounds {
+    fn default() -> Self {
+        Self {
+            response_latency: latency::bounds(),
+            request_handle: latency::bounds(),
+            tcp_connection_duration: latency::bounds(),
+        }
+    }
 }
 
 impl Metrics {
-    pub fn new(retain_idle: Duration) -> (Self, impl FmtMetrics + Clone + Send + 'static) {
+    pub fn new(
+        retain_idle: Duration,
+        bounds: HistogramBounds,
+    ) -> (Self, impl FmtMetrics + Clone + Send + 'static) {
         let process = telemetry::process::Report::new(SystemTime::now());
 
         let (control, control_report) = {
-            let m = metrics::Requests::<ControlLabels, Class>::default();
+            let m = metrics::Requests::<ControlLabels, Class>::new(bounds.response_latency.clone());
             let r = m.clone().into_report(retain_idle).with_prefix("control");
             (m, r)
         };
 
         let (http_endpoint, endpoint_report) = {
-            let m = metrics::Requests::<EndpointLabels, Class>::default();
+            let m =
+                metrics::Requests::<EndpointLabels, Class>::new(bounds.response_latency.clone());
             let r = m.clone().into_report(retain_idle);
             (m, r)
         };
 
         let (http_route, route_report) = {
-            let m = metrics::Requests::<RouteLabels, Class>::default();
+            let m = metrics::Requests::<RouteLabels, Class>::new(bounds.response_latency.clone());
             let r = m.clone().into_report(retain_idle).with_prefix("route");
             (m, r)
         };
@@ -42,8 +69,26 @@ impl Metrics {
             (m, r)
         };
 
+        let (http_route_hedge, hedge_report) = {
+            let m = metrics::Hedges::<RouteLabels>::default();
+            let r = m.clone().into_report(retain_idle).with_prefix("route");
+            (m, r)
+        };
+
+        let (http_route_fault, fault_report) = {
+            let m = metrics::Faults::<RouteLabels>::default();
+            let r = m.clone().into_report(retain_idle).with_prefix("route");
+            (m, r)
+        };
+
+        let (http_mirror, mirror_report) = {
+            let m = metrics::Mirrors::<MirrorLabels>::default();
+            let r = m.clone().into_report(retain_idle).with_prefix("mirror");
+            (m, r)
+        };
+
         let (http_route_actual, actual_report) = {
-            let m = metrics::Requests::<RouteLabels, Class>::default();
+            let m = metrics::Requests::<RouteLabels, Class>::new(bounds.response_latency.clone());
             let r = m
                 .clone()
                 .into_report(retain_idle)
@@ -53,16 +98,18 @@ impl Metrics {
 
         let http_errors = errors::Metrics::default();
 
-        let handle_time_report = handle_time::Metrics::new();
+        let handle_time_report = handle_time::Metrics::new(bounds.request_handle);
         let inbound_handle_time = handle_time_report.inbound();
         let outbound_handle_time = handle_time_report.outbound();
 
-        let stack = stack_metrics::Registry::default();
+        let stack = stack_metrics::Registry::new(retain_idle);
 
-        let (transport, transport_report) = transport::metrics::new();
+        let (transport, transport_report) = transport::metrics::new(bounds.tcp_connection_duration);
 
         let (opencensus, opencensus_report) = opencensus::metrics::new();
 
+        let (dst_snapshot, dst_snapshot_report) = dst::snapshot::new();
+
         let metrics = Metrics {
             inbound: ProxyMetrics {
                 http_handle_time: inbound_handle_time,
@@ -70,6 +117,9 @@ impl Metrics {
                 http_route: http_route.clone(),
                 http_route_actual: http_route_actual.clone(),
                 http_route_retry: http_route_retry.clone(),
+                http_route_hedge: http_route_hedge.clone(),
+                http_route_fault: http_route_fault.clone(),
+                http_mirror: http_mirror.clone(),
                 http_errors: http_errors.inbound(),
                 stack: stack.clone(),
                 transport: transport.clone(),
@@ -79,6 +129,9 @@ impl Metrics {
                 http_endpoint,
                 http_route,
                 http_route_retry,
+                http_route_hedge,
+                http_route_fault,
+                http_mirror,
                 http_route_actual,
                 http_errors: http_errors.outbound(),
                 stack: stack.clone(),
@@ -86,20 +139,93 @@ impl Metrics {
             },
             control,
             opencensus,
+            dst_snapshot,
         };
 
         let report = (http_errors.report())
             .and_then(endpoint_report)
             .and_then(route_report)
             .and_then(retry_report)
+            .and_then(hedge_report)
+            .and_then(fault_report)
+            .and_then(mirror_report)
             .and_then(actual_report)
             .and_then(control_report)
             .and_then(handle_time_report)
             .and_then(transport_report)
             .and_then(opencensus_report)
+            .and_then(dst_snapshot_report)
             .and_then(stack)
             .and_then(process);
 
         (metrics, report)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, sync::mpsc, Future, Poll, Stream};
+    use linkerd2_metrics_export::{Config, Exporter, Point, Snapshot, Value};
+    use tokio::runtime::current_thread::Runtime;
+
+    struct Sink(mpsc::UnboundedSender<Snapshot>);
+
+    impl tower::Service<Snapshot> for Sink {
+        type Response = ();
+        type Error = mpsc::SendError<Snapshot>;
+        type Future = future::FutureResult<(), Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, snapshot: Snapshot) -> Self::Future {
+            future::result(self.0.unbounded_send(snapshot))
+        }
+    }
+
+    #[test]
+    fn exports_report() {
+        let (metrics, report) = Metrics::new(Duration::from_secs(10), HistogramBounds::default());
+        metrics.dst_snapshot.endpoints.incr();
+
+        let config = Config {
+            interval: Duration::from_millis(1),
+            prefix: "linkerd.".into(),
+            labels: vec![("pod".into(), "web-0".into())],
+        };
+        let (tx, rx) = mpsc::unbounded();
+        let mut rt = Runtime::new().expect("runtime");
+        rt.spawn(future::lazy(move || {
+            Exporter::new(config, report, Sink(tx)).map_err(|e| panic!("exporter failed: {}", e))
+        }));
+        let snapshot = match rt.block_on(rx.into_future()) {
+            Ok((Some(snapshot), _)) => snapshot,
+            _ => panic!("no snapshot was exported"),
+        };
+
+        assert!(snapshot.metrics.iter().all(|m| !m.help.is_empty()));
+        assert!(snapshot
+            .metrics
+            .iter()
+            .any(|m| m.name == "linkerd.process_start_time_seconds"));
+
+        let stale = snapshot
+            .metrics
+            .iter()
+            .find(|m| m.name == "linkerd.destination_endpoints_stale")
+            .expect("stale endpoints must be exported");
+        assert_eq!(
+            stale.help,
+            "Number of destinations whose endpoints are served from a stale snapshot"
+        );
+        assert_eq!(
+            stale.value,
+            Value::Gauge(vec![Point {
+                labels: vec![("pod".into(), "web-0".into())],
+                value: 1.0,
+            }])
+        );
+    }
+}
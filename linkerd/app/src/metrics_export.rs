@@ -0,0 +1,85 @@
+use crate::{dns, identity::LocalIdentity};
+use futures::{future, Future};
+use linkerd2_app_core::{
+    config::ControlConfig,
+    control,
+    metrics::FmtMetrics,
+    proxy, reconnect,
+    svc::{self, NewService},
+    transport::tls,
+    Error,
+};
+pub use linkerd2_metrics_export::Config as ExportConfig;
+use linkerd2_metrics_export::{otlp::Otlp, statsd::StatsD, Exporter};
+use std::net::SocketAddr;
+use tracing::debug;
+
+/// Configures where metrics are pushed, in addition to being served by the
+/// admin server.
+#[derive(Clone, Debug)]
+pub enum Config {
+    Disabled,
+    StatsD {
+        addr: SocketAddr,
+        export: ExportConfig,
+    },
+    Otlp {
+        control: ControlConfig,
+        export: ExportConfig,
+    },
+}
+
+pub type Task = Box<dyn Future<Item = (), Error = Error> + Send + 'static>;
+
+pub enum MetricsExport {
+    Disabled,
+    Enabled { task: Task },
+}
+
+impl Config {
+    const SERVICE_NAME: &'static str = "linkerd-proxy";
+
+    pub fn build<R>(
+        self,
+        report: R,
+        identity: LocalIdentity,
+        dns: dns::Resolver,
+    ) -> Result<MetricsExport, Error>
+    where
+        R: FmtMetrics + Send + 'static,
+    {
+        match self {
+            Config::Disabled => Ok(MetricsExport::Disabled),
+            Config::StatsD { addr, export } => {
+                let statsd = StatsD::connect(addr)?;
+                let task = Box::new(future::lazy(move || {
+                    debug!(peer.addr = %addr, "running");
+                    Exporter::new(export, report, statsd)
+                }));
+                Ok(MetricsExport::Enabled { task })
+            }
+            Config::Otlp { control, export } => {
+                let addr = control.addr;
+                let svc = svc::connect(control.connect.keepalive)
+                    .push(tls::ConnectLayer::new(identity))
+                    .push_timeout(control.connect.timeout)
+                    .push(control::client::layer())
+                    .push(control::resolve::layer(dns))
+                    .push(reconnect::layer({
+                        let backoff = control.connect.backoff;
+                        move |_| Ok(backoff.stream())
+                    }))
+                    .push_on_response(proxy::grpc::req_body_as_payload::layer())
+                    .push(control::add_origin::Layer::new())
+                    .into_new_service()
+                    .new_service(addr.clone());
+
+                let task = Box::new(future::lazy(move || {
+                    debug!(peer.addr = ?addr, "running");
+                    Exporter::new(export, report, Otlp::new(svc, Self::SERVICE_NAME))
+                }));
+                Ok(MetricsExport::Enabled { task })
+            }
+        }
+    }
+}
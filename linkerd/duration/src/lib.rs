@@ -0,0 +1,89 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The magnitude is not a whole number or is too large.
+    NotANumber,
+    /// The value is not a magnitude followed by a known unit.
+    NotADuration,
+}
+
+/// Parses a whole number followed by a unit (`ms`, `s`, `m`, `h` or `d`),
+/// e.g. `500ms` or `10s`.
+///
+/// The unit may only be omitted when the magnitude is zero.
+pub fn parse(s: &str) -> Result<Duration, ParseError> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\s*(\d+)(ms|s|m|h|d)?\s*$").expect("duration regex");
+    }
+
+    let cap = RE.captures(s).ok_or(ParseError::NotADuration)?;
+
+    let magnitude = cap[1].parse::<u64>().map_err(|_| ParseError::NotANumber)?;
+    let secs = |scale: u64| {
+        magnitude
+            .checked_mul(scale)
+            .map(Duration::from_secs)
+            .ok_or(ParseError::NotANumber)
+    };
+    match cap.get(2).map(|m| m.as_str()) {
+        None if magnitude == 0 => Ok(Duration::from_secs(0)),
+        Some("ms") => Ok(Duration::from_millis(magnitude)),
+        Some("s") => secs(1),
+        Some("m") => secs(60),
+        Some("h") => secs(60 * 60),
+        Some("d") => secs(60 * 60 * 24),
+        _ => Err(ParseError::NotADuration),
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::NotANumber => write!(f, "duration magnitude is not a valid number"),
+            ParseError::NotADuration => write!(f, "not a duration"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse("0"), Ok(Duration::from_secs(0)));
+        assert_eq!(parse("10ms"), Ok(Duration::from_millis(10)));
+        assert_eq!(parse("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse("2d"), Ok(Duration::from_secs(2 * 60 * 60 * 24)));
+        assert_eq!(parse(" 2s "), Ok(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse("10"), Err(ParseError::NotADuration));
+        assert_eq!(parse("10 s"), Err(ParseError::NotADuration));
+        assert_eq!(parse("1.5s"), Err(ParseError::NotADuration));
+        assert_eq!(parse("12moons"), Err(ParseError::NotADuration));
+    }
+
+    #[test]
+    fn rejects_overflows() {
+        assert_eq!(
+            parse("123456789012345678901234567890ms"),
+            Err(ParseError::NotANumber)
+        );
+        assert_eq!(
+            parse(&format!("{}d", u64::max_value())),
+            Err(ParseError::NotANumber)
+        );
+    }
+}
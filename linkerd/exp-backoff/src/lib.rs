@@ -60,6 +60,13 @@ impl ExponentialBackoff {
         Ok(ExponentialBackoff { min, max, jitter })
     }
 
+    /// Returns the jittered duration to wait after `iterations` prior
+    /// attempts.
+    pub fn backoff<R: rand::Rng>(&self, iterations: u32, rng: &mut R) -> Duration {
+        let base = self.base(iterations);
+        base + self.jitter(base, rng)
+    }
+
     fn base(&self, iterations: u32) -> Duration {
         debug_assert!(
             self.min <= self.max,
@@ -111,10 +118,7 @@ impl Stream for ExponentialBackoffStream {
                 return Ok(None.into());
             }
 
-            let backoff = {
-                let base = self.backoff.base(self.iterations);
-                base + self.backoff.jitter(base, &mut self.rng)
-            };
+            let backoff = self.backoff.backoff(self.iterations, &mut self.rng);
             self.delay = Some(timer::Delay::new(timer::clock::now() + backoff));
         }
     }
@@ -0,0 +1,305 @@
+#![deny(warnings, rust_2018_idioms)]
+
+//! Hedges slow requests.
+//!
+//! When a request has not been answered within a policy-determined delay, a
+//! copy of the request is dispatched through the same inner service and the
+//! first response to arrive is used.
+
+use futures::{Async, Future, Poll};
+use linkerd2_error::Error;
+use linkerd2_stack::{NewService, Proxy, ProxyService};
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tower::util::{Oneshot, ServiceExt};
+use tracing::{debug, trace};
+
+/// The number of times a hedge may be dispatched again after it is rejected
+/// by `Policy::should_redispatch`.
+const MAX_REDISPATCHES: usize = 2;
+
+/// A strategy for obtaining per-target hedging polices.
+pub trait NewPolicy<T> {
+    type Policy;
+
+    fn new_policy(&self, target: &T) -> Option<Self::Policy>;
+}
+
+/// Determines whether and when requests are hedged.
+pub trait Policy<Req> {
+    /// Returns how long to wait for a response before hedging `req`, if it
+    /// may be hedged at all.
+    fn delay(&self, req: &Req) -> Option<Duration>;
+
+    /// Clones `req` so that it may be sent as a hedge.
+    ///
+    /// The original request may be annotated so that the hedge can be
+    /// distinguished from it, e.g. to dispatch the hedge to another endpoint.
+    fn clone_request(&self, req: &mut Req) -> Option<Req>;
+
+    /// Returns true if a hedge failed with `error` before it was processed and
+    /// should be dispatched again.
+    fn should_redispatch(&self, _error: &Error) -> bool {
+        false
+    }
+
+    /// Returns true if a hedge may be sent now, e.g. if a budget permits it.
+    fn can_hedge(&self) -> bool;
+
+    /// Notes that a hedge's response was used instead of the original
+    /// request's.
+    fn hedge_won(&self);
+}
+
+/// A layer that applies per-target hedging polcies.
+///
+/// Composes `NewService`s that produce a `Proxy`.
+#[derive(Clone, Debug)]
+pub struct NewHedgeLayer<P> {
+    new_policy: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewHedge<P, N> {
+    new_policy: P,
+    inner: N,
+}
+
+#[derive(Clone, Debug)]
+pub struct Hedge<P, S> {
+    policy: Option<P>,
+    inner: S,
+}
+
+pub struct ResponseFuture<R, P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+    S::Error: Into<Error>,
+{
+    /// The original request, until it fails.
+    primary: Option<P::Future>,
+    hedge: State<R, P, S, Req>,
+}
+
+enum State<R, P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+    S::Error: Into<Error>,
+{
+    /// The request will not be hedged.
+    Disabled,
+
+    /// Waiting to hedge the request.
+    Waiting {
+        delay: Delay,
+        policy: R,
+        request: Req,
+        service: ProxyService<P, S>,
+    },
+
+    /// The hedge has been sent.
+    Sent {
+        policy: R,
+        future: Oneshot<ProxyService<P, S>, Req>,
+        /// A clone of the hedge and the service through which it may be
+        /// dispatched again.
+        redispatch: Option<(Req, ProxyService<P, S>)>,
+        redispatches: usize,
+    },
+}
+
+// === impl NewHedgeLayer ===
+
+impl<P> NewHedgeLayer<P> {
+    pub fn new(new_policy: P) -> Self {
+        Self { new_policy }
+    }
+}
+
+impl<P: Clone, N> tower::layer::Layer<N> for NewHedgeLayer<P> {
+    type Service = NewHedge<P, N>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        Self::Service {
+            inner,
+            new_policy: self.new_policy.clone(),
+        }
+    }
+}
+
+// === impl NewHedge ===
+
+impl<T, N, P> NewService<T> for NewHedge<P, N>
+where
+    N: NewService<T>,
+    P: NewPolicy<T>,
+{
+    type Service = Hedge<P::Policy, N::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        // Determine if there is a hedging policy for the given target.
+        let policy = self.new_policy.new_policy(&target);
+
+        let inner = self.inner.new_service(target);
+        Hedge { policy, inner }
+    }
+}
+
+// === impl Hedge ===
+
+impl<R, P, Req, S> Proxy<Req, S> for Hedge<R, P>
+where
+    R: Policy<Req> + Clone,
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = Error;
+    type Future = ResponseFuture<R, P, S, Req>;
+
+    fn proxy(&self, svc: &mut S, mut req: Req) -> Self::Future {
+        let hedge = self
+            .policy
+            .as_ref()
+            .and_then(|policy| {
+                let delay = policy.delay(&req)?;
+                let request = policy.clone_request(&mut req)?;
+                trace!(?delay, "hedgeable");
+                Some(State::Waiting {
+                    delay: Delay::new(clock::now() + delay),
+                    policy: policy.clone(),
+                    request,
+                    service: self.inner.clone().wrap_service(svc.clone()),
+                })
+            })
+            .unwrap_or(State::Disabled);
+
+        ResponseFuture {
+            primary: Some(self.inner.proxy(svc, req)),
+            hedge,
+        }
+    }
+}
+
+impl<R, P, S, Req> Future for ResponseFuture<R, P, S, Req>
+where
+    R: Policy<Req>,
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Item = P::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(primary) = self.primary.as_mut() {
+            match primary.poll() {
+                Ok(Async::Ready(rsp)) => return Ok(Async::Ready(rsp)),
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    let error = e.into();
+                    // If a hedge is in flight, it may still succeed.
+                    match self.hedge {
+                        State::Sent { .. } => {
+                            debug!(%error, "Request failed; waiting for hedge");
+                            self.primary = None;
+                        }
+                        _ => return Err(error),
+                    }
+                }
+            }
+        }
+
+        loop {
+            self.hedge = match std::mem::replace(&mut self.hedge, State::Disabled) {
+                State::Disabled => return Ok(Async::NotReady),
+
+                State::Waiting {
+                    mut delay,
+                    policy,
+                    mut request,
+                    service,
+                } => match delay.poll() {
+                    Ok(Async::NotReady) => {
+                        self.hedge = State::Waiting {
+                            delay,
+                            policy,
+                            request,
+                            service,
+                        };
+                        return Ok(Async::NotReady);
+                    }
+                    Err(error) => {
+                        debug!(%error, "Hedge timer failed");
+                        State::Disabled
+                    }
+                    Ok(Async::Ready(())) => {
+                        if !policy.can_hedge() {
+                            trace!("Not hedging");
+                            return Ok(Async::NotReady);
+                        }
+                        trace!("Hedging");
+                        let redispatch = policy
+                            .clone_request(&mut request)
+                            .map(|req| (req, service.clone()));
+                        State::Sent {
+                            future: service.oneshot(request),
+                            policy,
+                            redispatch,
+                            redispatches: 0,
+                        }
+                    }
+                },
+
+                State::Sent {
+                    mut future,
+                    policy,
+                    redispatch,
+                    redispatches,
+                } => match future.poll() {
+                    Ok(Async::Ready(rsp)) => {
+                        policy.hedge_won();
+                        return Ok(Async::Ready(rsp));
+                    }
+                    Ok(Async::NotReady) => {
+                        self.hedge = State::Sent {
+                            future,
+                            policy,
+                            redispatch,
+                            redispatches,
+                        };
+                        return Ok(Async::NotReady);
+                    }
+                    Err(error) => match redispatch {
+                        Some((mut request, service))
+                            if redispatches < MAX_REDISPATCHES
+                                && policy.should_redispatch(&error) =>
+                        {
+                            debug!(%error, "Redispatching hedge");
+                            let redispatch = policy
+                                .clone_request(&mut request)
+                                .map(|req| (req, service.clone()));
+                            State::Sent {
+                                future: service.oneshot(request),
+                                policy,
+                                redispatch,
+                                redispatches: redispatches + 1,
+                            }
+                        }
+                        _ => {
+                            if self.primary.is_none() {
+                                return Err(error);
+                            }
+                            debug!(%error, "Hedge failed");
+                            State::Disabled
+                        }
+                    },
+                },
+            };
+        }
+    }
+}
@@ -0,0 +1,140 @@
+#![feature(test)]
+
+extern crate linkerd2_http_metrics;
+use futures::{future, Async, Future, Poll};
+use linkerd2_error::{Error, Never};
+use linkerd2_http_classify::{ClassifyEos, ClassifyResponse};
+use linkerd2_http_metrics::Requests;
+use linkerd2_metrics::FmtLabels;
+use std::{fmt, thread};
+use tower::{layer::Layer, Service};
+
+extern crate test;
+use test::Bencher;
+
+const REQUESTS_PER_THREAD: usize = 1_000;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Target(usize);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Class;
+
+#[derive(Clone, Debug, Default)]
+struct Classify;
+
+/// Makes services that respond immediately with an empty body.
+#[derive(Clone, Debug)]
+struct MakeEmpty;
+
+#[derive(Clone, Debug)]
+struct Empty;
+
+impl FmtLabels for Target {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "n=\"{}\"", self.0)
+    }
+}
+
+impl FmtLabels for Class {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "class=\"success\"")
+    }
+}
+
+impl ClassifyResponse for Classify {
+    type Class = Class;
+    type ClassifyEos = Self;
+
+    fn start<B>(self, _: &http::Response<B>) -> Self {
+        self
+    }
+
+    fn error(self, _: &Error) -> Class {
+        Class
+    }
+}
+
+impl ClassifyEos for Classify {
+    type Class = Class;
+
+    fn eos(self, _: Option<&http::HeaderMap>) -> Class {
+        Class
+    }
+
+    fn error(self, _: &Error) -> Class {
+        Class
+    }
+}
+
+impl Service<Target> for MakeEmpty {
+    type Response = Empty;
+    type Error = Never;
+    type Future = future::FutureResult<Empty, Never>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: Target) -> Self::Future {
+        future::ok(Empty)
+    }
+}
+
+impl<B> Service<http::Request<B>> for Empty {
+    type Response = http::Response<hyper::Body>;
+    type Error = Never;
+    type Future = future::FutureResult<Self::Response, Never>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: http::Request<B>) -> Self::Future {
+        future::ok(http::Response::new(hyper::Body::empty()))
+    }
+}
+
+/// Sends requests concurrently on `threads` threads, spread across `targets`
+/// distinct targets. Each response body is dropped as soon as it is received
+/// so that its latency and class are recorded.
+fn run_bench(threads: usize, targets: usize, b: &mut Bencher) {
+    let layer = Requests::<Target, Class>::default().into_layer::<Classify>();
+    let make = layer.layer(MakeEmpty);
+    b.iter(|| {
+        let handles = (0..threads)
+            .map(|t| {
+                let mut make = make.clone();
+                thread::spawn(move || {
+                    let mut svc = make.call(Target(t % targets)).wait().unwrap();
+                    for _ in 0..REQUESTS_PER_THREAD {
+                        svc.poll_ready().unwrap();
+                        let rsp = svc
+                            .call(http::Request::new(hyper::Body::empty()))
+                            .wait()
+                            .unwrap();
+                        drop(rsp);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+    });
+}
+
+#[bench]
+fn one_thread(b: &mut Bencher) {
+    run_bench(1, 1, b)
+}
+
+#[bench]
+fn many_threads_one_target(b: &mut Bencher) {
+    run_bench(64, 1, b)
+}
+
+#[bench]
+fn many_threads_many_targets(b: &mut Bencher) {
+    run_bench(64, 16, b)
+}
@@ -0,0 +1,147 @@
+use super::{LastUpdate, Prefixed, Registry, Report, UpdatedAt};
+use linkerd2_metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Metric};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::trace;
+
+#[derive(Debug)]
+pub struct Faults<T>(Arc<Mutex<Registry<T, Metrics>>>)
+where
+    T: Hash + Eq;
+
+#[derive(Clone, Debug)]
+pub struct Handle(Arc<Metrics>);
+
+#[derive(Debug)]
+pub struct Metrics {
+    last_update: UpdatedAt,
+    delay: Counter,
+    abort: Counter,
+}
+
+/// Describes the kind of fault that was injected into a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Delay,
+    Abort,
+}
+
+// === impl Faults ===
+
+impl<T: Hash + Eq> Default for Faults<T> {
+    fn default() -> Self {
+        Faults(Arc::new(Mutex::new(Registry::default())))
+    }
+}
+
+impl<T: Hash + Eq> Faults<T> {
+    pub fn into_report(self, retain_idle: Duration) -> Report<T, Metrics> {
+        Report::new(retain_idle, self.0)
+    }
+
+    pub fn get_handle(&self, target: impl Into<T>) -> Handle {
+        let mut reg = self.0.lock().expect("fault metrics registry poisoned");
+        Handle(
+            reg.by_target
+                .entry(target.into())
+                .or_insert_with(|| Arc::new(Metrics::default()))
+                .clone(),
+        )
+    }
+}
+
+impl<T: Hash + Eq> Clone for Faults<T> {
+    fn clone(&self) -> Self {
+        Faults(self.0.clone())
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    pub fn incr_injected(&self, kind: Kind) {
+        self.0.last_update.touch();
+        match kind {
+            Kind::Delay => self.0.delay.incr(),
+            Kind::Abort => self.0.abort.incr(),
+        }
+    }
+}
+
+// === impl Metrics ===
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            last_update: UpdatedAt::default(),
+            delay: Counter::default(),
+            abort: Counter::default(),
+        }
+    }
+}
+
+impl LastUpdate for Metrics {
+    fn last_update(&self) -> Instant {
+        self.last_update.get()
+    }
+}
+
+// === impl Report ===
+
+impl<T> Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn fault_injected_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("fault_injected_total"),
+            "Total count of HTTP requests into which a fault was injected.",
+        )
+    }
+}
+
+impl<T> FmtMetrics for Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.registry.lock() {
+            Err(_) => return Ok(()),
+            Ok(r) => r,
+        };
+        trace!(
+            prefix = %self.prefix,
+            targets = %registry.by_target.len(),
+            "Formatting HTTP fault metrics",
+        );
+
+        if registry.by_target.is_empty() {
+            return Ok(());
+        }
+
+        let metric = self.fault_injected_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.delay
+                .fmt_metric_labeled(f, &metric.name, (tgt, Kind::Delay))?;
+            tm.abort
+                .fmt_metric_labeled(f, &metric.name, (tgt, Kind::Abort))?;
+        }
+
+        registry.retain_since(clock::now() - self.retain_idle);
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for Kind {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Delay => write!(f, "fault=\"delay\""),
+            Kind::Abort => write!(f, "fault=\"abort\""),
+        }
+    }
+}
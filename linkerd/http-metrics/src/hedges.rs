@@ -0,0 +1,160 @@
+use super::{LastUpdate, Prefixed, Registry, Report, UpdatedAt};
+use linkerd2_metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Metric};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::trace;
+
+#[derive(Debug)]
+pub struct Hedges<T>(Arc<Mutex<Registry<T, Metrics>>>)
+where
+    T: Hash + Eq;
+
+#[derive(Clone, Debug)]
+pub struct Handle(Arc<Metrics>);
+
+#[derive(Debug)]
+pub struct Metrics {
+    last_update: UpdatedAt,
+    hedgeable: Counter,
+    no_budget: Counter,
+    won: Counter,
+}
+
+struct NoBudgetLabel;
+
+// === impl Hedges ===
+
+impl<T: Hash + Eq> Default for Hedges<T> {
+    fn default() -> Self {
+        Hedges(Arc::new(Mutex::new(Registry::default())))
+    }
+}
+
+impl<T: Hash + Eq> Hedges<T> {
+    pub fn into_report(self, retain_idle: Duration) -> Report<T, Metrics> {
+        Report::new(retain_idle, self.0)
+    }
+
+    pub fn get_handle(&self, target: impl Into<T>) -> Handle {
+        let mut reg = self.0.lock().expect("hedge metrics registry poisoned");
+        Handle(
+            reg.by_target
+                .entry(target.into())
+                .or_insert_with(|| Arc::new(Metrics::default()))
+                .clone(),
+        )
+    }
+}
+
+impl<T: Hash + Eq> Clone for Hedges<T> {
+    fn clone(&self) -> Self {
+        Hedges(self.0.clone())
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    /// Records that a request was slow enough to be hedged.
+    pub fn incr_hedgeable(&self, has_budget: bool) {
+        self.0.last_update.touch();
+        self.0.hedgeable.incr();
+        if !has_budget {
+            self.0.no_budget.incr();
+        }
+    }
+
+    /// Records that a hedge's response was used.
+    pub fn incr_won(&self) {
+        self.0.last_update.touch();
+        self.0.won.incr();
+    }
+}
+
+// === impl Metrics ===
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            last_update: UpdatedAt::default(),
+            hedgeable: Counter::default(),
+            no_budget: Counter::default(),
+            won: Counter::default(),
+        }
+    }
+}
+
+impl LastUpdate for Metrics {
+    fn last_update(&self) -> Instant {
+        self.last_update.get()
+    }
+}
+
+// === impl Report ===
+
+impl<T> Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn hedgeable_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("hedgeable_total"),
+            "Total count of HTTP requests that were slow enough to be hedged.",
+        )
+    }
+
+    fn hedge_won_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("hedge_won_total"),
+            "Total count of HTTP requests that were answered by a hedge.",
+        )
+    }
+}
+
+impl<T> FmtMetrics for Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.registry.lock() {
+            Err(_) => return Ok(()),
+            Ok(r) => r,
+        };
+        trace!(
+            prefix = %self.prefix,
+            targets = %registry.by_target.len(),
+            "Formatting HTTP hedge metrics",
+        );
+
+        if registry.by_target.is_empty() {
+            return Ok(());
+        }
+
+        let metric = self.hedgeable_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.hedgeable.fmt_metric_labeled(f, &metric.name, tgt)?;
+            tm.no_budget
+                .fmt_metric_labeled(f, &metric.name, (tgt, NoBudgetLabel))?;
+        }
+
+        let metric = self.hedge_won_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.won.fmt_metric_labeled(f, &metric.name, tgt)?;
+        }
+
+        registry.retain_since(clock::now() - self.retain_idle);
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for NoBudgetLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "skipped=\"no_budget\"")
+    }
+}
@@ -1,12 +1,21 @@
 #![deny(warnings, rust_2018_idioms)]
 
-pub use self::{requests::Requests, retries::Retries};
+pub use self::{
+    faults::Faults, hedges::Hedges, mirrors::Mirrors, requests::Requests, retries::Retries,
+};
 use indexmap::IndexMap;
 use std::fmt;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Duration, Instant};
+use tokio_timer::clock;
 
+pub mod faults;
+pub mod hedges;
+pub mod mirrors;
 pub mod requests;
 pub mod retries;
 
@@ -15,7 +24,7 @@ struct Registry<T, M>
 where
     T: Hash + Eq,
 {
-    by_target: IndexMap<T, Arc<Mutex<M>>>,
+    by_target: IndexMap<T, Arc<M>>,
 }
 
 /// Reports metrics for prometheus.
@@ -48,6 +57,16 @@ trait LastUpdate {
     fn last_update(&self) -> Instant;
 }
 
+/// Records when a target's metrics were last updated.
+///
+/// The time is stored as an atomic offset from when the metrics were created
+/// so that it may be updated without a lock.
+#[derive(Debug)]
+struct UpdatedAt {
+    created_at: Instant,
+    since_created_ns: AtomicU64,
+}
+
 impl<T, M> Default for Registry<T, M>
 where
     T: Hash + Eq,
@@ -67,9 +86,8 @@ where
     /// Retains metrics for all targets that (1) no longer have an active
     /// reference to the `RequestMetrics` structure and (2) have not been updated since `epoch`.
     fn retain_since(&mut self, epoch: Instant) {
-        self.by_target.retain(|_, m| {
-            Arc::strong_count(&m) > 1 || m.lock().map(|m| m.last_update() >= epoch).unwrap_or(false)
-        })
+        self.by_target
+            .retain(|_, m| Arc::strong_count(&m) > 1 || m.last_update() >= epoch)
     }
 }
 
@@ -101,6 +119,28 @@ where
     }
 }
 
+impl Default for UpdatedAt {
+    fn default() -> Self {
+        Self {
+            created_at: clock::now(),
+            since_created_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+impl UpdatedAt {
+    fn touch(&self) {
+        let elapsed = clock::now().saturating_duration_since(self.created_at);
+        self.since_created_ns
+            .store(elapsed.as_nanos() as u64, Ordering::Release);
+    }
+
+    fn get(&self) -> Instant {
+        let ns = self.since_created_ns.load(Ordering::Acquire);
+        self.created_at + Duration::from_nanos(ns)
+    }
+}
+
 impl<'p, N: fmt::Display> fmt::Display for Prefixed<'p, N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.prefix.is_empty() {
@@ -0,0 +1,192 @@
+use super::{LastUpdate, Prefixed, Registry, Report, UpdatedAt};
+use linkerd2_metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Metric};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::trace;
+
+#[derive(Debug)]
+pub struct Mirrors<T>(Arc<Mutex<Registry<T, Metrics>>>)
+where
+    T: Hash + Eq;
+
+#[derive(Clone, Debug)]
+pub struct Handle(Arc<Metrics>);
+
+#[derive(Debug)]
+pub struct Metrics {
+    last_update: UpdatedAt,
+    requests: Counter,
+    successes: Counter,
+    failures: Counter,
+    dropped: Counter,
+}
+
+/// Classifies the outcome of a mirrored request.
+///
+/// Mirrored requests fail if they could not be dispatched or if they received
+/// a server error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+// === impl Mirrors ===
+
+impl<T: Hash + Eq> Default for Mirrors<T> {
+    fn default() -> Self {
+        Mirrors(Arc::new(Mutex::new(Registry::default())))
+    }
+}
+
+impl<T: Hash + Eq> Mirrors<T> {
+    pub fn into_report(self, retain_idle: Duration) -> Report<T, Metrics> {
+        Report::new(retain_idle, self.0)
+    }
+
+    pub fn get_handle(&self, target: impl Into<T>) -> Handle {
+        let mut reg = self.0.lock().expect("mirror metrics registry poisoned");
+        Handle(
+            reg.by_target
+                .entry(target.into())
+                .or_insert_with(|| Arc::new(Metrics::default()))
+                .clone(),
+        )
+    }
+}
+
+impl<T: Hash + Eq> Clone for Mirrors<T> {
+    fn clone(&self) -> Self {
+        Mirrors(self.0.clone())
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    pub fn incr_request(&self) {
+        self.0.last_update.touch();
+        self.0.requests.incr();
+    }
+
+    /// Counts a mirrored request that was not sent because too many mirrored
+    /// requests were already in flight.
+    pub fn incr_dropped(&self) {
+        self.0.last_update.touch();
+        self.0.dropped.incr();
+    }
+
+    pub fn incr_response(&self, outcome: Outcome) {
+        self.0.last_update.touch();
+        match outcome {
+            Outcome::Success => self.0.successes.incr(),
+            Outcome::Failure => self.0.failures.incr(),
+        }
+    }
+}
+
+// === impl Metrics ===
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            last_update: UpdatedAt::default(),
+            requests: Counter::default(),
+            successes: Counter::default(),
+            failures: Counter::default(),
+            dropped: Counter::default(),
+        }
+    }
+}
+
+impl LastUpdate for Metrics {
+    fn last_update(&self) -> Instant {
+        self.last_update.get()
+    }
+}
+
+// === impl Report ===
+
+impl<T> Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn request_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("request_total"),
+            "Total count of mirrored HTTP requests.",
+        )
+    }
+
+    fn response_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("response_total"),
+            "Total count of outcomes of mirrored HTTP requests.",
+        )
+    }
+
+    fn dropped_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("dropped_total"),
+            "Total count of mirrored HTTP requests dropped while too many were in flight.",
+        )
+    }
+}
+
+impl<T> FmtMetrics for Report<T, Metrics>
+where
+    T: FmtLabels + Hash + Eq,
+{
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.registry.lock() {
+            Err(_) => return Ok(()),
+            Ok(r) => r,
+        };
+        trace!(
+            prefix = %self.prefix,
+            targets = %registry.by_target.len(),
+            "Formatting HTTP mirror metrics",
+        );
+
+        if registry.by_target.is_empty() {
+            return Ok(());
+        }
+
+        let metric = self.request_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.requests.fmt_metric_labeled(f, &metric.name, tgt)?;
+        }
+
+        let metric = self.response_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.successes
+                .fmt_metric_labeled(f, &metric.name, (tgt, Outcome::Success))?;
+            tm.failures
+                .fmt_metric_labeled(f, &metric.name, (tgt, Outcome::Failure))?;
+        }
+
+        let metric = self.dropped_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            tm.dropped.fmt_metric_labeled(f, &metric.name, tgt)?;
+        }
+
+        registry.retain_since(clock::now() - self.retain_idle);
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for Outcome {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "classification=\"success\""),
+            Outcome::Failure => write!(f, "classification=\"failure\""),
+        }
+    }
+}
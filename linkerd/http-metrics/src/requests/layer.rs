@@ -1,14 +1,17 @@
-use super::{ClassMetrics, Metrics, SharedRegistry, StatusMetrics};
+use super::{Metrics, SharedRegistry};
+use bytes::Buf;
 use futures::{try_ready, Async, Future, Poll};
 use http;
 use hyper::body::Payload;
 use linkerd2_error::Error;
 use linkerd2_http_classify::{ClassifyEos, ClassifyResponse};
+use linkerd2_metrics::Bounds;
 use linkerd2_stack::{NewService, Proxy};
+use linkerd2_trace_context as trace_context;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Instant;
 use tokio_timer::clock;
 
@@ -21,6 +24,7 @@ where
     C::Class: Hash + Eq,
 {
     registry: SharedRegistry<K, C::Class>,
+    latency_bounds: Bounds,
     _p: PhantomData<fn() -> C>,
 }
 
@@ -33,6 +37,7 @@ where
     C::Class: Hash + Eq,
 {
     registry: SharedRegistry<K, C::Class>,
+    latency_bounds: Bounds,
     inner: M,
     _p: PhantomData<fn() -> C>,
 }
@@ -44,7 +49,7 @@ where
     C: ClassifyResponse,
     C::Class: Hash + Eq,
 {
-    metrics: Option<Arc<Mutex<Metrics<C::Class>>>>,
+    metrics: Option<Arc<Metrics<C::Class>>>,
     inner: S,
     _p: PhantomData<fn() -> C>,
 }
@@ -55,8 +60,11 @@ where
     C::Class: Hash + Eq,
 {
     classify: Option<C>,
-    metrics: Option<Arc<Mutex<Metrics<C::Class>>>>,
+    metrics: Option<Arc<Metrics<C::Class>>>,
     stream_open_at: Instant,
+    /// The request's trace ID, if it was sampled, to be recorded as a latency
+    /// exemplar.
+    trace_id: Option<trace_context::Id>,
     inner: F,
 }
 
@@ -66,7 +74,11 @@ where
     B: Payload,
     C: Hash + Eq,
 {
-    metrics: Option<Arc<Mutex<Metrics<C>>>>,
+    metrics: Option<Arc<Metrics<C>>>,
+    /// Whether the request has been counted in its target's `request_total`.
+    total_recorded: bool,
+    /// The number of body bytes that have been read so far.
+    bytes: u64,
     inner: B,
 }
 
@@ -79,9 +91,12 @@ where
 {
     status: http::StatusCode,
     classify: Option<C>,
-    metrics: Option<Arc<Mutex<Metrics<C::Class>>>>,
+    metrics: Option<Arc<Metrics<C::Class>>>,
     stream_open_at: Instant,
+    trace_id: Option<trace_context::Id>,
     latency_recorded: bool,
+    /// The number of body bytes that have been read so far.
+    bytes: u64,
     inner: B,
 }
 
@@ -93,9 +108,10 @@ where
     C: ClassifyResponse + Send + Sync + 'static,
     C::Class: Hash + Eq,
 {
-    pub(super) fn new(registry: SharedRegistry<K, C::Class>) -> Self {
+    pub(super) fn new(registry: SharedRegistry<K, C::Class>, latency_bounds: Bounds) -> Self {
         Layer {
             registry,
+            latency_bounds,
             _p: PhantomData,
         }
     }
@@ -110,6 +126,7 @@ where
     fn clone(&self) -> Self {
         Self {
             registry: self.registry.clone(),
+            latency_bounds: self.latency_bounds.clone(),
             _p: PhantomData,
         }
     }
@@ -127,6 +144,7 @@ where
         MakeSvc {
             inner,
             registry: self.registry.clone(),
+            latency_bounds: self.latency_bounds.clone(),
             _p: PhantomData,
         }
     }
@@ -145,6 +163,7 @@ where
         Self {
             inner: self.inner.clone(),
             registry: self.registry.clone(),
+            latency_bounds: self.latency_bounds.clone(),
             _p: PhantomData,
         }
     }
@@ -161,11 +180,12 @@ where
     type Service = Service<M::Service, C>;
 
     fn new_service(&self, target: T) -> Self::Service {
+        let latency_bounds = &self.latency_bounds;
         let metrics = match self.registry.lock() {
             Ok(mut r) => Some(
                 r.by_target
                     .entry(target.clone().into())
-                    .or_insert_with(|| Arc::new(Mutex::new(Metrics::default())))
+                    .or_insert_with(|| Arc::new(Metrics::new(latency_bounds.clone())))
                     .clone(),
             ),
             Err(_) => None,
@@ -198,11 +218,12 @@ where
     }
 
     fn call(&mut self, target: T) -> Self::Future {
+        let latency_bounds = &self.latency_bounds;
         let metrics = match self.registry.lock() {
             Ok(mut r) => Some(
                 r.by_target
                     .entry(target.clone().into())
-                    .or_insert_with(|| Arc::new(Mutex::new(Metrics::default())))
+                    .or_insert_with(|| Arc::new(Metrics::new(latency_bounds.clone())))
                     .clone(),
             ),
             Err(_) => None,
@@ -270,22 +291,24 @@ where
     type Future = ResponseFuture<P::Future, C>;
 
     fn proxy(&self, svc: &mut S, req: http::Request<A>) -> Self::Future {
-        let mut req_metrics = self.metrics.clone();
-
-        if req.body().is_end_stream() {
-            if let Some(lock) = req_metrics.take() {
-                let now = clock::now();
-                if let Ok(mut metrics) = lock.lock() {
-                    (*metrics).last_update = now;
-                    (*metrics).total.incr();
-                }
+        let trace_id = self
+            .metrics
+            .as_ref()
+            .and_then(|_| trace_context::sampled_trace_id(&req));
+
+        let total_recorded = req.body().is_end_stream();
+        if total_recorded {
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.incr_total();
             }
         }
 
         let req = {
             let (head, inner) = req.into_parts();
             let body = RequestBody {
-                metrics: req_metrics,
+                metrics: self.metrics.clone(),
+                total_recorded,
+                bytes: 0,
                 inner,
             };
             http::Request::from_parts(head, body)
@@ -297,6 +320,7 @@ where
             classify: Some(classify),
             metrics: self.metrics.clone(),
             stream_open_at: clock::now(),
+            trace_id,
             inner: self.inner.proxy(svc, req),
         }
     }
@@ -320,22 +344,24 @@ where
     }
 
     fn call(&mut self, req: http::Request<A>) -> Self::Future {
-        let mut req_metrics = self.metrics.clone();
-
-        if req.body().is_end_stream() {
-            if let Some(lock) = req_metrics.take() {
-                let now = clock::now();
-                if let Ok(mut metrics) = lock.lock() {
-                    (*metrics).last_update = now;
-                    (*metrics).total.incr();
-                }
+        let trace_id = self
+            .metrics
+            .as_ref()
+            .and_then(|_| trace_context::sampled_trace_id(&req));
+
+        let total_recorded = req.body().is_end_stream();
+        if total_recorded {
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.incr_total();
             }
         }
 
         let req = {
             let (head, inner) = req.into_parts();
             let body = RequestBody {
-                metrics: req_metrics,
+                metrics: self.metrics.clone(),
+                total_recorded,
+                bytes: 0,
                 inner,
             };
             http::Request::from_parts(head, body)
@@ -347,6 +373,7 @@ where
             classify: Some(classify),
             metrics: self.metrics.clone(),
             stream_open_at: clock::now(),
+            trace_id,
             inner: self.inner.call(req),
         }
     }
@@ -381,17 +408,19 @@ where
                     classify,
                     metrics,
                     stream_open_at: self.stream_open_at,
+                    trace_id: self.trace_id.take(),
                     latency_recorded: false,
+                    bytes: 0,
                     inner,
                 };
                 Ok(http::Response::from_parts(head, body).into())
             }
             Err(e) => {
                 let e = e.into();
-                if let Some(lock) = metrics {
+                if let Some(metrics) = metrics {
                     if let Some(classify) = classify {
                         let class = classify.error(&e);
-                        measure_class(&lock, class, None);
+                        measure_class(&metrics, class, None);
                     }
                 }
                 Err(e)
@@ -415,11 +444,16 @@ where
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
         let frame = try_ready!(self.inner.poll_data());
 
-        if let Some(lock) = self.metrics.take() {
-            let now = clock::now();
-            if let Ok(mut metrics) = lock.lock() {
-                (*metrics).last_update = now;
-                (*metrics).total.incr();
+        if let Some(metrics) = self.metrics.as_ref() {
+            if !self.total_recorded {
+                metrics.incr_total();
+                self.total_recorded = true;
+            }
+
+            if let Some(data) = frame.as_ref() {
+                let sz = data.remaining() as u64;
+                self.bytes += sz;
+                metrics.request_bytes_total.add(sz);
             }
         }
 
@@ -460,11 +494,29 @@ where
     fn default() -> Self {
         Self {
             metrics: None,
+            total_recorded: false,
+            bytes: 0,
             inner: B::default(),
         }
     }
 }
 
+impl<B, C> Drop for RequestBody<B, C>
+where
+    B: Payload,
+    C: Hash + Eq,
+{
+    fn drop(&mut self) {
+        // Only observe the sizes of bodies that were counted in
+        // `request_total`, so that the histogram's count tracks the total.
+        if self.total_recorded {
+            if let Some(metrics) = self.metrics.take() {
+                metrics.request_body_bytes.add(self.bytes);
+            }
+        }
+    }
+}
+
 impl<B, C> Default for ResponseBody<B, C>
 where
     B: Payload + Default,
@@ -476,9 +528,11 @@ where
             status: http::StatusCode::OK,
             inner: B::default(),
             stream_open_at: clock::now(),
+            trace_id: None,
             classify: None,
             metrics: None,
             latency_recorded: false,
+            bytes: 0,
         }
     }
 }
@@ -490,32 +544,25 @@ where
     C::Class: Hash + Eq,
 {
     fn record_latency(&mut self) {
-        let now = clock::now();
-
-        let lock = match self.metrics.as_mut() {
-            Some(lock) => lock,
+        let metrics = match self.metrics.as_ref() {
+            Some(metrics) => metrics,
             None => return,
         };
-        let mut metrics = match lock.lock() {
-            Ok(m) => m,
-            Err(_) => return,
-        };
 
-        (*metrics).last_update = now;
-
-        let status_metrics = metrics
-            .by_status
-            .entry(Some(self.status))
-            .or_insert_with(|| StatusMetrics::default());
-
-        status_metrics.latency.add(now - self.stream_open_at);
+        let latency = clock::now() - self.stream_open_at;
+        let trace_id = self.trace_id.take();
+        metrics.with_status(Some(self.status), |m| match trace_id {
+            Some(id) => m.latency.add_with_exemplar(latency, id.to_string()),
+            None => m.latency.add(latency),
+        });
 
         self.latency_recorded = true;
     }
 
     fn record_class(&mut self, class: C::Class) {
-        if let Some(lock) = self.metrics.take() {
-            measure_class(&lock, class, Some(self.status));
+        if let Some(metrics) = self.metrics.take() {
+            metrics.response_body_bytes.add(self.bytes);
+            measure_class(&metrics, class, Some(self.status));
         }
     }
 
@@ -527,30 +574,8 @@ where
     }
 }
 
-fn measure_class<C: Hash + Eq>(
-    lock: &Arc<Mutex<Metrics<C>>>,
-    class: C,
-    status: Option<http::StatusCode>,
-) {
-    let now = clock::now();
-    let mut metrics = match lock.lock() {
-        Ok(m) => m,
-        Err(_) => return,
-    };
-
-    (*metrics).last_update = now;
-
-    let status_metrics = metrics
-        .by_status
-        .entry(status)
-        .or_insert_with(|| StatusMetrics::default());
-
-    let class_metrics = status_metrics
-        .by_class
-        .entry(class)
-        .or_insert_with(|| ClassMetrics::default());
-
-    class_metrics.total.incr();
+fn measure_class<C: Hash + Eq>(metrics: &Metrics<C>, class: C, status: Option<http::StatusCode>) {
+    metrics.with_status(status, |m| m.incr_class(class));
 }
 
 impl<B, C> Payload for ResponseBody<B, C>
@@ -576,6 +601,14 @@ where
             self.record_latency();
         }
 
+        if let Some(data) = frame.as_ref() {
+            if let Some(metrics) = self.metrics.as_ref() {
+                let sz = data.remaining() as u64;
+                self.bytes += sz;
+                metrics.response_bytes_total.add(sz);
+            }
+        }
+
         Ok(Async::Ready(frame))
     }
 
@@ -1,13 +1,12 @@
-use super::{LastUpdate, Registry, Report};
+use super::{LastUpdate, Registry, Report, UpdatedAt};
 use http;
 use indexmap::IndexMap;
 use linkerd2_http_classify::ClassifyResponse;
-use linkerd2_metrics::{latency, Counter, FmtMetrics, Histogram};
+use linkerd2_metrics::{latency, size, Bounds, Bucket, Counter, FmtMetrics, Histogram};
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
-use tokio_timer::clock;
 
 mod layer;
 mod report;
@@ -15,19 +14,33 @@ mod report;
 type SharedRegistry<T, C> = Arc<Mutex<Registry<T, Metrics<C>>>>;
 
 #[derive(Debug)]
-pub struct Requests<T, C>(SharedRegistry<T, C>)
+pub struct Requests<T, C>
 where
     T: Hash + Eq,
-    C: Hash + Eq;
+    C: Hash + Eq,
+{
+    registry: SharedRegistry<T, C>,
+    latency_bounds: Bounds,
+}
 
+/// Holds a target's request metrics.
+///
+/// Counters and histograms are updated atomically. The maps of statuses and
+/// classes are only locked for writing when a new key is recorded, so
+/// requests to a target do not contend with one another.
 #[derive(Debug)]
 pub struct Metrics<C>
 where
     C: Hash + Eq,
 {
-    last_update: Instant,
+    last_update: UpdatedAt,
     total: Counter,
-    by_status: IndexMap<Option<http::StatusCode>, StatusMetrics<C>>,
+    request_bytes_total: Counter,
+    response_bytes_total: Counter,
+    request_body_bytes: Histogram<size::Bytes>,
+    response_body_bytes: Histogram<size::Bytes>,
+    by_status: RwLock<IndexMap<Option<http::StatusCode>, StatusMetrics<C>>>,
+    latency_bounds: Bounds,
 }
 
 #[derive(Debug)]
@@ -36,9 +49,15 @@ where
     C: Hash + Eq,
 {
     latency: Histogram<latency::Ms>,
-    by_class: IndexMap<C, ClassMetrics>,
+    by_class: RwLock<IndexMap<C, ClassMetrics>>,
 }
 
+/// A handle to a target's latency histograms.
+#[derive(Debug)]
+pub struct Latencies<C>(Arc<Metrics<C>>)
+where
+    C: Hash + Eq;
+
 #[derive(Debug, Default)]
 pub struct ClassMetrics {
     total: Counter,
@@ -48,64 +67,241 @@ pub struct ClassMetrics {
 
 impl<T: Hash + Eq, C: Hash + Eq> Default for Requests<T, C> {
     fn default() -> Self {
-        Requests(Arc::new(Mutex::new(Registry::default())))
+        Self::new(latency::bounds())
     }
 }
 
 impl<T: Hash + Eq, C: Hash + Eq> Requests<T, C> {
+    /// Records response latencies into histograms with the given bounds, in
+    /// milliseconds.
+    pub fn new(latency_bounds: Bounds) -> Self {
+        Self {
+            registry: Arc::new(Mutex::new(Registry::default())),
+            latency_bounds,
+        }
+    }
+
     pub fn into_report(self, retain_idle: Duration) -> Report<T, Metrics<C>>
     where
         Report<T, Metrics<C>>: FmtMetrics,
     {
-        Report::new(retain_idle, self.0)
+        Report::new(retain_idle, self.registry)
     }
 
     pub fn into_layer<L>(self) -> layer::Layer<T, L>
     where
         L: ClassifyResponse<Class = C> + Send + Sync + 'static,
     {
-        layer::Layer::new(self.0)
+        layer::Layer::new(self.registry, self.latency_bounds)
+    }
+
+    /// Returns a handle to the latency histograms for `target`.
+    pub fn latencies(&self, target: impl Into<T>) -> Latencies<C> {
+        let mut reg = self
+            .registry
+            .lock()
+            .expect("request metrics registry poisoned");
+        let latency_bounds = &self.latency_bounds;
+        Latencies(
+            reg.by_target
+                .entry(target.into())
+                .or_insert_with(|| Arc::new(Metrics::new(latency_bounds.clone())))
+                .clone(),
+        )
     }
 }
 
 impl<T: Hash + Eq, C: Hash + Eq> Clone for Requests<T, C> {
     fn clone(&self) -> Self {
-        Requests(self.0.clone())
+        Self {
+            registry: self.registry.clone(),
+            latency_bounds: self.latency_bounds.clone(),
+        }
+    }
+}
+
+// === impl Latencies ===
+
+impl<C: Hash + Eq> Latencies<C> {
+    /// Estimates the `q`th quantile of response latencies across all status
+    /// codes.
+    ///
+    /// The estimate is the upper bound of the bucket containing the
+    /// quantile, so it is never less than the actual quantile. `None` is
+    /// returned if fewer than `min_samples` responses have been recorded or
+    /// if the quantile falls in the unbounded bucket.
+    pub fn quantile(&self, q: f64, min_samples: u64) -> Option<Duration> {
+        let by_status = self.0.by_status.read().ok()?;
+
+        let mut counts = Vec::<(Bucket, u64)>::new();
+        for status in by_status.values() {
+            for (i, (bucket, count)) in (&status.latency).into_iter().enumerate() {
+                match counts.get_mut(i) {
+                    Some((_, sum)) => *sum += count.value(),
+                    None => counts.push((*bucket, count.value())),
+                }
+            }
+        }
+
+        let total = counts.iter().map(|(_, c)| c).sum::<u64>();
+        if total == 0 || total < min_samples {
+            return None;
+        }
+
+        let rank = (q * total as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in counts.into_iter() {
+            seen += count;
+            if seen >= rank {
+                return match bucket {
+                    Bucket::Le(ms) => Some(Duration::from_millis(ms)),
+                    Bucket::Inf => None,
+                };
+            }
+        }
+
+        None
+    }
+}
+
+impl<C: Hash + Eq> Clone for Latencies<C> {
+    fn clone(&self) -> Self {
+        Latencies(self.0.clone())
     }
 }
 
 // === impl Metrics ===
 
-impl<C: Hash + Eq> Default for Metrics<C> {
-    fn default() -> Self {
+impl<C: Hash + Eq> Metrics<C> {
+    fn new(latency_bounds: Bounds) -> Self {
         Self {
-            last_update: clock::now(),
+            last_update: UpdatedAt::default(),
             total: Counter::default(),
-            by_status: IndexMap::default(),
+            request_bytes_total: Counter::default(),
+            response_bytes_total: Counter::default(),
+            request_body_bytes: Histogram::default(),
+            response_body_bytes: Histogram::default(),
+            by_status: RwLock::default(),
+            latency_bounds,
+        }
+    }
+
+    fn incr_total(&self) {
+        self.last_update.touch();
+        self.total.incr();
+    }
+
+    /// Calls `f` with the metrics for `status`.
+    ///
+    /// The status map is only locked for writing if `status` has not been
+    /// recorded before.
+    fn with_status<F>(&self, status: Option<http::StatusCode>, f: F)
+    where
+        F: FnOnce(&StatusMetrics<C>),
+    {
+        self.last_update.touch();
+
+        if let Ok(by_status) = self.by_status.read() {
+            if let Some(m) = by_status.get(&status) {
+                return f(m);
+            }
         }
+
+        if let Ok(mut by_status) = self.by_status.write() {
+            let latency_bounds = &self.latency_bounds;
+            let m = by_status
+                .entry(status)
+                .or_insert_with(|| StatusMetrics::new(latency_bounds.clone()));
+            f(m)
+        }
+    }
+}
+
+impl<C: Hash + Eq> Default for Metrics<C> {
+    fn default() -> Self {
+        Self::new(latency::bounds())
     }
 }
 
 impl<C: Hash + Eq> LastUpdate for Metrics<C> {
     fn last_update(&self) -> Instant {
-        self.last_update
+        self.last_update.get()
     }
 }
 
-impl<C> Default for StatusMetrics<C>
+impl<C> StatusMetrics<C>
 where
     C: Hash + Eq,
 {
-    fn default() -> Self {
+    fn new(latency_bounds: Bounds) -> Self {
         Self {
-            latency: Histogram::default(),
-            by_class: IndexMap::default(),
+            latency: Histogram::new(latency_bounds),
+            by_class: RwLock::default(),
+        }
+    }
+
+    fn incr_class(&self, class: C) {
+        if let Ok(by_class) = self.by_class.read() {
+            if let Some(m) = by_class.get(&class) {
+                m.total.incr();
+                return;
+            }
+        }
+
+        if let Ok(mut by_class) = self.by_class.write() {
+            by_class
+                .entry(class)
+                .or_insert_with(ClassMetrics::default)
+                .total
+                .incr();
         }
     }
 }
 
+impl<C> Default for StatusMetrics<C>
+where
+    C: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::new(latency::bounds())
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn quantile() {
+        use std::time::Duration;
+
+        let r = super::Requests::<usize, ()>::default();
+        let latencies = r.latencies(1);
+        assert_eq!(latencies.quantile(0.5, 0), None, "no samples");
+
+        {
+            let mut by_status = latencies.0.by_status.write().unwrap();
+            let ok = by_status
+                .entry(Some(http::StatusCode::OK))
+                .or_insert_with(Default::default);
+            for ms in 1..=90 {
+                ok.latency.add(Duration::from_millis(ms));
+            }
+            let err = by_status
+                .entry(Some(http::StatusCode::INTERNAL_SERVER_ERROR))
+                .or_insert_with(Default::default);
+            for _ in 0..10 {
+                err.latency.add(Duration::from_millis(250));
+            }
+        }
+
+        assert_eq!(latencies.quantile(0.5, 0), Some(Duration::from_millis(50)));
+        assert_eq!(latencies.quantile(0.9, 0), Some(Duration::from_millis(100)));
+        assert_eq!(
+            latencies.quantile(0.99, 0),
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(latencies.quantile(0.5, 101), None, "too few samples");
+    }
+
     #[test]
     fn expiry() {
         use linkerd2_metrics::FmtLabels;
@@ -140,7 +336,7 @@ mod tests {
         let retain_idle_for = Duration::from_secs(1);
         let r = super::Requests::<Target, Class>::default();
         let report = r.clone().into_report(retain_idle_for);
-        let mut registry = r.0.lock().unwrap();
+        let mut registry = r.registry.lock().unwrap();
 
         let before_update = clock::now();
         let metrics = registry
@@ -1,6 +1,8 @@
 use super::{ClassMetrics, Metrics, StatusMetrics};
 use crate::{Prefixed, Registry, Report};
-use linkerd2_metrics::{latency, Counter, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric};
+use linkerd2_metrics::{
+    latency, size, Counter, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric,
+};
 use std::fmt;
 use std::hash::Hash;
 use tokio_timer::clock;
@@ -36,6 +38,36 @@ where
              and its response stream completing",
         )
     }
+
+    fn request_bytes_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("request_bytes_total"),
+            "Total count of bytes in HTTP request bodies.",
+        )
+    }
+
+    fn response_bytes_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("response_bytes_total"),
+            "Total count of bytes in HTTP response bodies.",
+        )
+    }
+
+    fn request_body_bytes(&self) -> Metric<'_, Prefixed<'_, &'static str>, Histogram<size::Bytes>> {
+        Metric::new(
+            self.prefix_key("request_body_bytes"),
+            "Sizes of HTTP request bodies, in bytes",
+        )
+    }
+
+    fn response_body_bytes(
+        &self,
+    ) -> Metric<'_, Prefixed<'_, &'static str>, Histogram<size::Bytes>> {
+        Metric::new(
+            self.prefix_key("response_body_bytes"),
+            "Sizes of HTTP response bodies, in bytes",
+        )
+    }
 }
 
 impl<T, C> FmtMetrics for Report<T, Metrics<C>>
@@ -70,6 +102,22 @@ where
         metric.fmt_help(f)?;
         registry.fmt_by_class(f, metric, |s| &s.total)?;
 
+        let metric = self.request_bytes_total();
+        metric.fmt_help(f)?;
+        registry.fmt_by_target(f, metric, |s| &s.request_bytes_total)?;
+
+        let metric = self.request_body_bytes();
+        metric.fmt_help(f)?;
+        registry.fmt_by_target(f, metric, |s| &s.request_body_bytes)?;
+
+        let metric = self.response_bytes_total();
+        metric.fmt_help(f)?;
+        registry.fmt_by_target(f, metric, |s| &s.response_bytes_total)?;
+
+        let metric = self.response_body_bytes();
+        metric.fmt_help(f)?;
+        registry.fmt_by_target(f, metric, |s| &s.response_body_bytes)?;
+
         registry.retain_since(clock::now() - self.retain_idle);
 
         Ok(())
@@ -93,9 +141,7 @@ where
         F: Fn(&Metrics<C>) -> &V,
     {
         for (tgt, tm) in &self.by_target {
-            if let Ok(m) = tm.lock() {
-                get_metric(&*m).fmt_metric_labeled(f, &metric.name, tgt)?;
-            }
+            get_metric(&*tm).fmt_metric_labeled(f, &metric.name, tgt)?;
         }
 
         Ok(())
@@ -113,8 +159,8 @@ where
         F: Fn(&StatusMetrics<C>) -> &M,
     {
         for (tgt, tm) in &self.by_target {
-            if let Ok(tm) = tm.lock() {
-                for (status, m) in &tm.by_status {
+            if let Ok(by_status) = tm.by_status.read() {
+                for (status, m) in by_status.iter() {
                     let status = status.as_ref().map(|s| Status(*s));
                     let labels = (tgt, status);
                     get_metric(&*m).fmt_metric_labeled(f, &metric.name, labels)?;
@@ -137,12 +183,14 @@ where
         F: Fn(&ClassMetrics) -> &M,
     {
         for (tgt, tm) in &self.by_target {
-            if let Ok(tm) = tm.lock() {
-                for (status, sm) in &tm.by_status {
-                    for (cls, m) in &sm.by_class {
-                        let status = status.as_ref().map(|s| Status(*s));
-                        let labels = (tgt, (status, cls));
-                        get_metric(&*m).fmt_metric_labeled(f, &metric.name, labels)?;
+            if let Ok(by_status) = tm.by_status.read() {
+                for (status, sm) in by_status.iter() {
+                    if let Ok(by_class) = sm.by_class.read() {
+                        for (cls, m) in by_class.iter() {
+                            let status = status.as_ref().map(|s| Status(*s));
+                            let labels = (tgt, (status, cls));
+                            get_metric(&*m).fmt_metric_labeled(f, &metric.name, labels)?;
+                        }
                     }
                 }
             }
@@ -1,4 +1,4 @@
-use super::{LastUpdate, Prefixed, Registry, Report};
+use super::{LastUpdate, Prefixed, Registry, Report, UpdatedAt};
 use linkerd2_metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Metric};
 use std::fmt;
 use std::hash::Hash;
@@ -13,11 +13,27 @@ where
     T: Hash + Eq;
 
 #[derive(Clone, Debug)]
-pub struct Handle(Arc<Mutex<Metrics>>);
+pub struct Handle(Arc<Metrics>);
 
 #[derive(Debug)]
 pub struct Metrics {
-    last_update: Instant,
+    last_update: UpdatedAt,
+    response: Counts,
+    error: Counts,
+}
+
+/// Describes why a request was retryable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The response was classified as a failure.
+    Response,
+
+    /// The request failed before a response was received.
+    Error,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
     retryable: Counter,
     no_budget: Counter,
 }
@@ -42,7 +58,7 @@ impl<T: Hash + Eq> Retries<T> {
         Handle(
             reg.by_target
                 .entry(target.into())
-                .or_insert_with(|| Arc::new(Mutex::new(Metrics::default())))
+                .or_insert_with(|| Arc::new(Metrics::default()))
                 .clone(),
         )
     }
@@ -57,13 +73,15 @@ impl<T: Hash + Eq> Clone for Retries<T> {
 // === impl Handle ===
 
 impl Handle {
-    pub fn incr_retryable(&self, has_budget: bool) {
-        if let Ok(mut m) = self.0.lock() {
-            m.last_update = clock::now();
-            m.retryable.incr();
-            if !has_budget {
-                m.no_budget.incr();
-            }
+    pub fn incr_retryable(&self, reason: Reason, has_budget: bool) {
+        self.0.last_update.touch();
+        let counts = match reason {
+            Reason::Response => &self.0.response,
+            Reason::Error => &self.0.error,
+        };
+        counts.retryable.incr();
+        if !has_budget {
+            counts.no_budget.incr();
         }
     }
 }
@@ -73,16 +91,16 @@ impl Handle {
 impl Default for Metrics {
     fn default() -> Self {
         Self {
-            last_update: clock::now(),
-            retryable: Counter::default(),
-            no_budget: Counter::default(),
+            last_update: UpdatedAt::default(),
+            response: Counts::default(),
+            error: Counts::default(),
         }
     }
 }
 
 impl LastUpdate for Metrics {
     fn last_update(&self) -> Instant {
-        self.last_update
+        self.last_update.get()
     }
 }
 
@@ -95,7 +113,7 @@ where
     fn retryable_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
         Metric::new(
             self.prefix_key("retryable_total"),
-            "Total count of retryable HTTP responses that were not retried.",
+            "Total count of retryable HTTP responses and request failures.",
         )
     }
 }
@@ -122,10 +140,15 @@ where
         let metric = self.retryable_total();
         metric.fmt_help(f)?;
         for (tgt, tm) in &registry.by_target {
-            if let Ok(m) = tm.lock() {
-                m.retryable.fmt_metric_labeled(f, &metric.name, tgt)?;
-                m.no_budget
-                    .fmt_metric_labeled(f, &metric.name, (tgt, NoBudgetLabel))?;
+            for (reason, counts) in &[(Reason::Response, &tm.response), (Reason::Error, &tm.error)]
+            {
+                let labels = (tgt, *reason);
+                counts
+                    .retryable
+                    .fmt_metric_labeled(f, &metric.name, labels)?;
+                counts
+                    .no_budget
+                    .fmt_metric_labeled(f, &metric.name, (labels, NoBudgetLabel))?;
             }
         }
 
@@ -135,6 +158,15 @@ where
     }
 }
 
+impl FmtLabels for Reason {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Response => write!(f, "reason=\"response\""),
+            Reason::Error => write!(f, "reason=\"error\""),
+        }
+    }
+}
+
 impl FmtLabels for NoBudgetLabel {
     fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "skipped=\"no_budget\"")
@@ -0,0 +1,146 @@
+#![deny(warnings, rust_2018_idioms)]
+
+//! Pushes the proxy's metrics to collectors that cannot scrape the admin
+//! server.
+//!
+//! The exporter periodically formats the same report that is served on
+//! `/metrics`, computes how each series changed since the previous export,
+//! and sends those changes to a sink such as `statsd::StatsD` or
+//! `otlp::Otlp`.
+
+use futures::{try_ready, Async, Future, Poll, Stream};
+use linkerd2_error::Error;
+use linkerd2_metrics::FmtMetrics;
+use std::time::{Duration, SystemTime};
+use tokio_timer::{clock, Interval};
+use tracing::{trace, warn};
+
+pub mod otlp;
+mod scrape;
+mod snapshot;
+pub mod statsd;
+
+use self::snapshot::Deltas;
+pub use self::snapshot::{Distribution, Metric, Point, Snapshot, Value};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How often metrics are exported.
+    pub interval: Duration,
+    /// Prepended to the name of each exported metric.
+    pub prefix: String,
+    /// Added to every exported series.
+    pub labels: Vec<(String, String)>,
+}
+
+/// Periodically pushes changes in a report's metrics to a sink.
+///
+/// The sink is a `Service` that accepts a `Snapshot`. Failed exports are
+/// logged and are not retried; the changes they contained are not resent.
+pub struct Exporter<M, S>
+where
+    S: tower::Service<Snapshot>,
+{
+    config: Config,
+    report: M,
+    sink: S,
+    interval: Interval,
+    deltas: Deltas,
+    last_export: SystemTime,
+    pending: Option<Snapshot>,
+    exporting: Option<S::Future>,
+}
+
+// === impl Exporter ===
+
+impl<M, S> Exporter<M, S>
+where
+    M: FmtMetrics,
+    S: tower::Service<Snapshot, Response = ()>,
+    S::Error: Into<Error>,
+{
+    pub fn new(config: Config, report: M, sink: S) -> Self {
+        let interval = Interval::new(clock::now() + config.interval, config.interval);
+        Self {
+            config,
+            report,
+            sink,
+            interval,
+            deltas: Deltas::default(),
+            last_export: SystemTime::now(),
+            pending: None,
+            exporting: None,
+        }
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        let text = self.report.as_display().to_string();
+        let families = scrape::parse(&text);
+        let metrics = self
+            .deltas
+            .update(families, &self.config.prefix, &self.config.labels);
+
+        let end = SystemTime::now();
+        let start = std::mem::replace(&mut self.last_export, end);
+        Snapshot {
+            start,
+            end,
+            metrics,
+        }
+    }
+}
+
+impl<M, S> Future for Exporter<M, S>
+where
+    M: FmtMetrics,
+    S: tower::Service<Snapshot, Response = ()>,
+    S::Error: Into<Error>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        loop {
+            if let Some(mut exporting) = self.exporting.take() {
+                match exporting.poll() {
+                    Ok(Async::NotReady) => {
+                        self.exporting = Some(exporting);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => trace!("exported"),
+                    Err(e) => {
+                        let e: Error = e.into();
+                        warn!(message = "failed to export metrics", error = %e);
+                    }
+                }
+            }
+
+            if let Some(snapshot) = self.pending.take() {
+                match self.sink.poll_ready() {
+                    Ok(Async::NotReady) => {
+                        self.pending = Some(snapshot);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => {
+                        self.exporting = Some(self.sink.call(snapshot));
+                        continue;
+                    }
+                    Err(e) => {
+                        let e: Error = e.into();
+                        warn!(message = "metrics sink failed", error = %e);
+                    }
+                }
+            }
+
+            match try_ready!(self.interval.poll()) {
+                Some(_) => {
+                    let snapshot = self.snapshot();
+                    if !snapshot.metrics.is_empty() {
+                        self.pending = Some(snapshot);
+                    }
+                }
+                None => return Ok(Async::Ready(())),
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Pushes metrics to an OpenTelemetry collector over gRPC.
+
+use crate::{Distribution, Metric, Point, Snapshot, Value};
+use futures::{try_ready, Async, Future, Poll};
+use opentelemetry_proto::{
+    collector::metrics::v1::{
+        client::MetricsService, ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+    },
+    common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue},
+    metrics::v1 as proto,
+    resource::v1::Resource,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tower_grpc::{self as grpc, generic::client::GrpcService, BoxBody};
+use tracing::trace;
+
+const SCOPE_NAME: &str = "linkerd2-proxy";
+
+pub struct Otlp<T> {
+    client: MetricsService<T>,
+    resource: Resource,
+}
+
+pub struct ResponseFuture<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    inner: grpc::client::unary::ResponseFuture<
+        ExportMetricsServiceResponse,
+        T::Future,
+        T::ResponseBody,
+    >,
+}
+
+// === impl Otlp ===
+
+impl<T> Otlp<T>
+where
+    T: GrpcService<BoxBody>,
+    T::ResponseBody: grpc::Body,
+{
+    /// Exports metrics on behalf of the named service.
+    pub fn new(inner: T, service_name: impl Into<String>) -> Self {
+        Self {
+            client: MetricsService::new(inner),
+            resource: Resource {
+                attributes: vec![key_value("service.name", service_name.into())],
+                dropped_attributes_count: 0,
+            },
+        }
+    }
+}
+
+impl<T> tower::Service<Snapshot> for Otlp<T>
+where
+    T: GrpcService<BoxBody>,
+    T::ResponseBody: grpc::Body,
+{
+    type Response = ();
+    type Error = grpc::Status;
+    type Future = ResponseFuture<T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.client.poll_ready()
+    }
+
+    fn call(&mut self, snapshot: Snapshot) -> Self::Future {
+        let req = export_request(self.resource.clone(), snapshot);
+        trace!(
+            metrics = req.resource_metrics[0].scope_metrics[0].metrics.len(),
+            "exporting"
+        );
+        ResponseFuture {
+            inner: self.client.export(grpc::Request::new(req)),
+        }
+    }
+}
+
+impl<T> Future for ResponseFuture<T>
+where
+    T: GrpcService<BoxBody>,
+    T::ResponseBody: grpc::Body,
+{
+    type Item = ();
+    type Error = grpc::Status;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        try_ready!(self.inner.poll());
+        Ok(Async::Ready(()))
+    }
+}
+
+fn export_request(resource: Resource, snapshot: Snapshot) -> ExportMetricsServiceRequest {
+    let start = unix_nanos(snapshot.start);
+    let end = unix_nanos(snapshot.end);
+    let metrics = snapshot
+        .metrics
+        .into_iter()
+        .map(|m| metric(m, start, end))
+        .collect();
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![proto::ResourceMetrics {
+            resource: Some(resource),
+            scope_metrics: vec![proto::ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: SCOPE_NAME.to_string(),
+                    version: String::new(),
+                }),
+                metrics,
+            }],
+        }],
+    }
+}
+
+fn metric(metric: Metric, start: u64, end: u64) -> proto::Metric {
+    let Metric { name, help, value } = metric;
+    let number = |p: Point<f64>, start: u64| proto::NumberDataPoint {
+        attributes: attributes(p.labels),
+        start_time_unix_nano: start,
+        time_unix_nano: end,
+        value: Some(proto::number_data_point::Value::AsDouble(p.value)),
+    };
+
+    let data = match value {
+        Value::Counter(points) => proto::metric::Data::Sum(proto::Sum {
+            data_points: points.into_iter().map(|p| number(p, start)).collect(),
+            aggregation_temporality: proto::AggregationTemporality::Delta as i32,
+            is_monotonic: true,
+        }),
+        Value::Gauge(points) => proto::metric::Data::Gauge(proto::Gauge {
+            // Gauges are instantaneous, so they have no start time.
+            data_points: points.into_iter().map(|p| number(p, 0)).collect(),
+        }),
+        Value::Histogram(points) => proto::metric::Data::Histogram(proto::Histogram {
+            data_points: points
+                .into_iter()
+                .map(|p| {
+                    let Distribution {
+                        bounds,
+                        counts,
+                        count,
+                        sum,
+                    } = p.value;
+                    proto::HistogramDataPoint {
+                        attributes: attributes(p.labels),
+                        start_time_unix_nano: start,
+                        time_unix_nano: end,
+                        count,
+                        sum,
+                        bucket_counts: counts,
+                        explicit_bounds: bounds,
+                    }
+                })
+                .collect(),
+            aggregation_temporality: proto::AggregationTemporality::Delta as i32,
+        }),
+    };
+
+    proto::Metric {
+        name,
+        description: help,
+        unit: String::new(),
+        data: Some(data),
+    }
+}
+
+fn attributes(labels: Vec<(String, String)>) -> Vec<KeyValue> {
+    labels.into_iter().map(|(k, v)| key_value(k, v)).collect()
+}
+
+fn key_value(key: impl Into<String>, value: String) -> KeyValue {
+    KeyValue {
+        key: key.into(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value)),
+        }),
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> u64 {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn converts_snapshot() {
+        let start = UNIX_EPOCH + Duration::from_secs(10);
+        let end = UNIX_EPOCH + Duration::from_secs(20);
+        let labels = vec![("direction".to_string(), "inbound".to_string())];
+        let snapshot = Snapshot {
+            start,
+            end,
+            metrics: vec![
+                Metric {
+                    name: "request_total".into(),
+                    help: "Total count of HTTP requests.".into(),
+                    value: Value::Counter(vec![Point {
+                        labels: labels.clone(),
+                        value: 2.0,
+                    }]),
+                },
+                Metric {
+                    name: "response_latency_ms".into(),
+                    help: String::new(),
+                    value: Value::Histogram(vec![Point {
+                        labels,
+                        value: Distribution {
+                            bounds: vec![10.0],
+                            counts: vec![1, 2],
+                            count: 3,
+                            sum: 45.0,
+                        },
+                    }]),
+                },
+            ],
+        };
+
+        let resource = Resource {
+            attributes: vec![key_value("service.name", "linkerd-proxy".into())],
+            dropped_attributes_count: 0,
+        };
+        let req = export_request(resource.clone(), snapshot);
+        let rm = &req.resource_metrics[0];
+        assert_eq!(rm.resource, Some(resource));
+        let metrics = &rm.scope_metrics[0].metrics;
+        assert_eq!(metrics.len(), 2);
+
+        assert_eq!(metrics[0].name, "request_total");
+        assert_eq!(metrics[0].description, "Total count of HTTP requests.");
+        match metrics[0].data {
+            Some(proto::metric::Data::Sum(ref sum)) => {
+                assert!(sum.is_monotonic);
+                assert_eq!(
+                    sum.aggregation_temporality,
+                    proto::AggregationTemporality::Delta as i32
+                );
+                let p = &sum.data_points[0];
+                assert_eq!(p.start_time_unix_nano, 10_000_000_000);
+                assert_eq!(p.time_unix_nano, 20_000_000_000);
+                assert_eq!(p.attributes[0].key, "direction");
+                assert_eq!(
+                    p.value,
+                    Some(proto::number_data_point::Value::AsDouble(2.0))
+                );
+            }
+            ref data => panic!("unexpected data: {:?}", data),
+        }
+
+        match metrics[1].data {
+            Some(proto::metric::Data::Histogram(ref hist)) => {
+                let p = &hist.data_points[0];
+                assert_eq!(p.count, 3);
+                assert_eq!(p.sum, 45.0);
+                assert_eq!(p.bucket_counts, vec![1, 2]);
+                assert_eq!(p.explicit_bounds, vec![10.0]);
+            }
+            ref data => panic!("unexpected data: {:?}", data),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Reads metrics back from their Prometheus text representation.
+//!
+//! Reports only know how to format themselves, so the exporter formats a
+//! report exactly as the admin server would and then parses the result.
+
+pub(crate) type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Family {
+    pub name: String,
+    pub help: String,
+    pub kind: Kind,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+    Untyped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Sample {
+    pub name: String,
+    pub labels: Labels,
+    pub value: f64,
+}
+
+/// Parses each metric family in `text`.
+///
+/// Lines that cannot be parsed are skipped.
+pub(crate) fn parse(text: &str) -> Vec<Family> {
+    let mut families = Vec::<Family>::new();
+    let mut help: Option<(&str, &str)> = None;
+
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if line.starts_with('#') {
+            let mut parts = line[1..].trim_start().splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("HELP"), Some(name), text) => {
+                    help = Some((name, text.unwrap_or("")));
+                }
+                (Some("TYPE"), Some(name), kind) => {
+                    let kind = match kind {
+                        Some("counter") => Kind::Counter,
+                        Some("gauge") => Kind::Gauge,
+                        Some("histogram") => Kind::Histogram,
+                        _ => Kind::Untyped,
+                    };
+                    let help = match help.take() {
+                        Some((n, text)) if n == name => text.to_string(),
+                        _ => String::new(),
+                    };
+                    families.push(Family {
+                        name: name.to_string(),
+                        help,
+                        kind,
+                        samples: Vec::new(),
+                    });
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let sample = match parse_sample(line) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        match families.last_mut() {
+            Some(ref mut family) if family.contains(&sample.name) => family.samples.push(sample),
+            _ => families.push(Family {
+                name: sample.name.clone(),
+                help: String::new(),
+                kind: Kind::Untyped,
+                samples: vec![sample],
+            }),
+        }
+    }
+
+    families
+}
+
+// === impl Family ===
+
+impl Family {
+    fn contains(&self, sample: &str) -> bool {
+        if sample == self.name {
+            return true;
+        }
+        if self.kind != Kind::Histogram || !sample.starts_with(&self.name) {
+            return false;
+        }
+        match &sample[self.name.len()..] {
+            "_bucket" | "_count" | "_sum" => true,
+            _ => false,
+        }
+    }
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c| c == '{' || c == ' ')?;
+    let name = line[..name_end].to_string();
+    let mut rest = &line[name_end..];
+
+    let mut labels = Labels::new();
+    if rest.starts_with('{') {
+        let (parsed, len) = parse_labels(&rest[1..])?;
+        labels = parsed;
+        rest = &rest[1 + len..];
+    }
+
+    let value = match rest.split_whitespace().next()? {
+        "+Inf" => ::std::f64::INFINITY,
+        "-Inf" => ::std::f64::NEG_INFINITY,
+        "NaN" => ::std::f64::NAN,
+        v => v.parse().ok()?,
+    };
+
+    Some(Sample {
+        name,
+        labels,
+        value,
+    })
+}
+
+/// Parses labels up to and including the closing brace, returning the number
+/// of bytes consumed.
+fn parse_labels(s: &str) -> Option<(Labels, usize)> {
+    let mut labels = Labels::new();
+    let mut chars = s.char_indices();
+    loop {
+        let mut key = String::new();
+        loop {
+            match chars.next()? {
+                (i, '}') if key.is_empty() => return Some((labels, i + 1)),
+                (_, '=') => break,
+                (_, ',') if key.is_empty() => {}
+                (_, c) => key.push(c),
+            }
+        }
+
+        if chars.next()?.1 != '"' {
+            return None;
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next()?.1 {
+                '"' => break,
+                '\\' => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+
+        labels.push((key.trim().to_string(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_families() {
+        let text = "\
+# HELP request_total Total count of HTTP requests.
+# TYPE request_total counter
+request_total{direction=\"inbound\",authority=\"a\\\"b,c\"} 3
+# HELP response_latency_ms Elapsed times.
+# TYPE response_latency_ms histogram
+response_latency_ms_bucket{direction=\"inbound\",le=\"10\"} 1
+response_latency_ms_bucket{direction=\"inbound\",le=\"+Inf\"} 2
+response_latency_ms_count{direction=\"inbound\"} 2
+response_latency_ms_sum{direction=\"inbound\"} 15
+process_start_time_seconds 1580000000
+";
+        let families = parse(text);
+        assert_eq!(families.len(), 3);
+
+        assert_eq!(families[0].name, "request_total");
+        assert_eq!(families[0].help, "Total count of HTTP requests.");
+        assert_eq!(families[0].kind, Kind::Counter);
+        assert_eq!(
+            families[0].samples,
+            vec![Sample {
+                name: "request_total".into(),
+                labels: vec![
+                    ("direction".into(), "inbound".into()),
+                    ("authority".into(), "a\"b,c".into()),
+                ],
+                value: 3.0,
+            }]
+        );
+
+        assert_eq!(families[1].kind, Kind::Histogram);
+        assert_eq!(families[1].samples.len(), 4);
+        assert_eq!(families[1].samples[1].value, 2.0);
+        assert_eq!(
+            families[1].samples[1].labels[1],
+            ("le".into(), "+Inf".into())
+        );
+
+        assert_eq!(families[2].name, "process_start_time_seconds");
+        assert_eq!(families[2].kind, Kind::Untyped);
+        assert!(families[2].samples[0].labels.is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::scrape::{Family, Kind, Labels, Sample};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// The metrics that changed between two exports.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// When the previous export was taken.
+    pub start: SystemTime,
+    /// When this export was taken.
+    pub end: SystemTime,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub help: String,
+    pub value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The amount each counter increased since the previous export.
+    Counter(Vec<Point<f64>>),
+    /// The current value of each gauge.
+    Gauge(Vec<Point<f64>>),
+    /// The observations recorded by each histogram since the previous export.
+    Histogram(Vec<Point<Distribution>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point<V> {
+    pub labels: Vec<(String, String)>,
+    pub value: V,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Distribution {
+    /// The upper bound of each bucket except the last, which is unbounded.
+    pub bounds: Vec<f64>,
+    /// The number of observations in each bucket. Unlike Prometheus buckets,
+    /// these are not cumulative.
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+/// Remembers the last value of each cumulative series so that only changes
+/// are exported.
+#[derive(Debug, Default)]
+pub(crate) struct Deltas {
+    last: HashMap<(String, Labels), f64>,
+}
+
+// === impl Deltas ===
+
+impl Deltas {
+    /// Converts a scrape into the changes since the previous scrape.
+    ///
+    /// Series that are absent from `families` are forgotten, so that they
+    /// are exported in full if they reappear.
+    pub fn update(
+        &mut self,
+        families: Vec<Family>,
+        prefix: &str,
+        labels: &[(String, String)],
+    ) -> Vec<Metric> {
+        let mut next = HashMap::with_capacity(self.last.len());
+        let mut metrics = Vec::with_capacity(families.len());
+
+        for family in families {
+            let value = match family.kind {
+                Kind::Counter => {
+                    let points = family
+                        .samples
+                        .into_iter()
+                        .filter_map(|s| {
+                            let Sample {
+                                name,
+                                labels,
+                                value,
+                            } = s;
+                            let delta = self.delta(&mut next, name, labels.clone(), value);
+                            if delta > 0.0 {
+                                Some(Point {
+                                    labels,
+                                    value: delta,
+                                })
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    Value::Counter(points)
+                }
+                Kind::Gauge | Kind::Untyped => {
+                    let points = family
+                        .samples
+                        .into_iter()
+                        .map(|s| Point {
+                            labels: s.labels,
+                            value: s.value,
+                        })
+                        .collect();
+                    Value::Gauge(points)
+                }
+                Kind::Histogram => Value::Histogram(self.histogram(&mut next, family.samples)),
+            };
+
+            let is_empty = match value {
+                Value::Counter(ref p) | Value::Gauge(ref p) => p.is_empty(),
+                Value::Histogram(ref p) => p.is_empty(),
+            };
+            if is_empty {
+                continue;
+            }
+
+            metrics.push(Metric {
+                name: format!("{}{}", prefix, family.name),
+                help: family.help,
+                value: value.with_labels(labels),
+            });
+        }
+
+        self.last = next;
+        metrics
+    }
+
+    fn delta(
+        &self,
+        next: &mut HashMap<(String, Labels), f64>,
+        name: String,
+        labels: Labels,
+        value: f64,
+    ) -> f64 {
+        let key = (name, labels);
+        let delta = match self.last.get(&key) {
+            Some(&prior) if value >= prior => value - prior,
+            // The series is new, or it was reset or wrapped.
+            _ => value,
+        };
+        next.insert(key, value);
+        delta
+    }
+
+    fn histogram(
+        &self,
+        next: &mut HashMap<(String, Labels), f64>,
+        samples: Vec<Sample>,
+    ) -> Vec<Point<Distribution>> {
+        // Buckets are cumulative, so their deltas are collected before
+        // they're converted to per-bucket counts.
+        let mut by_labels = IndexMap::<Labels, (Distribution, Vec<u64>)>::new();
+
+        for Sample {
+            name,
+            labels,
+            value,
+        } in samples
+        {
+            let delta = self.delta(next, name.clone(), labels.clone(), value);
+            if name.ends_with("_bucket") {
+                let mut le = None;
+                let labels = labels
+                    .into_iter()
+                    .filter(|(k, v)| {
+                        if k == "le" {
+                            le = Some(v.clone());
+                            false
+                        } else {
+                            true
+                        }
+                    })
+                    .collect::<Labels>();
+                let (dist, cumulative) = by_labels.entry(labels).or_insert_with(Default::default);
+                match le.as_ref().map(String::as_str) {
+                    Some("+Inf") => {}
+                    Some(le) => match le.parse() {
+                        Ok(bound) => dist.bounds.push(bound),
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                }
+                cumulative.push(delta as u64);
+            } else if name.ends_with("_count") {
+                by_labels
+                    .entry(labels)
+                    .or_insert_with(Default::default)
+                    .0
+                    .count = delta as u64;
+            } else if name.ends_with("_sum") {
+                by_labels
+                    .entry(labels)
+                    .or_insert_with(Default::default)
+                    .0
+                    .sum = delta;
+            }
+        }
+
+        by_labels
+            .into_iter()
+            .filter(|(_, (dist, _))| dist.count > 0)
+            .map(|(labels, (mut dist, cumulative))| {
+                let mut prior = 0;
+                dist.counts = cumulative
+                    .into_iter()
+                    .map(|c| {
+                        let n = c.saturating_sub(prior);
+                        prior = c;
+                        n
+                    })
+                    .collect();
+                Point {
+                    labels,
+                    value: dist,
+                }
+            })
+            .collect()
+    }
+}
+
+// === impl Value ===
+
+impl Value {
+    fn with_labels(self, labels: &[(String, String)]) -> Self {
+        fn extend<V>(mut points: Vec<Point<V>>, labels: &[(String, String)]) -> Vec<Point<V>> {
+            for p in &mut points {
+                p.labels.extend(labels.iter().cloned());
+            }
+            points
+        }
+
+        if labels.is_empty() {
+            return self;
+        }
+        match self {
+            Value::Counter(p) => Value::Counter(extend(p, labels)),
+            Value::Gauge(p) => Value::Gauge(extend(p, labels)),
+            Value::Histogram(p) => Value::Histogram(extend(p, labels)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrape;
+
+    fn scrape(requests: u64, fast: u64, slow: u64) -> Vec<Family> {
+        scrape::parse(&format!(
+            "\
+# TYPE request_total counter
+request_total{{direction=\"inbound\"}} {requests}
+# TYPE tcp_open_connections gauge
+tcp_open_connections{{direction=\"inbound\"}} 2
+# TYPE response_latency_ms histogram
+response_latency_ms_bucket{{direction=\"inbound\",le=\"10\"}} {fast}
+response_latency_ms_bucket{{direction=\"inbound\",le=\"+Inf\"}} {total}
+response_latency_ms_count{{direction=\"inbound\"}} {total}
+response_latency_ms_sum{{direction=\"inbound\"}} {sum}
+",
+            requests = requests,
+            fast = fast,
+            total = fast + slow,
+            sum = fast * 5 + slow * 50,
+        ))
+    }
+
+    #[test]
+    fn exports_changes() {
+        let mut deltas = Deltas::default();
+        let extra = vec![("pod".to_string(), "web-0".to_string())];
+        let labels = |direction: &str| {
+            vec![
+                ("direction".to_string(), direction.to_string()),
+                ("pod".to_string(), "web-0".to_string()),
+            ]
+        };
+
+        let first = deltas.update(scrape(3, 1, 1), "linkerd.", &extra);
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[0].name, "linkerd.request_total");
+        assert_eq!(
+            first[0].value,
+            Value::Counter(vec![Point {
+                labels: labels("inbound"),
+                value: 3.0
+            }])
+        );
+        assert_eq!(
+            first[2].value,
+            Value::Histogram(vec![Point {
+                labels: labels("inbound"),
+                value: Distribution {
+                    bounds: vec![10.0],
+                    counts: vec![1, 1],
+                    count: 2,
+                    sum: 55.0,
+                }
+            }])
+        );
+
+        // Only the gauge is exported when nothing has changed.
+        let idle = deltas.update(scrape(3, 1, 1), "linkerd.", &extra);
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].name, "linkerd.tcp_open_connections");
+
+        let second = deltas.update(scrape(5, 1, 3), "linkerd.", &extra);
+        assert_eq!(
+            second[0].value,
+            Value::Counter(vec![Point {
+                labels: labels("inbound"),
+                value: 2.0
+            }])
+        );
+        assert_eq!(
+            second[2].value,
+            Value::Histogram(vec![Point {
+                labels: labels("inbound"),
+                value: Distribution {
+                    bounds: vec![10.0],
+                    counts: vec![0, 2],
+                    count: 2,
+                    sum: 100.0,
+                }
+            }])
+        );
+
+        // A counter that goes backwards was reset.
+        let reset = deltas.update(scrape(1, 1, 3), "linkerd.", &extra);
+        assert_eq!(
+            reset[0].value,
+            Value::Counter(vec![Point {
+                labels: labels("inbound"),
+                value: 1.0
+            }])
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Pushes metrics to a StatsD server over UDP.
+//!
+//! Labels are written as DogStatsD-style tags, i.e. `name:1|c|#key:value`.
+
+use crate::{Metric, Snapshot, Value};
+use futures::{future, Async, Poll};
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use tracing::{debug, trace};
+
+/// The largest datagram that fits in a typical Ethernet MTU.
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+#[derive(Debug)]
+pub struct StatsD {
+    socket: UdpSocket,
+}
+
+// === impl StatsD ===
+
+impl StatsD {
+    pub fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let bind: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(addr)?;
+        // StatsD is lossy by design, so datagrams are dropped rather than
+        // blocking the exporter.
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    fn send(&self, datagram: &str) -> io::Result<()> {
+        match self.socket.send(datagram.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                debug!("dropping StatsD datagram");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl tower::Service<Snapshot> for StatsD {
+    type Response = ();
+    type Error = io::Error;
+    type Future = future::FutureResult<(), io::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, snapshot: Snapshot) -> Self::Future {
+        let mut datagram = String::with_capacity(MAX_DATAGRAM_SIZE);
+        for line in snapshot.metrics.iter().flat_map(lines) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_SIZE {
+                if let Err(e) = self.send(&datagram) {
+                    return future::err(e);
+                }
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+
+        if !datagram.is_empty() {
+            trace!(bytes = datagram.len(), "sending");
+            if let Err(e) = self.send(&datagram) {
+                return future::err(e);
+            }
+        }
+
+        future::ok(())
+    }
+}
+
+/// Formats each line for a metric.
+///
+/// Histograms are written as a counter per bucket, tagged by the bucket's
+/// upper bound, along with `_count` and `_sum` counters.
+fn lines(metric: &Metric) -> Vec<String> {
+    let mut lines = Vec::new();
+    match metric.value {
+        Value::Counter(ref points) => {
+            for p in points {
+                lines.push(line(&metric.name, p.value, "c", &p.labels, None));
+            }
+        }
+        Value::Gauge(ref points) => {
+            for p in points {
+                lines.push(line(&metric.name, p.value, "g", &p.labels, None));
+            }
+        }
+        Value::Histogram(ref points) => {
+            let bucket = format!("{}_bucket", metric.name);
+            let count = format!("{}_count", metric.name);
+            let sum = format!("{}_sum", metric.name);
+            for p in points {
+                let dist = &p.value;
+                let mut cumulative = 0;
+                for (i, n) in dist.counts.iter().enumerate() {
+                    cumulative += n;
+                    let le = match dist.bounds.get(i) {
+                        Some(bound) => bound.to_string(),
+                        None => "+Inf".to_string(),
+                    };
+                    let le = ("le", le.as_str());
+                    lines.push(line(&bucket, cumulative as f64, "c", &p.labels, Some(le)));
+                }
+                lines.push(line(&count, dist.count as f64, "c", &p.labels, None));
+                lines.push(line(&sum, dist.sum, "c", &p.labels, None));
+            }
+        }
+    }
+    lines
+}
+
+fn line(
+    name: &str,
+    value: f64,
+    kind: &str,
+    labels: &[(String, String)],
+    extra: Option<(&str, &str)>,
+) -> String {
+    let mut line = format!("{}:{}|{}", sanitize(name), value, kind);
+    let tags = labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain(extra);
+    for (i, (k, v)) in tags.enumerate() {
+        let sep = if i == 0 { "|#" } else { "," };
+        let _ = write!(line, "{}{}:{}", sep, sanitize(k), sanitize(v));
+    }
+    line
+}
+
+/// Replaces characters that are reserved by the StatsD line protocol.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ':' | '|' | ',' | '#' | '@' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Distribution, Point};
+    use futures::Future;
+    use std::time::{Duration, SystemTime};
+    use tower::Service;
+
+    #[test]
+    fn sends_lines() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut statsd = StatsD::connect(server.local_addr().unwrap()).unwrap();
+
+        let labels = vec![("direction".to_string(), "inbound".to_string())];
+        let now = SystemTime::now();
+        let snapshot = Snapshot {
+            start: now,
+            end: now,
+            metrics: vec![
+                Metric {
+                    name: "request_total".into(),
+                    help: String::new(),
+                    value: Value::Counter(vec![Point {
+                        labels: labels.clone(),
+                        value: 2.0,
+                    }]),
+                },
+                Metric {
+                    name: "tcp_open_connections".into(),
+                    help: String::new(),
+                    value: Value::Gauge(vec![Point {
+                        labels: vec![],
+                        value: 1.0,
+                    }]),
+                },
+                Metric {
+                    name: "response_latency_ms".into(),
+                    help: String::new(),
+                    value: Value::Histogram(vec![Point {
+                        labels,
+                        value: Distribution {
+                            bounds: vec![10.0],
+                            counts: vec![1, 2],
+                            count: 3,
+                            sum: 45.0,
+                        },
+                    }]),
+                },
+            ],
+        };
+        statsd.call(snapshot).wait().expect("send must succeed");
+
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let n = server.recv(&mut buf).expect("datagram must be received");
+        let datagram = std::str::from_utf8(&buf[..n]).unwrap();
+        assert_eq!(
+            datagram.lines().collect::<Vec<_>>(),
+            vec![
+                "request_total:2|c|#direction:inbound",
+                "tcp_open_connections:1|g",
+                "response_latency_ms_bucket:1|c|#direction:inbound,le:10",
+                "response_latency_ms_bucket:3|c|#direction:inbound,le:+Inf",
+                "response_latency_ms_count:3|c|#direction:inbound",
+                "response_latency_ms_sum:45|c|#direction:inbound",
+            ]
+        );
+    }
+}
@@ -1,13 +1,16 @@
 use std::fmt;
 use std::marker::PhantomData;
-use std::{cmp, iter, slice};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{cmp, error, iter, slice};
 
 use super::{Counter, FmtLabels, FmtMetric};
 
 /// A series of latency values and counts.
 #[derive(Debug)]
 pub struct Histogram<V: Into<u64>> {
-    bounds: &'static Bounds,
+    bounds: Bounds,
     buckets: Box<[Counter]>,
 
     /// The total sum of all observed latency values.
@@ -32,9 +35,23 @@ pub struct Histogram<V: Into<u64>> {
     //       bits.
     sum: Counter,
 
+    /// The most recent exemplar recorded in each bucket, if any.
+    ///
+    /// Exemplars are only written in OpenMetrics output, so they are not
+    /// allocated until one is recorded.
+    exemplars: Mutex<Option<Box<[Option<Exemplar>]>>>,
+
     _p: PhantomData<V>,
 }
 
+/// Links an observation in a histogram bucket to the trace that produced it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    pub value: u64,
+    pub timestamp: SystemTime,
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Bucket {
     Le(u64),
@@ -42,8 +59,18 @@ pub enum Bucket {
 }
 
 /// A series of increasing Buckets values.
-#[derive(Debug)]
-pub struct Bounds(pub &'static [Bucket]);
+///
+/// Bounds may be parsed from a comma-separated list of positive, increasing upper
+/// bounds, e.g. `5,10,25,50`. A final `+Inf` bucket is always included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bounds(Arc<[Bucket]>);
+
+/// Indicates that histogram bounds could not be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InvalidBounds {
+    NotANumber(String),
+    NotIncreasing(u64),
+}
 
 /// Helper that lazily formats metric keys as {0}_{1}.
 struct Key<A: fmt::Display, B: fmt::Display>(A, B);
@@ -54,7 +81,7 @@ struct Label<K: fmt::Display, V: fmt::Display>(K, V);
 // ===== impl Histogram =====
 
 impl<V: Into<u64>> Histogram<V> {
-    pub fn new(bounds: &'static Bounds) -> Self {
+    pub fn new(bounds: Bounds) -> Self {
         let mut buckets = Vec::with_capacity(bounds.0.len());
         let mut prior = &Bucket::Le(0);
         for bound in bounds.0.iter() {
@@ -67,14 +94,37 @@ impl<V: Into<u64>> Histogram<V> {
             bounds,
             buckets: buckets.into_boxed_slice(),
             sum: Counter::default(),
+            exemplars: Mutex::new(None),
             _p: PhantomData,
         }
     }
 
     pub fn add<U: Into<V>>(&self, u: U) {
+        let v: V = u.into();
+        self.record(v.into());
+    }
+
+    /// Records a value along with the ID of the trace that observed it.
+    ///
+    /// The exemplar replaces any previously recorded in the same bucket.
+    pub fn add_with_exemplar<U: Into<V>>(&self, u: U, trace_id: String) {
         let v: V = u.into();
         let value: u64 = v.into();
+        let idx = self.record(value);
+
+        if let Ok(mut exemplars) = self.exemplars.lock() {
+            let len = self.buckets.len();
+            let exemplars = exemplars.get_or_insert_with(|| vec![None; len].into_boxed_slice());
+            exemplars[idx] = Some(Exemplar {
+                trace_id,
+                value,
+                timestamp: SystemTime::now(),
+            });
+        }
+    }
 
+    /// Increments the bucket that contains `value`, returning its index.
+    fn record(&self, value: u64) -> usize {
         let idx = self
             .bounds
             .0
@@ -87,6 +137,39 @@ impl<V: Into<u64>> Histogram<V> {
 
         self.buckets[idx].incr();
         self.sum.add(value);
+        idx
+    }
+
+    /// Writes a single cumulative bucket, followed by its exemplar when
+    /// formatting OpenMetrics.
+    fn fmt_bucket<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+        count: u64,
+        exemplar: Option<&Exemplar>,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        write!(f, "{}{{", name)?;
+        labels.fmt_labels(f)?;
+        write!(f, "}} {}", count)?;
+        if let Some(exemplar) = exemplar {
+            write!(f, " {}", exemplar)?;
+        }
+        writeln!(f)
+    }
+
+    /// Returns a copy of the recorded exemplars if OpenMetrics is being
+    /// formatted.
+    fn exemplars(&self, f: &fmt::Formatter<'_>) -> Option<Box<[Option<Exemplar>]>> {
+        if !f.alternate() {
+            return None;
+        }
+        self.exemplars.lock().ok().and_then(|e| e.clone())
     }
 }
 
@@ -186,10 +269,18 @@ impl<V: Into<u64>> FmtMetric for Histogram<V> {
     const KIND: &'static str = "histogram";
 
     fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        let exemplars = self.exemplars(f);
         let total = Counter::default();
-        for (le, count) in self {
+        for (i, (le, count)) in self.into_iter().enumerate() {
             total.add(count.value());
-            total.fmt_metric_labeled(f, Key(&name, "bucket"), Label("le", le))?;
+            let exemplar = exemplars.as_ref().and_then(|e| e[i].as_ref());
+            self.fmt_bucket(
+                f,
+                Key(&name, "bucket"),
+                Label("le", le),
+                total.value(),
+                exemplar,
+            )?;
         }
         total.fmt_metric(f, Key(&name, "count"))?;
         self.sum.fmt_metric(f, Key(&name, "sum"))?;
@@ -207,10 +298,18 @@ impl<V: Into<u64>> FmtMetric for Histogram<V> {
         N: fmt::Display,
         L: FmtLabels,
     {
+        let exemplars = self.exemplars(f);
         let total = Counter::default();
-        for (le, count) in self {
+        for (i, (le, count)) in self.into_iter().enumerate() {
             total.add(count.value());
-            total.fmt_metric_labeled(f, Key(&name, "bucket"), (&labels, Label("le", le)))?;
+            let exemplar = exemplars.as_ref().and_then(|e| e[i].as_ref());
+            self.fmt_bucket(
+                f,
+                Key(&name, "bucket"),
+                (&labels, Label("le", le)),
+                total.value(),
+                exemplar,
+            )?;
         }
         total.fmt_metric_labeled(f, Key(&name, "count"), &labels)?;
         self.sum.fmt_metric_labeled(f, Key(&name, "sum"), &labels)?;
@@ -219,6 +318,73 @@ impl<V: Into<u64>> FmtMetric for Histogram<V> {
     }
 }
 
+// ===== impl Bounds =====
+
+impl Bounds {
+    pub fn buckets(&self) -> &[Bucket] {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a [Bucket]> for Bounds {
+    fn from(buckets: &'a [Bucket]) -> Self {
+        Bounds(buckets.into())
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = InvalidBounds;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buckets = Vec::new();
+        let mut prior = 0;
+        for le in s.split(',').map(str::trim).filter(|le| !le.is_empty()) {
+            let le = le
+                .parse::<u64>()
+                .map_err(|_| InvalidBounds::NotANumber(le.to_string()))?;
+            if le <= prior {
+                return Err(InvalidBounds::NotIncreasing(le));
+            }
+            prior = le;
+            buckets.push(Bucket::Le(le));
+        }
+        buckets.push(Bucket::Inf);
+        Ok(Bounds(buckets.into()))
+    }
+}
+
+impl fmt::Display for InvalidBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidBounds::NotANumber(le) => write!(f, "invalid bucket bound: {}", le),
+            InvalidBounds::NotIncreasing(le) => {
+                write!(f, "bucket bounds must be positive and increasing: {}", le)
+            }
+        }
+    }
+}
+
+impl error::Error for InvalidBounds {}
+
+// ===== impl Exemplar =====
+
+impl fmt::Display for Exemplar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ts = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "# {{trace_id=\"{}\"}} {} {}.{:03}",
+            self.trace_id,
+            self.value,
+            ts.as_secs(),
+            ts.subsec_millis()
+        )
+    }
+}
+
 // ===== impl Key =====
 
 impl<A: fmt::Display, B: fmt::Display> fmt::Display for Key<A, B> {
@@ -293,7 +459,7 @@ mod tests {
     use std::collections::HashMap;
     use std::u64;
 
-    static BOUNDS: &'static Bounds = &Bounds(&[
+    static BUCKETS: &[Bucket] = &[
         Bucket::Le(10),
         Bucket::Le(20),
         Bucket::Le(30),
@@ -341,11 +507,77 @@ mod tests {
         Bucket::Le(900_000),
         Bucket::Le(1_000_000),
         Bucket::Inf,
-    ]);
+    ];
+
+    #[test]
+    fn parse_bounds() {
+        let bounds = "5, 10,25,50".parse::<Bounds>().unwrap();
+        assert_eq!(
+            bounds.buckets(),
+            &[
+                Bucket::Le(5),
+                Bucket::Le(10),
+                Bucket::Le(25),
+                Bucket::Le(50),
+                Bucket::Inf
+            ]
+        );
+        assert_eq!("".parse::<Bounds>().unwrap().buckets(), &[Bucket::Inf]);
+        assert_eq!(
+            "10,5".parse::<Bounds>(),
+            Err(InvalidBounds::NotIncreasing(5))
+        );
+        assert_eq!(
+            "10,10".parse::<Bounds>(),
+            Err(InvalidBounds::NotIncreasing(10))
+        );
+        assert_eq!(
+            "0,10".parse::<Bounds>(),
+            Err(InvalidBounds::NotIncreasing(0))
+        );
+        assert_eq!(
+            "1ms".parse::<Bounds>(),
+            Err(InvalidBounds::NotANumber("1ms".into()))
+        );
+    }
+
+    #[test]
+    fn exemplars_only_in_openmetrics() {
+        struct Fmt<'a>(&'a Histogram<u64>);
+        impl<'a> fmt::Display for Fmt<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_metric(f, "test")
+            }
+        }
+
+        let hist = Histogram::<u64>::new("10,20".parse().unwrap());
+        hist.add(5);
+        hist.add_with_exemplar(15u64, "abc".into());
+
+        let prom = format!("{}", Fmt(&hist));
+        assert_eq!(
+            prom,
+            "test_bucket{le=\"10\"} 1\n\
+             test_bucket{le=\"20\"} 2\n\
+             test_bucket{le=\"+Inf\"} 2\n\
+             test_count 2\n\
+             test_sum 20\n"
+        );
+
+        let om = format!("{:#}", Fmt(&hist));
+        let lines = om.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "test_bucket{le=\"10\"} 1");
+        assert!(
+            lines[1].starts_with("test_bucket{le=\"20\"} 2 # {trace_id=\"abc\"} 15 "),
+            "{}",
+            lines[1]
+        );
+        assert_eq!(lines[2], "test_bucket{le=\"+Inf\"} 2");
+    }
 
     quickcheck! {
         fn bucket_incremented(obs: u64) -> bool {
-            let hist = Histogram::<u64>::new(&BOUNDS);
+            let hist = Histogram::<u64>::new(BUCKETS.into());
             hist.add(obs);
             // The bucket containing `obs` must have count 1.
             hist.assert_bucket_exactly(obs, 1)
@@ -359,7 +591,7 @@ mod tests {
         }
 
         fn sum_equals_total_of_observations(observations: Vec<u64>) -> bool {
-            let hist = Histogram::<u64>::new(&BOUNDS);
+            let hist = Histogram::<u64>::new(BUCKETS.into());
 
             let expected_sum = Counter::default();
             for obs in observations {
@@ -371,7 +603,7 @@ mod tests {
         }
 
         fn count_equals_number_of_observations(observations: Vec<u64>) -> bool {
-            let hist = Histogram::<u64>::new(&BOUNDS);
+            let hist = Histogram::<u64>::new(BUCKETS.into());
 
             for obs in &observations {
                 hist.add(*obs);
@@ -383,10 +615,10 @@ mod tests {
 
         fn multiple_observations_increment_buckets(observations: Vec<u64>) -> bool {
             let mut buckets_and_counts: HashMap<usize, u64> = HashMap::new();
-            let hist = Histogram::<u64>::new(&BOUNDS);
+            let hist = Histogram::<u64>::new(BUCKETS.into());
 
             for obs in observations {
-                let incremented_bucket = &BOUNDS.0.iter()
+                let incremented_bucket = &BUCKETS.iter()
                     .position(|bucket| match *bucket {
                         Bucket::Le(ceiling) => obs <= ceiling,
                         Bucket::Inf => true,
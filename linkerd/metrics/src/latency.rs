@@ -2,9 +2,8 @@ use std::time::Duration;
 
 use super::histogram::{Bounds, Bucket, Histogram};
 
-/// The maximum value (inclusive) for each latency bucket in
-/// milliseconds.
-pub const BOUNDS: &Bounds = &Bounds(&[
+/// The default maximum value (inclusive) for each latency bucket.
+const BUCKETS: &[Bucket] = &[
     Bucket::Le(1),
     Bucket::Le(2),
     Bucket::Le(3),
@@ -32,7 +31,12 @@ pub const BOUNDS: &Bounds = &Bounds(&[
     Bucket::Le(50_000),
     // A final upper bound.
     Bucket::Inf,
-]);
+];
+
+/// Returns the default bounds for latency histograms.
+pub fn bounds() -> Bounds {
+    BUCKETS.into()
+}
 
 /// A duration in milliseconds.
 #[derive(Debug, Default, Clone)]
@@ -61,7 +65,7 @@ impl From<Duration> for Us {
 
 impl Default for Histogram<Us> {
     fn default() -> Self {
-        Histogram::new(BOUNDS)
+        Histogram::new(bounds())
     }
 }
 
@@ -82,6 +86,6 @@ impl From<Duration> for Ms {
 
 impl Default for Histogram<Ms> {
     fn default() -> Self {
-        Histogram::new(BOUNDS)
+        Histogram::new(bounds())
     }
 }
@@ -9,10 +9,11 @@ pub mod latency;
 mod prom;
 mod scopes;
 mod serve;
+pub mod size;
 
 pub use self::counter::Counter;
 pub use self::gauge::Gauge;
-pub use self::histogram::Histogram;
+pub use self::histogram::{Bounds, Bucket, Exemplar, Histogram, InvalidBounds};
 pub use self::prom::{FmtLabels, FmtMetric, FmtMetrics, Metric};
 pub use self::scopes::Scopes;
 pub use self::serve::Serve;
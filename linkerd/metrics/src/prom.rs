@@ -9,6 +9,11 @@ use std::marker::{PhantomData, Sized};
 pub(crate) const MAX_PRECISE_VALUE: u64 = 0x20_0000_0000_0000;
 
 /// Writes a block of metrics in prometheus-formatted output.
+///
+/// When formatted with the alternate flag (i.e. `{:#}`), metrics are written
+/// in the OpenMetrics text format instead, which permits histogram buckets to
+/// carry exemplars. Implementations should pass the formatter through
+/// unchanged so that the flag is preserved.
 pub trait FmtMetrics {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
 
@@ -91,11 +96,30 @@ impl<'a, N: fmt::Display, M: FmtMetric> Metric<'a, N, M> {
 
     /// Formats help messages for this metric.
     pub fn fmt_help(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() && M::KIND == "counter" {
+            return self.fmt_openmetrics_counter_help(f);
+        }
+
         writeln!(f, "# HELP {} {}", self.name, self.help)?;
         writeln!(f, "# TYPE {} {}", self.name, M::KIND)?;
         Ok(())
     }
 
+    /// OpenMetrics names counter families without their `_total` suffix.
+    /// Counters that lack the suffix cannot be described as counters, so they
+    /// are exposed as `unknown` instead.
+    fn fmt_openmetrics_counter_help(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name.to_string();
+        let (family, kind) = if name.ends_with("_total") {
+            (&name[..name.len() - "_total".len()], M::KIND)
+        } else {
+            (&name[..], "unknown")
+        };
+        writeln!(f, "# HELP {} {}", family, self.help)?;
+        writeln!(f, "# TYPE {} {}", family, kind)?;
+        Ok(())
+    }
+
     /// Formats a single metric without labels.
     pub fn fmt_metric(&self, f: &mut fmt::Formatter<'_>, metric: &M) -> fmt::Result {
         metric.fmt_metric(f, &self.name)
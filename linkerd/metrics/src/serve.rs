@@ -10,7 +10,13 @@ use tracing::{error, trace};
 
 use super::FmtMetrics;
 
+const OPENMETRICS: &str = "application/openmetrics-text";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
 /// Serve Prometheues metrics.
+///
+/// Metrics are written in the OpenMetrics format when the request's `Accept`
+/// header includes `application/openmetrics-text`.
 #[derive(Debug, Clone)]
 pub struct Serve<M: FmtMetrics> {
     metrics: M,
@@ -41,6 +47,25 @@ impl<M: FmtMetrics> Serve<M> {
                     .unwrap_or(false)
             })
     }
+
+    fn is_openmetrics<B>(req: &Request<B>) -> bool {
+        req.headers().get_all(header::ACCEPT).iter().any(|value| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| value.contains(OPENMETRICS))
+                .unwrap_or(false)
+        })
+    }
+
+    fn write_metrics<W: Write>(&self, writer: &mut W, openmetrics: bool) -> io::Result<()> {
+        if openmetrics {
+            write!(writer, "{:#}", self.metrics.as_display())?;
+            writer.write_all(b"# EOF\n")
+        } else {
+            write!(writer, "{}", self.metrics.as_display())
+        }
+    }
 }
 
 impl<M: FmtMetrics> Service for Serve<M> {
@@ -58,26 +83,33 @@ impl<M: FmtMetrics> Service for Serve<M> {
             return future::ok(rsp);
         }
 
+        let openmetrics = Self::is_openmetrics(&req);
+        let content_type = if openmetrics {
+            OPENMETRICS_CONTENT_TYPE
+        } else {
+            "text/plain"
+        };
+
         let resp = if Self::is_gzip(&req) {
             trace!("gzipping metrics");
             let mut writer = GzEncoder::new(Vec::<u8>::new(), CompressionOptions::fast());
-            write!(&mut writer, "{}", self.metrics.as_display())
+            self.write_metrics(&mut writer, openmetrics)
                 .and_then(|_| writer.finish())
                 .map_err(ServeError::from)
                 .and_then(|body| {
                     Response::builder()
                         .header(header::CONTENT_ENCODING, "gzip")
-                        .header(header::CONTENT_TYPE, "text/plain")
+                        .header(header::CONTENT_TYPE, content_type)
                         .body(Body::from(body))
                         .map_err(ServeError::from)
                 })
         } else {
             let mut writer = Vec::<u8>::new();
-            write!(&mut writer, "{}", self.metrics.as_display())
+            self.write_metrics(&mut writer, openmetrics)
                 .map_err(ServeError::from)
                 .and_then(|_| {
                     Response::builder()
-                        .header(header::CONTENT_TYPE, "text/plain")
+                        .header(header::CONTENT_TYPE, content_type)
                         .body(Body::from(writer))
                         .map_err(ServeError::from)
                 })
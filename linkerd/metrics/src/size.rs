@@ -0,0 +1,45 @@
+use super::histogram::{Bounds, Bucket, Histogram};
+
+/// The default maximum value (inclusive) for each size bucket.
+const BUCKETS: &[Bucket] = &[
+    Bucket::Le(64),
+    Bucket::Le(256),
+    Bucket::Le(1_024),
+    Bucket::Le(4_096),
+    Bucket::Le(16_384),
+    Bucket::Le(65_536),
+    Bucket::Le(262_144),
+    Bucket::Le(1_048_576),
+    Bucket::Le(4_194_304),
+    Bucket::Le(16_777_216),
+    Bucket::Le(67_108_864),
+    // A final upper bound.
+    Bucket::Inf,
+];
+
+/// Returns the default bounds for size histograms, in bytes.
+pub fn bounds() -> Bounds {
+    BUCKETS.into()
+}
+
+/// A size in bytes.
+#[derive(Debug, Default, Clone)]
+pub struct Bytes(u64);
+
+impl Into<u64> for Bytes {
+    fn into(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Bytes {
+    fn from(n: u64) -> Self {
+        Bytes(n)
+    }
+}
+
+impl Default for Histogram<Bytes> {
+    fn default() -> Self {
+        Histogram::new(bounds())
+    }
+}
@@ -0,0 +1,528 @@
+//! Resolves endpoints from a local file.
+//!
+//! The file maps each destination, as it would be resolved via the
+//! destination service, to its endpoints. It may be written as YAML or, if
+//! its name ends with `.json`, as JSON:
+//!
+//! ```yaml
+//! web.default.svc.cluster.local:8080:
+//!   - addr: 10.1.1.1:8080
+//!     weight: 10000
+//!     labels:
+//!       pod: web-1
+//!     identity: web.default.serviceaccount.identity.linkerd.cluster.local
+//!     protocol_hint: h2
+//!   - addr: 10.1.1.2:8080
+//! ```
+//!
+//! The file is polled for changes by a task on a dedicated thread, so that
+//! file IO never blocks the proxy's reactor. When it changes, each resolution
+//! is updated with the endpoints that were added or removed. If the file
+//! cannot be parsed, resolutions keep their current endpoints.
+
+use crate::core::resolve::{self, Update};
+use crate::identity;
+use crate::metadata::{Metadata, ProtocolHint};
+use futures::{future, Async, Future, Poll, Stream};
+use indexmap::IndexMap;
+use linkerd2_error::{Error, Never};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio_sync::watch;
+use tokio_timer::Interval;
+use tracing::{debug, trace, warn};
+
+/// Resolves endpoints from a file.
+#[derive(Clone, Debug)]
+pub struct Resolve {
+    rx: watch::Receiver<Option<Destinations>>,
+}
+
+/// Reads the file each time it changes, publishing its destinations to each
+/// `Resolve`.
+///
+/// Completes when all `Resolve`s and their resolutions are dropped.
+pub struct Task {
+    path: PathBuf,
+    interval: Interval,
+    /// The file's modification time when it was last read, if it has been
+    /// read.
+    loaded: Option<Option<SystemTime>>,
+    tx: watch::Sender<Option<Destinations>>,
+}
+
+pub struct Resolution {
+    target: String,
+    /// The file's destinations, or `None` until the file is first read.
+    rx: watch::Receiver<Option<Destinations>>,
+    endpoints: Endpoints,
+}
+
+/// The endpoints of each destination in the file, or `None` if the file does
+/// not exist.
+type Destinations = Option<Arc<HashMap<String, IndexMap<SocketAddr, Metadata>>>>;
+
+#[derive(Debug)]
+pub struct Stopped(());
+
+/// Tracks the endpoints that have been published for a destination.
+#[derive(Debug, Default)]
+struct Endpoints {
+    active: IndexMap<SocketAddr, Metadata>,
+    /// Whether the destination existed when it was last updated, if it has
+    /// been updated.
+    exists: Option<bool>,
+    pending: VecDeque<Update<Metadata>>,
+}
+
+/// An endpoint, as it is written in a file.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct EndpointSpec {
+    addr: SocketAddr,
+    #[serde(default = "EndpointSpec::default_weight")]
+    weight: u32,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    identity: Option<String>,
+    #[serde(default)]
+    protocol_hint: ProtocolHintSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    authority_override: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ProtocolHintSpec {
+    Unknown,
+    H2,
+}
+
+// === impl Resolve ===
+
+impl Resolve {
+    /// Returns a `Resolve` and the `Task` that reads endpoints from the file
+    /// at `path`, checking it for changes every `interval`.
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> (Self, Task) {
+        let (tx, rx) = watch::channel(None);
+        let task = Task {
+            path: path.into(),
+            // The file is read as soon as the task is first polled.
+            interval: Interval::new(Instant::now(), interval),
+            loaded: None,
+            tx,
+        };
+        (Self { rx }, task)
+    }
+
+    /// Returns a `Resolve` whose file is read on a dedicated thread.
+    pub fn spawn(path: impl Into<PathBuf>, interval: Duration) -> std::io::Result<Self> {
+        let (resolve, task) = Self::new(path, interval);
+        std::thread::Builder::new()
+            .name("endpoints-file".into())
+            .spawn(move || {
+                tokio::runtime::current_thread::Runtime::new()
+                    .expect("endpoints file runtime")
+                    .block_on(task)
+                    .unwrap_or_else(|n| match n {})
+            })?;
+        Ok(resolve)
+    }
+}
+
+impl<T: ToString> tower::Service<T> for Resolve {
+    type Response = Resolution;
+    type Error = Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let target = target.to_string();
+        debug!(dst = %target, "Resolving");
+        future::ok(Resolution {
+            target,
+            rx: self.rx.clone(),
+            endpoints: Endpoints::default(),
+        })
+    }
+}
+
+// === impl Task ===
+
+impl Task {
+    /// Reads the file if it has changed, returning its destinations.
+    ///
+    /// Returns `None` if the file has not changed or could not be read.
+    fn reload(&mut self) -> Option<Destinations> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if self.loaded == Some(modified) {
+            return None;
+        }
+        // Invalid files are not read again until they are modified.
+        self.loaded = Some(modified);
+
+        if modified.is_none() {
+            // If the file does not exist, no destinations exist.
+            debug!(path = %self.path.display(), "Endpoints file not found");
+            return Some(None);
+        }
+
+        match read(&self.path) {
+            Ok(dsts) => {
+                debug!(path = %self.path.display(), "Endpoints file loaded");
+                Some(Some(Arc::new(dsts)))
+            }
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Invalid endpoints file");
+                None
+            }
+        }
+    }
+}
+
+impl Future for Task {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.tx.poll_close() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(())) | Err(()) => {
+                    trace!("Endpoints file resolutions dropped");
+                    return Ok(Async::Ready(()));
+                }
+            }
+
+            match self.interval.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(_)) => {}
+                Err(error) => {
+                    warn!(%error, "Endpoints file watch failed");
+                    return Ok(Async::Ready(()));
+                }
+            }
+
+            if let Some(dsts) = self.reload() {
+                if self.tx.broadcast(Some(dsts)).is_err() {
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl resolve::Resolution for Resolution {
+    type Endpoint = Metadata;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        loop {
+            if let Some(update) = self.endpoints.pending.pop_front() {
+                trace!(?update);
+                return Ok(Async::Ready(update));
+            }
+
+            let endpoints = match self.rx.poll_ref() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(Some(dsts))) => match &*dsts {
+                    // The file has not been read yet.
+                    None => continue,
+                    Some(dsts) => dsts
+                        .as_ref()
+                        .and_then(|dsts| dsts.get(&self.target).cloned()),
+                },
+                Ok(Async::Ready(None)) | Err(_) => return Err(Stopped(()).into()),
+            };
+            self.endpoints.update(endpoints);
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<HashMap<String, IndexMap<SocketAddr, Metadata>>, Error> {
+    let contents = std::fs::read(path)?;
+    let dsts: HashMap<String, Vec<EndpointSpec>> = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_slice(&contents)?,
+        _ => serde_yaml::from_slice(&contents)?,
+    };
+    let dsts = dsts
+        .into_iter()
+        .map(|(dst, eps)| {
+            let eps = eps
+                .into_iter()
+                .filter_map(EndpointSpec::into_addr_meta)
+                .collect();
+            (dst, eps)
+        })
+        .collect();
+    Ok(dsts)
+}
+
+// === impl Stopped ===
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "endpoints file watch stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+// === impl Endpoints ===
+
+impl Endpoints {
+    /// Records the destination's current endpoints, or `None` if it does not
+    /// exist, queueing updates for the endpoints that changed.
+    fn update(&mut self, endpoints: Option<IndexMap<SocketAddr, Metadata>>) {
+        let endpoints = match endpoints {
+            None => {
+                if self.exists != Some(false) {
+                    self.active.clear();
+                    self.pending.push_back(Update::DoesNotExist);
+                }
+                self.exists = Some(false);
+                return;
+            }
+            Some(endpoints) => endpoints,
+        };
+
+        if endpoints.is_empty() {
+            if self.exists != Some(true) || !self.active.is_empty() {
+                self.active.clear();
+                self.pending.push_back(Update::Empty);
+            }
+            self.exists = Some(true);
+            return;
+        }
+
+        let removed = self
+            .active
+            .keys()
+            .filter(|addr| !endpoints.contains_key(*addr))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !removed.is_empty() {
+            debug!(endpoints = %removed.len(), "Remove");
+            self.pending.push_back(Update::Remove(removed));
+        }
+
+        // Endpoints with changed metadata are added again to replace them.
+        let added = endpoints
+            .iter()
+            .filter(|(addr, meta)| self.active.get(*addr) != Some(*meta))
+            .map(|(addr, meta)| (*addr, meta.clone()))
+            .collect::<Vec<_>>();
+        if !added.is_empty() {
+            debug!(endpoints = %added.len(), "Add");
+            self.pending.push_back(Update::Add(added));
+        }
+
+        self.active = endpoints;
+        self.exists = Some(true);
+    }
+}
+
+// === impl EndpointSpec ===
+
+impl EndpointSpec {
+    fn default_weight() -> u32 {
+        Metadata::empty().weight()
+    }
+
+    pub(crate) fn from_addr_meta(addr: SocketAddr, meta: &Metadata) -> Self {
+        Self {
+            addr,
+            weight: meta.weight(),
+            labels: meta
+                .labels()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            identity: meta.identity().map(|id| id.as_ref().to_string()),
+            protocol_hint: match meta.protocol_hint() {
+                ProtocolHint::Unknown => ProtocolHintSpec::Unknown,
+                ProtocolHint::Http2 => ProtocolHintSpec::H2,
+            },
+            authority_override: meta.authority_override().map(|a| a.to_string()),
+        }
+    }
+
+    pub(crate) fn into_addr_meta(self) -> Option<(SocketAddr, Metadata)> {
+        let identity = match self.identity {
+            None => None,
+            Some(id) => match identity::Name::from_hostname(id.as_bytes()) {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    warn!(addr = %self.addr, "Ignoring endpoint with invalid identity: {}", id);
+                    return None;
+                }
+            },
+        };
+
+        let protocol_hint = match self.protocol_hint {
+            ProtocolHintSpec::Unknown => ProtocolHint::Unknown,
+            ProtocolHintSpec::H2 => ProtocolHint::Http2,
+        };
+
+        let authority_override = match self.authority_override {
+            None => None,
+            Some(a) => match a.parse::<http::uri::Authority>() {
+                Ok(a) => Some(a),
+                Err(_) => {
+                    warn!(addr = %self.addr, "Ignoring endpoint with invalid authority override: {}", a);
+                    return None;
+                }
+            },
+        };
+
+        // Labels are sorted, as they are for endpoints from the destination
+        // service.
+        let labels = self.labels.into_iter().collect::<IndexMap<_, _>>();
+        let meta = Metadata::new(
+            labels,
+            protocol_hint,
+            identity,
+            self.weight,
+            authority_override,
+        );
+        Some((self.addr, meta))
+    }
+}
+
+impl Default for ProtocolHintSpec {
+    fn default() -> Self {
+        ProtocolHintSpec::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str, dst: &str) -> Option<IndexMap<SocketAddr, Metadata>> {
+        let mut dsts: HashMap<String, Vec<EndpointSpec>> = serde_yaml::from_str(s).unwrap();
+        dsts.remove(dst).map(|eps| {
+            eps.into_iter()
+                .filter_map(EndpointSpec::into_addr_meta)
+                .collect()
+        })
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let eps = parse(
+            r#"
+web.ns.svc.cluster.local:8080:
+  - addr: 10.1.1.1:8080
+    weight: 20000
+    labels: { zone: b, pod: web-1 }
+    identity: web.ns.serviceaccount.identity.linkerd.cluster.local
+    protocol_hint: h2
+  - addr: 10.1.1.2:8080
+"#,
+            "web.ns.svc.cluster.local:8080",
+        )
+        .expect("destination must exist");
+
+        let meta = &eps[&addr("10.1.1.1:8080")];
+        assert_eq!(meta.weight(), 20000);
+        assert_eq!(
+            meta.labels().keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["pod", "zone"]
+        );
+        assert!(meta.identity().is_some());
+        assert_eq!(meta.protocol_hint(), ProtocolHint::Http2);
+
+        assert_eq!(eps[&addr("10.1.1.2:8080")], Metadata::empty());
+    }
+
+    #[test]
+    fn updates_changed_endpoints() {
+        let mut endpoints = Endpoints::default();
+
+        endpoints.update(None);
+        endpoints.update(None);
+        assert_eq!(endpoints.pending.pop_front(), Some(Update::DoesNotExist));
+        assert!(endpoints.pending.is_empty());
+
+        let mut eps = IndexMap::new();
+        eps.insert(addr("10.1.1.1:80"), Metadata::empty());
+        eps.insert(addr("10.1.1.2:80"), Metadata::empty());
+        endpoints.update(Some(eps.clone()));
+        match endpoints.pending.pop_front() {
+            Some(Update::Add(added)) => assert_eq!(added.len(), 2),
+            update => panic!("unexpected update: {:?}", update),
+        }
+
+        // Unchanged endpoints are not published again.
+        endpoints.update(Some(eps.clone()));
+        assert!(endpoints.pending.is_empty());
+
+        eps.remove(&addr("10.1.1.1:80"));
+        eps.insert(addr("10.1.1.3:80"), Metadata::empty());
+        endpoints.update(Some(eps));
+        assert_eq!(
+            endpoints.pending.pop_front(),
+            Some(Update::Remove(vec![addr("10.1.1.1:80")]))
+        );
+        assert_eq!(
+            endpoints.pending.pop_front(),
+            Some(Update::Add(vec![(addr("10.1.1.3:80"), Metadata::empty())]))
+        );
+
+        endpoints.update(Some(IndexMap::new()));
+        assert_eq!(endpoints.pending.pop_front(), Some(Update::Empty));
+        assert!(endpoints.pending.is_empty());
+    }
+
+    #[test]
+    fn publishes_file_changes() {
+        use resolve::Resolution as _;
+        use tokio::runtime::current_thread::Runtime;
+        use tokio::timer::Timeout;
+        use tower::Service;
+
+        fn next(rt: &mut Runtime, resolution: &mut Resolution) -> Update<Metadata> {
+            let next = future::poll_fn(|| resolution.poll());
+            rt.block_on(Timeout::new(next, Duration::from_secs(5)))
+                .expect("update must be published")
+        }
+
+        let path = std::env::temp_dir().join(format!("endpoints-file-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "web.ns.svc.cluster.local:8080: [{ addr: 10.1.1.1:8080 }]",
+        )
+        .unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut resolve, task) = Resolve::new(&path, Duration::from_millis(10));
+        rt.spawn(task.map_err(|n| match n {}));
+        let mut resolution = rt
+            .block_on(resolve.call("web.ns.svc.cluster.local:8080"))
+            .expect("destination must be resolved");
+        assert_eq!(
+            next(&mut rt, &mut resolution),
+            Update::Add(vec![(addr("10.1.1.1:8080"), Metadata::empty())])
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(next(&mut rt, &mut resolution), Update::DoesNotExist);
+    }
+}
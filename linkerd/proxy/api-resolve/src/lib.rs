@@ -4,9 +4,11 @@ use linkerd2_identity as identity;
 use linkerd2_proxy_api as api;
 use linkerd2_proxy_core as core;
 
+pub mod file;
 mod metadata;
 mod pb;
 mod resolve;
+pub mod snapshot;
 
 pub use self::metadata::{Metadata, ProtocolHint};
 pub use self::resolve::Resolve;
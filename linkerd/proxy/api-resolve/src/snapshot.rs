@@ -0,0 +1,451 @@
+//! Persists resolved endpoints, so that they may be served if the
+//! destination service cannot be reached.
+//!
+//! Each destination's endpoints are written to a JSON file in a directory,
+//! named for the destination, in the same form as an endpoints file. When a
+//! destination is resolved and the inner resolution is not established within
+//! a timeout, the destination's last known endpoints are served until the
+//! resolution is established. The number of destinations that are served
+//! from snapshots is recorded by the `stale` gauge.
+//!
+//! Snapshots are written in the background by a `Writer`.
+
+use crate::core::resolve::{self, Update};
+use crate::file::EndpointSpec;
+use crate::metadata::Metadata;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_metrics::Gauge;
+use linkerd2_snapshot::{Stale, Writer};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_timer::Delay;
+use tracing::{debug, info, warn};
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    snapshot: Option<Snapshot>,
+}
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+    dir: Arc<PathBuf>,
+    timeout: Duration,
+    stale: Arc<Gauge>,
+    writer: Writer,
+}
+
+pub struct ResolveFuture<F> {
+    inner: Option<F>,
+    store: Option<Store>,
+    timeout: Option<Delay>,
+}
+
+pub struct Resolution<F, R> {
+    state: State<F, R>,
+    /// Held until the snapshot's endpoints are replaced by a live update.
+    stale: Option<Stale>,
+    store: Option<Store>,
+    /// The endpoints of the current resolution, whether live or stale.
+    active: IndexMap<SocketAddr, Metadata>,
+    pending: VecDeque<Update<Metadata>>,
+}
+
+enum State<F, R> {
+    /// Endpoints are served from a snapshot until the inner resolution is
+    /// established.
+    Stale(F),
+    Live(R),
+}
+
+/// Reads and writes a destination's snapshot.
+struct Store {
+    path: PathBuf,
+    stale: Arc<Gauge>,
+    writer: Writer,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            snapshot: None,
+        }
+    }
+
+    /// Persists endpoints in `dir`, via `writer`, serving them if a
+    /// resolution is not established within `timeout`.
+    pub fn with_snapshot(
+        self,
+        dir: PathBuf,
+        timeout: Duration,
+        stale: Arc<Gauge>,
+        writer: Writer,
+    ) -> Self {
+        Self {
+            snapshot: Some(Snapshot {
+                dir: Arc::new(dir),
+                timeout,
+                stale,
+                writer,
+            }),
+            ..self
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    T: ToString,
+    R: resolve::Resolve<T, Endpoint = Metadata>,
+    R::Error: Into<Error>,
+{
+    type Response = Resolution<R::Future, R::Resolution>;
+    type Error = Error;
+    type Future = ResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let store = self.snapshot.as_ref().map(|s| Store {
+            path: s.dir.join(format!("{}.json", target.to_string())),
+            stale: s.stale.clone(),
+            writer: s.writer.clone(),
+        });
+        let timeout = self
+            .snapshot
+            .as_ref()
+            .map(|s| Delay::new(Instant::now() + s.timeout));
+
+        ResolveFuture {
+            inner: Some(self.inner.resolve(target)),
+            store,
+            timeout,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: Future,
+    F::Item: resolve::Resolution<Endpoint = Metadata>,
+    F::Error: Into<Error>,
+{
+    type Item = Resolution<F, F::Item>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = self.inner.as_mut().expect("polled after ready");
+        if let Async::Ready(resolution) = inner.poll().map_err(Into::into)? {
+            return Ok(Async::Ready(Resolution {
+                state: State::Live(resolution),
+                stale: None,
+                store: self.store.take(),
+                active: IndexMap::new(),
+                pending: VecDeque::new(),
+            }));
+        }
+
+        let timeout = match self.timeout.as_mut() {
+            Some(timeout) => timeout,
+            None => return Ok(Async::NotReady),
+        };
+        if timeout.poll()?.is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        self.timeout = None;
+
+        let store = self.store.as_ref().expect("snapshots must be configured");
+        let active = match store.load() {
+            Some(active) => active,
+            // Without a snapshot, wait for the inner resolution.
+            None => return Ok(Async::NotReady),
+        };
+
+        info!(endpoints = %active.len(), "Using last known endpoints after timeout");
+        let mut pending = VecDeque::new();
+        pending.push_back(if active.is_empty() {
+            Update::Empty
+        } else {
+            Update::Add(
+                active
+                    .iter()
+                    .map(|(addr, meta)| (*addr, meta.clone()))
+                    .collect(),
+            )
+        });
+
+        let inner = self.inner.take().expect("polled after ready");
+        Ok(Async::Ready(Resolution {
+            state: State::Stale(inner),
+            stale: Some(Stale::new(store.stale.clone())),
+            store: self.store.take(),
+            active,
+            pending,
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<F> resolve::Resolution for Resolution<F, F::Item>
+where
+    F: Future,
+    F::Item: resolve::Resolution<Endpoint = Metadata>,
+    F::Error: Into<Error>,
+{
+    type Endpoint = Metadata;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        loop {
+            if let Some(update) = self.pending.pop_front() {
+                return Ok(Async::Ready(update));
+            }
+
+            let resolution = match self.state {
+                State::Stale(ref mut future) => {
+                    let resolution = try_ready!(future.poll().map_err(Into::into));
+                    self.state = State::Live(resolution);
+                    continue;
+                }
+                State::Live(ref mut resolution) => resolution,
+            };
+
+            let update = try_ready!(resolution.poll().map_err(Into::into));
+            if self.stale.take().is_none() {
+                self.record(std::iter::once(&update));
+                self.pending.push_back(update);
+                continue;
+            }
+
+            // The live resolution's initial endpoints may be split across
+            // several updates, so every update that is already available is
+            // applied before stale endpoints are removed. Endpoints from the
+            // snapshot that are not in this first live set are removed.
+            debug!("Replacing last known endpoints");
+            let mut updates = vec![update];
+            while let Async::Ready(update) = resolution.poll().map_err(Into::into)? {
+                updates.push(update);
+            }
+
+            let stale = std::mem::replace(&mut self.active, IndexMap::new());
+            self.record(&updates);
+            let removed = stale
+                .keys()
+                .filter(|addr| !self.active.contains_key(*addr))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !removed.is_empty() {
+                self.pending.push_back(Update::Remove(removed));
+            }
+            self.pending.extend(updates);
+        }
+    }
+}
+
+impl<F, R> Resolution<F, R> {
+    /// Updates the active endpoints with live updates, persisting them.
+    fn record<'a>(&mut self, updates: impl IntoIterator<Item = &'a Update<Metadata>>) {
+        let mut exists = true;
+        for update in updates {
+            exists = true;
+            match update {
+                Update::Add(ref eps) => {
+                    self.active.extend(eps.iter().cloned());
+                }
+                Update::Remove(ref addrs) => {
+                    for addr in addrs {
+                        self.active.remove(addr);
+                    }
+                }
+                Update::Empty => self.active.clear(),
+                Update::DoesNotExist => {
+                    self.active.clear();
+                    exists = false;
+                }
+            }
+        }
+
+        if let Some(store) = self.store.as_ref() {
+            if exists {
+                store.save(&self.active);
+            } else {
+                store.remove();
+            }
+        }
+    }
+}
+
+// === impl Store ===
+
+impl Store {
+    fn load(&self) -> Option<IndexMap<SocketAddr, Metadata>> {
+        let contents = std::fs::read(&self.path).ok()?;
+        match serde_json::from_slice::<Vec<EndpointSpec>>(&contents) {
+            Ok(eps) => Some(
+                eps.into_iter()
+                    .filter_map(EndpointSpec::into_addr_meta)
+                    .collect(),
+            ),
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Invalid endpoints snapshot");
+                None
+            }
+        }
+    }
+
+    fn save(&self, active: &IndexMap<SocketAddr, Metadata>) {
+        let eps = active
+            .iter()
+            .map(|(addr, meta)| EndpointSpec::from_addr_meta(*addr, meta))
+            .collect::<Vec<_>>();
+        match serde_json::to_vec(&eps) {
+            Ok(contents) => self.writer.write(self.path.clone(), contents),
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Failed to encode endpoints snapshot")
+            }
+        }
+    }
+
+    fn remove(&self) {
+        self.writer.remove(self.path.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_snapshot::Task;
+
+    /// Writes a store's pending snapshots, returning a store that reads them.
+    fn written(store: Store, task: Task) -> Store {
+        let Store {
+            path,
+            stale,
+            writer,
+        } = store;
+        drop(writer);
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_or_else(|n| match n {});
+
+        let (writer, _) = Writer::new(Duration::from_secs(60));
+        Store {
+            path,
+            stale,
+            writer,
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_endpoints() {
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", std::process::id()));
+        let (writer, task) = Writer::new(Duration::from_secs(60));
+        let store = Store {
+            path: dir.join("web.ns.svc.cluster.local:8080.json"),
+            stale: Arc::new(Gauge::default()),
+            writer,
+        };
+        assert!(store.load().is_none());
+
+        let mut active = IndexMap::new();
+        let mut labels = IndexMap::new();
+        labels.insert("pod".to_string(), "web-1".to_string());
+        let meta = Metadata::new(
+            labels,
+            crate::ProtocolHint::Http2,
+            None,
+            20_000,
+            Some("web.ns.svc.cluster.local:8080".parse().unwrap()),
+        );
+        active.insert("10.1.1.1:8080".parse().unwrap(), meta);
+        active.insert("10.1.1.2:8080".parse().unwrap(), Metadata::empty());
+        store.save(&active);
+        let store = written(store, task);
+        assert_eq!(store.load(), Some(active));
+
+        let (writer, task) = Writer::new(Duration::from_secs(60));
+        let store = Store { writer, ..store };
+        store.remove();
+        let store = written(store, task);
+        assert!(store.load().is_none());
+        let _ = std::fs::remove_dir(&dir);
+    }
+
+    struct Updates(VecDeque<Update<Metadata>>);
+
+    impl resolve::Resolution for Updates {
+        type Endpoint = Metadata;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<Metadata>, Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    #[test]
+    fn replaces_stale_endpoints() {
+        let a = "10.1.1.1:8080".parse::<SocketAddr>().unwrap();
+        let b = "10.1.1.2:8080".parse::<SocketAddr>().unwrap();
+        let c = "10.1.1.3:8080".parse::<SocketAddr>().unwrap();
+
+        let mut active = IndexMap::new();
+        active.insert(a, Metadata::empty());
+        active.insert(b, Metadata::empty());
+        active.insert(c, Metadata::empty());
+
+        // The live resolution's initial endpoints are split across updates.
+        let gauge = Arc::new(Gauge::default());
+        let live = Updates(
+            vec![
+                Update::Add(vec![(b, Metadata::empty())]),
+                Update::Add(vec![(c, Metadata::empty())]),
+            ]
+            .into(),
+        );
+        let mut resolution = Resolution {
+            state: State::Stale(futures::future::ok::<_, Error>(live)),
+            stale: Some(Stale::new(gauge.clone())),
+            store: None,
+            active,
+            pending: VecDeque::new(),
+        };
+        assert_eq!(gauge.value(), 1);
+
+        // Only endpoints missing from the whole first live set are removed.
+        let poll = resolve::Resolution::poll(&mut resolution).unwrap();
+        assert_eq!(poll, Async::Ready(Update::Remove(vec![a])));
+        assert_eq!(gauge.value(), 0);
+        let poll = resolve::Resolution::poll(&mut resolution).unwrap();
+        assert_eq!(
+            poll,
+            Async::Ready(Update::Add(vec![(b, Metadata::empty())]))
+        );
+        let poll = resolve::Resolution::poll(&mut resolution).unwrap();
+        assert_eq!(
+            poll,
+            Async::Ready(Update::Add(vec![(c, Metadata::empty())]))
+        );
+        assert_eq!(resolution.active.keys().collect::<Vec<_>>(), vec![&b, &c]);
+        assert!(resolve::Resolution::poll(&mut resolution)
+            .unwrap()
+            .is_not_ready());
+    }
+}
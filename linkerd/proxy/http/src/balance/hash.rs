@@ -0,0 +1,404 @@
+//! A consistent-hash balancer.
+//!
+//! Each endpoint is placed at several points on a hash ring. A request's
+//! affinity key is hashed onto the ring and the request is dispatched to the
+//! first ready endpoint at or after that point, so requests with the same key
+//! are served by the same endpoint while the endpoint set is stable. Because an
+//! endpoint only owns the ring segments that precede its points, adding or
+//! removing an endpoint only moves the keys in those segments.
+//!
+//! Each endpoint's share of the ring is proportional to its weight.
+//! Zero-weighted endpoints are placed on a separate ring that is only used
+//! when no weighted endpoint is ready.
+
+use super::weight::HasWeight;
+use futures::{future, try_ready, Async, Future, Poll};
+use http;
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_http_box::{response::Layer as BoxResponseLayer, BoxResponse};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tower::util::Either;
+use tower_discover::{Change, Discover};
+use tracing::{debug, trace};
+
+/// The number of points an endpoint with the default weight occupies on the
+/// ring.
+const POINTS_PER_ENDPOINT: u64 = 100;
+
+/// Determines the affinity key of a request.
+pub trait HashRequest<B> {
+    /// Returns the hash of the request's affinity key, if it has one.
+    ///
+    /// Requests without a key are dispatched to an arbitrary endpoint.
+    fn hash_request(&self, req: &http::Request<B>) -> Option<u64>;
+}
+
+/// Builds a consistent-hash balancer for each target that has an affinity key,
+/// and a `P`-typed balancer for all other targets.
+#[derive(Clone, Debug)]
+pub struct Layer<F, P> {
+    affinity: F,
+    balance: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeAffinity<M, F, P> {
+    inner: M,
+    affinity: F,
+    balance: P,
+}
+
+pub struct MakeFuture<F, P, H> {
+    inner: F,
+    affinity: Option<H>,
+    balance: P,
+}
+
+/// Dispatches each request to an endpoint selected by its affinity key.
+pub struct HashBalance<D: Discover, H> {
+    discover: D,
+    hash: H,
+    endpoints: IndexMap<D::Key, Endpoint<D::Service>>,
+    ring: Vec<(u64, D::Key)>,
+    /// Zero-weighted endpoints, used only when no weighted endpoint is ready.
+    fallback: Vec<(u64, D::Key)>,
+    rng: SmallRng,
+}
+
+struct Endpoint<S> {
+    service: S,
+    points: u64,
+    ready: bool,
+}
+
+// === impl Layer ===
+
+/// Returns a `Layer` that builds a consistent-hash balancer for targets for
+/// which `affinity` returns a key.
+///
+/// The consistent-hash balancer boxes its response bodies, so the `balance`
+/// layer must produce services with boxed response bodies.
+pub fn layer<F, P>(affinity: F, balance: P) -> Layer<F, P> {
+    Layer { affinity, balance }
+}
+
+impl<M, F: Clone, P: Clone> tower::layer::Layer<M> for Layer<F, P> {
+    type Service = MakeAffinity<M, F, P>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeAffinity {
+            inner,
+            affinity: self.affinity.clone(),
+            balance: self.balance.clone(),
+        }
+    }
+}
+
+// === impl MakeAffinity ===
+
+impl<T, M, F, P, H> tower::Service<T> for MakeAffinity<M, F, P>
+where
+    M: tower::Service<T>,
+    M::Response: Discover,
+    F: Fn(&T) -> Option<H>,
+    P: tower::layer::Layer<M::Response> + Clone,
+{
+    type Response = Either<P::Service, BoxResponse<HashBalance<M::Response, H>>>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, P, H>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let affinity = (self.affinity)(&target);
+        MakeFuture {
+            affinity,
+            balance: self.balance.clone(),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+impl<F, P, H> Future for MakeFuture<F, P, H>
+where
+    F: Future,
+    F::Item: Discover,
+    <F::Item as Discover>::Key: Clone,
+    <F::Item as Discover>::Service: HasWeight,
+    P: tower::layer::Layer<F::Item>,
+{
+    type Item = Either<P::Service, BoxResponse<HashBalance<F::Item, H>>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let discover = try_ready!(self.inner.poll());
+        let balance = match self.affinity.take() {
+            Some(hash) => {
+                let balance = HashBalance::new(discover, hash);
+                Either::B(tower::layer::Layer::layer(
+                    &BoxResponseLayer::new(),
+                    balance,
+                ))
+            }
+            None => Either::A(tower::layer::Layer::layer(&self.balance, discover)),
+        };
+        Ok(Async::Ready(balance))
+    }
+}
+
+// === impl HashBalance ===
+
+impl<D, H> HashBalance<D, H>
+where
+    D: Discover,
+    D::Key: Clone,
+    D::Service: HasWeight,
+{
+    pub fn new(discover: D, hash: H) -> Self {
+        Self {
+            discover,
+            hash,
+            endpoints: IndexMap::new(),
+            ring: Vec::new(),
+            fallback: Vec::new(),
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
+    /// Applies all pending changes from discovery, rebuilding the ring if the
+    /// endpoint set changed.
+    fn poll_discover(&mut self) -> Result<(), Error>
+    where
+        D::Error: Into<Error>,
+    {
+        let mut changed = false;
+        loop {
+            match self.discover.poll().map_err(Into::into)? {
+                Async::NotReady => break,
+                Async::Ready(Change::Insert(key, service)) => {
+                    let weight: f64 = service.weight().into();
+                    let points = (weight * POINTS_PER_ENDPOINT as f64).round() as u64;
+                    let endpoint = Endpoint {
+                        service,
+                        // Weighted endpoints always occupy at least one point.
+                        points: if weight > 0.0 { points.max(1) } else { 0 },
+                        ready: false,
+                    };
+                    self.endpoints.insert(key, endpoint);
+                    changed = true;
+                }
+                Async::Ready(Change::Remove(key)) => {
+                    self.endpoints.remove(&key);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            self.rebuild_ring();
+        }
+        Ok(())
+    }
+
+    fn rebuild_ring(&mut self) {
+        let mut ring = Vec::new();
+        let mut fallback = Vec::new();
+        for (key, endpoint) in self.endpoints.iter() {
+            let (points, target) = if endpoint.points > 0 {
+                (endpoint.points, &mut ring)
+            } else {
+                (POINTS_PER_ENDPOINT, &mut fallback)
+            };
+            for point in 0..points {
+                target.push((hash_point(key, point), key.clone()));
+            }
+        }
+        ring.sort_by_key(|(p, _)| *p);
+        fallback.sort_by_key(|(p, _)| *p);
+        trace!(endpoints = self.endpoints.len(), "Rebuilt hash ring");
+        self.ring = ring;
+        self.fallback = fallback;
+    }
+
+    /// Returns the first ready endpoint at or after `hash` on the ring,
+    /// falling back to zero-weighted endpoints if no weighted endpoint is
+    /// ready.
+    fn select(&self, hash: u64) -> Option<&D::Key> {
+        self.select_from(&self.ring, hash)
+            .or_else(|| self.select_from(&self.fallback, hash))
+    }
+
+    fn select_from<'a>(&'a self, ring: &'a [(u64, D::Key)], hash: u64) -> Option<&'a D::Key> {
+        let start = match ring.binary_search_by_key(&hash, |(p, _)| *p) {
+            Ok(i) | Err(i) => i,
+        };
+        let (head, tail) = ring.split_at(start);
+        tail.iter()
+            .chain(head.iter())
+            .map(|(_, key)| key)
+            .find(|key| self.endpoints.get(*key).map(|ep| ep.ready).unwrap_or(false))
+    }
+}
+
+impl<D, H, B> tower::Service<http::Request<B>> for HashBalance<D, H>
+where
+    D: Discover,
+    D::Key: Clone,
+    D::Error: Into<Error>,
+    D::Service: tower::Service<http::Request<B>> + HasWeight,
+    <D::Service as tower::Service<http::Request<B>>>::Error: Into<Error>,
+    H: HashRequest<B>,
+{
+    type Response = <D::Service as tower::Service<http::Request<B>>>::Response;
+    type Error = Error;
+    type Future = future::MapErr<
+        <D::Service as tower::Service<http::Request<B>>>::Future,
+        fn(<D::Service as tower::Service<http::Request<B>>>::Error) -> Error,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.poll_discover()?;
+
+        // Endpoints are polled on each call, so that endpoints that become
+        // unavailable are skipped.
+        let mut failed = Vec::new();
+        let mut any_ready = false;
+        for (key, endpoint) in self.endpoints.iter_mut() {
+            endpoint.ready = match endpoint.service.poll_ready() {
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => false,
+                Err(e) => {
+                    let error: Error = e.into();
+                    debug!(%error, "Endpoint failed");
+                    failed.push(key.clone());
+                    false
+                }
+            };
+            any_ready = any_ready || endpoint.ready;
+        }
+
+        if !failed.is_empty() {
+            for key in failed.iter() {
+                self.endpoints.remove(key);
+            }
+            self.rebuild_ring();
+        }
+
+        if any_ready {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let hash = match self.hash.hash_request(&req) {
+            Some(hash) => hash,
+            None => self.rng.gen(),
+        };
+        let key = self.select(hash).cloned().expect("called before ready");
+        let endpoint = self.endpoints.get_mut(&key).expect("selected endpoint");
+        endpoint.ready = false;
+        endpoint
+            .service
+            .call(req)
+            .map_err(Into::into as fn(_) -> Error)
+    }
+}
+
+fn hash_point<K: Hash>(key: &K, point: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    point.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::weight::{Weight, Weighted};
+    use futures::stream;
+    use tower_discover::ServiceStream;
+
+    type Changes = stream::IterOk<std::vec::IntoIter<Change<u16, Weighted<()>>>, Error>;
+
+    fn balance(keys: &[u16]) -> HashBalance<ServiceStream<Changes>, ()> {
+        weighted_balance(&keys.iter().map(|k| (*k, 1.0)).collect::<Vec<_>>())
+    }
+
+    fn weighted_balance(endpoints: &[(u16, f64)]) -> HashBalance<ServiceStream<Changes>, ()> {
+        let changes = endpoints
+            .iter()
+            .map(|(k, w)| Change::Insert(*k, Weighted::new((), Weight::from(*w))))
+            .collect::<Vec<_>>();
+        let mut balance = HashBalance::new(ServiceStream::new(stream::iter_ok(changes)), ());
+        balance.poll_discover().expect("discover");
+        for endpoint in balance.endpoints.values_mut() {
+            endpoint.ready = true;
+        }
+        balance
+    }
+
+    fn selections(balance: &HashBalance<ServiceStream<Changes>, ()>) -> Vec<u16> {
+        (0..1_000u64)
+            .map(|i| *balance.select(hash_point(&"request", i)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn selection_is_stable() {
+        let a = selections(&balance(&[1, 2, 3]));
+        let b = selections(&balance(&[3, 2, 1]));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn removing_an_endpoint_only_moves_its_keys() {
+        let before = selections(&balance(&[1, 2, 3, 4]));
+        let after = selections(&balance(&[1, 2, 3]));
+        for (b, a) in before.iter().zip(after.iter()) {
+            if *b != 4 {
+                assert_eq!(b, a);
+            }
+        }
+    }
+
+    #[test]
+    fn skips_unready_endpoints() {
+        let mut balance = balance(&[1, 2]);
+        let hash = hash_point(&"request", 0);
+        let selected = *balance.select(hash).unwrap();
+        balance.endpoints.get_mut(&selected).unwrap().ready = false;
+        let fallback = *balance.select(hash).unwrap();
+        assert_ne!(selected, fallback);
+
+        balance.endpoints.get_mut(&fallback).unwrap().ready = false;
+        assert!(balance.select(hash).is_none());
+    }
+
+    #[test]
+    fn selection_is_proportional_to_weight() {
+        let balance = weighted_balance(&[(1, 3.0), (2, 1.0)]);
+        let selections = selections(&balance);
+        let heavy = selections.iter().filter(|k| **k == 1).count();
+        assert!(
+            heavy > 600 && heavy < 900,
+            "heavy endpoint selected {} of 1000 times",
+            heavy
+        );
+    }
+
+    #[test]
+    fn zero_weighted_endpoints_are_only_a_fallback() {
+        let mut balance = weighted_balance(&[(1, 1.0), (2, 0.0)]);
+        assert!(selections(&balance).iter().all(|k| *k == 1));
+
+        balance.endpoints.get_mut(&1).unwrap().ready = false;
+        assert!(selections(&balance).iter().all(|k| *k == 2));
+    }
+}
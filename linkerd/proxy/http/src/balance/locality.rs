@@ -0,0 +1,397 @@
+//! Locality-aware endpoint selection.
+//!
+//! Endpoints whose labels match the proxy's configured locality (e.g. its
+//! zone) are preferred. All other endpoints are held unready while enough local
+//! endpoints are available, so that requests only spill over to other
+//! localities when local endpoints are too few or unhealthy.
+
+use super::peak_ewma::{Cost, ReportLoad};
+use super::weight::{HasWeight, Weight};
+use futures::{task, try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use std::sync::{Arc, Mutex};
+use tower_discover::{Change, Discover};
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Labels describing the proxy's own locality.
+    ///
+    /// An endpoint is local if its labels include all of these labels. If
+    /// empty, no endpoints are preferred.
+    pub labels: IndexMap<String, String>,
+
+    /// The number of local endpoints that must be available for other
+    /// endpoints to be held unready. At least one local endpoint is always
+    /// required.
+    pub min_endpoints: usize,
+}
+
+/// Describes the labels of a target.
+pub trait HasLabels {
+    fn labels(&self) -> &IndexMap<String, String>;
+}
+
+/// Describes whether a service is local to the proxy.
+pub trait HasLocality {
+    fn is_local(&self) -> bool;
+}
+
+/// Annotates each service produced by an inner `MakeService` with whether its
+/// target is local.
+#[derive(Clone, Debug)]
+pub struct MakeLocal<M> {
+    inner: M,
+    labels: Arc<IndexMap<String, String>>,
+}
+
+#[derive(Debug)]
+pub struct MakeFuture<F> {
+    inner: F,
+    is_local: bool,
+}
+
+/// An endpoint service annotated with its locality.
+#[derive(Clone, Debug)]
+pub struct Local<S> {
+    inner: S,
+    is_local: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    labels: Arc<IndexMap<String, String>>,
+}
+
+/// Wraps a `Discover` so that non-local endpoints are only used when too few
+/// local endpoints are available.
+#[derive(Copy, Clone, Debug)]
+pub struct PreferLocalLayer {
+    min_endpoints: usize,
+}
+
+pub struct PreferLocalDiscover<D> {
+    discover: D,
+    available: Available,
+}
+
+pub struct PreferLocal<S> {
+    inner: S,
+    is_local: bool,
+    is_available: bool,
+    available: Available,
+}
+
+/// Tracks the number of available local endpoints in a balancer.
+#[derive(Clone, Debug)]
+struct Available(Arc<Mutex<AvailableInner>>);
+
+#[derive(Debug)]
+struct AvailableInner {
+    min_endpoints: usize,
+    local_endpoints: usize,
+    parked: Vec<task::Task>,
+}
+
+// === impl Config ===
+
+impl Config {
+    pub fn is_local(&self, labels: &IndexMap<String, String>) -> bool {
+        is_local(&self.labels, labels)
+    }
+}
+
+fn is_local(locality: &IndexMap<String, String>, labels: &IndexMap<String, String>) -> bool {
+    !locality.is_empty()
+        && locality
+            .iter()
+            .all(|(k, v)| labels.get(k).map(|l| l == v).unwrap_or(false))
+}
+
+// === impl Layer ===
+
+pub fn layer(config: &Config) -> Layer {
+    Layer {
+        labels: Arc::new(config.labels.clone()),
+    }
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeLocal<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeLocal {
+            inner,
+            labels: self.labels.clone(),
+        }
+    }
+}
+
+// === impl MakeLocal ===
+
+impl<T, M> tower::Service<T> for MakeLocal<M>
+where
+    T: HasLabels,
+    M: tower::Service<T>,
+{
+    type Response = Local<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let is_local = is_local(&self.labels, target.labels());
+        MakeFuture {
+            is_local,
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Local<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Local::new(inner, self.is_local)))
+    }
+}
+
+// === impl Local ===
+
+impl<S> Local<S> {
+    pub fn new(inner: S, is_local: bool) -> Self {
+        Self { inner, is_local }
+    }
+}
+
+impl<S> HasLocality for Local<S> {
+    fn is_local(&self) -> bool {
+        self.is_local
+    }
+}
+
+impl<S: HasWeight> HasWeight for Local<S> {
+    fn weight(&self) -> Weight {
+        self.inner.weight()
+    }
+}
+
+impl<S: ReportLoad> ReportLoad for Local<S> {
+    fn report_load(&self, cost: Cost) {
+        self.inner.report_load(cost)
+    }
+}
+
+impl<S, Req> tower::Service<Req> for Local<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+// === impl PreferLocalLayer ===
+
+impl PreferLocalLayer {
+    pub fn new(min_endpoints: usize) -> Self {
+        Self { min_endpoints }
+    }
+}
+
+impl<D> tower::layer::Layer<D> for PreferLocalLayer {
+    type Service = PreferLocalDiscover<D>;
+
+    fn layer(&self, discover: D) -> Self::Service {
+        PreferLocalDiscover {
+            discover,
+            available: Available::new(self.min_endpoints),
+        }
+    }
+}
+
+// === impl PreferLocalDiscover ===
+
+impl<D> Discover for PreferLocalDiscover<D>
+where
+    D: Discover,
+    D::Service: HasLocality,
+{
+    type Key = D::Key;
+    type Service = PreferLocal<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<D::Key, Self::Service>, D::Error> {
+        let change = match try_ready!(self.discover.poll()) {
+            Change::Insert(key, inner) => {
+                let svc = PreferLocal {
+                    is_local: inner.is_local(),
+                    is_available: false,
+                    available: self.available.clone(),
+                    inner,
+                };
+                Change::Insert(key, svc)
+            }
+            Change::Remove(key) => Change::Remove(key),
+        };
+
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl PreferLocal ===
+
+impl<S> PreferLocal<S> {
+    fn set_available(&mut self, is_available: bool) {
+        if self.is_available != is_available {
+            self.is_available = is_available;
+            self.available.update(is_available);
+        }
+    }
+}
+
+impl<S, Req> tower::Service<Req> for PreferLocal<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if !self.is_local {
+            // Other localities are only used when too few local endpoints
+            // are available.
+            if !self.available.poll_spillover() {
+                return Ok(Async::NotReady);
+            }
+            return self.inner.poll_ready();
+        }
+
+        let ready = self.inner.poll_ready();
+        self.set_available(match ready {
+            Ok(Async::Ready(())) => true,
+            _ => false,
+        });
+        ready
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+impl<S: HasWeight> HasWeight for PreferLocal<S> {
+    fn weight(&self) -> Weight {
+        self.inner.weight()
+    }
+}
+
+impl<S: ReportLoad> ReportLoad for PreferLocal<S> {
+    fn report_load(&self, cost: Cost) {
+        self.inner.report_load(cost)
+    }
+}
+
+impl<S> Drop for PreferLocal<S> {
+    fn drop(&mut self) {
+        self.set_available(false);
+    }
+}
+
+// === impl Available ===
+
+impl Available {
+    fn new(min_endpoints: usize) -> Self {
+        Available(Arc::new(Mutex::new(AvailableInner {
+            min_endpoints: min_endpoints.max(1),
+            local_endpoints: 0,
+            parked: Vec::new(),
+        })))
+    }
+
+    fn update(&self, is_available: bool) {
+        let mut inner = self.0.lock().expect("locality lock");
+        if is_available {
+            inner.local_endpoints += 1;
+            return;
+        }
+
+        inner.local_endpoints -= 1;
+        if inner.local_endpoints < inner.min_endpoints {
+            // Wake non-local services so that they may become ready.
+            for task in inner.parked.drain(..) {
+                task.notify();
+            }
+        }
+    }
+
+    /// Returns true if non-local endpoints may be used. Otherwise, the current
+    /// task is notified when this changes.
+    fn poll_spillover(&self) -> bool {
+        let mut inner = self.0.lock().expect("locality lock");
+        if inner.local_endpoints < inner.min_endpoints {
+            return true;
+        }
+
+        if !inner.parked.iter().any(|t| t.will_notify_current()) {
+            inner.parked.push(task::current());
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    fn labels(pairs: &[(&str, &str)]) -> IndexMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn is_local_requires_all_labels() {
+        let config = Config {
+            labels: labels(&[("zone", "a"), ("region", "r")]),
+            min_endpoints: 1,
+        };
+        assert!(config.is_local(&labels(&[("zone", "a"), ("region", "r"), ("pod", "p")])));
+        assert!(!config.is_local(&labels(&[("zone", "b"), ("region", "r")])));
+        assert!(!config.is_local(&labels(&[("region", "r")])));
+        assert!(!Config::default().is_local(&labels(&[("zone", "a")])));
+    }
+
+    #[test]
+    fn spills_over_with_too_few_local_endpoints() {
+        let available = Available::new(2);
+        assert!(available.poll_spillover());
+
+        available.update(true);
+        assert!(available.poll_spillover());
+
+        available.update(true);
+        let spillover = future::lazy(|| Ok::<_, ()>(available.poll_spillover()))
+            .wait()
+            .unwrap();
+        assert!(!spillover);
+
+        available.update(false);
+        assert!(available.poll_spillover());
+    }
+}
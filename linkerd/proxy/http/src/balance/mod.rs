@@ -6,10 +6,29 @@ use rand::{rngs::SmallRng, SeedableRng};
 use std::{marker::PhantomData, time::Duration};
 pub use tower_balance::p2c::Balance;
 use tower_discover::Discover;
-pub use tower_load::{Load, PeakEwmaDiscover};
+pub use tower_load::Load;
+
+pub mod hash;
+pub mod locality;
+pub mod outlier;
+pub mod peak_ewma;
+pub mod weight;
+
+pub use self::peak_ewma::{Cost, PeakEwmaDiscover, ReportLoad};
+pub use self::weight::{HasWeight, Weight, Weighted};
+
+/// Indicates that an endpoint declined a request without processing it.
+///
+/// Declined requests are counted neither in the endpoint's load nor in its
+/// outlier detection, so that the balancer may safely redispatch them.
+#[derive(Debug)]
+pub struct Declined(Error);
 
 /// Configures a stack to resolve `T` typed targets to balance requests over
 /// `M`-typed endpoint stacks.
+///
+/// Endpoint services must be annotated with their weights (i.e. with
+/// `weight::layer`), so that requests are distributed proportionally.
 #[derive(Debug)]
 pub struct Layer<A, B> {
     decay: Duration,
@@ -45,7 +64,8 @@ where
     A: Payload,
     B: Payload,
     D: Discover<Service = S>,
-    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    D::Key: Clone,
+    S: tower::Service<http::Request<A>, Response = http::Response<B>> + HasWeight + ReportLoad,
     S::Error: Into<Error>,
     Balance<PeakEwmaDiscover<D, PendingUntilFirstData>, http::Request<A>>:
         tower::Service<http::Request<A>>,
@@ -58,3 +78,27 @@ where
         Balance::new(loaded, self.rng.clone())
     }
 }
+
+// === impl Declined ===
+
+impl Declined {
+    pub fn new(reason: impl Into<Error>) -> Self {
+        Declined(reason.into())
+    }
+}
+
+impl std::fmt::Display for Declined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "endpoint declined the request: {}", self.0)
+    }
+}
+
+impl std::error::Error for Declined {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+fn is_declined(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.is::<Declined>() || error.source().map(is_declined).unwrap_or(false)
+}
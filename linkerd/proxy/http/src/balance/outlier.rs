@@ -0,0 +1,700 @@
+//! Outlier detection for balanced endpoints.
+//!
+//! Each endpoint's responses are classified with the `ClassifyResponse`
+//! extension set on each request. When an endpoint fails too many consecutive
+//! requests, or its failure rate exceeds a threshold, it is ejected: the
+//! endpoint is held unready, so that the balancer will not select it, until a
+//! backoff elapses. Each successive ejection of an endpoint increases its
+//! backoff until the endpoint serves a successful response.
+
+use super::is_declined;
+use super::locality::HasLocality;
+use super::peak_ewma::{Cost, ReportLoad};
+use super::weight::{HasWeight, Weight};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use http;
+use hyper::body::Payload;
+use linkerd2_error::Error;
+use linkerd2_exp_backoff::{ExponentialBackoff, ExponentialBackoffStream};
+use linkerd2_http_classify::{ClassifyEos, ClassifyResponse};
+use linkerd2_stack_metrics::Ejections;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use tower_discover::{Change, Discover};
+use tracing::{debug, warn};
+
+/// Determines whether a response class indicates a failure.
+pub trait IsFailure {
+    fn is_failure(&self) -> bool;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The number of consecutive failures after which an endpoint is ejected.
+    ///
+    /// If zero, endpoints are not ejected for consecutive failures.
+    pub consecutive_failures: u32,
+
+    /// The ratio of failed responses, on `[0.0, 1.0)`, above which an endpoint
+    /// is ejected.
+    ///
+    /// If greater than or equal to 1.0, endpoints are not ejected for their
+    /// failure rate.
+    pub failure_rate: f64,
+
+    /// The number of responses over which each endpoint's failure rate is
+    /// computed.
+    pub failure_rate_window: u32,
+
+    /// Determines how long an ejected endpoint is held unready.
+    pub backoff: ExponentialBackoff,
+}
+
+/// Wraps each target's `Discover` so that its endpoints may be ejected.
+pub struct Layer<F, C> {
+    config: Config,
+    metrics: F,
+    _marker: PhantomData<fn() -> C>,
+}
+
+pub struct MakeOutlier<M, F, C> {
+    inner: M,
+    config: Config,
+    metrics: F,
+    _marker: PhantomData<fn() -> C>,
+}
+
+pub struct MakeFuture<F, C> {
+    inner: F,
+    shared: Arc<Shared>,
+    _marker: PhantomData<fn() -> C>,
+}
+
+pub struct OutlierDiscover<D, C> {
+    discover: D,
+    shared: Arc<Shared>,
+    _marker: PhantomData<fn() -> C>,
+}
+
+/// An endpoint service that is held unready while it is ejected.
+pub struct Outlier<S, C> {
+    inner: S,
+    endpoint: Arc<Endpoint>,
+    _marker: PhantomData<fn() -> C>,
+}
+
+pub struct ResponseFuture<F, C> {
+    inner: F,
+    classify: Option<C>,
+    endpoint: Arc<Endpoint>,
+}
+
+#[derive(Debug)]
+pub struct ResponseBody<B, C: ClassifyEos>
+where
+    C::Class: IsFailure,
+{
+    inner: B,
+    classify: Option<C>,
+    endpoint: Arc<Endpoint>,
+}
+
+/// State shared by all endpoints in a balancer.
+#[derive(Debug)]
+struct Shared {
+    config: Config,
+    metrics: Ejections,
+    pool: Mutex<Pool>,
+}
+
+#[derive(Debug, Default)]
+struct Pool {
+    endpoints: usize,
+    ejected: usize,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    shared: Arc<Shared>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    consecutive_failures: u32,
+    failures: u32,
+    responses: u32,
+    ejected: bool,
+    removed: bool,
+    /// Set when an endpoint is restored, until it serves a successful response.
+    probation: bool,
+    backoff: ExponentialBackoffStream,
+}
+
+// === impl Layer ===
+
+/// Returns a `Layer` that ejects endpoints from each target's balancer,
+/// recording ejections with the metrics that `metrics` returns for the target.
+pub fn layer<C, F>(config: Config, metrics: F) -> Layer<F, C> {
+    Layer {
+        config,
+        metrics,
+        _marker: PhantomData,
+    }
+}
+
+impl<F: Clone, C> Clone for Layer<F, C> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config,
+            metrics: self.metrics.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M, F: Clone, C> tower::layer::Layer<M> for Layer<F, C> {
+    type Service = MakeOutlier<M, F, C>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeOutlier {
+            inner,
+            config: self.config,
+            metrics: self.metrics.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+// === impl MakeOutlier ===
+
+impl<M: Clone, F: Clone, C> Clone for MakeOutlier<M, F, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config,
+            metrics: self.metrics.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, M, F, C> tower::Service<T> for MakeOutlier<M, F, C>
+where
+    M: tower::Service<T>,
+    M::Response: Discover,
+    F: Fn(&T) -> Ejections,
+{
+    type Response = OutlierDiscover<M::Response, C>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        // Each balancer tracks its own pool of endpoints.
+        let shared = Arc::new(Shared {
+            config: self.config,
+            metrics: (self.metrics)(&target),
+            pool: Mutex::new(Pool::default()),
+        });
+        MakeFuture {
+            shared,
+            inner: self.inner.call(target),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F: Future, C> Future for MakeFuture<F, C> {
+    type Item = OutlierDiscover<F::Item, C>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let discover = try_ready!(self.inner.poll());
+        Ok(Async::Ready(OutlierDiscover {
+            discover,
+            shared: self.shared.clone(),
+            _marker: PhantomData,
+        }))
+    }
+}
+
+// === impl OutlierDiscover ===
+
+impl<D, C> Discover for OutlierDiscover<D, C>
+where
+    D: Discover,
+{
+    type Key = D::Key;
+    type Service = Outlier<D::Service, C>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<D::Key, Self::Service>, D::Error> {
+        let change = match try_ready!(self.discover.poll()) {
+            Change::Insert(key, inner) => {
+                let endpoint = Endpoint::new(self.shared.clone());
+                Change::Insert(
+                    key,
+                    Outlier {
+                        inner,
+                        endpoint,
+                        _marker: PhantomData,
+                    },
+                )
+            }
+            Change::Remove(key) => Change::Remove(key),
+        };
+
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl Outlier ===
+
+impl<S, C, A, B> tower::Service<http::Request<A>> for Outlier<S, C>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    C: ClassifyResponse + Clone + Send + Sync + 'static,
+    C::Class: IsFailure,
+{
+    type Response = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if !self.endpoint.poll_restored() {
+            return Ok(Async::NotReady);
+        }
+
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let classify = req.extensions().get::<C>().cloned();
+        ResponseFuture {
+            classify,
+            endpoint: self.endpoint.clone(),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+impl<S: HasWeight, C> HasWeight for Outlier<S, C> {
+    fn weight(&self) -> Weight {
+        self.inner.weight()
+    }
+}
+
+impl<S: HasLocality, C> HasLocality for Outlier<S, C> {
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+}
+
+impl<S: ReportLoad, C> ReportLoad for Outlier<S, C> {
+    fn report_load(&self, cost: Cost) {
+        self.inner.report_load(cost)
+    }
+}
+
+impl<S, C> Drop for Outlier<S, C> {
+    fn drop(&mut self) {
+        self.endpoint.remove();
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, C, B> Future for ResponseFuture<F, C>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    C: ClassifyResponse,
+    C::Class: IsFailure,
+{
+    type Item = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => rsp,
+            Err(e) => {
+                let error = e.into();
+                // A declined request was never processed by the endpoint.
+                if let Some(classify) = self.classify.take() {
+                    if !is_declined(&*error) {
+                        self.endpoint.record(&classify.error(&error));
+                    }
+                }
+                return Err(error);
+            }
+        };
+
+        let classify = self.classify.take().map(|c| c.start(&rsp));
+        let endpoint = self.endpoint.clone();
+        Ok(Async::Ready(rsp.map(|inner| ResponseBody {
+            inner,
+            classify,
+            endpoint,
+        })))
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<B, C> ResponseBody<B, C>
+where
+    C: ClassifyEos,
+    C::Class: IsFailure,
+{
+    fn record_error(&mut self, error: Error) -> Error {
+        if let Some(classify) = self.classify.take() {
+            self.endpoint.record(&classify.error(&error));
+        }
+        error
+    }
+}
+
+impl<B, C> Payload for ResponseBody<B, C>
+where
+    B: Payload,
+    C: ClassifyEos + Send + 'static,
+    C::Class: IsFailure,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        self.inner
+            .poll_data()
+            .map_err(|e| self.record_error(e.into()))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self
+            .inner
+            .poll_trailers()
+            .map_err(|e| self.record_error(e.into())));
+
+        if let Some(classify) = self.classify.take() {
+            self.endpoint.record(&classify.eos(trailers.as_ref()));
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B, C> http_body::Body for ResponseBody<B, C>
+where
+    B: Payload,
+    C: ClassifyEos + Send + 'static,
+    C::Class: IsFailure,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        Payload::is_end_stream(self)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Payload::poll_data(self)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Payload::poll_trailers(self)
+    }
+}
+
+impl<B, C> Drop for ResponseBody<B, C>
+where
+    C: ClassifyEos,
+    C::Class: IsFailure,
+{
+    fn drop(&mut self) {
+        if let Some(classify) = self.classify.take() {
+            self.endpoint.record(&classify.eos(None));
+        }
+    }
+}
+
+// === impl Shared ===
+
+impl Shared {
+    /// Marks an endpoint as ejected, unless doing so would leave the balancer
+    /// without any endpoints.
+    fn try_eject(&self) -> bool {
+        let mut pool = self.pool.lock().expect("outlier pool lock");
+        if pool.ejected + 1 >= pool.endpoints {
+            return false;
+        }
+
+        pool.ejected += 1;
+        self.metrics.eject();
+        true
+    }
+
+    fn restore(&self) {
+        let mut pool = self.pool.lock().expect("outlier pool lock");
+        pool.ejected -= 1;
+        self.metrics.restore();
+    }
+}
+
+// === impl Endpoint ===
+
+impl Endpoint {
+    fn new(shared: Arc<Shared>) -> Arc<Self> {
+        shared.pool.lock().expect("outlier pool lock").endpoints += 1;
+        let backoff = shared.config.backoff.stream();
+        Arc::new(Self {
+            shared,
+            state: Mutex::new(State {
+                consecutive_failures: 0,
+                failures: 0,
+                responses: 0,
+                ejected: false,
+                removed: false,
+                probation: false,
+                backoff,
+            }),
+        })
+    }
+
+    /// Records a response classification, ejecting the endpoint if it has
+    /// become an outlier.
+    fn record<F: IsFailure>(&self, class: &F) {
+        let config = &self.shared.config;
+        let mut state = self.state.lock().expect("outlier state lock");
+        // Responses that complete while the endpoint is ejected are ignored.
+        if state.ejected || state.removed {
+            return;
+        }
+
+        state.responses += 1;
+        if class.is_failure() {
+            state.consecutive_failures += 1;
+            state.failures += 1;
+        } else {
+            state.consecutive_failures = 0;
+            if state.probation {
+                // The endpoint has recovered, so subsequent ejections start
+                // with the minimum backoff.
+                state.probation = false;
+                state.backoff = config.backoff.stream();
+            }
+        }
+
+        let mut eject = config.consecutive_failures > 0
+            && state.consecutive_failures >= config.consecutive_failures;
+        if config.failure_rate_window > 0 && state.responses >= config.failure_rate_window {
+            let rate = f64::from(state.failures) / f64::from(state.responses);
+            eject = eject || rate > config.failure_rate;
+            state.failures = 0;
+            state.responses = 0;
+        }
+
+        if eject && self.shared.try_eject() {
+            debug!(
+                consecutive_failures = state.consecutive_failures,
+                "Ejecting endpoint"
+            );
+            state.ejected = true;
+            state.consecutive_failures = 0;
+            state.failures = 0;
+            state.responses = 0;
+        }
+    }
+
+    /// Returns true if the endpoint is not ejected. Otherwise, the current
+    /// task is notified when the endpoint's backoff elapses.
+    fn poll_restored(&self) -> bool {
+        let mut state = self.state.lock().expect("outlier state lock");
+        if !state.ejected {
+            return true;
+        }
+
+        match state.backoff.poll() {
+            Ok(Async::NotReady) => return false,
+            Ok(Async::Ready(_)) => {}
+            Err(error) => warn!(%error, "Ejection backoff failed"),
+        }
+
+        debug!("Restoring ejected endpoint");
+        state.ejected = false;
+        state.probation = true;
+        self.shared.restore();
+        true
+    }
+
+    fn remove(&self) {
+        let mut state = self.state.lock().expect("outlier state lock");
+        state.removed = true;
+        if state.ejected {
+            state.ejected = false;
+            self.shared.restore();
+        }
+        self.shared
+            .pool
+            .lock()
+            .expect("outlier pool lock")
+            .endpoints -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Declined;
+    use super::*;
+    use futures::future;
+    use linkerd2_stack_metrics::Registry;
+    use std::time::Duration;
+
+    struct Class(bool);
+
+    impl IsFailure for Class {
+        fn is_failure(&self) -> bool {
+            self.0
+        }
+    }
+
+    const SUCCESS: Class = Class(false);
+    const FAILURE: Class = Class(true);
+
+    /// Classifies every response as a failure.
+    #[derive(Clone)]
+    struct Failures;
+
+    impl ClassifyResponse for Failures {
+        type Class = Class;
+        type ClassifyEos = Self;
+
+        fn start<B>(self, _: &http::Response<B>) -> Self {
+            self
+        }
+
+        fn error(self, _: &Error) -> Class {
+            FAILURE
+        }
+    }
+
+    impl ClassifyEos for Failures {
+        type Class = Class;
+
+        fn eos(self, _: Option<&http::HeaderMap>) -> Class {
+            FAILURE
+        }
+
+        fn error(self, _: &Error) -> Class {
+            FAILURE
+        }
+    }
+
+    fn shared(
+        consecutive_failures: u32,
+        failure_rate: f64,
+        failure_rate_window: u32,
+    ) -> Arc<Shared> {
+        let config = Config {
+            consecutive_failures,
+            failure_rate,
+            failure_rate_window,
+            backoff: ExponentialBackoff::new(Duration::from_secs(10), Duration::from_secs(60), 0.0)
+                .unwrap(),
+        };
+        Arc::new(Shared {
+            config,
+            metrics: Registry::<&'static str>::new(Duration::from_secs(10)).ejections("test"),
+            pool: Mutex::new(Pool::default()),
+        })
+    }
+
+    fn is_ejected(endpoint: &Endpoint) -> bool {
+        endpoint.state.lock().unwrap().ejected
+    }
+
+    #[test]
+    fn ejects_after_consecutive_failures() {
+        let shared = shared(3, 1.0, 0);
+        let endpoint = Endpoint::new(shared.clone());
+        let _other = Endpoint::new(shared.clone());
+
+        endpoint.record(&FAILURE);
+        endpoint.record(&FAILURE);
+        endpoint.record(&SUCCESS);
+        endpoint.record(&FAILURE);
+        endpoint.record(&FAILURE);
+        assert!(!is_ejected(&endpoint));
+
+        endpoint.record(&FAILURE);
+        assert!(is_ejected(&endpoint));
+        assert_eq!(shared.pool.lock().unwrap().ejected, 1);
+    }
+
+    #[test]
+    fn ejects_above_failure_rate() {
+        let shared = shared(0, 0.5, 4);
+        let endpoint = Endpoint::new(shared.clone());
+        let _other = Endpoint::new(shared.clone());
+
+        for class in &[FAILURE, SUCCESS, FAILURE, SUCCESS] {
+            endpoint.record(class);
+        }
+        assert!(!is_ejected(&endpoint));
+
+        for class in &[FAILURE, SUCCESS, FAILURE, FAILURE] {
+            endpoint.record(class);
+        }
+        assert!(is_ejected(&endpoint));
+    }
+
+    #[test]
+    fn does_not_eject_last_endpoint() {
+        let shared = shared(1, 1.0, 0);
+        let a = Endpoint::new(shared.clone());
+        let b = Endpoint::new(shared.clone());
+
+        a.record(&FAILURE);
+        assert!(is_ejected(&a));
+
+        b.record(&FAILURE);
+        assert!(!is_ejected(&b));
+
+        a.remove();
+        assert_eq!(shared.pool.lock().unwrap().ejected, 0);
+        assert_eq!(shared.pool.lock().unwrap().endpoints, 1);
+    }
+
+    #[test]
+    fn declined_requests_are_not_failures() {
+        let shared = shared(1, 1.0, 0);
+        let endpoint = Endpoint::new(shared.clone());
+        let _other = Endpoint::new(shared.clone());
+
+        let fail = |error: Error| {
+            let mut rsp = ResponseFuture {
+                inner: future::err::<http::Response<()>, Error>(error),
+                classify: Some(Failures),
+                endpoint: endpoint.clone(),
+            };
+            assert!(rsp.poll().is_err());
+        };
+
+        // A hedge declined by its original request's endpoint is not a
+        // failure of that endpoint.
+        fail(Declined::new("hedged").into());
+        assert!(!is_ejected(&endpoint));
+
+        fail("failed".into());
+        assert!(is_ejected(&endpoint));
+    }
+}
@@ -0,0 +1,524 @@
+//! A weight-aware Peak-EWMA load metric.
+//!
+//! This is derived from `tower_load::PeakEwma`; but each endpoint's cost is
+//! divided by its weight so that more heavily-weighted endpoints receive a
+//! proportionally larger share of requests. Zero-weighted endpoints are held
+//! unready while any non-zero-weighted endpoint in the balancer is ready.
+//! Requests that an endpoint `Declined` are not counted in its RTT estimate.
+
+use super::weight::{HasWeight, Weight};
+use super::{is_declined, Error};
+use futures::{task, try_ready, Async, Future, Poll};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tower_discover::{Change, Discover};
+use tower_load::{Instrument, Load};
+
+/// Wraps a `D`-typed `Discover` so that each endpoint service is load-tracked
+/// with a `PeakEwma`.
+pub struct PeakEwmaDiscover<D: Discover, I> {
+    discover: D,
+    decay_ns: f64,
+    default_rtt: Duration,
+    instrument: I,
+    zero_weight: ZeroWeight,
+}
+
+/// Tracks the RTT of each request issued to an `S`-typed service, scaled by the
+/// service's weight.
+pub struct PeakEwma<S, I> {
+    service: S,
+    weight: Weight,
+    decay_ns: f64,
+    rtt_estimate: Arc<Mutex<RttEstimate>>,
+    instrument: I,
+    zero_weight: ZeroWeight,
+    /// Whether this (non-zero-weighted) service is counted as ready by
+    /// `zero_weight`.
+    weighted_ready: bool,
+}
+
+/// Updates the RTT estimate when dropped.
+pub struct Handle {
+    sent_at: Instant,
+    decay_ns: f64,
+    rtt_estimate: Option<Arc<Mutex<RttEstimate>>>,
+}
+
+pub struct ResponseFuture<F, I> {
+    inner: F,
+    instrument: I,
+    handle: Option<Handle>,
+}
+
+/// The relative cost of communicating with a service.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Cost(f64);
+
+/// Receives each cost computed for a service, e.g. so that it may be
+/// inspected.
+///
+/// Services that wrap an endpoint should forward costs to their inner
+/// service.
+pub trait ReportLoad {
+    fn report_load(&self, _cost: Cost) {}
+}
+
+struct RttEstimate {
+    update_at: Instant,
+    rtt_ns: f64,
+}
+
+/// Shared between a discover and its services to determine whether
+/// zero-weighted endpoints may be used.
+#[derive(Clone, Debug, Default)]
+struct ZeroWeight(Arc<Mutex<ZeroWeightInner>>);
+
+#[derive(Debug, Default)]
+struct ZeroWeightInner {
+    ready_weighted: usize,
+    parked: Vec<task::Task>,
+}
+
+// === impl PeakEwmaDiscover ===
+
+impl<D: Discover, I> PeakEwmaDiscover<D, I> {
+    pub fn new(discover: D, default_rtt: Duration, decay: Duration, instrument: I) -> Self {
+        Self {
+            discover,
+            decay_ns: nanos(decay),
+            default_rtt,
+            instrument,
+            zero_weight: ZeroWeight::default(),
+        }
+    }
+}
+
+impl<D, I> Discover for PeakEwmaDiscover<D, I>
+where
+    D: Discover,
+    D::Key: Clone,
+    D::Service: HasWeight,
+    I: Clone,
+{
+    type Key = D::Key;
+    type Service = PeakEwma<D::Service, I>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<D::Key, Self::Service>, D::Error> {
+        let change = match try_ready!(self.discover.poll()) {
+            Change::Insert(key, svc) => {
+                let svc = PeakEwma::new(
+                    svc,
+                    self.default_rtt,
+                    self.decay_ns,
+                    self.instrument.clone(),
+                    self.zero_weight.clone(),
+                );
+                Change::Insert(key, svc)
+            }
+            Change::Remove(key) => Change::Remove(key),
+        };
+
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl PeakEwma ===
+
+impl<S: HasWeight, I> PeakEwma<S, I> {
+    fn new(
+        service: S,
+        default_rtt: Duration,
+        decay_ns: f64,
+        instrument: I,
+        zero_weight: ZeroWeight,
+    ) -> Self {
+        let weight = service.weight();
+        // Weighted services are assumed to be ready until they are polled, so
+        // that zero-weighted services aren't used before they've had a chance
+        // to become ready.
+        let weighted_ready = !weight.is_zero();
+        if weighted_ready {
+            zero_weight.set_weighted_ready(true);
+        }
+        Self {
+            weight,
+            service,
+            decay_ns,
+            rtt_estimate: Arc::new(Mutex::new(RttEstimate::new(nanos(default_rtt)))),
+            instrument,
+            zero_weight,
+            weighted_ready,
+        }
+    }
+}
+
+impl<S, I> PeakEwma<S, I> {
+    fn handle(&self) -> Handle {
+        Handle {
+            decay_ns: self.decay_ns,
+            sent_at: clock::now(),
+            rtt_estimate: Some(self.rtt_estimate.clone()),
+        }
+    }
+
+    fn update_estimate(&self) -> f64 {
+        let mut rtt = self.rtt_estimate.lock().expect("peak ewma prior_estimate");
+        rtt.decay(self.decay_ns)
+    }
+
+    fn set_weighted_ready(&mut self, ready: bool) {
+        if self.weighted_ready != ready {
+            self.weighted_ready = ready;
+            self.zero_weight.set_weighted_ready(ready);
+        }
+    }
+}
+
+impl<S, I> Drop for PeakEwma<S, I> {
+    fn drop(&mut self) {
+        self.set_weighted_ready(false);
+    }
+}
+
+impl<S, I, Req> tower::Service<Req> for PeakEwma<S, I>
+where
+    S: tower::Service<Req>,
+    S::Error: Into<Error>,
+    I: Instrument<Handle, S::Response> + Clone,
+{
+    type Response = I::Output;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future, I>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Zero-weighted endpoints are only used as a last resort.
+        if self.weight.is_zero() {
+            if !self.zero_weight.poll_usable() {
+                return Ok(Async::NotReady);
+            }
+            return self.service.poll_ready().map_err(Into::into);
+        }
+
+        let poll = self.service.poll_ready();
+        let ready = match poll {
+            Ok(Async::Ready(())) => true,
+            _ => false,
+        };
+        self.set_weighted_ready(ready);
+        poll.map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        ResponseFuture {
+            handle: Some(self.handle()),
+            instrument: self.instrument.clone(),
+            inner: self.service.call(req),
+        }
+    }
+}
+
+impl<S: ReportLoad, I> Load for PeakEwma<S, I> {
+    type Metric = Cost;
+
+    fn load(&self) -> Self::Metric {
+        let pending = Arc::strong_count(&self.rtt_estimate) as u32 - 1;
+
+        // Update the RTT estimate to account for decay since the last update.
+        // If an estimate has not been established, a default is provided.
+        let estimate = self.update_estimate();
+        let cost = estimate * f64::from(pending + 1);
+
+        // Zero-weighted endpoints are only compared with each other, so their
+        // costs are not scaled.
+        let cost = if self.weight.is_zero() {
+            Cost(cost)
+        } else {
+            let weight: f64 = self.weight.into();
+            Cost(cost / weight)
+        };
+        self.service.report_load(cost);
+        cost
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, I> Future for ResponseFuture<F, I>
+where
+    F: Future,
+    F::Error: Into<Error>,
+    I: Instrument<Handle, F::Item>,
+{
+    type Item = I::Output;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => {
+                let handle = self.handle.take().expect("polled after complete");
+                Ok(Async::Ready(self.instrument.instrument(handle, rsp)))
+            }
+            Err(e) => {
+                let error = e.into();
+                if is_declined(&*error) {
+                    if let Some(handle) = self.handle.take() {
+                        handle.discard();
+                    }
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+// === impl Cost ===
+
+impl From<f64> for Cost {
+    fn from(cost: f64) -> Self {
+        Cost(cost)
+    }
+}
+
+impl Into<f64> for Cost {
+    fn into(self) -> f64 {
+        self.0
+    }
+}
+
+// === impl RttEstimate ===
+
+impl RttEstimate {
+    fn new(rtt_ns: f64) -> Self {
+        debug_assert!(0.0 < rtt_ns, "rtt must be positive");
+        Self {
+            rtt_ns,
+            update_at: clock::now(),
+        }
+    }
+
+    /// Decays the RTT estimate with a decay period of `decay_ns`.
+    fn decay(&mut self, decay_ns: f64) -> f64 {
+        // Updates with a 0 duration so that the estimate decays towards 0.
+        let now = clock::now();
+        self.update(now, now, decay_ns)
+    }
+
+    /// Updates the Peak-EWMA RTT estimate with the elapsed time from `sent_at`
+    /// to `recv_at`.
+    fn update(&mut self, sent_at: Instant, recv_at: Instant, decay_ns: f64) -> f64 {
+        let rtt = nanos(recv_at.saturating_duration_since(sent_at));
+        let now = clock::now();
+
+        self.rtt_ns = if self.rtt_ns < rtt {
+            // For Peak-EWMA, always use the worst-case (peak) value as the
+            // estimate for subsequent requests.
+            rtt
+        } else {
+            // When an RTT is observed that is less than the estimated RTT, we
+            // decay the prior estimate according to how much time has elapsed
+            // since the last estimate update.
+            let elapsed = nanos(now.saturating_duration_since(self.update_at));
+            let decay = (-elapsed / decay_ns).exp();
+            let recency = 1.0 - decay;
+            (self.rtt_ns * decay) + (rtt * recency)
+        };
+        self.update_at = now;
+
+        self.rtt_ns
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    /// Releases the handle without updating the RTT estimate.
+    fn discard(mut self) {
+        self.rtt_estimate = None;
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let recv_at = clock::now();
+
+        if let Some(rtt_estimate) = self.rtt_estimate.take() {
+            if let Ok(mut rtt) = rtt_estimate.lock() {
+                rtt.update(self.sent_at, recv_at, self.decay_ns);
+            }
+        }
+    }
+}
+
+// === impl ZeroWeight ===
+
+impl ZeroWeight {
+    /// Records that a non-zero-weighted service has become ready or unready.
+    fn set_weighted_ready(&self, ready: bool) {
+        let mut inner = self.0.lock().expect("zero weight lock");
+        if ready {
+            inner.ready_weighted += 1;
+            return;
+        }
+
+        debug_assert!(inner.ready_weighted > 0);
+        inner.ready_weighted = inner.ready_weighted.saturating_sub(1);
+        if inner.ready_weighted == 0 {
+            // Wake zero-weighted services so that they may become ready.
+            for task in inner.parked.drain(..) {
+                task.notify();
+            }
+        }
+    }
+
+    /// Returns true if zero-weighted endpoints may be used. Otherwise, the
+    /// current task is notified when this changes.
+    fn poll_usable(&self) -> bool {
+        let mut inner = self.0.lock().expect("zero weight lock");
+        if inner.ready_weighted == 0 {
+            return true;
+        }
+
+        if !inner.parked.iter().any(|t| t.will_notify_current()) {
+            inner.parked.push(task::current());
+        }
+        false
+    }
+}
+
+fn nanos(d: Duration) -> f64 {
+    const NANOS_PER_SEC: u64 = 1_000_000_000;
+    let n = f64::from(d.subsec_nanos());
+    let s = d.as_secs().saturating_mul(NANOS_PER_SEC) as f64;
+    n + s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{weight::Weighted, Balance};
+    use crate::Error;
+    use futures::{future, Future};
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tower::Service;
+    use tower_load::NoInstrument;
+
+    fn peak_ewma(weight: f64) -> PeakEwma<Weighted<()>, ()> {
+        PeakEwma::new(
+            Weighted::new((), Weight::from(weight)),
+            Duration::from_millis(10),
+            nanos(Duration::from_secs(10)),
+            (),
+            ZeroWeight::default(),
+        )
+    }
+
+    #[test]
+    fn cost_is_divided_by_weight() {
+        let default = peak_ewma(1.0).load();
+        let heavy = peak_ewma(2.0).load();
+        let light = peak_ewma(0.5).load();
+        assert!(heavy < default, "{:?} < {:?}", heavy, default);
+        assert!(default < light, "{:?} < {:?}", default, light);
+    }
+
+    #[test]
+    fn zero_weight_is_unscaled() {
+        let zero = peak_ewma(0.0).load();
+        let default = peak_ewma(1.0).load();
+        assert!(zero.0.is_finite());
+        assert!((zero.0 - default.0).abs() / default.0 < 0.01);
+    }
+
+    #[test]
+    fn zero_weight_usable_only_without_ready_weighted() {
+        let zero_weight = ZeroWeight::default();
+        assert!(zero_weight.poll_usable());
+
+        zero_weight.set_weighted_ready(true);
+        let usable = future::lazy(|| Ok::<_, ()>(zero_weight.poll_usable()))
+            .wait()
+            .unwrap();
+        assert!(!usable);
+
+        zero_weight.set_weighted_ready(false);
+        assert!(zero_weight.poll_usable());
+    }
+
+    /// A service that is ready only while its flag is set.
+    struct Flagged(usize, Arc<AtomicBool>);
+
+    impl tower::Service<()> for Flagged {
+        type Response = usize;
+        type Error = Error;
+        type Future = future::FutureResult<usize, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            if self.1.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            future::ok(self.0)
+        }
+    }
+
+    /// Discovers a fixed set of endpoints.
+    struct Fixed(Vec<Change<usize, Weighted<Flagged>>>);
+
+    impl Discover for Fixed {
+        type Key = usize;
+        type Service = Weighted<Flagged>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, Self::Service>, Error> {
+            Ok(self.0.pop().map(Async::Ready).unwrap_or(Async::NotReady))
+        }
+    }
+
+    #[test]
+    fn balancer_falls_back_while_weighted_unready() {
+        let weighted_ready = Arc::new(AtomicBool::new(false));
+        let endpoint = |id: usize, weight: f64, ready: &Arc<AtomicBool>| {
+            let svc = Weighted::new(Flagged(id, ready.clone()), Weight::from(weight));
+            Change::Insert(id, svc)
+        };
+        let discover = Fixed(vec![
+            endpoint(0, 0.0, &Arc::new(AtomicBool::new(true))),
+            endpoint(1, 1.0, &weighted_ready),
+        ]);
+        let discover = PeakEwmaDiscover::new(
+            discover,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+            NoInstrument,
+        );
+        let mut balance = Balance::new(discover, SmallRng::from_entropy());
+
+        future::lazy(move || {
+            // The weighted endpoint is polled before it is known to be
+            // unready, so the fallback may take another poll to become ready.
+            if !balance.poll_ready().unwrap().is_ready() {
+                assert!(balance.poll_ready().unwrap().is_ready());
+            }
+            assert_eq!(balance.call(()).wait().unwrap(), 0);
+
+            // Once the weighted endpoint becomes ready, the fallback is no
+            // longer used.
+            weighted_ready.store(true, Ordering::SeqCst);
+            assert!(balance.poll_ready().unwrap().is_ready());
+            assert_eq!(balance.call(()).wait().unwrap(), 1);
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
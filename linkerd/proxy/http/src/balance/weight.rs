@@ -0,0 +1,148 @@
+use super::peak_ewma::ReportLoad;
+use futures::{try_ready, Async, Future, Poll};
+
+/// An endpoint's relative weight, where `1.0` is the default weight.
+///
+/// A weight of zero indicates that the endpoint should only be used when no
+/// non-zero-weighted endpoints are available.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Weight(f64);
+
+/// Describes the weight of a target or service.
+pub trait HasWeight {
+    fn weight(&self) -> Weight;
+}
+
+/// Wraps each service produced by an inner `MakeService` with the weight of
+/// its target.
+#[derive(Clone, Debug)]
+pub struct MakeWeighted<M> {
+    inner: M,
+}
+
+#[derive(Debug)]
+pub struct MakeFuture<F> {
+    inner: F,
+    weight: Weight,
+}
+
+/// An endpoint service annotated with its weight.
+#[derive(Clone, Debug)]
+pub struct Weighted<S> {
+    inner: S,
+    weight: Weight,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Layer(());
+
+// === impl Weight ===
+
+impl Weight {
+    pub fn is_zero(&self) -> bool {
+        self.0 <= 0.0
+    }
+}
+
+impl Default for Weight {
+    fn default() -> Self {
+        Weight(1.0)
+    }
+}
+
+impl From<f64> for Weight {
+    fn from(w: f64) -> Self {
+        if w.is_finite() && w > 0.0 {
+            Weight(w)
+        } else {
+            Weight(0.0)
+        }
+    }
+}
+
+impl Into<f64> for Weight {
+    fn into(self) -> f64 {
+        self.0
+    }
+}
+
+// === impl Layer ===
+
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeWeighted<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeWeighted { inner }
+    }
+}
+
+// === impl MakeWeighted ===
+
+impl<T, M> tower::Service<T> for MakeWeighted<M>
+where
+    T: HasWeight,
+    M: tower::Service<T>,
+{
+    type Response = Weighted<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let weight = target.weight();
+        MakeFuture {
+            weight,
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Weighted<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Weighted::new(inner, self.weight)))
+    }
+}
+
+// === impl Weighted ===
+
+impl<S> Weighted<S> {
+    pub fn new(inner: S, weight: Weight) -> Self {
+        Self { inner, weight }
+    }
+}
+
+impl<S> HasWeight for Weighted<S> {
+    fn weight(&self) -> Weight {
+        self.weight
+    }
+}
+
+impl<S> ReportLoad for Weighted<S> {}
+
+impl<S, Req> tower::Service<Req> for Weighted<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
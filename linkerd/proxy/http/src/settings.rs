@@ -1,3 +1,4 @@
+use super::Version;
 use http::{self, header::HOST};
 
 /// HTTP Client Settings portion of the `Recognize` key for a request.
@@ -74,4 +75,11 @@ impl Settings {
             Settings::Http1 { .. } => false,
         }
     }
+
+    pub fn version(&self) -> Version {
+        match self {
+            Settings::Http2 => Version::H2,
+            Settings::Http1 { .. } => Version::Http1,
+        }
+    }
 }
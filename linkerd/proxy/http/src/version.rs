@@ -39,4 +39,11 @@ impl Version {
 
         None
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http1 => "http/1",
+            Version::H2 => "h2",
+        }
+    }
 }
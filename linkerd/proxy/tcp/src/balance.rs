@@ -0,0 +1,44 @@
+use linkerd2_error::Error;
+use rand::{rngs::SmallRng, SeedableRng};
+use std::time::Duration;
+pub use tower_balance::p2c::Balance;
+use tower_discover::Discover;
+pub use tower_load::{Load, NoInstrument, PeakEwmaDiscover};
+
+/// Configures a stack to balance connections over `D`-typed discovered
+/// endpoint connectors.
+///
+/// Each endpoint service is expected to establish a new connection when
+/// called; so endpoint load is tracked by the latency of establishing
+/// connections.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    decay: Duration,
+    default_rtt: Duration,
+    rng: SmallRng,
+}
+
+// === impl Layer ===
+
+pub fn layer(default_rtt: Duration, decay: Duration) -> Layer {
+    Layer {
+        decay,
+        default_rtt,
+        rng: SmallRng::from_entropy(),
+    }
+}
+
+impl<D, S> tower::layer::Layer<D> for Layer
+where
+    D: Discover<Service = S>,
+    S: tower::Service<()>,
+    S::Error: Into<Error>,
+    Balance<PeakEwmaDiscover<D, NoInstrument>, ()>: tower::Service<()>,
+{
+    type Service = Balance<PeakEwmaDiscover<D, NoInstrument>, ()>;
+
+    fn layer(&self, discover: D) -> Self::Service {
+        let loaded = PeakEwmaDiscover::new(discover, self.default_rtt, self.decay, NoInstrument);
+        Balance::new(loaded, self.rng.clone())
+    }
+}
@@ -0,0 +1,95 @@
+#![feature(test)]
+
+extern crate linkerd2_proxy_transport;
+use futures::{Async, Poll};
+use linkerd2_metrics::{latency, FmtLabels};
+use linkerd2_proxy_transport::metrics;
+use std::io::{self, Read, Write};
+use std::{fmt, thread};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+extern crate test;
+use test::Bencher;
+
+const CONNECTIONS_PER_THREAD: usize = 100;
+const OPS_PER_CONNECTION: usize = 100;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Labels(usize);
+
+/// A transport that reads and writes without doing any I/O.
+#[derive(Debug)]
+struct Loopback;
+
+impl FmtLabels for Labels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "n=\"{}\"", self.0)
+    }
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Loopback {}
+
+impl AsyncWrite for Loopback {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Opens connections concurrently on `threads` threads, spread across `keys`
+/// distinct labels. Each connection is read from and written to before it is
+/// closed.
+fn run_bench(threads: usize, keys: usize, b: &mut Bencher) {
+    let (registry, _report) = metrics::new::<Labels>(latency::bounds());
+    b.iter(|| {
+        let handles = (0..threads)
+            .map(|t| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    let mut buf = [0u8; 64];
+                    for c in 0..CONNECTIONS_PER_THREAD {
+                        let labels = Labels((t + c) % keys);
+                        let mut io = registry.wrap_server_transport(labels, None, Loopback);
+                        for _ in 0..OPS_PER_CONNECTION {
+                            io.read(&mut buf).unwrap();
+                            io.write(&buf).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+    });
+}
+
+#[bench]
+fn one_thread(b: &mut Bencher) {
+    run_bench(1, 1, b)
+}
+
+#[bench]
+fn many_threads_one_key(b: &mut Bencher) {
+    run_bench(64, 1, b)
+}
+
+#[bench]
+fn many_threads_many_keys(b: &mut Bencher) {
+    run_bench(64, 16, b)
+}
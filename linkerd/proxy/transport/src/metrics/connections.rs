@@ -0,0 +1,145 @@
+use crate::tls;
+use indexmap::IndexMap;
+use linkerd2_metrics::Counter;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Describes a connection so that it may be listed while it is open.
+#[derive(Clone, Debug)]
+pub struct Description {
+    pub direction: &'static str,
+    /// The address of the connection's peer.
+    pub peer_addr: SocketAddr,
+    /// The original destination address of an accepted connection.
+    pub orig_dst_addr: Option<SocketAddr>,
+    pub peer_identity: tls::PeerIdentity,
+    /// The protocol detected on the connection, if it is known.
+    pub protocol: Option<&'static str>,
+}
+
+/// Lists the connections that are currently open.
+#[derive(Clone, Debug, Default)]
+pub struct Connections(Arc<Mutex<Open>>);
+
+/// Describes a connection at the time it was listed.
+#[derive(Clone, Debug)]
+pub struct OpenConnection {
+    pub description: Description,
+    pub age: Duration,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    next_id: u64,
+    by_id: IndexMap<u64, Arc<Connection>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    description: Description,
+    opened_at: Instant,
+    read_bytes: Counter,
+    write_bytes: Counter,
+}
+
+/// Lists a connection until it is dropped.
+#[derive(Debug)]
+pub(super) struct Tracked {
+    id: u64,
+    connection: Arc<Connection>,
+    connections: Connections,
+}
+
+// === impl Connections ===
+
+impl Connections {
+    /// Describes each connection that is currently open.
+    pub fn list(&self) -> Vec<OpenConnection> {
+        let open = self.0.lock().expect("connections lock poisoned");
+        open.by_id
+            .values()
+            .map(|c| OpenConnection {
+                description: c.description.clone(),
+                age: c.opened_at.elapsed(),
+                read_bytes: c.read_bytes.value(),
+                write_bytes: c.write_bytes.value(),
+            })
+            .collect()
+    }
+
+    pub(super) fn track(&self, description: Description) -> Tracked {
+        let connection = Arc::new(Connection {
+            description,
+            opened_at: Instant::now(),
+            read_bytes: Counter::default(),
+            write_bytes: Counter::default(),
+        });
+
+        let mut open = self.0.lock().expect("connections lock poisoned");
+        let id = open.next_id;
+        open.next_id += 1;
+        open.by_id.insert(id, connection.clone());
+
+        Tracked {
+            id,
+            connection,
+            connections: self.clone(),
+        }
+    }
+}
+
+// === impl Tracked ===
+
+impl Tracked {
+    pub(super) fn record_read(&self, sz: usize) {
+        self.connection.read_bytes.add(sz as u64);
+    }
+
+    pub(super) fn record_write(&self, sz: usize) {
+        self.connection.write_bytes.add(sz as u64);
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.connections.0.lock() {
+            open.by_id.swap_remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_open_connections() {
+        let connections = Connections::default();
+        let description = Description {
+            direction: "inbound",
+            peer_addr: ([10, 1, 1, 1], 40000).into(),
+            orig_dst_addr: Some(([10, 1, 1, 2], 8080).into()),
+            peer_identity: tls::Conditional::None(tls::ReasonForNoPeerName::NotHttp.into()),
+            protocol: None,
+        };
+
+        let a = connections.track(description.clone());
+        let b = connections.track(description);
+        a.record_read(10);
+        a.record_write(20);
+
+        let open = connections.list();
+        assert_eq!(open.len(), 2);
+        assert_eq!(open[0].read_bytes, 10);
+        assert_eq!(open[0].write_bytes, 20);
+        assert_eq!(open[1].read_bytes, 0);
+
+        drop(a);
+        assert_eq!(connections.list().len(), 1);
+        drop(b);
+        assert!(connections.list().is_empty());
+    }
+}
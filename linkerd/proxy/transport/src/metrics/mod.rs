@@ -1,19 +1,25 @@
 use futures::{try_ready, Future, Poll};
 use indexmap::IndexMap;
 use linkerd2_metrics::{
-    latency, metrics, Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge, Histogram, Metric,
+    latency, metrics, Bounds, Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge, Histogram, Metric,
 };
 use std::fmt;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::debug;
 
+mod connections;
 mod errno;
 mod io;
 
-pub use self::{errno::Errno, io::Io};
+use self::connections::Tracked;
+pub use self::{
+    connections::{Connections, Description, OpenConnection},
+    errno::Errno,
+    io::Io,
+};
 
 metrics! {
     tcp_open_total: Counter { "Total count of opened connections" },
@@ -25,8 +31,10 @@ metrics! {
     tcp_connection_duration_ms: Histogram<latency::Ms> { "Connection lifetimes" }
 }
 
-pub fn new<K: Eq + Hash + FmtLabels>() -> (Registry<K>, Report<K>) {
-    let inner = Arc::new(Mutex::new(Inner::default()));
+/// Builds a registry that records connection durations into histograms with
+/// the given bounds, in milliseconds.
+pub fn new<K: Eq + Hash + FmtLabels>(duration_bounds: Bounds) -> (Registry<K>, Report<K>) {
+    let inner = Arc::new(RwLock::new(Inner::new(duration_bounds)));
     (Registry(inner.clone()), Report(inner))
 }
 
@@ -34,26 +42,32 @@ pub trait TransportLabels<T> {
     type Labels: Hash + Eq + FmtLabels;
 
     fn transport_labels(&self, transport: &T) -> Self::Labels;
+
+    /// Describes an individual connection so that it may be listed while it
+    /// is open. Connections that are not described are not listed.
+    fn describe(&self, _transport: &T) -> Option<Description> {
+        None
+    }
 }
 
 /// Implements `FmtMetrics` to render prometheus-formatted metrics for all transports.
 #[derive(Clone, Debug, Default)]
-pub struct Report<K: Eq + Hash + FmtLabels>(Arc<Mutex<Inner<K>>>);
+pub struct Report<K: Eq + Hash + FmtLabels>(Arc<RwLock<Inner<K>>>);
 
 #[derive(Clone, Debug, Default)]
-pub struct Registry<K: Eq + Hash + FmtLabels>(Arc<Mutex<Inner<K>>>);
+pub struct Registry<K: Eq + Hash + FmtLabels>(Arc<RwLock<Inner<K>>>);
 
 #[derive(Debug)]
 pub struct ConnectLayer<L, K: Eq + Hash + FmtLabels> {
     label: L,
-    registry: Arc<Mutex<Inner<K>>>,
+    registry: Arc<RwLock<Inner<K>>>,
 }
 
 #[derive(Debug)]
 pub struct Connect<L, K: Eq + Hash + FmtLabels, M> {
     label: L,
     inner: M,
-    registry: Arc<Mutex<Inner<K>>>,
+    registry: Arc<RwLock<Inner<K>>>,
 }
 
 pub struct Connecting<F> {
@@ -63,16 +77,18 @@ pub struct Connecting<F> {
 
 /// Stores a class of transport's metrics.
 ///
-/// TODO We should probaby use AtomicUsize for most of these counters so that
-/// simple increments don't require a lock. Especially for read|write_bytes_total.
-#[derive(Debug, Default)]
+/// Each connection holds a reference to its class's metrics, so its counters
+/// are updated atomically without a lock. The map of end-of-stream metrics is
+/// only locked for writing when a new `Eos` is recorded.
+#[derive(Debug)]
 struct Metrics {
     open_total: Counter,
     open_connections: Gauge,
     write_bytes_total: Counter,
     read_bytes_total: Counter,
 
-    by_eos: Arc<Mutex<IndexMap<Eos, EosMetrics>>>,
+    by_eos: RwLock<IndexMap<Eos, EosMetrics>>,
+    duration_bounds: Bounds,
 }
 
 /// Describes a classtransport end.
@@ -84,7 +100,7 @@ struct Metrics {
 struct Eos(Option<Errno>);
 
 /// Holds metrics for a class of end-of-stream.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct EosMetrics {
     close_total: Counter,
     connection_duration: Histogram<latency::Ms>,
@@ -94,32 +110,51 @@ struct EosMetrics {
 #[derive(Debug)]
 struct Sensor {
     metrics: Option<Arc<Metrics>>,
+    tracked: Option<Tracked>,
     opened_at: Instant,
 }
 
 /// Lazily builds instances of `Sensor`.
 #[derive(Clone, Debug)]
-struct NewSensor(Arc<Metrics>);
+struct NewSensor {
+    metrics: Arc<Metrics>,
+    track: Option<(Connections, Description)>,
+}
 
 /// Shares state between `Report` and `Registry`.
+///
+/// Connections only lock the registry for writing when they are the first to
+/// be opened with a given key.
 #[derive(Debug)]
-struct Inner<K: Eq + Hash + FmtLabels>(IndexMap<K, Arc<Metrics>>);
+struct Inner<K: Eq + Hash + FmtLabels> {
+    metrics: IndexMap<K, Arc<Metrics>>,
+    connections: Connections,
+    duration_bounds: Bounds,
+}
 
 // ===== impl Inner =====
 
 impl<K: Eq + Hash + FmtLabels> Default for Inner<K> {
     fn default() -> Self {
-        Inner(IndexMap::default())
+        Inner::new(latency::bounds())
     }
 }
 
 impl<K: Eq + Hash + FmtLabels> Inner<K> {
+    fn new(duration_bounds: Bounds) -> Self {
+        Inner {
+            metrics: IndexMap::default(),
+            connections: Connections::default(),
+            duration_bounds,
+        }
+    }
+
     fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.metrics.is_empty()
     }
 
     fn iter(&self) -> impl Iterator<Item = (&K, &Arc<Metrics>)> {
-        self.0.iter()
+        self.metrics.iter()
     }
 
     /// Formats a metric across all instances of `Metrics` in the registry.
@@ -154,7 +189,7 @@ impl<K: Eq + Hash + FmtLabels> Inner<K> {
         M: FmtMetric,
     {
         for (key, metrics) in self.iter() {
-            if let Ok(by_eos) = (*metrics).by_eos.lock() {
+            if let Ok(by_eos) = (*metrics).by_eos.read() {
                 for (eos, m) in by_eos.iter() {
                     get_metric(&*m).fmt_metric_labeled(f, &metric.name, (key, eos))?;
                 }
@@ -165,7 +200,34 @@ impl<K: Eq + Hash + FmtLabels> Inner<K> {
     }
 
     fn get_or_default(&mut self, k: K) -> &Arc<Metrics> {
-        self.0.entry(k).or_insert_with(|| Default::default())
+        let duration_bounds = &self.duration_bounds;
+        self.metrics
+            .entry(k)
+            .or_insert_with(|| Arc::new(Metrics::new(duration_bounds.clone())))
+    }
+
+    /// Builds a `NewSensor` for `k`.
+    ///
+    /// The registry is only locked for writing if `k` has not been registered
+    /// before.
+    fn new_sensor(this: &RwLock<Self>, k: K, description: Option<Description>) -> NewSensor {
+        let track = |connections: &Connections| description.map(|d| (connections.clone(), d));
+
+        {
+            let inner = this.read().expect("metrics registry poisoned");
+            if let Some(metrics) = inner.metrics.get(&k) {
+                return NewSensor {
+                    metrics: metrics.clone(),
+                    track: track(&inner.connections),
+                };
+            }
+        }
+
+        let mut inner = this.write().expect("metrics registry poisoned");
+        NewSensor {
+            metrics: inner.get_or_default(k).clone(),
+            track: track(&inner.connections),
+        }
     }
 }
 
@@ -176,19 +238,28 @@ impl<K: Eq + Hash + FmtLabels> Registry<K> {
         ConnectLayer::new(label, self.0.clone())
     }
 
-    pub fn wrap_server_transport<T: AsyncRead + AsyncWrite>(&self, labels: K, io: T) -> Io<T> {
-        let metrics = self
-            .0
-            .lock()
+    /// Lists the described connections that are currently open.
+    pub fn connections(&self) -> Connections {
+        self.0
+            .read()
             .expect("metrics registry poisoned")
-            .get_or_default(labels)
-            .clone();
-        Io::new(io, Sensor::open(metrics))
+            .connections
+            .clone()
+    }
+
+    pub fn wrap_server_transport<T: AsyncRead + AsyncWrite>(
+        &self,
+        labels: K,
+        description: Option<Description>,
+        io: T,
+    ) -> Io<T> {
+        let new_sensor = Inner::new_sensor(&self.0, labels, description);
+        Io::new(io, new_sensor.new_sensor())
     }
 }
 
 impl<L, K: Eq + Hash + FmtLabels> ConnectLayer<L, K> {
-    fn new(label: L, registry: Arc<Mutex<Inner<K>>>) -> Self {
+    fn new(label: L, registry: Arc<RwLock<Inner<K>>>) -> Self {
         Self { label, registry }
     }
 }
@@ -243,15 +314,11 @@ where
 
     fn call(&mut self, target: T) -> Self::Future {
         let labels = self.label.transport_labels(&target);
-        let metrics = self
-            .registry
-            .lock()
-            .expect("metrics registr poisoned")
-            .get_or_default(labels)
-            .clone();
+        let description = self.label.describe(&target);
+        let new_sensor = Inner::new_sensor(&self.registry, labels, description);
 
         Connecting {
-            new_sensor: Some(NewSensor(metrics)),
+            new_sensor: Some(new_sensor),
             underlying: self.inner.make_connection(target),
         }
     }
@@ -285,7 +352,7 @@ where
 
 impl<K: Eq + Hash + FmtLabels> FmtMetrics for Report<K> {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let metrics = self.0.lock().expect("metrics registry poisoned");
+        let metrics = self.0.read().expect("metrics registry poisoned");
         if metrics.is_empty() {
             return Ok(());
         }
@@ -315,11 +382,12 @@ impl<K: Eq + Hash + FmtLabels> FmtMetrics for Report<K> {
 // ===== impl Sensor =====
 
 impl Sensor {
-    pub fn open(metrics: Arc<Metrics>) -> Self {
+    fn open(metrics: Arc<Metrics>, tracked: Option<Tracked>) -> Self {
         metrics.open_total.incr();
         metrics.open_connections.incr();
         Self {
             metrics: Some(metrics),
+            tracked,
             opened_at: Instant::now(),
         }
     }
@@ -328,26 +396,29 @@ impl Sensor {
         if let Some(ref m) = self.metrics {
             m.read_bytes_total.add(sz as u64);
         }
+        if let Some(ref t) = self.tracked {
+            t.record_read(sz);
+        }
     }
 
     pub fn record_write(&mut self, sz: usize) {
         if let Some(ref m) = self.metrics {
             m.write_bytes_total.add(sz as u64);
         }
+        if let Some(ref t) = self.tracked {
+            t.record_write(sz);
+        }
     }
 
     pub fn record_close(&mut self, eos: Option<Errno>) {
         let duration = self.opened_at.elapsed();
         // When closed, the metrics structure is dropped so that no further
         // updates can occur (i.e. so that an additional close won't be recorded
-        // on Drop).
+        // on Drop). The connection is no longer listed once it is closed.
+        self.tracked = None;
         if let Some(m) = self.metrics.take() {
             m.open_connections.decr();
-
-            let mut by_eos = m.by_eos.lock().expect("transport eos metrics lock");
-            let class = by_eos.entry(Eos(eos)).or_insert_with(EosMetrics::default);
-            class.close_total.incr();
-            class.connection_duration.add(duration);
+            m.record_eos(Eos(eos), duration);
         }
     }
 }
@@ -358,11 +429,66 @@ impl Drop for Sensor {
     }
 }
 
+// ===== impl Metrics =====
+
+impl Metrics {
+    fn new(duration_bounds: Bounds) -> Self {
+        Self {
+            open_total: Counter::default(),
+            open_connections: Gauge::default(),
+            write_bytes_total: Counter::default(),
+            read_bytes_total: Counter::default(),
+            by_eos: RwLock::default(),
+            duration_bounds,
+        }
+    }
+
+    /// Records a connection's close.
+    ///
+    /// The end-of-stream map is only locked for writing if `eos` has not
+    /// been recorded before.
+    fn record_eos(&self, eos: Eos, duration: Duration) {
+        {
+            let by_eos = self.by_eos.read().expect("transport eos metrics lock");
+            if let Some(class) = by_eos.get(&eos) {
+                class.record(duration);
+                return;
+            }
+        }
+
+        let mut by_eos = self.by_eos.write().expect("transport eos metrics lock");
+        let bounds = &self.duration_bounds;
+        by_eos
+            .entry(eos)
+            .or_insert_with(|| EosMetrics::new(bounds.clone()))
+            .record(duration);
+    }
+}
+
+// ===== impl EosMetrics =====
+
+impl EosMetrics {
+    fn new(duration_bounds: Bounds) -> Self {
+        Self {
+            close_total: Counter::default(),
+            connection_duration: Histogram::new(duration_bounds),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        self.close_total.incr();
+        self.connection_duration.add(duration);
+    }
+}
+
 // ===== impl NewSensor =====
 
 impl NewSensor {
     fn new_sensor(self) -> Sensor {
-        Sensor::open(self.0)
+        let tracked = self
+            .track
+            .map(|(connections, description)| connections.track(description));
+        Sensor::open(self.metrics, tracked)
     }
 }
 
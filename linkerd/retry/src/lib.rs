@@ -14,6 +14,14 @@ pub trait NewPolicy<T> {
     fn new_policy(&self, target: &T) -> Option<Self::Policy>;
 }
 
+/// Prepares a request to be dispatched with a retry policy.
+///
+/// This allows a policy to alter a request before it is first sent so that
+/// it can be cloned later (e.g., by buffering its body).
+pub trait PrepareRequest<Req> {
+    fn prepare_request(&self, req: Req) -> Req;
+}
+
 /// A layer that applies per-target retry polcies.
 ///
 /// Composes `NewService`s that produce a `Proxy`.
@@ -86,7 +94,7 @@ where
 
 impl<R, P, Req, S> Proxy<Req, S> for Retry<R, P>
 where
-    R: tower::retry::Policy<Req, P::Response, Error> + Clone,
+    R: tower::retry::Policy<Req, P::Response, Error> + PrepareRequest<Req> + Clone,
     P: Proxy<Req, S> + Clone,
     S: tower::Service<P::Request> + Clone,
     S::Error: Into<Error>,
@@ -100,6 +108,7 @@ where
         trace!(retryable = %self.policy.is_some());
 
         if let Some(policy) = self.policy.as_ref() {
+            let req = policy.prepare_request(req);
             let inner = self.inner.clone().wrap_service(svc.clone());
             let retry = tower::retry::Retry::new(policy.clone(), inner);
             return ResponseFuture::Retry(retry.oneshot(req));
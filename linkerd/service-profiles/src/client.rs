@@ -1,11 +1,15 @@
 use crate::http as profiles;
+use crate::snapshot::{Snapshot, Stale, Store};
 use futures::{try_ready, Async, Future, Poll, Stream};
 use http;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_dns as dns;
 use linkerd2_error::{Error, Never, Recover};
+use linkerd2_metrics::Gauge;
 use linkerd2_proxy_api::destination as api;
+use linkerd2_snapshot::Writer;
 use regex::Regex;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::watch;
@@ -22,6 +26,7 @@ pub struct Client<S, R> {
     initial_timeout: Duration,
     context_token: String,
     suffixes: Vec<dns::Suffix>,
+    snapshot: Option<Snapshot>,
 }
 
 pub type Receiver = watch::Receiver<profiles::Routes>;
@@ -29,7 +34,7 @@ pub type Receiver = watch::Receiver<profiles::Routes>;
 type Sender = watch::Sender<profiles::Routes>;
 
 #[derive(Clone, Debug)]
-pub struct InvalidProfileAddr(Addr);
+pub struct InvalidProfileAddr(pub(crate) Addr);
 
 pub struct ProfileFuture<S, R>
 where
@@ -55,6 +60,8 @@ where
 {
     tx: Sender,
     inner: Inner<S, R>,
+    /// Held while a profile from a snapshot is published.
+    stale: Option<Stale>,
 }
 
 struct Inner<S, R>
@@ -66,6 +73,7 @@ where
     recover: R,
     state: State<S, R::Backoff>,
     request: api::GetDestination,
+    store: Option<Store>,
 }
 
 enum State<S, B>
@@ -109,6 +117,20 @@ where
             initial_timeout,
             context_token,
             suffixes: suffixes.into_iter().collect(),
+            snapshot: None,
+        }
+    }
+
+    /// Persists the profiles received for each destination in `dir`, via
+    /// `writer`.
+    ///
+    /// If a destination's profile is not received within the initial
+    /// timeout, its last known profile is used until a profile is received.
+    /// Destinations using a last known profile are counted by `stale`.
+    pub fn with_snapshot(self, dir: PathBuf, stale: Arc<Gauge>, writer: Writer) -> Self {
+        Self {
+            snapshot: Some(Snapshot::new(dir, stale, writer)),
+            ..self
         }
     }
 }
@@ -164,11 +186,13 @@ where
 
         let timeout = Delay::new(Instant::now() + self.initial_timeout);
 
+        let store = self.snapshot.as_ref().map(|s| s.store(&dst));
         let inner = Inner {
             service,
             request,
             recover: self.recover.clone(),
             state: State::Disconnected { backoff: None },
+            store,
         };
         ProfileFuture {
             inner: ProfileFutureInner::Pending(Some(inner), timeout),
@@ -192,6 +216,7 @@ where
         match self.inner {
             ProfileFutureInner::Invalid(ref addr) => Err(InvalidProfileAddr(addr.clone()).into()),
             ProfileFutureInner::Pending(ref mut inner, ref mut timeout) => {
+                let mut stale = None;
                 let profile = match inner.as_mut().expect("polled after ready").poll_profile() {
                     Err(error) => {
                         trace!(%error, "failed to fetch profile");
@@ -202,8 +227,22 @@ where
                             return Ok(Async::NotReady);
                         }
 
-                        info!("Using default service profile after timeout");
-                        profiles::Routes::default()
+                        let inner = inner.as_ref().expect("polled after ready");
+                        match inner
+                            .store
+                            .as_ref()
+                            .and_then(|store| store.load().map(|proto| (store, proto)))
+                        {
+                            Some((store, proto)) => {
+                                info!("Using last known service profile after timeout");
+                                stale = Some(store.stale());
+                                convert_profile(proto)
+                            }
+                            None => {
+                                info!("Using default service profile after timeout");
+                                profiles::Routes::default()
+                            }
+                        }
                     }
                     Ok(Async::Ready(profile)) => profile,
                 };
@@ -212,7 +251,7 @@ where
                 let (tx, rx) = watch::channel(profile);
                 let inner = inner.take().expect("polled after ready");
                 tokio::spawn(
-                    Daemon { inner, tx }
+                    Daemon { inner, tx, stale }
                         .in_current_span()
                         .map_err(|n| match n {}),
                 );
@@ -232,27 +271,13 @@ where
 {
     fn poll_rx(
         rx: &mut grpc::Streaming<api::DestinationProfile, S::ResponseBody>,
-    ) -> Poll<Option<profiles::Routes>, grpc::Status> {
+    ) -> Poll<Option<api::DestinationProfile>, grpc::Status> {
         trace!("poll");
-        let profile = try_ready!(rx.poll()).map(|proto| {
+        let proto = try_ready!(rx.poll());
+        if let Some(ref proto) = proto {
             debug!("profile received: {:?}", proto);
-            let retry_budget = proto.retry_budget.and_then(convert_retry_budget);
-            let routes = proto
-                .routes
-                .into_iter()
-                .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()))
-                .collect();
-            let dst_overrides = proto
-                .dst_overrides
-                .into_iter()
-                .filter_map(convert_dst_override)
-                .collect();
-            profiles::Routes {
-                routes,
-                dst_overrides,
-            }
-        });
-        Ok(profile.into())
+        }
+        Ok(proto.into())
     }
 
     fn poll_profile(&mut self) -> Poll<profiles::Routes, Error> {
@@ -293,7 +318,13 @@ where
                     trace!("streaming");
                     let status = match Self::poll_rx(s) {
                         Ok(Async::NotReady) => return Ok(Async::NotReady),
-                        Ok(Async::Ready(Some(profile))) => return Ok(profile.into()),
+                        Ok(Async::Ready(Some(proto))) => {
+                            if let Some(store) = self.store.as_ref() {
+                                store.save(&proto);
+                            }
+                            let profile = convert_profile(proto);
+                            return Ok(profile.into());
+                        }
                         Ok(Async::Ready(None)) => grpc::Status::new(grpc::Code::Ok, ""),
                         Err(status) => status,
                     };
@@ -352,15 +383,46 @@ where
                 trace!("failed to publish profile");
                 return Ok(().into());
             }
+            if self.stale.take().is_some() {
+                debug!("Replaced last known service profile");
+            }
         }
     }
 }
 
+fn convert_profile(proto: api::DestinationProfile) -> profiles::Routes {
+    let retry_budget = proto.retry_budget.and_then(convert_retry_budget);
+    let routes = proto
+        .routes
+        .into_iter()
+        .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()))
+        .collect();
+    let dst_overrides = proto
+        .dst_overrides
+        .into_iter()
+        .filter_map(convert_dst_override)
+        .collect();
+    profiles::Routes {
+        routes,
+        dst_overrides,
+        // The profile API does not describe mirrors, so destinations
+        // discovered from the controller are never mirrored. Mirrors are
+        // only configured by profile files.
+        mirror: None,
+    }
+}
+
 fn convert_route(
     orig: api::Route,
     retry_budget: Option<&Arc<Budget>>,
 ) -> Option<(profiles::RequestMatch, profiles::Route)> {
-    let req_match = orig.condition.and_then(convert_req_match)?;
+    let req_match = match orig.condition.and_then(convert_req_match) {
+        Some(m) => m,
+        None => {
+            warn!(labels = ?orig.metrics_labels, "Ignoring route with an unsupported condition");
+            return None;
+        }
+    };
     let rsp_classes = orig
         .response_classes
         .into_iter()
@@ -373,6 +435,9 @@ fn convert_route(
     if let Some(timeout) = orig.timeout {
         set_route_timeout(&mut route, timeout.into());
     }
+    // The profile API does not describe faults, so routes discovered from the
+    // controller never inject them. Faults are only configured by profile
+    // files.
     Some((req_match, route))
 }
 
@@ -385,6 +450,10 @@ fn convert_dst_override(orig: api::WeightedDst) -> Option<profiles::WeightedAddr
         .map(|addr| profiles::WeightedAddr {
             addr,
             weight: orig.weight,
+            // The profile API does not describe header conditions, so
+            // overrides discovered from the controller are only selected by
+            // weight. Header conditions are only configured by profile files.
+            headers: Vec::new(),
         })
 }
 
@@ -411,34 +480,35 @@ fn set_route_timeout(route: &mut profiles::Route, timeout: Result<Duration, Dura
     }
 }
 
+/// Converts a request match from the profile API.
+///
+/// The profile API only describes path and method conditions. Header, query
+/// parameter, and authority conditions may only be configured by profile
+/// files. A condition that is not recognized, e.g. because it was added in a
+/// newer version of the API, cannot be converted; and neither can an `all`
+/// condition that contains it, since ignoring it would broaden the match.
 fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch> {
-    let m = match orig.r#match? {
-        api::request_match::Match::All(ms) => {
-            let ms = ms.matches.into_iter().filter_map(convert_req_match);
-            profiles::RequestMatch::All(ms.collect())
+    let m = match orig.r#match {
+        None => {
+            debug!("Unrecognized request match");
+            return None;
         }
-        api::request_match::Match::Any(ms) => {
+        Some(api::request_match::Match::All(ms)) => {
+            let ms = ms.matches.into_iter().map(convert_req_match);
+            profiles::RequestMatch::All(ms.collect::<Option<_>>()?)
+        }
+        Some(api::request_match::Match::Any(ms)) => {
             let ms = ms.matches.into_iter().filter_map(convert_req_match);
             profiles::RequestMatch::Any(ms.collect())
         }
-        api::request_match::Match::Not(m) => {
+        Some(api::request_match::Match::Not(m)) => {
             let m = convert_req_match(*m)?;
             profiles::RequestMatch::Not(Box::new(m))
         }
-        api::request_match::Match::Path(api::PathMatch { regex }) => {
-            let regex = regex.trim();
-            let re = match (regex.starts_with('^'), regex.ends_with('$')) {
-                (true, true) => Regex::new(regex).ok()?,
-                (hd_anchor, tl_anchor) => {
-                    let hd = if hd_anchor { "" } else { "^" };
-                    let tl = if tl_anchor { "" } else { "$" };
-                    let re = format!("{}{}{}", hd, regex, tl);
-                    Regex::new(&re).ok()?
-                }
-            };
-            profiles::RequestMatch::Path(re)
+        Some(api::request_match::Match::Path(api::PathMatch { regex })) => {
+            profiles::RequestMatch::Path(convert_regex(&regex)?)
         }
-        api::request_match::Match::Method(mm) => {
+        Some(api::request_match::Match::Method(mm)) => {
             let m = mm.r#type.and_then(|m| m.try_as_http().ok())?;
             profiles::RequestMatch::Method(m)
         }
@@ -447,25 +517,59 @@ fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch>
     Some(m)
 }
 
+/// Parses a regular expression that must match the entire value.
+pub(crate) fn convert_regex(regex: &str) -> Option<Regex> {
+    let regex = regex.trim();
+    match (regex.starts_with('^'), regex.ends_with('$')) {
+        (true, true) => Regex::new(regex).ok(),
+        (hd_anchor, tl_anchor) => {
+            let hd = if hd_anchor { "" } else { "^" };
+            let tl = if tl_anchor { "" } else { "$" };
+            let re = format!("{}{}{}", hd, regex, tl);
+            Regex::new(&re).ok()
+        }
+    }
+}
+
 fn convert_rsp_class(orig: api::ResponseClass) -> Option<profiles::ResponseClass> {
-    let c = orig.condition.and_then(convert_rsp_match)?;
+    let c = match orig.condition.and_then(convert_rsp_match) {
+        Some(c) => c,
+        None => {
+            warn!(
+                is_failure = orig.is_failure,
+                "Ignoring response class with an unsupported condition"
+            );
+            return None;
+        }
+    };
     Some(profiles::ResponseClass::new(orig.is_failure, c))
 }
 
+/// Converts a response match from the profile API.
+///
+/// The profile API only describes status conditions. Header and gRPC status
+/// conditions may only be configured by profile files. A condition that is not
+/// recognized, e.g. because it was added in a newer version of the API, cannot
+/// be converted; and neither can an `all` condition that contains it, since
+/// ignoring it would broaden the match.
 fn convert_rsp_match(orig: api::ResponseMatch) -> Option<profiles::ResponseMatch> {
-    let m = match orig.r#match? {
-        api::response_match::Match::All(ms) => {
+    let m = match orig.r#match {
+        None => {
+            debug!("Unrecognized response match");
+            return None;
+        }
+        Some(api::response_match::Match::All(ms)) => {
             let ms = ms
                 .matches
                 .into_iter()
-                .filter_map(convert_rsp_match)
-                .collect::<Vec<_>>();
+                .map(convert_rsp_match)
+                .collect::<Option<Vec<_>>>()?;
             if ms.is_empty() {
                 return None;
             }
             profiles::ResponseMatch::All(ms)
         }
-        api::response_match::Match::Any(ms) => {
+        Some(api::response_match::Match::Any(ms)) => {
             let ms = ms
                 .matches
                 .into_iter()
@@ -476,11 +580,11 @@ fn convert_rsp_match(orig: api::ResponseMatch) -> Option<profiles::ResponseMatch
             }
             profiles::ResponseMatch::Any(ms)
         }
-        api::response_match::Match::Not(m) => {
+        Some(api::response_match::Match::Not(m)) => {
             let m = convert_rsp_match(*m)?;
             profiles::ResponseMatch::Not(Box::new(m))
         }
-        api::response_match::Match::Status(range) => {
+        Some(api::response_match::Match::Status(range)) => {
             let min = http::StatusCode::from_u16(range.min as u16).ok()?;
             let max = http::StatusCode::from_u16(range.max as u16).ok()?;
             profiles::ResponseMatch::Status { min, max }
@@ -533,6 +637,62 @@ mod tests {
     use super::*;
     use quickcheck::*;
 
+    fn path_match(regex: &str) -> api::RequestMatch {
+        api::RequestMatch {
+            r#match: Some(api::request_match::Match::Path(api::PathMatch {
+                regex: regex.into(),
+            })),
+        }
+    }
+
+    #[test]
+    fn response_class_with_unrecognized_condition_is_ignored() {
+        let status = api::ResponseMatch {
+            r#match: Some(api::response_match::Match::Status(api::HttpStatusRange {
+                min: 500,
+                max: 599,
+            })),
+        };
+        let unrecognized = api::ResponseMatch { r#match: None };
+        let all = |matches| api::ResponseMatch {
+            r#match: Some(api::response_match::Match::All(api::response_match::Seq {
+                matches,
+            })),
+        };
+        let class = |condition| api::ResponseClass {
+            condition: Some(condition),
+            is_failure: true,
+        };
+
+        assert!(convert_rsp_class(class(status.clone())).is_some());
+        assert!(convert_rsp_class(class(unrecognized.clone())).is_none());
+        assert!(
+            convert_rsp_class(class(all(vec![status, unrecognized]))).is_none(),
+            "a partially understood condition must not match more broadly"
+        );
+    }
+
+    #[test]
+    fn route_with_unrecognized_condition_is_ignored() {
+        let unrecognized = api::RequestMatch { r#match: None };
+        let all = |matches| api::RequestMatch {
+            r#match: Some(api::request_match::Match::All(api::request_match::Seq {
+                matches,
+            })),
+        };
+        let route = |condition| api::Route {
+            condition: Some(condition),
+            ..Default::default()
+        };
+
+        assert!(convert_route(route(path_match("/.*")), None).is_some());
+        assert!(convert_route(route(unrecognized.clone()), None).is_none());
+        assert!(
+            convert_route(route(all(vec![path_match("/.*"), unrecognized])), None).is_none(),
+            "a partially understood condition must not match more broadly"
+        );
+    }
+
     quickcheck! {
         fn retry_budget_from_proto(
             min_retries_per_second: u32,
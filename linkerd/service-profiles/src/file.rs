@@ -0,0 +1,990 @@
+//! Serves service profiles from local files.
+//!
+//! Each destination's profile is read from a file in a directory, named for
+//! the destination without its port, e.g. `web.default.svc.cluster.local.yaml`.
+//! Profiles may be written as YAML (`.yaml` or `.yml`) or JSON (`.json`), in
+//! the same shape as `Routes`:
+//!
+//! ```yaml
+//! routes:
+//!   - match:
+//!       all:
+//!         - method: GET
+//!         - path: /api/.*
+//!     route:
+//!       labels:
+//!         route: api
+//!       response_classes:
+//!         - is_failure: true
+//!           match:
+//!             status: { min: 500, max: 599 }
+//!       timeout: 500ms
+//!       retries:
+//!         budget: { retry_ratio: 0.2, min_retries_per_second: 10, ttl: 10s }
+//! dst_overrides:
+//!   - addr: web-v2.default.svc.cluster.local:8080
+//!     weight: 1000
+//! ```
+//!
+//! Files are polled for changes by a task on a dedicated thread, so that file
+//! IO never blocks the proxy's reactor. When a file changes, its profile is
+//! re-read and published; if it cannot be parsed, the last profile remains
+//! in use. Destinations without a file have the default (empty) profile.
+
+use crate::client::{convert_regex, InvalidProfileAddr};
+use crate::http as profiles;
+use futures::{
+    sync::{mpsc, oneshot},
+    Async, Future, Poll, Stream,
+};
+use linkerd2_addr::{Addr, NameAddr};
+use linkerd2_error::{Error, Never};
+use linkerd2_exp_backoff::ExponentialBackoff;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+use tokio::timer::Interval;
+use tower::retry::budget::Budget;
+use tracing::{debug, info_span, trace, warn};
+use tracing_futures::Instrument;
+
+/// The file extensions that are searched for a destination's profile, in
+/// order.
+const EXTENSIONS: &[&str] = &["yaml", "yml", "json"];
+
+/// Discovers service profiles from files in a directory.
+#[derive(Clone, Debug)]
+pub struct Client {
+    tx: mpsc::UnboundedSender<Subscribe>,
+}
+
+/// Reads each subscribed destination's profile, publishing it each time its
+/// file changes.
+///
+/// Completes when all `Client`s are dropped and every profile watch has
+/// ended.
+pub struct Task {
+    rx: mpsc::UnboundedReceiver<Subscribe>,
+    dir: Arc<PathBuf>,
+    interval: Duration,
+}
+
+/// A request to watch a destination's profile.
+#[derive(Debug)]
+struct Subscribe {
+    dst: NameAddr,
+    rsp: oneshot::Sender<profiles::Receiver>,
+}
+
+pub enum ResponseFuture {
+    Invalid(Option<Error>),
+    Subscribed(oneshot::Receiver<profiles::Receiver>),
+}
+
+/// Tracks changes to a destination's profile file.
+struct Watch {
+    dir: Arc<PathBuf>,
+    name: String,
+    loaded: Option<(PathBuf, SystemTime)>,
+}
+
+/// Publishes a destination's profile each time its file changes.
+struct Daemon {
+    watch: Watch,
+    interval: Interval,
+    tx: profiles::Sender,
+}
+
+#[derive(Debug)]
+pub struct InvalidProfile(String);
+
+#[derive(Debug)]
+pub struct Stopped(());
+
+// === impl Client ===
+
+impl Client {
+    /// Returns a `Client` and the `Task` that reads profiles from `dir`,
+    /// checking for changes every `interval`.
+    pub fn new(dir: impl Into<PathBuf>, interval: Duration) -> (Self, Task) {
+        let (tx, rx) = mpsc::unbounded();
+        let task = Task {
+            rx,
+            dir: Arc::new(dir.into()),
+            interval,
+        };
+        (Self { tx }, task)
+    }
+
+    /// Returns a `Client` whose profiles are read on a dedicated thread.
+    pub fn spawn(dir: impl Into<PathBuf>, interval: Duration) -> std::io::Result<Self> {
+        let (client, task) = Self::new(dir, interval);
+        std::thread::Builder::new()
+            .name("profile-files".into())
+            .spawn(move || tokio::runtime::current_thread::run(task.map_err(|n| match n {})))?;
+        Ok(client)
+    }
+}
+
+impl tower::Service<Addr> for Client {
+    type Response = profiles::Receiver;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, dst: Addr) -> Self::Future {
+        let dst = match dst {
+            Addr::Name(n) => n,
+            Addr::Socket(_) => {
+                return ResponseFuture::Invalid(Some(InvalidProfileAddr(dst).into()))
+            }
+        };
+
+        // If the task has stopped, the response is dropped and the future
+        // fails.
+        let (rsp, rx) = oneshot::channel();
+        let _ = self.tx.unbounded_send(Subscribe { dst, rsp });
+        ResponseFuture::Subscribed(rx)
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl Future for ResponseFuture {
+    type Item = profiles::Receiver;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Invalid(e) => Err(e.take().expect("polled after failure")),
+            ResponseFuture::Subscribed(rx) => rx.poll().map_err(|_| Stopped(()).into()),
+        }
+    }
+}
+
+// === impl Task ===
+
+impl Future for Task {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let Subscribe { dst, rsp } = match self.rx.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(Some(subscribe))) => subscribe,
+                Ok(Async::Ready(None)) | Err(()) => {
+                    debug!("Profile clients dropped");
+                    return Ok(Async::Ready(()));
+                }
+            };
+            let span = info_span!("profile", %dst);
+
+            // The initial profile is read before the watch is returned, so
+            // that the first requests to the destination are routed by it.
+            let mut watch = Watch {
+                dir: self.dir.clone(),
+                name: dst.name().without_trailing_dot().to_string(),
+                loaded: None,
+            };
+            let routes = span.in_scope(|| watch.load().unwrap_or_default());
+            let (tx, rx) = watch::channel(routes);
+            if rsp.send(rx).is_err() {
+                continue;
+            }
+
+            let daemon = Daemon {
+                watch,
+                interval: Interval::new(Instant::now() + self.interval, self.interval),
+                tx,
+            };
+            tokio::spawn(daemon.instrument(span).map_err(|n| match n {}));
+        }
+    }
+}
+
+// === impl Watch ===
+
+impl Watch {
+    /// Returns the path and modification time of the destination's profile
+    /// file, if one exists.
+    fn find(&self) -> Option<(PathBuf, SystemTime)> {
+        EXTENSIONS.iter().find_map(|ext| {
+            let path = self.dir.join(format!("{}.{}", self.name, ext));
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+    }
+
+    /// Reads the profile if its file has changed since it was last read.
+    ///
+    /// Returns `None` if the file has not changed or could not be read.
+    fn load(&mut self) -> Option<profiles::Routes> {
+        let found = self.find();
+        if found == self.loaded {
+            return None;
+        }
+
+        let (path, modified) = match found {
+            Some(found) => found,
+            None => {
+                debug!("Profile removed");
+                self.loaded = None;
+                return Some(profiles::Routes::default());
+            }
+        };
+
+        // Invalid files are not read again until they are modified.
+        match read(&path) {
+            Ok(routes) => {
+                debug!(path = %path.display(), "Profile loaded");
+                self.loaded = Some((path, modified));
+                Some(routes)
+            }
+            Err(error) => {
+                warn!(path = %path.display(), %error, "Invalid profile");
+                self.loaded = Some((path, modified));
+                None
+            }
+        }
+    }
+}
+
+// === impl Daemon ===
+
+impl Future for Daemon {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.tx.poll_close() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(())) | Err(()) => {
+                    trace!("profile observation dropped");
+                    return Ok(().into());
+                }
+            }
+
+            match self.interval.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(_)) => {}
+                Err(error) => {
+                    warn!(%error, "Profile watch failed");
+                    return Ok(().into());
+                }
+            }
+
+            if let Some(routes) = self.watch.load() {
+                trace!(?routes, "publishing");
+                if self.tx.broadcast(routes).is_err() {
+                    return Ok(().into());
+                }
+            }
+        }
+    }
+}
+
+/// Reads a profile from a YAML or JSON file.
+fn read(path: &Path) -> Result<profiles::Routes, Error> {
+    let contents = std::fs::read(path)?;
+    let spec = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_slice::<RoutesSpec>(&contents)?,
+        _ => serde_yaml::from_slice::<RoutesSpec>(&contents)?,
+    };
+    spec.into_routes()
+}
+
+// === impl InvalidProfile ===
+
+impl fmt::Display for InvalidProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid profile: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidProfile {}
+
+// === impl Stopped ===
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "profile file watch stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+fn invalid<T>(msg: impl Into<String>) -> Result<T, Error> {
+    Err(InvalidProfile(msg.into()).into())
+}
+
+// === Profile files ===
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoutesSpec {
+    #[serde(default)]
+    routes: Vec<RouteSpec>,
+    #[serde(default)]
+    dst_overrides: Vec<WeightedAddrSpec>,
+    #[serde(default)]
+    mirror: Option<MirrorSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteSpec {
+    #[serde(rename = "match")]
+    match_: RequestMatchSpec,
+    #[serde(default)]
+    route: RouteParamsSpec,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteParamsSpec {
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    response_classes: Vec<ResponseClassSpec>,
+    #[serde(default)]
+    timeout: Option<String>,
+    #[serde(default)]
+    retries: Option<RetriesSpec>,
+    #[serde(default)]
+    fault: Option<FaultSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RequestMatchSpec {
+    All(Vec<RequestMatchSpec>),
+    Any(Vec<RequestMatchSpec>),
+    Not(Box<RequestMatchSpec>),
+    Path(String),
+    Method(String),
+    Authority(String),
+    Header(NamedValueMatchSpec),
+    Query(NamedValueMatchSpec),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NamedValueMatchSpec {
+    name: String,
+    #[serde(default = "ValueMatchSpec::present")]
+    value: ValueMatchSpec,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ValueMatchSpec {
+    Present,
+    Exact(String),
+    Regex(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResponseClassSpec {
+    #[serde(default)]
+    is_failure: bool,
+    #[serde(rename = "match")]
+    match_: ResponseMatchSpec,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseMatchSpec {
+    All(Vec<ResponseMatchSpec>),
+    Any(Vec<ResponseMatchSpec>),
+    Not(Box<ResponseMatchSpec>),
+    Status { min: u16, max: u16 },
+    Header(NamedValueMatchSpec),
+    GrpcStatus { min: u32, max: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetriesSpec {
+    budget: BudgetSpec,
+    #[serde(default)]
+    backoff: Option<BackoffSpec>,
+    #[serde(default)]
+    hedge_percentile: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BudgetSpec {
+    retry_ratio: f32,
+    min_retries_per_second: u32,
+    ttl: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackoffSpec {
+    min: String,
+    max: String,
+    #[serde(default)]
+    jitter: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaultSpec {
+    #[serde(default)]
+    delay: Option<FaultDelaySpec>,
+    #[serde(default)]
+    abort: Option<FaultAbortSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaultDelaySpec {
+    ratio: f64,
+    min: String,
+    max: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaultAbortSpec {
+    ratio: f64,
+    #[serde(default)]
+    http_status: Option<u16>,
+    #[serde(default)]
+    grpc_status: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WeightedAddrSpec {
+    addr: String,
+    weight: u32,
+    #[serde(default)]
+    headers: Vec<NamedValueMatchSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MirrorSpec {
+    addr: String,
+    ratio: f64,
+}
+
+impl RoutesSpec {
+    fn into_routes(self) -> Result<profiles::Routes, Error> {
+        let routes = self
+            .routes
+            .into_iter()
+            .map(RouteSpec::into_route)
+            .collect::<Result<_, _>>()?;
+        let dst_overrides = self
+            .dst_overrides
+            .into_iter()
+            // As with the profile API, overrides without weight are ignored,
+            // unless they are selected by request headers.
+            .filter(|o| o.weight > 0 || !o.headers.is_empty())
+            .map(WeightedAddrSpec::into_weighted_addr)
+            .collect::<Result<_, _>>()?;
+        let mirror = self.mirror.map(MirrorSpec::into_mirror).transpose()?;
+        Ok(profiles::Routes {
+            routes,
+            dst_overrides,
+            mirror,
+        })
+    }
+}
+
+impl RouteSpec {
+    fn into_route(self) -> Result<(profiles::RequestMatch, profiles::Route), Error> {
+        let req_match = self.match_.into_match()?;
+        let RouteParamsSpec {
+            labels,
+            response_classes,
+            timeout,
+            retries,
+            fault,
+        } = self.route;
+
+        let rsp_classes = response_classes
+            .into_iter()
+            .map(|c| {
+                Ok(profiles::ResponseClass::new(
+                    c.is_failure,
+                    c.match_.into_match()?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        let mut route = profiles::Route::new(labels.into_iter(), rsp_classes);
+
+        if let Some(timeout) = timeout {
+            route.set_timeout(parse_duration(&timeout)?);
+        }
+
+        if let Some(retries) = retries {
+            route.set_retries(retries.budget.into_budget()?);
+            if let Some(backoff) = retries.backoff {
+                let min = parse_duration(&backoff.min)?;
+                let max = parse_duration(&backoff.max)?;
+                match ExponentialBackoff::new(min, max, backoff.jitter) {
+                    Ok(backoff) => route.set_retry_backoff(backoff),
+                    Err(e) => return invalid(format!("retry backoff: {:?}", e)),
+                }
+            }
+            if let Some(percentile) = retries.hedge_percentile {
+                if !(percentile > 0.0 && percentile < 1.0) {
+                    return invalid(format!("hedge percentile {}", percentile));
+                }
+                route.set_hedge_percentile(percentile);
+            }
+        }
+
+        if let Some(fault) = fault {
+            route.set_fault(fault.into_fault()?);
+        }
+
+        Ok((req_match, route))
+    }
+}
+
+impl RequestMatchSpec {
+    fn into_match(self) -> Result<profiles::RequestMatch, Error> {
+        let m = match self {
+            RequestMatchSpec::All(ms) => profiles::RequestMatch::All(
+                ms.into_iter()
+                    .map(Self::into_match)
+                    .collect::<Result<_, _>>()?,
+            ),
+            RequestMatchSpec::Any(ms) => profiles::RequestMatch::Any(
+                ms.into_iter()
+                    .map(Self::into_match)
+                    .collect::<Result<_, _>>()?,
+            ),
+            RequestMatchSpec::Not(m) => profiles::RequestMatch::Not(Box::new(m.into_match()?)),
+            RequestMatchSpec::Path(re) => profiles::RequestMatch::Path(regex(&re)?),
+            RequestMatchSpec::Method(m) => match http::Method::from_bytes(m.as_bytes()) {
+                Ok(m) => profiles::RequestMatch::Method(m),
+                Err(_) => return invalid(format!("method {}", m)),
+            },
+            RequestMatchSpec::Authority(re) => profiles::RequestMatch::Authority(regex(&re)?),
+            RequestMatchSpec::Header(h) => {
+                let (name, value) = h.into_header_match()?;
+                profiles::RequestMatch::Header(name, value)
+            }
+            RequestMatchSpec::Query(q) => {
+                profiles::RequestMatch::Query(q.name, q.value.into_value_match()?)
+            }
+        };
+        Ok(m)
+    }
+}
+
+impl ResponseMatchSpec {
+    fn into_match(self) -> Result<profiles::ResponseMatch, Error> {
+        let m = match self {
+            ResponseMatchSpec::All(ms) => profiles::ResponseMatch::All(
+                ms.into_iter()
+                    .map(Self::into_match)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ResponseMatchSpec::Any(ms) => profiles::ResponseMatch::Any(
+                ms.into_iter()
+                    .map(Self::into_match)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ResponseMatchSpec::Not(m) => profiles::ResponseMatch::Not(Box::new(m.into_match()?)),
+            ResponseMatchSpec::Status { min, max } => {
+                let (min, max) = match (
+                    http::StatusCode::from_u16(min),
+                    http::StatusCode::from_u16(max),
+                ) {
+                    (Ok(min), Ok(max)) if min <= max => (min, max),
+                    _ => return invalid(format!("status range {}-{}", min, max)),
+                };
+                profiles::ResponseMatch::Status { min, max }
+            }
+            ResponseMatchSpec::Header(h) => {
+                let (name, value) = h.into_header_match()?;
+                profiles::ResponseMatch::Header(name, value)
+            }
+            ResponseMatchSpec::GrpcStatus { min, max } => {
+                if min > max {
+                    return invalid(format!("grpc-status range {}-{}", min, max));
+                }
+                profiles::ResponseMatch::GrpcStatus { min, max }
+            }
+        };
+        Ok(m)
+    }
+}
+
+impl NamedValueMatchSpec {
+    fn into_header_match(self) -> Result<(http::header::HeaderName, profiles::ValueMatch), Error> {
+        let name = match http::header::HeaderName::from_bytes(self.name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => return invalid(format!("header name {}", self.name)),
+        };
+        Ok((name, self.value.into_value_match()?))
+    }
+}
+
+impl ValueMatchSpec {
+    fn present() -> Self {
+        ValueMatchSpec::Present
+    }
+
+    fn into_value_match(self) -> Result<profiles::ValueMatch, Error> {
+        let m = match self {
+            ValueMatchSpec::Present => profiles::ValueMatch::Present,
+            ValueMatchSpec::Exact(v) => profiles::ValueMatch::Exact(v),
+            ValueMatchSpec::Regex(re) => profiles::ValueMatch::Regex(regex(&re)?),
+        };
+        Ok(m)
+    }
+}
+
+impl BudgetSpec {
+    fn into_budget(self) -> Result<Arc<Budget>, Error> {
+        // Budgets are bounded as they are for the profile API.
+        if self.retry_ratio > 1000.0 || self.retry_ratio < 0.0 {
+            return invalid(format!("retry ratio {}", self.retry_ratio));
+        }
+        if self.min_retries_per_second > std::i32::MAX as u32 {
+            return invalid(format!(
+                "min retries per second {}",
+                self.min_retries_per_second
+            ));
+        }
+        let ttl = parse_duration(&self.ttl)?;
+        if ttl > Duration::from_secs(60) || ttl < Duration::from_secs(1) {
+            return invalid(format!("retry budget ttl {:?}", ttl));
+        }
+        Ok(Arc::new(Budget::new(
+            ttl,
+            self.min_retries_per_second,
+            self.retry_ratio,
+        )))
+    }
+}
+
+impl FaultSpec {
+    fn into_fault(self) -> Result<profiles::Fault, Error> {
+        let delay = match self.delay {
+            None => None,
+            Some(d) => {
+                let min = parse_duration(&d.min)?;
+                let max = parse_duration(&d.max)?;
+                if min > max {
+                    return invalid(format!("fault delay {:?}-{:?}", min, max));
+                }
+                Some(profiles::FaultDelay {
+                    ratio: ratio(d.ratio)?,
+                    min,
+                    max,
+                })
+            }
+        };
+
+        let abort = match self.abort {
+            None => None,
+            Some(a) => {
+                let abort = match (a.http_status, a.grpc_status) {
+                    (Some(status), None) => match http::StatusCode::from_u16(status) {
+                        Ok(status) => profiles::Abort::Http(status),
+                        Err(_) => return invalid(format!("fault abort status {}", status)),
+                    },
+                    (None, Some(code)) => profiles::Abort::Grpc(code),
+                    _ => return invalid("fault abort requires one of http_status or grpc_status"),
+                };
+                Some(profiles::FaultAbort {
+                    ratio: ratio(a.ratio)?,
+                    abort,
+                })
+            }
+        };
+
+        Ok(profiles::Fault { delay, abort })
+    }
+}
+
+impl WeightedAddrSpec {
+    fn into_weighted_addr(self) -> Result<profiles::WeightedAddr, Error> {
+        let headers = self
+            .headers
+            .into_iter()
+            .map(NamedValueMatchSpec::into_header_match)
+            .collect::<Result<_, _>>()?;
+        Ok(profiles::WeightedAddr {
+            addr: name_addr(&self.addr)?,
+            weight: self.weight,
+            headers,
+        })
+    }
+}
+
+impl MirrorSpec {
+    fn into_mirror(self) -> Result<profiles::Mirror, Error> {
+        Ok(profiles::Mirror {
+            addr: name_addr(&self.addr)?,
+            ratio: ratio(self.ratio)?,
+        })
+    }
+}
+
+fn regex(re: &str) -> Result<Regex, Error> {
+    convert_regex(re).ok_or_else(|| InvalidProfile(format!("regex {}", re)).into())
+}
+
+fn name_addr(addr: &str) -> Result<NameAddr, Error> {
+    NameAddr::from_str(addr).map_err(|_| InvalidProfile(format!("address {}", addr)).into())
+}
+
+fn ratio(ratio: f64) -> Result<f64, Error> {
+    if ratio >= 0.0 && ratio <= 1.0 {
+        Ok(ratio)
+    } else {
+        invalid(format!("ratio {}", ratio))
+    }
+}
+
+/// Parses durations in the form used by the proxy's environment, e.g. `500ms`
+/// or `10s`.
+fn parse_duration(s: &str) -> Result<Duration, Error> {
+    linkerd2_duration::parse(s).map_err(|_| InvalidProfile(format!("duration {}", s)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_yaml(s: &str) -> Result<profiles::Routes, Error> {
+        serde_yaml::from_str::<RoutesSpec>(s)?.into_routes()
+    }
+
+    #[test]
+    fn parses_yaml_profile() {
+        let routes = parse_yaml(
+            r#"
+routes:
+  - match:
+      all:
+        - method: GET
+        - path: /api/.*
+        - header: { name: x-canary }
+    route:
+      labels:
+        route: api
+      response_classes:
+        - is_failure: true
+          match:
+            status: { min: 500, max: 599 }
+      timeout: 500ms
+      retries:
+        budget: { retry_ratio: 0.2, min_retries_per_second: 10, ttl: 10s }
+        backoff: { min: 10ms, max: 1s, jitter: 0.1 }
+        hedge_percentile: 0.95
+      fault:
+        abort: { ratio: 0.5, grpc_status: 14 }
+dst_overrides:
+  - addr: web-v1.default.svc.cluster.local:8080
+    weight: 0
+  - addr: web-v2.default.svc.cluster.local:8080
+    weight: 1000
+    headers:
+      - name: x-version
+        value: { exact: v2 }
+  - addr: web-canary.default.svc.cluster.local:8080
+    weight: 0
+    headers:
+      - name: x-canary
+mirror:
+  addr: web-shadow.default.svc.cluster.local:8080
+  ratio: 0.1
+"#,
+        )
+        .expect("profile must be valid");
+
+        assert_eq!(routes.routes.len(), 1);
+        let (req_match, route) = &routes.routes[0];
+        match req_match {
+            profiles::RequestMatch::All(ms) => assert_eq!(ms.len(), 3),
+            m => panic!("unexpected match: {:?}", m),
+        }
+        assert_eq!(route.labels().get("route").map(String::as_str), Some("api"));
+        assert_eq!(route.timeout(), Some(Duration::from_millis(500)));
+        assert_eq!(
+            route.retries().and_then(|r| r.hedge_percentile()),
+            Some(0.95)
+        );
+        assert_eq!(
+            route
+                .fault()
+                .and_then(|f| f.abort.as_ref())
+                .map(|a| a.abort),
+            Some(profiles::Abort::Grpc(14))
+        );
+
+        // Overrides without weight are only kept if they match headers.
+        assert_eq!(routes.dst_overrides.len(), 2);
+        assert_eq!(routes.dst_overrides[0].weight, 1000);
+        assert_eq!(routes.dst_overrides[0].headers.len(), 1);
+        assert_eq!(routes.dst_overrides[1].weight, 0);
+        assert_eq!(routes.dst_overrides[1].headers.len(), 1);
+        assert!(routes.mirror.is_some());
+    }
+
+    #[test]
+    fn parses_json_profile() {
+        let spec = serde_json::from_str::<RoutesSpec>(
+            r#"{
+                "routes": [{
+                    "match": { "not": { "query": { "name": "debug" } } },
+                    "route": {
+                        "response_classes": [
+                            { "match": { "grpc_status": { "min": 1, "max": 16 } }, "is_failure": true }
+                        ]
+                    }
+                }]
+            }"#,
+        )
+        .expect("profile must be valid JSON");
+        let routes = spec.into_routes().expect("profile must be valid");
+        assert_eq!(routes.routes.len(), 1);
+        assert!(routes.dst_overrides.is_empty());
+        assert!(routes.mirror.is_none());
+    }
+
+    #[test]
+    fn anchors_path_regexes() {
+        let routes = parse_yaml("routes: [{ match: { path: /api/.* } }]").unwrap();
+        match &routes.routes[0].0 {
+            profiles::RequestMatch::Path(re) => assert_eq!(re.as_str(), "^/api/.*$"),
+            m => panic!("unexpected match: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        // Unknown fields.
+        assert!(parse_yaml("routes: [{ match: { method: GET }, timeout: 1s }]").is_err());
+        // Invalid durations.
+        assert!(
+            parse_yaml("routes: [{ match: { method: GET }, route: { timeout: 1.5s } }]").is_err()
+        );
+        // Hedge percentiles outside of (0, 1).
+        assert!(parse_yaml(
+            r#"
+routes:
+  - match: { method: GET }
+    route:
+      retries:
+        budget: { retry_ratio: 0.2, min_retries_per_second: 10, ttl: 10s }
+        hedge_percentile: 1.0
+"#
+        )
+        .is_err());
+        // Ratios outside of [0, 1].
+        assert!(parse_yaml(
+            r#"
+routes:
+  - match: { method: GET }
+    route:
+      fault:
+        abort: { ratio: 2.0, http_status: 503 }
+"#
+        )
+        .is_err());
+        assert!(parse_yaml("mirror: { addr: 'web.ns.svc.cluster.local:80', ratio: 2.0 }").is_err());
+        // Addresses without ports.
+        assert!(
+            parse_yaml("dst_overrides: [{ addr: web.ns.svc.cluster.local, weight: 1 }]").is_err()
+        );
+        assert!(parse_yaml("mirror: { addr: web.ns.svc.cluster.local, ratio: 0.5 }").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0").unwrap(), Duration::from_secs(0));
+        assert_eq!(parse_duration("10ms").unwrap(), Duration::from_millis(10));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("10 s").is_err());
+    }
+
+    #[test]
+    fn publishes_profile_updates() {
+        use std::str::FromStr;
+        use tokio::runtime::current_thread::Runtime;
+        use tokio::timer::{Delay, Timeout};
+        use tower::Service;
+
+        fn next(
+            rt: &mut Runtime,
+            rx: profiles::Receiver,
+        ) -> (profiles::Routes, profiles::Receiver) {
+            let next = Timeout::new(rx.into_future().map_err(|(e, _)| e), Duration::from_secs(5));
+            let (routes, rx) = rt.block_on(next).expect("profile must be published");
+            (routes.expect("profile watch must not end"), rx)
+        }
+
+        let dir = std::env::temp_dir().join(format!("profile-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let yaml = dir.join("web.ns.svc.cluster.local.yaml");
+        let yml = dir.join("web.ns.svc.cluster.local.yml");
+        let json = dir.join("web.ns.svc.cluster.local.json");
+        std::fs::write(
+            &yaml,
+            "dst_overrides: [{ addr: 'web-v1.ns.svc.cluster.local:80', weight: 1 }]",
+        )
+        .unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut client, task) = Client::new(&dir, Duration::from_millis(10));
+        rt.spawn(task.map_err(|n| match n {}));
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        let rx = rt
+            .block_on(client.call(dst.into()))
+            .expect("profile must be watched");
+        assert_eq!(rx.get_ref().dst_overrides.len(), 1);
+
+        // The JSON file is read once the YAML file, which takes precedence,
+        // is removed.
+        std::fs::write(
+            &json,
+            r#"{ "dst_overrides": [
+                { "addr": "web-v1.ns.svc.cluster.local:80", "weight": 1 },
+                { "addr": "web-v2.ns.svc.cluster.local:80", "weight": 1 }
+            ] }"#,
+        )
+        .unwrap();
+        std::fs::remove_file(&yaml).unwrap();
+        let (routes, rx) = next(&mut rt, rx);
+        assert_eq!(routes.dst_overrides.len(), 2);
+
+        // An invalid file does not replace the last valid profile.
+        std::fs::write(&yml, "dst_overrides: [{ weight: 1 }]").unwrap();
+        rt.block_on(Delay::new(Instant::now() + Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(rx.get_ref().dst_overrides.len(), 2);
+
+        // Once every file is removed, the default profile is published.
+        std::fs::remove_file(&yml).unwrap();
+        std::fs::remove_file(&json).unwrap();
+        let (routes, _rx) = next(&mut rt, rx);
+        assert!(routes.dst_overrides.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,16 @@
-use super::{OverrideDestination, WeightedAddr};
-use futures::{future, Async, Future, Poll};
+use super::{mirror, OverrideDestination, WeightedAddr};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_addr::NameAddr;
 use linkerd2_error::Error;
 use rand::distributions::{Distribution, WeightedIndex};
 use rand::rngs::SmallRng;
+use std::sync::Arc;
 use tokio::sync::watch;
 pub use tokio::sync::watch::error::SendError;
+use tower::util::{Oneshot, ServiceExt};
 use tracing::{debug, trace};
 
-pub fn default<M>(make: M, rng: SmallRng) -> (Service<M>, Update) {
+pub fn default<M>(make: M, rng: SmallRng, mirror_buffer_limit: usize) -> (Service<M>, Update) {
     let routes = Routes::Forward(None);
     let (tx, rx) = watch::channel(routes.clone());
     let concrete = Service {
@@ -16,6 +18,7 @@ pub fn default<M>(make: M, rng: SmallRng) -> (Service<M>, Update) {
         routes: routes.clone(),
         updates: rx.clone(),
         rng,
+        mirror_buffer_limit,
     };
     let update = Update { tx, routes };
     (concrete, update)
@@ -27,6 +30,34 @@ pub struct Service<M> {
     routes: Routes,
     updates: watch::Receiver<Routes>,
     rng: SmallRng,
+    mirror_buffer_limit: usize,
+}
+
+pub struct MakeFuture<M, T, F> {
+    future: F,
+    make: Option<(M, T)>,
+    by_header: Option<Arc<Vec<WeightedAddr>>>,
+    mirror_buffer_limit: usize,
+}
+
+/// Dispatches requests to the service that was built for a target, unless a
+/// request's headers match an override, in which case the request is
+/// dispatched to a service built for the override's destination.
+pub struct Dispatch<M, T, S> {
+    inner: S,
+    make: M,
+    target: T,
+    by_header: Option<Arc<Vec<WeightedAddr>>>,
+}
+
+pub enum DispatchFuture<M, T, Req>
+where
+    M: tower::Service<T>,
+    M::Response: tower::Service<Req>,
+{
+    Inner(<M::Response as tower::Service<Req>>::Future),
+    Make(Oneshot<M, T>, Option<Req>),
+    Override(Oneshot<M::Response, Req>),
 }
 
 #[derive(Debug)]
@@ -39,11 +70,34 @@ pub struct Update {
 enum Routes {
     Forward(Option<NameAddr>),
     Override {
-        distribution: WeightedIndex<u32>,
+        /// Selects an override for each request by weight. `None` when every
+        /// override has a weight of zero and is only selected by headers, in
+        /// which case other requests keep the original destination.
+        distribution: Option<WeightedIndex<u32>>,
         overrides: Vec<NameAddr>,
+        /// Overrides that are selected by request headers, in order.
+        by_header: Arc<Vec<WeightedAddr>>,
     },
 }
 
+impl Routes {
+    fn split(addrs: Vec<WeightedAddr>) -> Self {
+        debug_assert!(!addrs.is_empty());
+        let distribution = WeightedIndex::new(addrs.iter().map(|w| w.weight)).ok();
+        let by_header = addrs
+            .iter()
+            .filter(|w| !w.headers.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        let overrides = addrs.into_iter().map(|w| w.addr).collect();
+        Routes::Override {
+            distribution,
+            overrides,
+            by_header: Arc::new(by_header),
+        }
+    }
+}
+
 impl std::fmt::Debug for Routes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -66,13 +120,13 @@ impl std::fmt::Debug for Routes {
 
 impl<T, S> tower::Service<T> for Service<S>
 where
-    T: OverrideDestination,
-    S: tower::Service<T>,
+    T: OverrideDestination + Clone,
+    S: tower::Service<T> + Clone,
     S::Error: Into<Error>,
 {
-    type Response = S::Response;
+    type Response = mirror::Service<S, T, Dispatch<S, T, S::Response>>;
     type Error = Error;
-    type Future = future::MapErr<S::Future, fn(S::Error) -> Self::Error>;
+    type Future = MakeFuture<S, T, S::Future>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         loop {
@@ -89,6 +143,11 @@ where
     }
 
     fn call(&mut self, mut target: T) -> Self::Future {
+        // Requests that are overridden by their headers or that are marked to
+        // be mirrored are sent to other destinations through the same stack.
+        let make = (self.make.clone(), target.clone());
+
+        let mut by_header = None;
         match self.routes {
             Routes::Forward(None) => {}
             Routes::Forward(Some(ref addr)) => {
@@ -97,15 +156,111 @@ where
             Routes::Override {
                 ref distribution,
                 ref overrides,
+                by_header: ref headers,
             } => {
-                debug_assert!(overrides.len() > 1);
-                let idx = distribution.sample(&mut self.rng);
-                debug_assert!(idx < overrides.len());
-                *target.dst_mut() = overrides[idx].clone().into();
+                if let Some(ref distribution) = distribution {
+                    let idx = distribution.sample(&mut self.rng);
+                    debug_assert!(idx < overrides.len());
+                    *target.dst_mut() = overrides[idx].clone().into();
+                }
+                if !headers.is_empty() {
+                    by_header = Some(headers.clone());
+                }
+            }
+        }
+
+        MakeFuture {
+            future: self.make.call(target),
+            make: Some(make),
+            by_header,
+            mirror_buffer_limit: self.mirror_buffer_limit,
+        }
+    }
+}
+
+impl<M, T, F> Future for MakeFuture<M, T, F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+    M: Clone,
+    T: Clone,
+{
+    type Item = mirror::Service<M, T, Dispatch<M, T, F::Item>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.future.poll().map_err(Into::into));
+        let (make, target) = self.make.take().expect("polled after ready");
+        let dispatch = Dispatch {
+            inner,
+            make: make.clone(),
+            target: target.clone(),
+            by_header: self.by_header.take(),
+        };
+        let svc = mirror::Service::new(dispatch, make, target, self.mirror_buffer_limit);
+        Ok(Async::Ready(svc))
+    }
+}
+
+// === impl Dispatch ===
+
+impl<M, T, S, B> tower::Service<http::Request<B>> for Dispatch<M, T, S>
+where
+    T: OverrideDestination + Clone,
+    M: tower::Service<T, Response = S> + Clone,
+    M::Error: Into<Error>,
+    S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = DispatchFuture<M, T, http::Request<B>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let addr = self
+            .by_header
+            .as_ref()
+            .and_then(|ws| ws.iter().find(|w| w.is_match(req.headers())))
+            .map(|w| w.addr.clone());
+
+        match addr {
+            None => DispatchFuture::Inner(self.inner.call(req)),
+            Some(addr) => {
+                debug!(%addr, "Overriding destination by request headers");
+                let mut target = self.target.clone();
+                *target.dst_mut() = addr.into();
+                DispatchFuture::Make(self.make.clone().oneshot(target), Some(req))
             }
         }
+    }
+}
+
+impl<M, T, Req> Future for DispatchFuture<M, T, Req>
+where
+    M: tower::Service<T>,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<Req>,
+    <M::Response as tower::Service<Req>>::Error: Into<Error>,
+{
+    type Item = <M::Response as tower::Service<Req>>::Response;
+    type Error = Error;
 
-        self.make.call(target).map_err(Into::into)
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                DispatchFuture::Inner(ref mut f) => return f.poll().map_err(Into::into),
+                DispatchFuture::Override(ref mut f) => return f.poll().map_err(Into::into),
+                DispatchFuture::Make(ref mut make, ref mut req) => {
+                    let svc = try_ready!(make.poll().map_err(Into::into));
+                    let req = req.take().expect("polled after ready");
+                    DispatchFuture::Override(svc.oneshot(req))
+                }
+            };
+        }
     }
 }
 
@@ -125,39 +280,20 @@ impl Update {
     }
 
     pub fn set_split(&mut self, mut addrs: Vec<WeightedAddr>) -> Result<(), error::LostService> {
-        let routes = match self.routes {
-            Routes::Forward(ref addr) => {
-                if addrs.len() == 1 {
-                    let new_addr = addrs.pop().unwrap().addr;
-                    if addr.as_ref().map(|a| a == &new_addr).unwrap_or(false) {
-                        trace!("forward already set to {}", new_addr);
-                        return Ok(());
-                    }
-                    Routes::Forward(Some(new_addr))
-                } else {
-                    let distribution = WeightedIndex::new(addrs.iter().map(|w| w.weight))
-                        .expect("invalid weight distribution");
-                    let overrides = addrs.into_iter().map(|w| w.addr).collect();
-                    Routes::Override {
-                        distribution,
-                        overrides,
-                    }
-                }
-            }
-            Routes::Override { .. } => {
-                if addrs.len() == 1 {
-                    let new_addr = addrs.pop().unwrap().addr;
-                    Routes::Forward(Some(new_addr))
-                } else {
-                    let distribution = WeightedIndex::new(addrs.iter().map(|w| w.weight))
-                        .expect("invalid weight distribution");
-                    let overrides = addrs.into_iter().map(|w| w.addr).collect();
-                    Routes::Override {
-                        distribution,
-                        overrides,
-                    }
+        // A single override replaces the destination outright, unless it is
+        // selected by request headers, in which case only matching requests
+        // may be sent to it.
+        let routes = if addrs.len() == 1 && addrs[0].headers.is_empty() {
+            let new_addr = addrs.pop().unwrap().addr;
+            if let Routes::Forward(Some(ref addr)) = self.routes {
+                if *addr == new_addr {
+                    trace!("forward already set to {}", new_addr);
+                    return Ok(());
                 }
             }
+            Routes::Forward(Some(new_addr))
+        } else {
+            Routes::split(addrs)
         };
 
         self.routes = routes.clone();
@@ -179,3 +315,127 @@ pub mod error {
 
     impl std::error::Error for LostService {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::ValueMatch;
+    use futures::future;
+    use linkerd2_addr::Addr;
+    use linkerd2_http_box as boxed;
+    use rand::SeedableRng;
+    use std::str::FromStr;
+    use tower::Service as _;
+
+    #[derive(Clone, Debug)]
+    struct Target(Addr);
+
+    /// Builds services that respond with the destination they were built for.
+    #[derive(Clone, Debug)]
+    struct MakeEcho;
+
+    #[derive(Clone, Debug)]
+    struct Echo(Addr);
+
+    impl OverrideDestination for Target {
+        fn dst_mut(&mut self) -> &mut Addr {
+            &mut self.0
+        }
+    }
+
+    impl tower::Service<Target> for MakeEcho {
+        type Response = Echo;
+        type Error = Error;
+        type Future = future::FutureResult<Echo, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, Target(dst): Target) -> Self::Future {
+            future::ok(Echo(dst))
+        }
+    }
+
+    impl tower::Service<http::Request<boxed::Payload>> for Echo {
+        type Response = Addr;
+        type Error = Error;
+        type Future = future::FutureResult<Addr, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<boxed::Payload>) -> Self::Future {
+            future::ok(self.0.clone())
+        }
+    }
+
+    fn addr(s: &str) -> NameAddr {
+        NameAddr::from_str(s).unwrap()
+    }
+
+    fn canary(weight: u32) -> WeightedAddr {
+        WeightedAddr {
+            addr: addr("canary.ns.svc.cluster.local:80"),
+            weight,
+            headers: vec![(
+                http::header::HeaderName::from_static("x-canary"),
+                ValueMatch::Exact("true".into()),
+            )],
+        }
+    }
+
+    fn dispatch_to(svc: &mut Service<MakeEcho>, req: http::Request<hyper::Body>) -> Addr {
+        let target = Target(addr("web.ns.svc.cluster.local:80").into());
+        future::lazy(|| tower::Service::<Target>::poll_ready(svc))
+            .wait()
+            .expect("service must be ready");
+        let mut dispatch = svc.call(target).wait().expect("service must be built");
+        dispatch.call(req).wait().expect("request must succeed")
+    }
+
+    fn req(canary: bool) -> http::Request<hyper::Body> {
+        let mut req = http::Request::builder();
+        if canary {
+            req.header("x-canary", "true");
+        }
+        req.body(hyper::Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn single_override_without_headers_forwards() {
+        let (mut svc, mut update) = default(MakeEcho, SmallRng::seed_from_u64(0), 0);
+        let weighted = WeightedAddr {
+            headers: Vec::new(),
+            ..canary(100)
+        };
+        update.set_split(vec![weighted]).unwrap();
+
+        let canary = Addr::from(addr("canary.ns.svc.cluster.local:80"));
+        assert_eq!(dispatch_to(&mut svc, req(false)), canary);
+        assert_eq!(dispatch_to(&mut svc, req(true)), canary);
+    }
+
+    #[test]
+    fn single_override_with_headers_only_matches_headers() {
+        let (mut svc, mut update) = default(MakeEcho, SmallRng::seed_from_u64(0), 0);
+        update.set_split(vec![canary(0)]).unwrap();
+        match update.routes {
+            Routes::Override {
+                ref distribution,
+                ref by_header,
+                ..
+            } => {
+                assert!(distribution.is_none());
+                assert_eq!(by_header.len(), 1);
+            }
+            ref routes => panic!("unexpected routes: {:?}", routes),
+        }
+
+        let web = Addr::from(addr("web.ns.svc.cluster.local:80"));
+        let canary = Addr::from(addr("canary.ns.svc.cluster.local:80"));
+        assert_eq!(dispatch_to(&mut svc, req(false)), web);
+        assert_eq!(dispatch_to(&mut svc, req(true)), canary);
+    }
+}
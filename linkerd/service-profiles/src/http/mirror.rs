@@ -0,0 +1,327 @@
+//! Mirrors requests to a shadow destination.
+//!
+//! Requests are marked to be mirrored as they are routed. When a marked
+//! request is dispatched to its concrete destination, its body is copied as
+//! it is read, up to a limit. Once the body completes, a copy of the request
+//! is sent to the mirror in the background. The mirror's response is
+//! discarded, and its failures never affect the original request.
+
+use super::OverrideDestination;
+use bytes::{Buf, Bytes};
+use futures::{sync::oneshot, try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use linkerd2_addr::NameAddr;
+use linkerd2_error::Error;
+use linkerd2_http_box as boxed;
+use std::collections::VecDeque;
+use std::io::Cursor;
+use tower::util::ServiceExt;
+use tracing::{debug, trace};
+
+/// Marks a request that is to be mirrored to `addr`.
+///
+/// Set as a request extension on the original request, until it is
+/// dispatched, and on the mirrored request.
+#[derive(Clone, Debug)]
+pub struct Mirrored {
+    pub addr: NameAddr,
+}
+
+/// Dispatches requests to an inner service, mirroring marked requests to
+/// services built by `make`.
+///
+/// Request bodies are boxed, so that original and mirrored requests may be
+/// dispatched to services of the same type.
+#[derive(Clone, Debug)]
+pub struct Service<M, T, S> {
+    inner: S,
+    make: M,
+    target: T,
+    buffer_limit: usize,
+}
+
+/// Copies a request body's data and trailers as they are read.
+struct Tee<B> {
+    inner: B,
+    copy: Option<Pending>,
+    buffer_limit: usize,
+    data_done: bool,
+}
+
+/// The data that has been copied from a body that has not yet completed.
+struct Pending {
+    data: VecDeque<Bytes>,
+    len: usize,
+    tx: oneshot::Sender<Buffered>,
+}
+
+/// A mirrored request body.
+struct Buffered {
+    data: VecDeque<Bytes>,
+    trailers: Option<http::HeaderMap>,
+}
+
+// === impl Service ===
+
+impl<M, T, S> Service<M, T, S> {
+    pub fn new(inner: S, make: M, target: T, buffer_limit: usize) -> Self {
+        Self {
+            inner,
+            make,
+            target,
+            buffer_limit,
+        }
+    }
+}
+
+impl<M, T, S, B> tower::Service<http::Request<B>> for Service<M, T, S>
+where
+    T: OverrideDestination + Clone + Send + 'static,
+    M: tower::Service<T> + Clone + Send + 'static,
+    M::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Response: tower::Service<http::Request<boxed::Payload>> + Send + 'static,
+    <M::Response as tower::Service<http::Request<boxed::Payload>>>::Error: Into<Error>,
+    <M::Response as tower::Service<http::Request<boxed::Payload>>>::Future: Send + 'static,
+    S: tower::Service<http::Request<boxed::Payload>>,
+    B: Payload + 'static,
+    B::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        match req.extensions_mut().remove::<Mirrored>() {
+            None => self.inner.call(req.map(boxed::Payload::new)),
+            Some(mirrored) => {
+                let req = self.mirror(mirrored, req);
+                self.inner.call(req)
+            }
+        }
+    }
+}
+
+impl<M, T, S> Service<M, T, S>
+where
+    T: OverrideDestination + Clone + Send + 'static,
+    M: tower::Service<T> + Clone + Send + 'static,
+    M::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Response: tower::Service<http::Request<boxed::Payload>> + Send + 'static,
+    <M::Response as tower::Service<http::Request<boxed::Payload>>>::Error: Into<Error>,
+    <M::Response as tower::Service<http::Request<boxed::Payload>>>::Future: Send + 'static,
+{
+    /// Spawns a task that sends a copy of `req` to the mirror once the
+    /// original request's body has been read, returning the original request.
+    fn mirror<B>(&self, mirrored: Mirrored, req: http::Request<B>) -> http::Request<boxed::Payload>
+    where
+        B: Payload + 'static,
+        B::Error: Into<Error>,
+    {
+        let mut head = http::Request::new(());
+        *head.method_mut() = req.method().clone();
+        *head.uri_mut() = req.uri().clone();
+        *head.version_mut() = req.version();
+        *head.headers_mut() = req.headers().clone();
+
+        let mut target = self.target.clone();
+        *target.dst_mut() = mirrored.addr.clone().into();
+        head.extensions_mut().insert(mirrored);
+
+        let (parts, body) = req.into_parts();
+        let (body, buffered) = Tee::new(body, self.buffer_limit);
+
+        let make = self.make.clone();
+        let mirror = buffered
+            .map_err(|_| debug!("Request body could not be mirrored"))
+            .and_then(move |body| {
+                trace!("Dispatching mirrored request");
+                make.oneshot(target)
+                    .map_err(Into::<Error>::into)
+                    .and_then(move |svc| {
+                        svc.oneshot(head.map(|()| boxed::Payload::new(body)))
+                            .map_err(Into::<Error>::into)
+                    })
+                    // The mirror's response is dropped without being read.
+                    .map(|_| trace!("Mirror responded"))
+                    .map_err(|error| debug!(%error, "Mirrored request failed"))
+            });
+        tokio::spawn(mirror);
+
+        http::Request::from_parts(parts, boxed::Payload::new(body))
+    }
+}
+
+// === impl Tee ===
+
+impl<B: Payload> Tee<B> {
+    fn new(inner: B, buffer_limit: usize) -> (Self, oneshot::Receiver<Buffered>) {
+        let (tx, rx) = oneshot::channel();
+
+        let copy = Pending {
+            data: VecDeque::new(),
+            len: 0,
+            tx,
+        };
+        let mut tee = Self {
+            inner,
+            copy: Some(copy),
+            buffer_limit,
+            data_done: false,
+        };
+
+        // Bodies that are already complete may never be polled.
+        if tee.inner.is_end_stream() {
+            tee.complete(None);
+        }
+
+        (tee, rx)
+    }
+}
+
+impl<B> Tee<B> {
+    /// Sends the copied body to the mirror, unless it has already been sent
+    /// or it exceeded the buffer.
+    fn complete(&mut self, trailers: Option<http::HeaderMap>) {
+        if let Some(Pending { data, tx, .. }) = self.copy.take() {
+            let _ = tx.send(Buffered { data, trailers });
+        }
+    }
+}
+
+impl<B> Payload for Tee<B>
+where
+    B: Payload,
+    B::Error: Into<Error>,
+{
+    type Data = Cursor<Bytes>;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let chunk = match try_ready!(self.inner.poll_data().map_err(Into::into)) {
+            Some(chunk) => chunk.collect::<Bytes>(),
+            None => {
+                self.data_done = true;
+                if self.inner.is_end_stream() {
+                    self.complete(None);
+                }
+                return Ok(Async::Ready(None));
+            }
+        };
+
+        if let Some(copy) = self.copy.as_mut() {
+            copy.len += chunk.len();
+            if copy.len > self.buffer_limit {
+                debug!(
+                    limit = self.buffer_limit,
+                    "Request body exceeds the mirror buffer"
+                );
+                self.copy = None;
+            } else {
+                copy.data.push_back(chunk.clone());
+            }
+        }
+
+        Ok(Async::Ready(Some(Cursor::new(chunk))))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers().map_err(Into::into));
+        self.complete(trailers.clone());
+        Ok(Async::Ready(trailers))
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.inner.content_length()
+    }
+}
+
+impl<B> Drop for Tee<B> {
+    fn drop(&mut self) {
+        // HTTP/1 clients do not read trailers, so a body whose data has been
+        // read is complete. Bodies that were not fully read are not mirrored.
+        if self.data_done {
+            self.complete(None);
+        }
+    }
+}
+
+// === impl Buffered ===
+
+impl Payload for Buffered {
+    type Data = Cursor<Bytes>;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty() && self.trailers.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Ok(Async::Ready(self.data.pop_front().map(Cursor::new)))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(Async::Ready(self.trailers.take()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream};
+
+    fn read<B: Payload<Error = Error>>(body: &mut B) -> String {
+        let mut data = Vec::new();
+        while let Some(chunk) = future::poll_fn(|| body.poll_data()).wait().unwrap() {
+            data.extend_from_slice(chunk.bytes());
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn body(chunks: Vec<&'static str>) -> hyper::Body {
+        hyper::Body::wrap_stream(stream::iter_ok::<_, Error>(chunks))
+    }
+
+    #[test]
+    fn copies_read_body() {
+        let (mut tee, rx) = Tee::new(body(vec!["hello ", "world"]), 64);
+        assert_eq!(read(&mut tee), "hello world");
+        drop(tee);
+
+        let mut buffered = rx.wait().expect("body must be mirrored");
+        assert_eq!(read(&mut buffered), "hello world");
+        assert!(buffered.is_end_stream());
+    }
+
+    #[test]
+    fn copies_empty_body() {
+        let (_tee, rx) = Tee::new(hyper::Body::empty(), 64);
+        let buffered = rx.wait().expect("body must be mirrored");
+        assert!(buffered.is_end_stream());
+    }
+
+    #[test]
+    fn does_not_copy_large_bodies() {
+        let (mut tee, rx) = Tee::new(body(vec!["hello ", "world"]), 8);
+        assert_eq!(read(&mut tee), "hello world");
+        drop(tee);
+        assert!(rx.wait().is_err());
+    }
+
+    #[test]
+    fn does_not_copy_unread_bodies() {
+        let (mut tee, rx) = Tee::new(body(vec!["hello ", "world"]), 64);
+        let _ = future::poll_fn(|| tee.poll_data()).wait().unwrap();
+        drop(tee);
+        assert!(rx.wait().is_err());
+    }
+}
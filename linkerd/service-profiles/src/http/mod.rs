@@ -3,6 +3,7 @@ use http;
 use indexmap::IndexMap;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_error::Error;
+use linkerd2_exp_backoff::ExponentialBackoff;
 use regex::Regex;
 use std::fmt;
 use std::hash::{Hash, Hasher};
@@ -14,21 +15,46 @@ use tokio::sync::watch;
 use tower::retry::budget::Budget;
 
 mod concrete;
+mod mirror;
 mod requests;
 pub mod service;
 
+pub use self::mirror::Mirrored;
 pub use self::service::Layer;
 
 #[derive(Clone, Debug)]
 pub struct WeightedAddr {
     pub addr: NameAddr,
     pub weight: u32,
+    /// Requests with headers that match all of these conditions are sent to
+    /// `addr` regardless of its weight.
+    ///
+    /// Header conditions are checked in the order that overrides are listed,
+    /// before an override is selected by weight.
+    ///
+    /// Header conditions are only configured by profile files; overrides
+    /// discovered from the controller are only selected by weight.
+    pub headers: Vec<(http::header::HeaderName, ValueMatch)>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Routes {
     pub routes: Vec<(RequestMatch, Route)>,
     pub dst_overrides: Vec<WeightedAddr>,
+    pub mirror: Option<Mirror>,
+}
+
+/// Copies a ratio of a destination's requests to another destination.
+///
+/// Mirrored requests are sent in addition to the original requests; their
+/// responses are discarded.
+///
+/// The destination profile API does not describe mirrors, so they may only be
+/// configured by profile files.
+#[derive(Clone, Debug)]
+pub struct Mirror {
+    pub addr: NameAddr,
+    pub ratio: f64,
 }
 
 pub type Receiver = watch::Receiver<Routes>;
@@ -90,8 +116,14 @@ pub struct Route {
     response_classes: ResponseClasses,
     retries: Option<Retries>,
     timeout: Option<Duration>,
+    fault: Option<Fault>,
 }
 
+/// Matches requests to a route.
+///
+/// The destination profile API only describes path and method conditions, so
+/// authority, header, and query parameter conditions may only be configured by
+/// profile files.
 #[derive(Clone, Debug)]
 pub enum RequestMatch {
     All(Vec<RequestMatch>),
@@ -99,6 +131,22 @@ pub enum RequestMatch {
     Not(Box<RequestMatch>),
     Path(Regex),
     Method(http::Method),
+    /// Matches the request's `:authority`, or its `host` header if the URI has
+    /// no authority.
+    Authority(Regex),
+    Header(http::header::HeaderName, ValueMatch),
+    /// Matches a query parameter. Parameters are compared without being
+    /// percent-decoded.
+    Query(String, ValueMatch),
+}
+
+/// Matches the value of a request header or query parameter.
+#[derive(Clone, Debug)]
+pub enum ValueMatch {
+    /// Matches any value, so long as the header or parameter is present.
+    Present,
+    Exact(String),
+    Regex(Regex),
 }
 
 #[derive(Clone, Debug)]
@@ -110,6 +158,10 @@ pub struct ResponseClass {
 #[derive(Clone, Default)]
 pub struct ResponseClasses(Arc<Vec<ResponseClass>>);
 
+/// Matches responses to a response class.
+///
+/// The destination profile API only describes status conditions, so header
+/// and gRPC status conditions may only be configured by profile files.
 #[derive(Clone, Debug)]
 pub enum ResponseMatch {
     All(Vec<ResponseMatch>),
@@ -119,11 +171,56 @@ pub enum ResponseMatch {
         min: http::StatusCode,
         max: http::StatusCode,
     },
+    /// Matches a response header or, at the end of the stream, a trailer.
+    Header(http::header::HeaderName, ValueMatch),
+    /// Matches the `grpc-status` of a response, from either its headers or,
+    /// at the end of the stream, its trailers.
+    GrpcStatus {
+        min: u32,
+        max: u32,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct Retries {
     budget: Arc<Budget>,
+    backoff: Option<ExponentialBackoff>,
+    hedge_percentile: Option<f64>,
+}
+
+/// Faults that are injected into a route's requests.
+///
+/// The destination profile API does not describe faults, so they may only be
+/// configured by profile files.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Fault {
+    pub delay: Option<FaultDelay>,
+    pub abort: Option<FaultAbort>,
+}
+
+/// Delays a ratio of requests by a duration chosen uniformly between `min`
+/// and `max`.
+#[derive(Clone, Debug)]
+pub struct FaultDelay {
+    /// The ratio of requests that are delayed, between 0.0 and 1.0.
+    pub ratio: f64,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// Fails a ratio of requests without dispatching them.
+#[derive(Clone, Debug)]
+pub struct FaultAbort {
+    /// The ratio of requests that are aborted, between 0.0 and 1.0.
+    pub ratio: f64,
+    pub abort: Abort,
+}
+
+/// The response returned for an aborted request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Abort {
+    Http(http::StatusCode),
+    Grpc(u32),
 }
 
 #[derive(Clone, Default)]
@@ -147,6 +244,7 @@ impl Route {
             response_classes: ResponseClasses(response_classes.into()),
             retries: None,
             timeout: None,
+            fault: None,
         }
     }
 
@@ -166,13 +264,58 @@ impl Route {
         self.timeout
     }
 
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
     pub fn set_retries(&mut self, budget: Arc<Budget>) {
-        self.retries = Some(Retries { budget });
+        self.retries = Some(Retries {
+            budget,
+            backoff: None,
+            hedge_percentile: None,
+        });
+    }
+
+    /// Sets the backoff between retries of this route, if it is retryable.
+    pub fn set_retry_backoff(&mut self, backoff: ExponentialBackoff) {
+        if let Some(retries) = self.retries.as_mut() {
+            retries.backoff = Some(backoff);
+        }
+    }
+
+    /// Marks this route as hedgeable, if it is retryable.
+    ///
+    /// Requests are hedged once they have been outstanding for longer than
+    /// the route's `percentile` latency.
+    pub fn set_hedge_percentile(&mut self, percentile: f64) {
+        if let Some(retries) = self.retries.as_mut() {
+            retries.hedge_percentile = Some(percentile);
+        }
     }
 
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    pub fn set_fault(&mut self, fault: Fault) {
+        self.fault = Some(fault);
+    }
+}
+
+// === impl WeightedAddr ===
+
+impl WeightedAddr {
+    /// Returns true if this override has header conditions and all of them
+    /// match `headers`.
+    fn is_match(&self, headers: &http::HeaderMap) -> bool {
+        !self.headers.is_empty()
+            && self.headers.iter().all(|(name, value)| {
+                headers
+                    .get_all(name)
+                    .iter()
+                    .any(|v| value.is_match(v.as_bytes()))
+            })
+    }
 }
 
 // === impl RequestMatch ===
@@ -182,6 +325,32 @@ impl RequestMatch {
         match self {
             RequestMatch::Method(ref method) => req.method() == *method,
             RequestMatch::Path(ref re) => re.is_match(req.uri().path()),
+            RequestMatch::Authority(ref re) => {
+                let authority = req.uri().authority_part().map(|a| a.as_str()).or_else(|| {
+                    req.headers()
+                        .get(http::header::HOST)
+                        .and_then(|h| h.to_str().ok())
+                });
+                authority.map(|a| re.is_match(a)).unwrap_or(false)
+            }
+            RequestMatch::Header(ref name, ref value) => req
+                .headers()
+                .get_all(name)
+                .iter()
+                .any(|v| value.is_match(v.as_bytes())),
+            RequestMatch::Query(ref name, ref value) => req
+                .uri()
+                .query()
+                .map(|q| {
+                    q.split('&').any(|param| {
+                        let mut parts = param.splitn(2, '=');
+                        match (parts.next(), parts.next()) {
+                            (Some(n), v) if n == name => value.is_match(v.unwrap_or("").as_bytes()),
+                            _ => false,
+                        }
+                    })
+                })
+                .unwrap_or(false),
             RequestMatch::Not(ref m) => !m.is_match(req),
             RequestMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
             RequestMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
@@ -189,6 +358,20 @@ impl RequestMatch {
     }
 }
 
+// === impl ValueMatch ===
+
+impl ValueMatch {
+    fn is_match(&self, value: &[u8]) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Exact(ref v) => v.as_bytes() == value,
+            ValueMatch::Regex(ref re) => std::str::from_utf8(value)
+                .map(|v| re.is_match(v))
+                .unwrap_or(false),
+        }
+    }
+}
+
 // === impl ResponseClass ===
 
 impl ResponseClass {
@@ -200,8 +383,25 @@ impl ResponseClass {
         self.is_failure
     }
 
-    pub fn is_match<B>(&self, req: &http::Response<B>) -> bool {
-        self.match_.is_match(req)
+    pub fn is_match<B>(&self, rsp: &http::Response<B>) -> bool {
+        self.match_.is_match(rsp.status(), rsp.headers(), None)
+    }
+
+    /// Matches a response once its stream has ended, considering its
+    /// trailers, if any.
+    pub fn is_match_eos(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        self.match_.is_match(status, headers, trailers)
+    }
+
+    /// Returns true if the class may match on a response's trailers, so that
+    /// the response cannot be classified until its stream ends.
+    pub fn inspects_trailers(&self) -> bool {
+        self.match_.inspects_trailers()
     }
 }
 
@@ -238,24 +438,99 @@ impl fmt::Debug for ResponseClasses {
 // === impl ResponseMatch ===
 
 impl ResponseMatch {
-    fn is_match<B>(&self, req: &http::Response<B>) -> bool {
+    fn is_match(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        match self {
+            ResponseMatch::Status { ref min, ref max } => *min <= status && status <= *max,
+            ResponseMatch::Header(ref name, ref value) => headers
+                .get_all(name)
+                .iter()
+                .chain(trailers.into_iter().flat_map(|t| t.get_all(name)))
+                .any(|v| value.is_match(v.as_bytes())),
+            ResponseMatch::GrpcStatus { min, max } => grpc_status(headers)
+                .or_else(|| trailers.and_then(grpc_status))
+                .map(|code| *min <= code && code <= *max)
+                .unwrap_or(false),
+            ResponseMatch::Not(ref m) => !m.is_match(status, headers, trailers),
+            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(status, headers, trailers)),
+            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(status, headers, trailers)),
+        }
+    }
+
+    fn inspects_trailers(&self) -> bool {
         match self {
-            ResponseMatch::Status { ref min, ref max } => {
-                *min <= req.status() && req.status() <= *max
+            ResponseMatch::Status { .. } => false,
+            ResponseMatch::Header(..) | ResponseMatch::GrpcStatus { .. } => true,
+            ResponseMatch::Not(ref m) => m.inspects_trailers(),
+            ResponseMatch::All(ref ms) | ResponseMatch::Any(ref ms) => {
+                ms.iter().any(|m| m.inspects_trailers())
             }
-            ResponseMatch::Not(ref m) => !m.is_match(req),
-            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
-            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
         }
     }
 }
 
+fn grpc_status(headers: &http::HeaderMap) -> Option<u32> {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse().ok())
+}
+
+// === impl FaultDelay ===
+
+impl PartialEq for FaultDelay {
+    fn eq(&self, other: &Self) -> bool {
+        self.ratio.to_bits() == other.ratio.to_bits()
+            && self.min == other.min
+            && self.max == other.max
+    }
+}
+
+impl Eq for FaultDelay {}
+
+impl Hash for FaultDelay {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ratio.to_bits().hash(state);
+        self.min.hash(state);
+        self.max.hash(state);
+    }
+}
+
+// === impl FaultAbort ===
+
+impl PartialEq for FaultAbort {
+    fn eq(&self, other: &Self) -> bool {
+        self.ratio.to_bits() == other.ratio.to_bits() && self.abort == other.abort
+    }
+}
+
+impl Eq for FaultAbort {}
+
+impl Hash for FaultAbort {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ratio.to_bits().hash(state);
+        self.abort.hash(state);
+    }
+}
+
 // === impl Retries ===
 
 impl Retries {
     pub fn budget(&self) -> &Arc<Budget> {
         &self.budget
     }
+
+    pub fn backoff(&self) -> Option<&ExponentialBackoff> {
+        self.backoff.as_ref()
+    }
+
+    pub fn hedge_percentile(&self) -> Option<f64> {
+        self.hedge_percentile
+    }
 }
 
 impl PartialEq for Retries {
@@ -293,3 +568,153 @@ impl fmt::Debug for Labels {
         self.0.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(uri: &str, headers: &[(&str, &str)]) -> http::Request<()> {
+        let mut req = http::Request::builder();
+        req.uri(uri);
+        for (k, v) in headers {
+            req.header(*k, *v);
+        }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    fn matches_headers() {
+        let content_type = http::header::CONTENT_TYPE;
+        let exact = RequestMatch::Header(
+            content_type.clone(),
+            ValueMatch::Exact("application/grpc".into()),
+        );
+        let regex = RequestMatch::Header(
+            content_type.clone(),
+            ValueMatch::Regex(Regex::new("^application/grpc(\\+.*)?$").unwrap()),
+        );
+        let present = RequestMatch::Header(content_type, ValueMatch::Present);
+
+        let grpc = req("/", &[("content-type", "application/grpc")]);
+        assert!(exact.is_match(&grpc));
+        assert!(regex.is_match(&grpc));
+        assert!(present.is_match(&grpc));
+
+        let proto = req("/", &[("content-type", "application/grpc+proto")]);
+        assert!(!exact.is_match(&proto));
+        assert!(regex.is_match(&proto));
+
+        let none = req("/", &[]);
+        assert!(!exact.is_match(&none));
+        assert!(!present.is_match(&none));
+    }
+
+    #[test]
+    fn matches_override_headers() {
+        use std::str::FromStr;
+
+        let canary = WeightedAddr {
+            addr: NameAddr::from_str("canary.ns.svc.cluster.local:80").unwrap(),
+            weight: 100,
+            headers: vec![
+                (
+                    http::header::HeaderName::from_static("x-canary"),
+                    ValueMatch::Exact("true".into()),
+                ),
+                (
+                    http::header::HeaderName::from_static("x-user"),
+                    ValueMatch::Present,
+                ),
+            ],
+        };
+        assert!(canary.is_match(req("/", &[("x-canary", "true"), ("x-user", "a")]).headers()));
+        assert!(!canary.is_match(req("/", &[("x-canary", "true")]).headers()));
+        assert!(!canary.is_match(req("/", &[("x-canary", "false"), ("x-user", "a")]).headers()));
+
+        let weighted = WeightedAddr {
+            headers: Vec::new(),
+            ..canary
+        };
+        assert!(!weighted.is_match(req("/", &[("x-canary", "true"), ("x-user", "a")]).headers()));
+    }
+
+    #[test]
+    fn matches_query_parameters() {
+        let tenant = RequestMatch::Query("tenant".into(), ValueMatch::Exact("a".into()));
+        let debug = RequestMatch::Query("debug".into(), ValueMatch::Present);
+
+        assert!(tenant.is_match(&req("/?tenant=a", &[])));
+        assert!(tenant.is_match(&req("/?debug&tenant=a", &[])));
+        assert!(!tenant.is_match(&req("/?tenant=b", &[])));
+        assert!(!tenant.is_match(&req("/?tenants=a", &[])));
+        assert!(!tenant.is_match(&req("/", &[])));
+
+        assert!(debug.is_match(&req("/?debug", &[])));
+        assert!(debug.is_match(&req("/?tenant=a&debug=1", &[])));
+        assert!(!debug.is_match(&req("/?tenant=a", &[])));
+    }
+
+    fn rsp(status: u16, headers: &[(&str, &str)]) -> http::Response<()> {
+        let mut rsp = http::Response::builder();
+        rsp.status(status);
+        for (k, v) in headers {
+            rsp.header(*k, *v);
+        }
+        rsp.body(()).unwrap()
+    }
+
+    #[test]
+    fn matches_response_headers_and_trailers() {
+        let class = ResponseClass::new(
+            true,
+            ResponseMatch::Header(
+                http::header::HeaderName::from_static("x-error"),
+                ValueMatch::Present,
+            ),
+        );
+        assert!(class.inspects_trailers());
+        assert!(class.is_match(&rsp(200, &[("x-error", "1")])));
+        assert!(!class.is_match(&rsp(200, &[])));
+
+        let head = rsp(200, &[]);
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-error", "1".parse().unwrap());
+        assert!(class.is_match_eos(head.status(), head.headers(), Some(&trailers)));
+    }
+
+    #[test]
+    fn matches_grpc_status() {
+        let class = ResponseClass::new(true, ResponseMatch::GrpcStatus { min: 2, max: 2 });
+        assert!(class.inspects_trailers());
+        assert!(class.is_match(&rsp(200, &[("grpc-status", "2")])));
+        assert!(!class.is_match(&rsp(200, &[("grpc-status", "0")])));
+
+        let head = rsp(200, &[]);
+        assert!(!class.is_match_eos(head.status(), head.headers(), None));
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", 2.into());
+        assert!(class.is_match_eos(head.status(), head.headers(), Some(&trailers)));
+        trailers.insert("grpc-status", 0.into());
+        assert!(!class.is_match_eos(head.status(), head.headers(), Some(&trailers)));
+
+        let status = ResponseClass::new(
+            true,
+            ResponseMatch::Status {
+                min: http::StatusCode::INTERNAL_SERVER_ERROR,
+                max: http::StatusCode::from_u16(599).unwrap(),
+            },
+        );
+        assert!(!status.inspects_trailers());
+    }
+
+    #[test]
+    fn matches_authority() {
+        let m = RequestMatch::Authority(Regex::new("^web\\.ns(:80)?$").unwrap());
+
+        assert!(m.is_match(&req("http://web.ns/", &[])));
+        assert!(m.is_match(&req("http://web.ns:80/", &[])));
+        assert!(m.is_match(&req("/", &[("host", "web.ns")])));
+        assert!(!m.is_match(&req("http://api.ns/", &[("host", "web.ns")])));
+        assert!(!m.is_match(&req("/", &[])));
+    }
+}
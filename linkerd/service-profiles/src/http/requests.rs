@@ -1,5 +1,6 @@
-use super::{RequestMatch, Route, WithRoute};
+use super::{Mirror, Mirrored, RequestMatch, Route, WithRoute};
 use linkerd2_stack::{NewService, Proxy};
+use rand::Rng;
 use tracing::trace;
 
 /// A proxy that applies per-request "routes" over a common inner service.
@@ -9,6 +10,7 @@ pub struct Requests<T: WithRoute, M: NewService<T::Route>> {
     make: M,
     default: M::Service,
     routes: Vec<(RequestMatch, M::Service)>,
+    mirror: Option<Mirror>,
 }
 
 impl<T, M> Requests<T, M>
@@ -26,6 +28,7 @@ where
             make,
             default,
             routes: Vec::default(),
+            mirror: None,
         }
     }
 
@@ -38,6 +41,10 @@ where
             })
             .collect();
     }
+
+    pub fn set_mirror(&mut self, mirror: Option<Mirror>) {
+        self.mirror = mirror;
+    }
 }
 
 impl<T, M, P, B, S> Proxy<http::Request<B>, S> for Requests<T, M>
@@ -52,7 +59,18 @@ where
     type Error = P::Error;
     type Future = P::Future;
 
-    fn proxy(&self, inner: &mut S, req: http::Request<B>) -> Self::Future {
+    fn proxy(&self, inner: &mut S, mut req: http::Request<B>) -> Self::Future {
+        // Requests are marked to be mirrored before they are routed, so that
+        // requests that are retried or hedged are only mirrored once.
+        if let Some(mirror) = self.mirror.as_ref() {
+            if rand::thread_rng().gen::<f64>() < mirror.ratio {
+                trace!(addr = %mirror.addr, "Mirroring request");
+                req.extensions_mut().insert(Mirrored {
+                    addr: mirror.addr.clone(),
+                });
+            }
+        }
+
         for (ref condition, ref route) in &self.routes {
             if condition.is_match(&req) {
                 trace!(?condition, "Using configured route");
@@ -8,7 +8,11 @@
 //! router picks a concrete dst (NameAddr) from the profile's `dst_overrides` if
 //! they exist, or uses the router's target's addr if no `dst_overrides` exist.
 //! The concrete dst router uses the concrete dst as the target for the
-//! underlying stack.
+//! underlying stack. Overrides with header conditions are selected for the
+//! requests that match them, before an override is picked by weight.
+//!
+//! When the profile configures a mirror, a ratio of requests are also copied
+//! to the mirror's dst through the same underlying stack.
 
 use super::concrete;
 use super::requests::Requests;
@@ -20,11 +24,16 @@ use rand::{rngs::SmallRng, SeedableRng};
 use tokio::sync::watch;
 use tracing::{debug, trace};
 
+/// The default maximum number of bytes of each mirrored request's body that
+/// are buffered.
+const DEFAULT_MIRROR_BUFFER_LIMIT: usize = 64 * 1024;
+
 #[derive(Clone, Debug)]
 pub struct Layer<G, R, O = ()> {
     get_routes: G,
     make_route: R,
     dst_override: O,
+    mirror_buffer_limit: usize,
     /// This is saved into a field so that the same `Arc`s are used and
     /// cloned, instead of calling `Route::default()` every time.
     default_route: Route,
@@ -37,6 +46,7 @@ pub struct MakeSvc<G, R, CMake, O = ()> {
     make_route: R,
     make_concrete: CMake,
     dst_override: O,
+    mirror_buffer_limit: usize,
 }
 
 pub struct MakeFuture<T, F, R, CMake, O> {
@@ -50,6 +60,7 @@ struct Inner<T, R, CMake, O> {
     make_route: R,
     make_concrete: CMake,
     dst_override: O,
+    mirror_buffer_limit: usize,
 }
 
 pub struct Service<T, R, C>
@@ -75,6 +86,7 @@ impl<G, R, O> Layer<G, R, O> {
             get_routes,
             make_route,
             dst_override,
+            mirror_buffer_limit: DEFAULT_MIRROR_BUFFER_LIMIT,
             default_route: Route::default(),
         }
     }
@@ -90,6 +102,15 @@ impl<G, R> Layer<G, R, SmallRng> {
     pub fn with_overrides(get_routes: G, make_route: R) -> Self {
         Self::new(get_routes, make_route, SmallRng::from_entropy())
     }
+
+    /// Sets the maximum number of bytes of each mirrored request's body that
+    /// are buffered. Requests with larger bodies are not mirrored.
+    pub fn with_mirror_buffer_limit(self, mirror_buffer_limit: usize) -> Self {
+        Self {
+            mirror_buffer_limit,
+            ..self
+        }
+    }
 }
 
 impl<G, R, C, O> tower::layer::Layer<C> for Layer<G, R, O>
@@ -107,6 +128,7 @@ where
             make_route: self.make_route.clone(),
             default_route: self.default_route.clone(),
             dst_override: self.dst_override.clone(),
+            mirror_buffer_limit: self.mirror_buffer_limit,
         }
     }
 }
@@ -137,6 +159,7 @@ where
                 default_route: self.default_route.clone(),
                 make_concrete: self.make_concrete.clone(),
                 dst_override: self.dst_override.clone(),
+                mirror_buffer_limit: self.mirror_buffer_limit,
             }),
         }
     }
@@ -168,6 +191,7 @@ where
                 default_route: self.default_route.clone(),
                 make_concrete: self.make_concrete.clone(),
                 dst_override: self.dst_override.clone(),
+                mirror_buffer_limit: self.mirror_buffer_limit,
             }),
         }
     }
@@ -193,6 +217,7 @@ where
             default_route,
             make_concrete,
             dst_override: (),
+            ..
         } = self.inner.take().unwrap();
 
         let requests = Requests::new(target.clone(), make_route, default_route);
@@ -227,10 +252,11 @@ where
             default_route,
             make_concrete,
             dst_override: rng,
+            mirror_buffer_limit,
         } = self.inner.take().unwrap();
 
         let requests = Requests::new(target.clone(), make_route, default_route);
-        let (service, update) = concrete::default(make_concrete, rng);
+        let (service, update) = concrete::default(make_concrete, rng, mirror_buffer_limit);
         let svc = Service {
             profiles,
             requests,
@@ -276,6 +302,11 @@ where
                     .expect("both sides of the concrete updater must be held");
             }
 
+            if let Some(ref mirror) = profile.mirror {
+                debug!(addr = %mirror.addr, ratio = mirror.ratio, "updating mirror");
+            }
+            self.requests.set_mirror(profile.mirror);
+
             debug!(routes = profile.routes.len(), "updating routes");
             self.requests.set_routes(profile.routes);
         }
@@ -361,11 +392,11 @@ impl<T, M: tower::Service<T>> tower::Service<T> for Forward<M> {
 
 impl<T, M> tower::Service<T> for Override<M>
 where
-    T: OverrideDestination,
-    M: tower::Service<T>,
+    T: OverrideDestination + Clone,
+    M: tower::Service<T> + Clone,
     M::Error: Into<Error>,
 {
-    type Response = M::Response;
+    type Response = <concrete::Service<M> as tower::Service<T>>::Response;
     type Error = Error;
     type Future = <concrete::Service<M> as tower::Service<T>>::Future;
 
@@ -1,7 +1,9 @@
 #![deny(warnings, rust_2018_idioms)]
 
 mod client;
+pub mod file;
 mod http;
+mod snapshot;
 
 pub use self::client::*;
 pub use self::http::*;
@@ -0,0 +1,123 @@
+//! Persists the last profile received for each destination, so that it may be
+//! used if the destination service cannot be reached.
+//!
+//! Profiles are saved as they are received from the destination service, as
+//! protobuf-encoded `DestinationProfile` messages, to a file named for the
+//! destination. Files are written in the background by a `Writer`.
+
+use linkerd2_addr::NameAddr;
+use linkerd2_metrics::Gauge;
+use linkerd2_proxy_api::destination as api;
+pub(crate) use linkerd2_snapshot::Stale;
+use linkerd2_snapshot::Writer;
+use prost::Message;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Snapshot {
+    dir: Arc<PathBuf>,
+    stale: Arc<Gauge>,
+    writer: Writer,
+}
+
+/// Reads and writes a destination's snapshot.
+pub(crate) struct Store {
+    path: PathBuf,
+    stale: Arc<Gauge>,
+    writer: Writer,
+}
+
+// === impl Snapshot ===
+
+impl Snapshot {
+    pub fn new(dir: PathBuf, stale: Arc<Gauge>, writer: Writer) -> Self {
+        Self {
+            dir: Arc::new(dir),
+            stale,
+            writer,
+        }
+    }
+
+    pub fn store(&self, dst: &NameAddr) -> Store {
+        Store {
+            path: self.dir.join(format!("{}.pb", dst)),
+            stale: self.stale.clone(),
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+// === impl Store ===
+
+impl Store {
+    pub fn load(&self) -> Option<api::DestinationProfile> {
+        let contents = std::fs::read(&self.path).ok()?;
+        match api::DestinationProfile::decode(&contents[..]) {
+            Ok(profile) => Some(profile),
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Invalid profile snapshot");
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, profile: &api::DestinationProfile) {
+        let mut contents = Vec::with_capacity(profile.encoded_len());
+        match profile.encode(&mut contents) {
+            Ok(()) => self.writer.write(self.path.clone(), contents),
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Failed to encode profile snapshot")
+            }
+        }
+    }
+
+    /// Marks the destination as stale until the returned guard is dropped.
+    pub fn stale(&self) -> Stale {
+        Stale::new(self.stale.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn saves_and_loads_profiles() {
+        let dir = std::env::temp_dir().join(format!("profile-snapshot-{}", std::process::id()));
+        let stale = Arc::new(Gauge::default());
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let (writer, task) = Writer::new(Duration::from_secs(60));
+        let store = Snapshot::new(dir.clone(), stale.clone(), writer).store(&dst);
+        assert!(store.load().is_none());
+
+        let profile = api::DestinationProfile {
+            routes: vec![api::Route {
+                is_retryable: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        store.save(&profile);
+
+        // Pending snapshots are written once the writer is dropped.
+        drop(store);
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_or_else(|n| match n {});
+
+        let (writer, _task) = Writer::new(Duration::from_secs(60));
+        let store = Snapshot::new(dir.clone(), stale.clone(), writer).store(&dst);
+        assert_eq!(store.load(), Some(profile));
+
+        let guard = store.stale();
+        assert_eq!(stale.value(), 1);
+        drop(guard);
+        assert_eq!(stale.value(), 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
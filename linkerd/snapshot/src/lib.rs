@@ -0,0 +1,216 @@
+//! Persists the last known state of discovered destinations, so that it may
+//! be used if the destination service cannot be reached.
+//!
+//! Snapshot files are written by a task on a dedicated thread, so that file
+//! IO never blocks the proxy's reactor. Changes to a file are coalesced, so
+//! that a file is written at most once per debounce interval however often
+//! its destination changes.
+
+#![deny(warnings, rust_2018_idioms)]
+
+use futures::{sync::mpsc, Async, Future, Poll, Stream};
+use indexmap::IndexMap;
+use linkerd2_error::{Error, Never};
+use linkerd2_metrics::Gauge;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use tracing::{debug, trace, warn};
+
+/// Sends snapshots to be written in the background.
+#[derive(Clone, Debug)]
+pub struct Writer {
+    tx: mpsc::UnboundedSender<(PathBuf, Option<Vec<u8>>)>,
+}
+
+/// Writes snapshots as they are sent by a `Writer`.
+///
+/// Completes, once all pending snapshots are written, when all `Writer`s are
+/// dropped.
+pub struct Task {
+    rx: mpsc::UnboundedReceiver<(PathBuf, Option<Vec<u8>>)>,
+    debounce: Duration,
+    /// The latest contents of each changed file, or `None` if the file is to
+    /// be removed.
+    pending: IndexMap<PathBuf, Option<Vec<u8>>>,
+    delay: Option<Delay>,
+}
+
+/// Counts a destination as stale while it is held.
+#[derive(Debug)]
+pub struct Stale(Arc<Gauge>);
+
+// === impl Writer ===
+
+impl Writer {
+    /// Returns a `Writer` and the `Task` that writes its snapshots, waiting
+    /// `debounce` after a file changes before it is written.
+    pub fn new(debounce: Duration) -> (Self, Task) {
+        let (tx, rx) = mpsc::unbounded();
+        let task = Task {
+            rx,
+            debounce,
+            pending: IndexMap::new(),
+            delay: None,
+        };
+        (Self { tx }, task)
+    }
+
+    /// Returns a `Writer` whose snapshots are written on a dedicated thread.
+    pub fn spawn(debounce: Duration) -> std::io::Result<Self> {
+        let (writer, task) = Self::new(debounce);
+        std::thread::Builder::new()
+            .name("snapshot".into())
+            .spawn(move || {
+                tokio::runtime::current_thread::Runtime::new()
+                    .expect("snapshot runtime")
+                    .block_on(task)
+                    .unwrap_or_else(|n| match n {})
+            })?;
+        Ok(writer)
+    }
+
+    /// Replaces the contents of the file at `path`.
+    pub fn write(&self, path: PathBuf, contents: Vec<u8>) {
+        self.send(path, Some(contents));
+    }
+
+    /// Removes the file at `path`.
+    pub fn remove(&self, path: PathBuf) {
+        self.send(path, None);
+    }
+
+    fn send(&self, path: PathBuf, contents: Option<Vec<u8>>) {
+        if self.tx.unbounded_send((path, contents)).is_err() {
+            warn!("Snapshot writer has stopped");
+        }
+    }
+}
+
+// === impl Task ===
+
+impl Task {
+    fn flush(&mut self) {
+        for (path, contents) in self.pending.drain(..) {
+            let result = match contents {
+                Some(contents) => write(&path, &contents),
+                None => remove(&path),
+            };
+            match result {
+                Ok(()) => trace!(path = %path.display(), "Saved snapshot"),
+                Err(error) => warn!(path = %path.display(), %error, "Failed to save snapshot"),
+            }
+        }
+    }
+}
+
+impl Future for Task {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.rx.poll() {
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(Some((path, contents)))) => {
+                    self.pending.insert(path, contents);
+                    if self.delay.is_none() {
+                        self.delay = Some(Delay::new(Instant::now() + self.debounce));
+                    }
+                }
+                Ok(Async::Ready(None)) | Err(()) => {
+                    debug!("Writing remaining snapshots");
+                    self.flush();
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+
+        if let Some(delay) = self.delay.as_mut() {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(error) => warn!(%error, "Snapshot timer failed"),
+            }
+            self.delay = None;
+            self.flush();
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// Replaces a file atomically, so that a snapshot is never partially read.
+fn write(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// === impl Stale ===
+
+impl Stale {
+    /// Counts a destination as stale until the returned guard is dropped.
+    pub fn new(gauge: Arc<Gauge>) -> Self {
+        gauge.incr();
+        Stale(gauge)
+    }
+}
+
+impl Drop for Stale {
+    fn drop(&mut self) {
+        self.0.decr();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesces_writes() {
+        let dir = std::env::temp_dir().join(format!("snapshot-writer-{}", std::process::id()));
+        let web = dir.join("web");
+        let api = dir.join("api");
+
+        let (writer, task) = Writer::new(Duration::from_secs(60));
+        writer.write(web.clone(), b"a".to_vec());
+        writer.write(api.clone(), b"a".to_vec());
+        writer.write(web.clone(), b"b".to_vec());
+        writer.remove(api.clone());
+        drop(writer);
+
+        // Pending snapshots are written when the writer is dropped, without
+        // waiting for the debounce interval.
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_or_else(|n| match n {});
+        assert_eq!(std::fs::read(&web).unwrap(), b"b");
+        assert!(!api.exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn counts_stale_destinations() {
+        let gauge = Arc::new(Gauge::default());
+        let stale = Stale::new(gauge.clone());
+        assert_eq!(gauge.value(), 1);
+        drop(stale);
+        assert_eq!(gauge.value(), 0);
+    }
+}
@@ -6,24 +6,39 @@ mod service;
 pub use self::layer::TrackServiceLayer;
 pub use self::service::TrackService;
 use indexmap::IndexMap;
-use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
 use std::fmt;
 use std::hash::Hash;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
 
 metrics! {
     stack_create_total: Counter { "Total number of services created" },
     stack_drop_total: Counter { "Total number of services dropped" },
     stack_poll_total: Counter { "Total number of stack polls" },
-    stack_poll_total_ms: Counter { "Total number of milliseconds this service has spent awaiting readiness" }
+    stack_poll_total_ms: Counter { "Total number of milliseconds this service has spent awaiting readiness" },
+    stack_ejection_total: Counter { "Total number of endpoints ejected by outlier detection" },
+    stack_ejected: Gauge { "Number of endpoints currently ejected by outlier detection" }
 }
 
 type Shared<L> = Arc<Mutex<IndexMap<L, Arc<Metrics>>>>;
 
 #[derive(Debug)]
-pub struct Registry<L: Hash + Eq>(Shared<L>);
+pub struct Registry<L: Hash + Eq> {
+    metrics: Shared<L>,
+    retain_idle: Duration,
+}
+
+/// Records endpoint ejections for a stack.
+///
+/// A scope's metrics are removed from the registry once all of its
+/// `Ejections` handles have been dropped and it has not been updated for the
+/// registry's idle retention period.
+#[derive(Clone, Debug)]
+pub struct Ejections(Arc<Metrics>);
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct Metrics {
     create_total: Counter,
     drop_total: Counter,
@@ -31,39 +46,58 @@ struct Metrics {
     not_ready_total: Counter,
     poll_millis: Counter,
     error_total: Counter,
+    ejection_total: Counter,
+    ejected: Gauge,
+    last_update: Mutex<Instant>,
 }
 
 impl<L> Registry<L>
 where
     L: Hash + Eq,
 {
+    pub fn new(retain_idle: Duration) -> Self {
+        Self {
+            metrics: Shared::default(),
+            retain_idle,
+        }
+    }
+
     pub fn layer(&self, labels: L) -> TrackServiceLayer {
-        let metrics = self
-            .0
+        TrackServiceLayer::new(self.metrics(labels))
+    }
+
+    pub fn ejections(&self, labels: L) -> Ejections {
+        Ejections(self.metrics(labels))
+    }
+
+    fn metrics(&self, labels: L) -> Arc<Metrics> {
+        self.metrics
             .lock()
             .expect("stack metrics lock poisoned")
             .entry(labels.into())
             .or_insert_with(Default::default)
-            .clone();
-        TrackServiceLayer::new(metrics)
-    }
-}
-
-impl<L: Hash + Eq> Default for Registry<L> {
-    fn default() -> Self {
-        Registry(Shared::default())
+            .clone()
     }
 }
 
 impl<L: Hash + Eq> Clone for Registry<L> {
     fn clone(&self) -> Self {
-        Registry(self.0.clone())
+        Self {
+            metrics: self.metrics.clone(),
+            retain_idle: self.retain_idle,
+        }
     }
 }
 
 impl<L: FmtLabels + Hash + Eq> FmtMetrics for Registry<L> {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let metrics = self.0.lock().expect("metrics registry poisoned");
+        let mut metrics = self.metrics.lock().expect("metrics registry poisoned");
+
+        // Drop the metrics of scopes that are no longer referenced and have
+        // been idle for longer than `retain_idle`.
+        let epoch = clock::now() - self.retain_idle;
+        metrics.retain(|_, m| Arc::strong_count(&m) > 1 || m.last_update() >= epoch);
+
         if metrics.is_empty() {
             return Ok(());
         }
@@ -94,10 +128,74 @@ impl<L: FmtLabels + Hash + Eq> FmtMetrics for Registry<L> {
         stack_poll_total_ms.fmt_help(f)?;
         stack_poll_total_ms.fmt_scopes(f, metrics.iter(), |m| &m.poll_millis)?;
 
+        stack_ejection_total.fmt_help(f)?;
+        stack_ejection_total.fmt_scopes(f, metrics.iter(), |m| &m.ejection_total)?;
+
+        stack_ejected.fmt_help(f)?;
+        stack_ejected.fmt_scopes(f, metrics.iter(), |m| &m.ejected)?;
+
         Ok(())
     }
 }
 
+// === impl Metrics ===
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            create_total: Counter::default(),
+            drop_total: Counter::default(),
+            ready_total: Counter::default(),
+            not_ready_total: Counter::default(),
+            poll_millis: Counter::default(),
+            error_total: Counter::default(),
+            ejection_total: Counter::default(),
+            ejected: Gauge::default(),
+            last_update: Mutex::new(clock::now()),
+        }
+    }
+}
+
+impl Metrics {
+    fn touch(&self) {
+        *self
+            .last_update
+            .lock()
+            .expect("stack metrics lock poisoned") = clock::now();
+    }
+
+    fn last_update(&self) -> Instant {
+        *self
+            .last_update
+            .lock()
+            .expect("stack metrics lock poisoned")
+    }
+}
+
+// === impl Ejections ===
+
+impl Ejections {
+    /// Records that an endpoint has been ejected.
+    pub fn eject(&self) {
+        self.0.ejection_total.incr();
+        self.0.ejected.incr();
+        self.0.touch();
+    }
+
+    /// Records that an ejected endpoint has been restored or dropped.
+    pub fn restore(&self) {
+        self.0.ejected.decr();
+        self.0.touch();
+    }
+}
+
+impl Drop for Ejections {
+    fn drop(&mut self) {
+        // The scope's idle period starts when its last handle is released.
+        self.0.touch();
+    }
+}
+
 enum Ready {
     Ready,
     NotReady,
@@ -113,3 +211,37 @@ impl FmtLabels for Ready {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Hash, PartialEq, Eq)]
+    struct Scope(&'static str);
+
+    impl FmtLabels for Scope {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "scope=\"{}\"", self.0)
+        }
+    }
+
+    #[test]
+    fn drops_released_ejections_once_idle() {
+        let registry = Registry::new(Duration::from_secs(0));
+        let held = registry.ejections(Scope("held"));
+        held.eject();
+        let released = registry.ejections(Scope("released"));
+        released.eject();
+        released.restore();
+        drop(released);
+        std::thread::sleep(Duration::from_millis(1));
+
+        let report = registry.as_display().to_string();
+        assert!(
+            report.contains("stack_ejection_total{scope=\"held\"} 1"),
+            "{}",
+            report
+        );
+        assert!(!report.contains("released"), "{}", report);
+    }
+}
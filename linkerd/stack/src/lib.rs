@@ -6,6 +6,7 @@ pub mod fallback;
 mod future_service;
 pub mod layer;
 pub mod make_ready;
+pub mod make_thunk;
 pub mod map_response;
 pub mod map_target;
 pub mod new_service;
@@ -16,6 +17,7 @@ mod proxy;
 pub use self::fallback::{Fallback, FallbackLayer};
 pub use self::future_service::FutureService;
 pub use self::make_ready::{MakeReady, MakeReadyLayer};
+pub use self::make_thunk::{MakeThunk, MakeThunkLayer, Thunk};
 pub use self::map_response::{MapResponse, MapResponseLayer};
 pub use self::map_target::{MapTarget, MapTargetLayer, MapTargetService};
 pub use self::new_service::NewService;
@@ -0,0 +1,81 @@
+//! A `MakeService` that binds an inner `Service` to a fixed target.
+
+use futures::{future, Poll};
+use linkerd2_error::Never;
+
+/// A Layer that modifies an inner `Service<T>` to produce `Thunk`s.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MakeThunkLayer(());
+
+/// Produces a `Thunk` for each target, such that each call on the thunk
+/// invokes the inner service with a clone of the target.
+#[derive(Clone, Debug)]
+pub struct MakeThunk<S> {
+    inner: S,
+}
+
+/// A `Service<()>` that calls an inner `Service` with a fixed target.
+#[derive(Clone, Debug)]
+pub struct Thunk<S, T> {
+    inner: S,
+    target: T,
+}
+
+// === impl MakeThunkLayer ===
+
+impl MakeThunkLayer {
+    pub fn new() -> Self {
+        MakeThunkLayer(())
+    }
+}
+
+impl<S> tower::layer::Layer<S> for MakeThunkLayer {
+    type Service = MakeThunk<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MakeThunk { inner }
+    }
+}
+
+// === impl MakeThunk ===
+
+impl<S, T> tower::Service<T> for MakeThunk<S>
+where
+    S: tower::Service<T> + Clone,
+    T: Clone,
+{
+    type Response = Thunk<S, T>;
+    type Error = Never;
+    type Future = future::FutureResult<Self::Response, Never>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        future::ok(Thunk {
+            inner: self.inner.clone(),
+            target,
+        })
+    }
+}
+
+// === impl Thunk ===
+
+impl<S, T> tower::Service<()> for Thunk<S, T>
+where
+    S: tower::Service<T>,
+    T: Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, (): ()) -> Self::Future {
+        self.inner.call(self.target.clone())
+    }
+}
@@ -14,6 +14,13 @@ mod propagation;
 
 pub use layer::{TraceContext, TraceContextLayer};
 
+/// Returns the ID of the trace that `request` belongs to, if it is sampled.
+pub fn sampled_trace_id<B>(request: &http::Request<B>) -> Option<Id> {
+    propagation::unpack_trace_context(request)
+        .filter(|context| context.is_sampled())
+        .map(|context| context.trace_id)
+}
+
 const SPAN_ID_LEN: usize = 8;
 
 #[derive(Debug, Default)]
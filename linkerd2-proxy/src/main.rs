@@ -53,12 +53,14 @@ fn main() {
                 }
             }
 
-            let dst_addr = app.dst_addr();
-            match dst_addr.identity.value() {
-                None => info!("Destinations resolved via {}", dst_addr.addr),
-                Some(identity) => {
-                    info!("Destinations resolved via {} ({})", dst_addr.addr, identity)
-                }
+            match app.dst_addr() {
+                None => info!("Destinations resolved from local files"),
+                Some(dst_addr) => match dst_addr.identity.value() {
+                    None => info!("Destinations resolved via {}", dst_addr.addr),
+                    Some(identity) => {
+                        info!("Destinations resolved via {} ({})", dst_addr.addr, identity)
+                    }
+                },
             }
 
             if let Some(oc) = app.opencensus_addr() {
@@ -0,0 +1,40 @@
+//! gRPC bindings for OpenTelemetry metrics.
+//!
+//! Vendored from https://github.com/open-telemetry/opentelemetry-proto/.
+
+#![deny(warnings, rust_2018_idioms)]
+
+pub mod collector {
+    pub mod metrics {
+        pub mod v1 {
+            include!(concat!(
+                env!("OUT_DIR"),
+                "/opentelemetry.proto.collector.metrics.v1.rs"
+            ));
+        }
+    }
+}
+pub mod metrics {
+    pub mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.metrics.v1.rs"
+        ));
+    }
+}
+pub mod common {
+    pub mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.common.v1.rs"
+        ));
+    }
+}
+pub mod resource {
+    pub mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.resource.v1.rs"
+        ));
+    }
+}